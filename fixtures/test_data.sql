@@ -9,3 +9,8 @@ INSERT INTO users (username, password, created_at, updated_at) VALUES
 ('heidi', '$argon2id$v=19$m=19456,t=2,p=1$l1dyTTu6AGbIr++b8oXV6w$UX29Pq3b+IvdBrRh8SXoFZW4ritpnUhZUOsUyzR7eww', NOW(), NOW()),
 ('ivan', '$argon2id$v=19$m=19456,t=2,p=1$l1dyTTu6AGbIr++b8oXV6w$UX29Pq3b+IvdBrRh8SXoFZW4ritpnUhZUOsUyzR7eww', NOW(), NOW()),
 ('judy', '$argon2id$v=19$m=19456,t=2,p=1$l1dyTTu6AGbIr++b8oXV6w$UX29Pq3b+IvdBrRh8SXoFZW4ritpnUhZUOsUyzR7eww', NOW(), NOW());
+
+-- test users belong to the default organization
+INSERT INTO organization_members (organization_id, user_id)
+SELECT 1, id FROM users
+ON CONFLICT DO NOTHING;
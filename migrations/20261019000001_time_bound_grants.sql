@@ -0,0 +1,13 @@
+-- Track who granted a role or direct permission, and optionally when it expires
+ALTER TABLE user_roles
+    ADD COLUMN granted_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    ADD COLUMN expires_at TIMESTAMPTZ,
+    ADD COLUMN granted_by INTEGER REFERENCES users (id) ON DELETE SET NULL;
+
+ALTER TABLE user_permissions
+    ADD COLUMN granted_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    ADD COLUMN expires_at TIMESTAMPTZ,
+    ADD COLUMN granted_by INTEGER REFERENCES users (id) ON DELETE SET NULL;
+
+CREATE INDEX user_roles_expires_at_idx ON user_roles (expires_at) WHERE expires_at IS NOT NULL;
+CREATE INDEX user_permissions_expires_at_idx ON user_permissions (expires_at) WHERE expires_at IS NOT NULL;
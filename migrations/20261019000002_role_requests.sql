@@ -0,0 +1,27 @@
+-- Pending role escalations awaiting approval by a second admin
+CREATE TABLE role_requests (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    requested_by INTEGER REFERENCES users (id) ON DELETE SET NULL,
+    roles JSONB NOT NULL,
+    status VARCHAR(20) NOT NULL DEFAULT 'pending',
+    reviewed_by INTEGER REFERENCES users (id) ON DELETE SET NULL,
+    reviewed_at TIMESTAMPTZ,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX role_requests_status_idx ON role_requests (status);
+
+-- Every state change of a role request
+CREATE TABLE role_request_events (
+    id SERIAL PRIMARY KEY,
+    request_id INTEGER NOT NULL REFERENCES role_requests (id) ON DELETE CASCADE,
+    actor_id INTEGER REFERENCES users (id) ON DELETE SET NULL,
+    event VARCHAR(20) NOT NULL,
+    comment TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX role_request_events_request_id_idx ON role_request_events (request_id);
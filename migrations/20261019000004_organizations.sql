@@ -0,0 +1,90 @@
+-- Organizations (tenants) with membership and per-organization role assignments
+CREATE TABLE organizations (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR(255) NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- every existing user and grant belongs to the default organization, ID: 1
+INSERT INTO organizations (name) VALUES ('default');
+
+CREATE TABLE organization_members (
+    organization_id INTEGER NOT NULL REFERENCES organizations (id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (organization_id, user_id)
+);
+
+CREATE INDEX organization_members_user_id_idx ON organization_members (user_id);
+
+INSERT INTO organization_members (organization_id, user_id)
+SELECT 1, id FROM users;
+
+ALTER TABLE user_roles
+    ADD COLUMN organization_id INTEGER NOT NULL DEFAULT 1 REFERENCES organizations (id) ON DELETE CASCADE;
+ALTER TABLE user_roles DROP CONSTRAINT user_roles_pkey;
+ALTER TABLE user_roles ADD PRIMARY KEY (user_id, role_id, organization_id);
+ALTER TABLE user_roles ALTER COLUMN organization_id DROP DEFAULT;
+
+ALTER TABLE user_permissions
+    ADD COLUMN organization_id INTEGER NOT NULL DEFAULT 1 REFERENCES organizations (id) ON DELETE CASCADE;
+ALTER TABLE user_permissions DROP CONSTRAINT user_permissions_pkey;
+ALTER TABLE user_permissions ADD PRIMARY KEY (user_id, permission_id, organization_id);
+ALTER TABLE user_permissions ALTER COLUMN organization_id DROP DEFAULT;
+
+ALTER TABLE groups
+    ADD COLUMN organization_id INTEGER NOT NULL DEFAULT 1 REFERENCES organizations (id) ON DELETE CASCADE;
+ALTER TABLE groups DROP CONSTRAINT groups_name_key;
+ALTER TABLE groups ADD CONSTRAINT groups_organization_id_name_key UNIQUE (organization_id, name);
+ALTER TABLE groups ALTER COLUMN organization_id DROP DEFAULT;
+
+ALTER TABLE role_requests
+    ADD COLUMN organization_id INTEGER NOT NULL DEFAULT 1 REFERENCES organizations (id) ON DELETE CASCADE;
+ALTER TABLE role_requests ALTER COLUMN organization_id DROP DEFAULT;
+
+-- Row-level security as a defence in depth: when a transaction sets `app.tenant_id`,
+-- rows of other organizations are invisible even if a query forgets to filter them.
+-- Without the setting (migrations, background sweeps, sign in) every row is visible.
+CREATE FUNCTION current_tenant_id() RETURNS INTEGER
+LANGUAGE sql STABLE AS $$
+    SELECT NULLIF(current_setting('app.tenant_id', true), '')::INTEGER
+$$;
+
+ALTER TABLE organization_members ENABLE ROW LEVEL SECURITY;
+ALTER TABLE organization_members FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON organization_members
+    USING (current_tenant_id() IS NULL OR organization_id = current_tenant_id());
+
+ALTER TABLE user_roles ENABLE ROW LEVEL SECURITY;
+ALTER TABLE user_roles FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON user_roles
+    USING (current_tenant_id() IS NULL OR organization_id = current_tenant_id());
+
+ALTER TABLE user_permissions ENABLE ROW LEVEL SECURITY;
+ALTER TABLE user_permissions FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON user_permissions
+    USING (current_tenant_id() IS NULL OR organization_id = current_tenant_id());
+
+ALTER TABLE groups ENABLE ROW LEVEL SECURITY;
+ALTER TABLE groups FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON groups
+    USING (current_tenant_id() IS NULL OR organization_id = current_tenant_id());
+
+ALTER TABLE role_requests ENABLE ROW LEVEL SECURITY;
+ALTER TABLE role_requests FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON role_requests
+    USING (current_tenant_id() IS NULL OR organization_id = current_tenant_id());
+
+-- users are shared across organizations, a tenant only sees its members
+ALTER TABLE users ENABLE ROW LEVEL SECURITY;
+ALTER TABLE users FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON users
+    USING (
+        current_tenant_id() IS NULL
+        OR EXISTS (
+            SELECT 1 FROM organization_members m
+            WHERE m.user_id = users.id AND m.organization_id = current_tenant_id()
+        )
+    )
+    WITH CHECK (true);
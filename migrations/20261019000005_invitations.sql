@@ -0,0 +1,19 @@
+-- Invitations to sign up with pre-assigned roles
+CREATE TABLE invitations (
+    id SERIAL PRIMARY KEY,
+    organization_id INTEGER NOT NULL REFERENCES organizations (id) ON DELETE CASCADE,
+    email VARCHAR(255),
+    roles JSONB NOT NULL,
+    invited_by INTEGER REFERENCES users (id) ON DELETE SET NULL,
+    accepted_by INTEGER REFERENCES users (id) ON DELETE SET NULL,
+    accepted_at TIMESTAMPTZ,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX invitations_organization_id_idx ON invitations (organization_id);
+
+ALTER TABLE invitations ENABLE ROW LEVEL SECURITY;
+ALTER TABLE invitations FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON invitations
+    USING (current_tenant_id() IS NULL OR organization_id = current_tenant_id());
@@ -0,0 +1,2 @@
+-- Optional email, checked against the registration domain allowlist
+ALTER TABLE users ADD COLUMN email VARCHAR(255);
@@ -0,0 +1,22 @@
+-- Indexes for the filters and sort orders of `GET /users`
+
+-- The `username` substring filter only needs pg_trgm to be fast. Creating the extension takes
+-- the CREATE privilege on the database (pg_trgm is trusted) or a superuser; without it the
+-- index is skipped and the filter scans the members of the organization instead.
+DO $$
+BEGIN
+    CREATE EXTENSION IF NOT EXISTS pg_trgm;
+    CREATE INDEX users_username_trgm_idx ON users USING GIN (username gin_trgm_ops);
+EXCEPTION
+    WHEN insufficient_privilege OR undefined_file THEN
+        RAISE NOTICE 'pg_trgm is not available, users_username_trgm_idx not created';
+END
+$$;
+
+CREATE INDEX users_username_lower_idx ON users (lower(username) text_pattern_ops);
+CREATE INDEX users_created_at_idx ON users (created_at, id);
+CREATE INDEX users_updated_at_idx ON users (updated_at, id);
+CREATE INDEX user_roles_role_id_idx ON user_roles (role_id, organization_id);
+CREATE INDEX user_permissions_permission_id_idx ON user_permissions (permission_id, organization_id);
+CREATE INDEX group_roles_role_id_idx ON group_roles (role_id);
+CREATE INDEX group_permissions_permission_id_idx ON group_permissions (permission_id);
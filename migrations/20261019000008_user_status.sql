@@ -0,0 +1,8 @@
+-- Account lifecycle: soft delete and suspension instead of removing the row,
+-- `active` until suspended or deleted
+ALTER TABLE users
+    ADD COLUMN status VARCHAR(20) NOT NULL DEFAULT 'active',
+    ADD COLUMN deleted_at TIMESTAMPTZ,
+    ADD CONSTRAINT users_status_check CHECK (status IN ('active', 'suspended', 'deleted'));
+
+CREATE INDEX users_status_idx ON users (status);
@@ -0,0 +1,12 @@
+-- Optional profile of a user, shared by all its organizations
+CREATE TABLE user_profiles (
+    user_id INTEGER PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    display_name VARCHAR(100),
+    locale VARCHAR(35),
+    timezone VARCHAR(64),
+    bio TEXT,
+    -- storage key of the re-encoded avatar
+    avatar_key VARCHAR(255),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
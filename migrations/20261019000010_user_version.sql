@@ -0,0 +1,45 @@
+-- Optimistic concurrency: a user's `version` changes with the user row and with its grants,
+-- group memberships and profile. It's served as the ETag of `GET /users/{id}`.
+ALTER TABLE users ADD COLUMN version BIGINT NOT NULL DEFAULT 1;
+
+CREATE FUNCTION bump_user_version() RETURNS TRIGGER
+LANGUAGE plpgsql AS $$
+BEGIN
+    NEW.version := OLD.version + 1;
+    RETURN NEW;
+END
+$$;
+
+CREATE TRIGGER users_bump_version
+    BEFORE UPDATE ON users
+    FOR EACH ROW EXECUTE FUNCTION bump_user_version();
+
+-- rows keyed by `user_id` touch their user, which bumps its version
+CREATE FUNCTION touch_user_version() RETURNS TRIGGER
+LANGUAGE plpgsql AS $$
+BEGIN
+    IF TG_OP <> 'INSERT' THEN
+        UPDATE users SET version = version WHERE id = OLD.user_id;
+    END IF;
+    IF TG_OP <> 'DELETE' AND (TG_OP = 'INSERT' OR NEW.user_id <> OLD.user_id) THEN
+        UPDATE users SET version = version WHERE id = NEW.user_id;
+    END IF;
+    RETURN NULL;
+END
+$$;
+
+CREATE TRIGGER user_roles_touch_user
+    AFTER INSERT OR UPDATE OR DELETE ON user_roles
+    FOR EACH ROW EXECUTE FUNCTION touch_user_version();
+
+CREATE TRIGGER user_permissions_touch_user
+    AFTER INSERT OR UPDATE OR DELETE ON user_permissions
+    FOR EACH ROW EXECUTE FUNCTION touch_user_version();
+
+CREATE TRIGGER group_members_touch_user
+    AFTER INSERT OR UPDATE OR DELETE ON group_members
+    FOR EACH ROW EXECUTE FUNCTION touch_user_version();
+
+CREATE TRIGGER user_profiles_touch_user
+    AFTER INSERT OR UPDATE OR DELETE ON user_profiles
+    FOR EACH ROW EXECUTE FUNCTION touch_user_version();
@@ -0,0 +1,52 @@
+-- Usernames are unique regardless of case, through a normalized column
+
+-- Names differing only in case were allowed until now: the oldest account keeps its name,
+-- the others are renamed to `<username>_<id>` and reported, their owners have to be told.
+DO $$
+DECLARE
+    duplicate RECORD;
+    renamed VARCHAR(255);
+    suffix TEXT;
+    attempt INTEGER;
+BEGIN
+    FOR duplicate IN
+        SELECT id, username
+        FROM (
+            SELECT id, username, row_number() OVER (PARTITION BY lower(username) ORDER BY id) AS rank
+            FROM users
+        ) ranked
+        WHERE rank > 1
+        ORDER BY id
+    LOOP
+        suffix := '_' || duplicate.id;
+        attempt := 1;
+        LOOP
+            renamed := left(duplicate.username, 255 - length(suffix)) || suffix;
+            EXIT WHEN NOT EXISTS (SELECT 1 FROM users WHERE lower(username) = lower(renamed));
+            attempt := attempt + 1;
+            suffix := '_' || duplicate.id || '_' || attempt;
+        END LOOP;
+        UPDATE users SET username = renamed WHERE id = duplicate.id;
+        RAISE NOTICE 'username % of user % differs only in case from an older one, renamed to %',
+            duplicate.username, duplicate.id, renamed;
+    END LOOP;
+END
+$$;
+
+ALTER TABLE users
+    ADD COLUMN username_normalized VARCHAR(255) GENERATED ALWAYS AS (lower(username)) STORED;
+ALTER TABLE users DROP CONSTRAINT users_username_unique;
+ALTER TABLE users ADD CONSTRAINT users_username_normalized_unique UNIQUE (username_normalized);
+
+-- Every rename, old names stay held by their previous owner and still resolve to it
+CREATE TABLE username_history (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    old_username VARCHAR(255) NOT NULL,
+    new_username VARCHAR(255) NOT NULL,
+    changed_by INTEGER REFERENCES users (id) ON DELETE SET NULL,
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX username_history_old_username_idx ON username_history (lower(old_username));
+CREATE INDEX username_history_user_id_idx ON username_history (user_id, changed_at DESC);
@@ -0,0 +1,31 @@
+-- Security relevant changes, written in the transaction of the change itself.
+-- No foreign keys on the actor and target: events outlive purged users.
+CREATE TABLE audit_events (
+    id BIGSERIAL PRIMARY KEY,
+    organization_id INTEGER NOT NULL,
+    actor_id INTEGER,
+    action VARCHAR(64) NOT NULL,
+    target_type VARCHAR(64) NOT NULL,
+    target_id INTEGER,
+    before JSONB,
+    after JSONB,
+    ip VARCHAR(64),
+    request_id VARCHAR(128),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX audit_events_organization_id_idx ON audit_events (organization_id, id DESC);
+CREATE INDEX audit_events_actor_id_idx ON audit_events (actor_id, id DESC);
+CREATE INDEX audit_events_target_idx ON audit_events (target_type, target_id, id DESC);
+CREATE INDEX audit_events_request_id_idx ON audit_events (request_id);
+
+-- The log is append only
+CREATE FUNCTION reject_audit_event_change() RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_events is append only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_events_append_only
+    BEFORE UPDATE OR DELETE ON audit_events
+    FOR EACH ROW EXECUTE FUNCTION reject_audit_event_change();
@@ -0,0 +1,26 @@
+-- Tamper evidence: every audit event carries the hash of the previous one.
+-- Events recorded before the chain existed keep a NULL hash and precede it.
+ALTER TABLE audit_events
+    ADD COLUMN prev_hash VARCHAR(64),
+    ADD COLUMN hash VARCHAR(64);
+
+-- Signatures of the chain head, made with the token signing key
+CREATE TABLE audit_anchors (
+    id BIGSERIAL PRIMARY KEY,
+    event_id BIGINT NOT NULL,
+    hash VARCHAR(64) NOT NULL,
+    signature VARCHAR(128) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX audit_anchors_event_id_idx ON audit_anchors (event_id);
+
+CREATE OR REPLACE FUNCTION reject_audit_event_change() RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION '% is append only', TG_TABLE_NAME;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_anchors_append_only
+    BEFORE UPDATE OR DELETE ON audit_anchors
+    FOR EACH ROW EXECUTE FUNCTION reject_audit_event_change();
@@ -0,0 +1,14 @@
+-- Every signin attempt, flagged by the anomaly rules when suspicious
+CREATE TABLE signin_attempts (
+    id BIGSERIAL PRIMARY KEY,
+    user_id INTEGER REFERENCES users (id) ON DELETE CASCADE,
+    username VARCHAR(255) NOT NULL,
+    succeeded BOOLEAN NOT NULL,
+    failure_reason VARCHAR(64),
+    ip VARCHAR(64),
+    user_agent VARCHAR(512),
+    flags TEXT[] NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX signin_attempts_user_id_idx ON signin_attempts (user_id, id DESC);
@@ -0,0 +1,18 @@
+-- Transactional outbox: domain events written with the change, delivered by the relay
+CREATE TABLE outbox_events (
+    id BIGSERIAL PRIMARY KEY,
+    organization_id INTEGER NOT NULL,
+    aggregate_type VARCHAR(64) NOT NULL,
+    aggregate_id INTEGER NOT NULL,
+    event_type VARCHAR(64) NOT NULL,
+    payload JSONB NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_error TEXT,
+    delivered_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- the relay reads the oldest pending event of each aggregate
+CREATE INDEX outbox_events_pending_idx ON outbox_events (aggregate_type, aggregate_id, id)
+    WHERE delivered_at IS NULL;
@@ -0,0 +1,22 @@
+-- Background jobs, claimed by workers with FOR UPDATE SKIP LOCKED
+CREATE TABLE jobs (
+    id BIGSERIAL PRIMARY KEY,
+    organization_id INTEGER NOT NULL REFERENCES organizations (id) ON DELETE CASCADE,
+    kind VARCHAR(64) NOT NULL,
+    payload JSONB NOT NULL,
+    -- queued, running, succeeded or dead
+    status VARCHAR(16) NOT NULL DEFAULT 'queued',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    max_attempts INTEGER NOT NULL,
+    run_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- while running: the worker holding the job and until when, after that it may be claimed again
+    locked_by VARCHAR(64),
+    locked_until TIMESTAMPTZ,
+    last_error TEXT,
+    finished_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX jobs_queued_idx ON jobs (run_at) WHERE status = 'queued';
+CREATE INDEX jobs_running_idx ON jobs (locked_until) WHERE status = 'running';
+CREATE INDEX jobs_organization_id_idx ON jobs (organization_id, id);
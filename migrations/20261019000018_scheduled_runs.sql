@@ -0,0 +1,16 @@
+-- Run history of the cron schedules of app.yaml, one row per schedule and tick
+CREATE TABLE scheduled_runs (
+    id BIGSERIAL PRIMARY KEY,
+    organization_id INTEGER NOT NULL REFERENCES organizations (id) ON DELETE CASCADE,
+    schedule VARCHAR(64) NOT NULL,
+    -- the tick of the cron expression, not when the job ran
+    scheduled_for TIMESTAMPTZ NOT NULL,
+    -- the job enqueued for the tick, its status is the outcome of the run
+    job_id BIGINT REFERENCES jobs (id) ON DELETE SET NULL,
+    -- the instance that won the tick
+    instance VARCHAR(64) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (schedule, scheduled_for)
+);
+
+CREATE INDEX scheduled_runs_organization_id_idx ON scheduled_runs (organization_id, id);
@@ -0,0 +1,23 @@
+-- grants of a group are grants of its members: changing them bumps the version of every member
+CREATE FUNCTION touch_group_members_version() RETURNS TRIGGER
+LANGUAGE plpgsql AS $$
+BEGIN
+    IF TG_OP <> 'INSERT' THEN
+        UPDATE users SET version = version
+        WHERE id IN (SELECT user_id FROM group_members WHERE group_id = OLD.group_id);
+    END IF;
+    IF TG_OP <> 'DELETE' AND (TG_OP = 'INSERT' OR NEW.group_id <> OLD.group_id) THEN
+        UPDATE users SET version = version
+        WHERE id IN (SELECT user_id FROM group_members WHERE group_id = NEW.group_id);
+    END IF;
+    RETURN NULL;
+END
+$$;
+
+CREATE TRIGGER group_roles_touch_members
+    AFTER INSERT OR UPDATE OR DELETE ON group_roles
+    FOR EACH ROW EXECUTE FUNCTION touch_group_members_version();
+
+CREATE TRIGGER group_permissions_touch_members
+    AFTER INSERT OR UPDATE OR DELETE ON group_permissions
+    FOR EACH ROW EXECUTE FUNCTION touch_group_members_version();
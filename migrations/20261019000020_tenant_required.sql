@@ -0,0 +1,16 @@
+-- Every pooled connection now carries `app.tenant_id`: an organization, or 'all' for work
+-- done on behalf of none. A connection without it is a bug, so its queries on tenant tables
+-- fail instead of seeing every organization. Sessions outside of the application, like
+-- later migrations touching these tables, run `SET app.tenant_id = 'all'` first.
+CREATE OR REPLACE FUNCTION current_tenant_id() RETURNS INTEGER
+LANGUAGE plpgsql STABLE AS $$
+DECLARE
+    tenant TEXT := current_setting('app.tenant_id', true);
+BEGIN
+    IF tenant IS NULL OR tenant = '' THEN
+        RAISE EXCEPTION 'no tenant set on the connection'
+            USING ERRCODE = 'insufficient_privilege';
+    END IF;
+    RETURN NULLIF(tenant, 'all')::INTEGER;
+END
+$$;
@@ -0,0 +1,14 @@
+-- Signin attempts belong to the organization signed in to, so one organization doesn't see
+-- where its members sign in to the others from. Earlier attempts go to the user's first one.
+SET LOCAL app.tenant_id = 'all';
+
+ALTER TABLE signin_attempts
+    ADD COLUMN organization_id INTEGER REFERENCES organizations (id) ON DELETE CASCADE;
+
+UPDATE signin_attempts a
+SET organization_id = (
+    SELECT MIN(m.organization_id) FROM organization_members m WHERE m.user_id = a.user_id
+);
+
+CREATE INDEX signin_attempts_organization_id_idx
+    ON signin_attempts (organization_id, user_id, id DESC);
@@ -14,6 +14,28 @@ pub struct JwtClaims {
   pub iat: usize,
   pub iss: String,
   pub aud: String,
+  /// active organization, absent in tokens issued before multi-tenancy
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub org: Option<i32>,
+}
+
+/// Identity carried by a verified token.
+#[derive(Debug, Clone, Copy)]
+pub struct TokenSubject {
+  pub user_id: i32,
+  pub organization_id: Option<i32>,
+  pub expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Claims of an invitation token. The audience differs from access tokens so
+/// an invitation can't be used as a bearer token.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InvitationClaims {
+  pub sub: String,
+  pub exp: usize,
+  pub iat: usize,
+  pub iss: String,
+  pub aud: String,
 }
 
 pub fn hash_password(password: &str) -> Result<String, AppError> {
@@ -34,7 +56,7 @@ pub fn verify_password(password: &str, hashed_password: &str) -> Result<bool, Ap
   Ok(is_valid)
 }
 
-pub fn sign(user_id: i32, config: &AppConfig) -> Result<String, AppError> {
+pub fn sign(user_id: i32, organization_id: i32, config: &AppConfig) -> Result<String, AppError> {
   let now = chrono::Utc::now().timestamp() as usize;
   let claims = JwtClaims {
     sub: user_id.to_string(),
@@ -42,13 +64,14 @@ pub fn sign(user_id: i32, config: &AppConfig) -> Result<String, AppError> {
     iat: now,
     iss: config.auth.jwt_iss.clone(),
     aud: config.auth.jwt_aud.clone(),
+    org: Some(organization_id),
   };
   let header = Header::new(Algorithm::EdDSA);
   let token = encode(&header, &claims, &config.auth.encoding_key)?;
   Ok(token)
 }
 
-pub fn verify(token: &str, config: &AppConfig) -> Result<i32, AppError> {
+pub fn verify(token: &str, config: &AppConfig) -> Result<TokenSubject, AppError> {
   let mut validation = Validation::new(Algorithm::EdDSA);
   validation.set_issuer(&[&config.auth.jwt_iss]);
   validation.set_audience(&[&config.auth.jwt_aud]);
@@ -59,5 +82,47 @@ pub fn verify(token: &str, config: &AppConfig) -> Result<i32, AppError> {
     .sub
     .parse()
     .map_err(|_| AppError::Unauthorized("invalid user id in token".to_string()))?;
-  Ok(user_id)
+  let expires_at = chrono::DateTime::from_timestamp(token_data.claims.exp as i64, 0).ok_or(
+    AppError::Unauthorized("invalid expiry in token".to_string()),
+  )?;
+  Ok(TokenSubject {
+    user_id,
+    organization_id: token_data.claims.org,
+    expires_at,
+  })
+}
+
+fn invitation_audience(config: &AppConfig) -> String {
+  format!("{}/invitations", config.auth.jwt_aud)
+}
+
+pub fn sign_invitation(
+  invitation_id: i32,
+  expires_at: chrono::DateTime<chrono::Utc>,
+  config: &AppConfig,
+) -> Result<String, AppError> {
+  let claims = InvitationClaims {
+    sub: invitation_id.to_string(),
+    exp: expires_at.timestamp() as usize,
+    iat: chrono::Utc::now().timestamp() as usize,
+    iss: config.auth.jwt_iss.clone(),
+    aud: invitation_audience(config),
+  };
+  let header = Header::new(Algorithm::EdDSA);
+  let token = encode(&header, &claims, &config.auth.encoding_key)?;
+  Ok(token)
+}
+
+pub fn verify_invitation(token: &str, config: &AppConfig) -> Result<i32, AppError> {
+  let mut validation = Validation::new(Algorithm::EdDSA);
+  validation.set_issuer(&[&config.auth.jwt_iss]);
+  validation.set_audience(&[invitation_audience(config)]);
+
+  let token_data = decode::<InvitationClaims>(token, &config.auth.decoding_key, &validation)?;
+  let invitation_id: i32 = token_data
+    .claims
+    .sub
+    .parse()
+    .map_err(|_| AppError::Unauthorized("invalid invitation id in token".to_string()))?;
+  Ok(invitation_id)
 }
@@ -1,19 +1,255 @@
+use std::collections::HashSet;
 use std::fs::read_to_string;
+use std::str::FromStr;
 
-use anyhow::Result;
+use anyhow::{Result, bail};
+use ed25519_dalek::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use ed25519_dalek::{SigningKey, VerifyingKey};
 use jsonwebtoken::{DecodingKey, EncodingKey};
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
+
+use super::DEFAULT_ORGANIZATION_ID;
+
+use crate::modules::authz::Policy;
 
 #[allow(unused)]
 #[derive(Clone, Debug, Deserialize)]
 pub struct ServerConfig {
   pub port: u16,
+  /// take the client address from `X-Forwarded-For`, only behind a proxy that sets it
+  #[serde(default)]
+  pub trust_forwarded_for: bool,
 }
 
 #[allow(unused)]
 #[derive(Clone, Debug, Deserialize)]
 pub struct DatabaseConfig {
   pub db_url: String,
+  /// role the connections switch to, subject to row-level security unlike a superuser
+  #[serde(default)]
+  pub role: Option<String>,
+}
+
+#[allow(unused)]
+#[derive(Clone, Debug, Deserialize)]
+pub struct ApprovalsConfig {
+  /// require a second admin to approve role escalations
+  pub enabled: bool,
+  /// roles whose grant counts as an escalation
+  pub privileged_roles: Vec<String>,
+  /// seconds a pending request stays open
+  pub request_ttl: u64,
+}
+
+#[allow(unused)]
+#[derive(Clone, Debug, Deserialize)]
+pub struct InvitationsConfig {
+  /// seconds an invitation stays valid
+  pub ttl: u64,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RegistrationMode {
+  /// anyone can sign up
+  Open,
+  /// signing up needs an invitation
+  InviteOnly,
+  /// nobody can sign up, not even with an invitation
+  Disabled,
+}
+
+#[allow(unused)]
+#[derive(Clone, Debug, Deserialize)]
+pub struct RegistrationConfig {
+  pub mode: RegistrationMode,
+  /// email domains open signups are restricted to, empty allows any
+  pub allowed_email_domains: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum StorageBackend {
+  /// files under `root` on the local filesystem
+  Local,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct StorageConfig {
+  pub backend: StorageBackend,
+  pub root: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct AvatarsConfig {
+  /// largest accepted upload in bytes
+  pub max_bytes: usize,
+  /// largest accepted width or height of the uploaded image
+  pub max_dimension: u32,
+  /// width and height of the stored, re-encoded avatar
+  pub size: u32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct UserTransferConfig {
+  /// rows per import transaction and users per export page
+  pub batch_size: usize,
+  /// passwords of an import batch hashed at the same time, on the blocking thread pool
+  pub hash_concurrency: usize,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct UsernamesConfig {
+  /// seconds a user has to wait between two renames
+  pub change_cooldown: u64,
+  /// names nobody can sign up with or rename to, compared case-insensitively
+  pub reserved: Vec<String>,
+}
+
+impl UsernamesConfig {
+  pub fn is_reserved(&self, username: &str) -> bool {
+    self
+      .reserved
+      .iter()
+      .any(|reserved| reserved.eq_ignore_ascii_case(username))
+  }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ConcurrencyConfig {
+  /// reject `PATCH` and `DELETE` of a user without `If-Match` (428)
+  pub require_if_match: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SigninsConfig {
+  /// seconds within which a signin from another address than the previous one is flagged
+  pub rapid_switch_window: u64,
+  /// failed attempts that flag the next successful signin
+  pub failure_threshold: i64,
+  /// seconds the failed attempts are counted over
+  pub failure_window: u64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct OutboxConfig {
+  /// seconds between passes of the relay
+  pub poll_interval: u64,
+  /// events read per query of a pass
+  pub batch_size: i64,
+  /// seconds before the first retry of a failed delivery, doubled on every further failure
+  pub retry_base: u64,
+  /// longest wait between two retries, in seconds
+  pub retry_max: u64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct WebhooksConfig {
+  /// seconds between passes of the dispatcher
+  pub poll_interval: u64,
+  /// deliveries sent per pass
+  pub batch_size: i64,
+  /// seconds an endpoint gets to respond
+  pub timeout: u64,
+  /// attempts before a delivery is given up as failed
+  pub max_attempts: i32,
+  /// seconds before the first retry, doubled on every further failure
+  pub retry_base: u64,
+  /// longest wait between two retries, in seconds
+  pub retry_max: u64,
+  /// hosts webhooks may target even when they resolve to loopback or private addresses
+  #[serde(default)]
+  pub allowed_hosts: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct EventsConfig {
+  /// seconds between keep-alive comments on idle streams
+  pub keep_alive: u64,
+  /// events a slow subscriber may fall behind before its stream is closed
+  pub buffer: usize,
+  /// most events replayed after `Last-Event-ID`, the stream closes after a full replay
+  pub replay_limit: i64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct JobsConfig {
+  /// seconds between polls of an idle worker
+  pub poll_interval: u64,
+  /// jobs a worker runs at once
+  pub concurrency: usize,
+  /// seconds a job may run before another worker may claim it again
+  pub visibility_timeout: u64,
+  /// attempts before a job is dead-lettered
+  pub max_attempts: i32,
+  /// seconds before the first retry, doubled on every further failure
+  pub retry_base: u64,
+  /// longest wait between two retries, in seconds
+  pub retry_max: u64,
+  /// seconds a stopping worker waits for its running jobs before putting them back
+  pub shutdown_timeout: u64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SchedulerConfig {
+  /// seconds between checks for due schedules
+  pub poll_interval: u64,
+  #[serde(default)]
+  pub schedules: Vec<ScheduleConfig>,
+}
+
+impl SchedulerConfig {
+  fn check(&self) -> Result<()> {
+    let mut names = HashSet::new();
+    for schedule in &self.schedules {
+      if !names.insert(&schedule.name) {
+        bail!("duplicate schedule name: {}", schedule.name);
+      }
+    }
+    Ok(())
+  }
+}
+
+/// A job enqueued on every tick of a cron expression.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ScheduleConfig {
+  /// unique, the key of the run history
+  pub name: String,
+  /// in UTC, `min hour day month weekday` or with a leading seconds field
+  #[serde(deserialize_with = "deserialize_cron")]
+  pub cron: cron::Schedule,
+  /// the `Job::KIND` to enqueue
+  pub job: String,
+  #[serde(default = "empty_payload")]
+  pub payload: serde_json::Value,
+  /// the tenant the job runs as
+  #[serde(default = "default_organization")]
+  pub organization_id: i32,
+}
+
+fn deserialize_cron<'de, D: Deserializer<'de>>(
+  deserializer: D,
+) -> Result<cron::Schedule, D::Error> {
+  let expression = String::deserialize(deserializer)?;
+  let expression = match expression.split_whitespace().count() {
+    5 => format!("0 {}", expression),
+    _ => expression,
+  };
+  cron::Schedule::from_str(&expression).map_err(serde::de::Error::custom)
+}
+
+fn empty_payload() -> serde_json::Value {
+  serde_json::json!({})
+}
+
+fn default_organization() -> i32 {
+  DEFAULT_ORGANIZATION_ID
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct AuditConfig {
+  /// seconds between signatures of the audit hash chain head
+  pub anchor_interval: u64,
 }
 
 #[allow(unused)]
@@ -26,6 +262,9 @@ pub struct AuthConfig {
   pub jwt_aud: String,
   pub encoding_key: EncodingKey,
   pub decoding_key: DecodingKey,
+  /// the same key pair for signatures other than tokens, e.g. audit anchors
+  pub signing_key: SigningKey,
+  pub verifying_key: VerifyingKey,
 }
 
 impl std::fmt::Debug for AuthConfig {
@@ -38,6 +277,8 @@ impl std::fmt::Debug for AuthConfig {
       .field("jwt_aud", &self.jwt_aud)
       .field("encoding_key", &"<hidden>")
       .field("decoding_key", &"<hidden>")
+      .field("signing_key", &"<hidden>")
+      .field("verifying_key", &self.verifying_key)
       .finish()
   }
 }
@@ -52,9 +293,11 @@ impl AuthConfig {
   ) -> Result<Self> {
     let secret_key_pem = read_to_string(&secret_key_path)?;
     let encoding_key = EncodingKey::from_ed_pem(secret_key_pem.as_bytes())?;
+    let signing_key = SigningKey::from_pkcs8_pem(&secret_key_pem)?;
 
     let public_key_pem = read_to_string(&public_key_path)?;
     let decoding_key = DecodingKey::from_ed_pem(public_key_pem.as_bytes())?;
+    let verifying_key = VerifyingKey::from_public_key_pem(&public_key_pem)?;
 
     Ok(Self {
       secret_key_path,
@@ -64,6 +307,25 @@ impl AuthConfig {
       jwt_aud,
       encoding_key,
       decoding_key,
+      signing_key,
+      verifying_key,
+    })
+  }
+}
+
+#[allow(unused)]
+#[derive(Clone, Debug)]
+pub struct AuthzConfig {
+  pub policy_path: String,
+  pub policy: Policy,
+}
+
+impl AuthzConfig {
+  pub fn new(policy_path: String) -> Result<Self> {
+    let policy = Policy::from_file(&policy_path)?;
+    Ok(Self {
+      policy_path,
+      policy,
     })
   }
 }
@@ -74,6 +336,22 @@ pub struct AppConfig {
   pub server: ServerConfig,
   pub database: DatabaseConfig,
   pub auth: AuthConfig,
+  pub authz: AuthzConfig,
+  pub approvals: ApprovalsConfig,
+  pub invitations: InvitationsConfig,
+  pub registration: RegistrationConfig,
+  pub storage: StorageConfig,
+  pub avatars: AvatarsConfig,
+  pub user_transfer: UserTransferConfig,
+  pub concurrency: ConcurrencyConfig,
+  pub usernames: UsernamesConfig,
+  pub audit: AuditConfig,
+  pub signins: SigninsConfig,
+  pub outbox: OutboxConfig,
+  pub webhooks: WebhooksConfig,
+  pub events: EventsConfig,
+  pub jobs: JobsConfig,
+  pub scheduler: SchedulerConfig,
 }
 
 #[derive(Debug, Deserialize)]
@@ -81,6 +359,22 @@ struct AppConfigRaw {
   pub server: ServerConfig,
   pub database: DatabaseConfig,
   pub auth: AuthConfigRaw,
+  pub authz: AuthzConfigRaw,
+  pub approvals: ApprovalsConfig,
+  pub invitations: InvitationsConfig,
+  pub registration: RegistrationConfig,
+  pub storage: StorageConfig,
+  pub avatars: AvatarsConfig,
+  pub user_transfer: UserTransferConfig,
+  pub concurrency: ConcurrencyConfig,
+  pub usernames: UsernamesConfig,
+  pub audit: AuditConfig,
+  pub signins: SigninsConfig,
+  pub outbox: OutboxConfig,
+  pub webhooks: WebhooksConfig,
+  pub events: EventsConfig,
+  pub jobs: JobsConfig,
+  pub scheduler: SchedulerConfig,
 }
 
 #[derive(Debug, Deserialize)]
@@ -92,6 +386,11 @@ struct AuthConfigRaw {
   pub jwt_aud: String,
 }
 
+#[derive(Debug, Deserialize)]
+struct AuthzConfigRaw {
+  pub policy: String,
+}
+
 #[allow(unused)]
 impl AppConfig {
   pub fn from_file(file_path: &str) -> Result<Self> {
@@ -105,6 +404,8 @@ impl AppConfig {
       config_raw.auth.jwt_iss,
       config_raw.auth.jwt_aud,
     )?;
+    let authz_config = AuthzConfig::new(config_raw.authz.policy)?;
+    config_raw.scheduler.check()?;
 
     // Allow DATABASE_URL env var to override config file
     let mut database = config_raw.database;
@@ -116,6 +417,22 @@ impl AppConfig {
       server: config_raw.server,
       database,
       auth: auth_config,
+      authz: authz_config,
+      approvals: config_raw.approvals,
+      invitations: config_raw.invitations,
+      registration: config_raw.registration,
+      storage: config_raw.storage,
+      avatars: config_raw.avatars,
+      user_transfer: config_raw.user_transfer,
+      concurrency: config_raw.concurrency,
+      usernames: config_raw.usernames,
+      audit: config_raw.audit,
+      signins: config_raw.signins,
+      outbox: config_raw.outbox,
+      webhooks: config_raw.webhooks,
+      events: config_raw.events,
+      jobs: config_raw.jobs,
+      scheduler: config_raw.scheduler,
     })
   }
 }
@@ -8,6 +8,8 @@ use thiserror::Error;
 use uuid::Uuid;
 use validator::ValidationErrors;
 
+use crate::modules::authz::Decision;
+
 #[derive(Debug, Error)]
 pub enum AppError {
   #[error("not found: {0}")]
@@ -19,6 +21,9 @@ pub enum AppError {
   #[error("forbidden: {0}")]
   Forbidden(String),
 
+  #[error("access denied: {} ({})", .0.action, .0.reason)]
+  AccessDenied(Box<Decision>),
+
   #[error("bad request: {0}")]
   BadRequest(String),
 
@@ -48,14 +53,32 @@ pub enum AppError {
 
   #[error("user existed: {0}")]
   UserExisted(String),
+
+  #[error("conflict: {0}")]
+  Conflict(String),
+
+  #[error("invariant violation: {0}")]
+  InvariantViolation(String),
+
+  #[error("payload too large: {0}")]
+  PayloadTooLarge(String),
+
+  #[error("unsupported media type: {0}")]
+  UnsupportedMediaType(String),
+
+  #[error("precondition failed: {0}")]
+  PreconditionFailed(String),
+
+  #[error("precondition required: {0}")]
+  PreconditionRequired(String),
 }
 
 impl From<ValidationErrors> for AppError {
   fn from(errors: ValidationErrors) -> Self {
     let errors = errors
       .field_errors()
-      .iter()
-      .flat_map(|(_, errors)| {
+      .values()
+      .flat_map(|errors| {
         errors.iter().map(|error| {
           if let Some(message) = &error.message {
             message.clone().into_owned()
@@ -92,10 +115,20 @@ impl IntoResponse for AppError {
       Self::NotFound(msg) => (StatusCode::NOT_FOUND, msg.clone()),
       Self::Unauthorized(msg) => (StatusCode::UNAUTHORIZED, msg.clone()),
       Self::Forbidden(msg) => (StatusCode::FORBIDDEN, msg.clone()),
+      Self::AccessDenied(decision) => (
+        StatusCode::FORBIDDEN,
+        format!("{} is not allowed: {}", decision.action, decision.reason),
+      ),
       Self::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg.clone()),
       Self::ValidationError(msg) => (StatusCode::UNPROCESSABLE_ENTITY, msg.clone()),
       Self::PasswordError(msg) => (StatusCode::UNPROCESSABLE_ENTITY, msg.clone()),
       Self::UserExisted(msg) => (StatusCode::CONFLICT, msg.clone()),
+      Self::Conflict(msg) => (StatusCode::CONFLICT, msg.clone()),
+      Self::InvariantViolation(msg) => (StatusCode::CONFLICT, msg.clone()),
+      Self::PayloadTooLarge(msg) => (StatusCode::PAYLOAD_TOO_LARGE, msg.clone()),
+      Self::UnsupportedMediaType(msg) => (StatusCode::UNSUPPORTED_MEDIA_TYPE, msg.clone()),
+      Self::PreconditionFailed(msg) => (StatusCode::PRECONDITION_FAILED, msg.clone()),
+      Self::PreconditionRequired(msg) => (StatusCode::PRECONDITION_REQUIRED, msg.clone()),
       Self::JwtError(_) => (
         StatusCode::UNAUTHORIZED,
         "invalid or expired token".to_string(),
@@ -111,7 +144,13 @@ impl IntoResponse for AppError {
       ),
     };
 
-    (status, Json(ErrorOutput::with_id(client_message, error_id))).into_response()
+    let mut response =
+      (status, Json(ErrorOutput::with_id(client_message, error_id))).into_response();
+    // kept for `authz_debug_middleware`, which exposes it to admins on request
+    if let Self::AccessDenied(decision) = self {
+      response.extensions_mut().insert(*decision);
+    }
+    response
   }
 }
 
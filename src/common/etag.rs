@@ -0,0 +1,109 @@
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+
+use crate::AppError;
+
+/// Strong entity tag of a resource version, `"<version>"`.
+pub fn etag(version: i64) -> HeaderValue {
+  HeaderValue::from_str(&format!("\"{}\"", version)).expect("a number is a valid header value")
+}
+
+/// One tag of an `If-Match` / `If-None-Match` list.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EntityTag {
+  pub weak: bool,
+  pub opaque: String,
+}
+
+/// Value of an `If-Match` or `If-None-Match` header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EntityTags {
+  /// `*`, any current representation
+  Any,
+  Tags(Vec<EntityTag>),
+}
+
+impl EntityTags {
+  /// `None` without the header, all its occurrences are combined.
+  pub fn from_headers(headers: &HeaderMap, name: HeaderName) -> Result<Option<Self>, AppError> {
+    let mut tags = Vec::new();
+    let mut any = false;
+    for value in headers.get_all(&name) {
+      let value = value
+        .to_str()
+        .map_err(|_| AppError::BadRequest(format!("invalid {} header", name)))?;
+      if value.trim() == "*" {
+        any = true;
+        continue;
+      }
+      tags.extend(
+        parse_tags(value)
+          .ok_or_else(|| AppError::BadRequest(format!("invalid {} header", name)))?,
+      );
+    }
+    if any {
+      Ok(Some(EntityTags::Any))
+    } else if headers.contains_key(&name) {
+      Ok(Some(EntityTags::Tags(tags)))
+    } else {
+      Ok(None)
+    }
+  }
+
+  /// Strong comparison, as `If-Match` uses: weak tags never match.
+  pub fn matches_strong(&self, version: i64) -> bool {
+    match self {
+      EntityTags::Any => true,
+      EntityTags::Tags(tags) => tags
+        .iter()
+        .any(|tag| !tag.weak && tag.opaque == version.to_string()),
+    }
+  }
+
+  /// Weak comparison, as `If-None-Match` uses.
+  pub fn matches_weak(&self, version: i64) -> bool {
+    match self {
+      EntityTags::Any => true,
+      EntityTags::Tags(tags) => tags.iter().any(|tag| tag.opaque == version.to_string()),
+    }
+  }
+
+  /// Versions named by strong tags, `None` for `*`.
+  pub fn strong_versions(&self) -> Option<Vec<i64>> {
+    match self {
+      EntityTags::Any => None,
+      EntityTags::Tags(tags) => Some(
+        tags
+          .iter()
+          .filter(|tag| !tag.weak)
+          .filter_map(|tag| tag.opaque.parse().ok())
+          .collect(),
+      ),
+    }
+  }
+}
+
+/// Parse `#entity-tag`, e.g. `"1", W/"2"`; `None` when malformed.
+fn parse_tags(value: &str) -> Option<Vec<EntityTag>> {
+  let mut tags = Vec::new();
+  let mut rest = value;
+  loop {
+    rest = rest.trim_start_matches([' ', '\t', ',']);
+    if rest.is_empty() {
+      return Some(tags);
+    }
+    let weak = rest.starts_with("W/");
+    if weak {
+      rest = &rest[2..];
+    }
+    rest = rest.strip_prefix('"')?;
+    let end = rest.find('"')?;
+    tags.push(EntityTag {
+      weak,
+      opaque: rest[..end].to_string(),
+    });
+    rest = &rest[end + 1..];
+    if !(rest.is_empty() || rest.starts_with([' ', '\t', ','])) {
+      return None;
+    }
+  }
+}
@@ -0,0 +1,179 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::AppError;
+
+/// Apply an RFC 7396 JSON Merge Patch: `null` removes a member, objects merge, anything else replaces.
+pub fn merge_patch(target: &mut Value, patch: &Value) {
+  let Value::Object(patch) = patch else {
+    *target = patch.clone();
+    return;
+  };
+  if !target.is_object() {
+    *target = Value::Object(Map::new());
+  }
+  let Value::Object(target) = target else {
+    unreachable!("target was just made an object");
+  };
+  for (name, value) in patch {
+    if value.is_null() {
+      target.remove(name);
+    } else {
+      merge_patch(target.entry(name.as_str()).or_insert(Value::Null), value);
+    }
+  }
+}
+
+/// One operation of an RFC 6902 JSON Patch.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOperation {
+  Add { path: String, value: Value },
+  Remove { path: String },
+  Replace { path: String, value: Value },
+  Move { from: String, path: String },
+  Copy { from: String, path: String },
+  Test { path: String, value: Value },
+}
+
+/// Apply an RFC 6902 JSON Patch, all operations or none of them.
+///
+/// A path that can't be applied is a `ValidationError` (422), a failed `test` a `Conflict` (409).
+pub fn apply_patch(target: &mut Value, operations: &[PatchOperation]) -> Result<(), AppError> {
+  let mut document = target.clone();
+  for (index, operation) in operations.iter().enumerate() {
+    apply_operation(&mut document, operation).map_err(|err| match err {
+      AppError::ValidationError(message) => {
+        AppError::ValidationError(format!("patch operation {}: {}", index, message))
+      }
+      err => err,
+    })?;
+  }
+  *target = document;
+  Ok(())
+}
+
+fn apply_operation(document: &mut Value, operation: &PatchOperation) -> Result<(), AppError> {
+  match operation {
+    PatchOperation::Add { path, value } => add(document, &parse_pointer(path)?, value.clone()),
+    PatchOperation::Remove { path } => remove(document, &parse_pointer(path)?).map(drop),
+    PatchOperation::Replace { path, value } => {
+      let target = get_mut(document, &parse_pointer(path)?)
+        .ok_or_else(|| unprocessable(format!("{} does not exist", path)))?;
+      *target = value.clone();
+      Ok(())
+    }
+    PatchOperation::Move { from, path } => {
+      let (from_tokens, tokens) = (parse_pointer(from)?, parse_pointer(path)?);
+      if tokens.len() > from_tokens.len() && tokens.starts_with(&from_tokens) {
+        return Err(unprocessable(format!(
+          "cannot move {} into its own child {}",
+          from, path
+        )));
+      }
+      let value = remove(document, &from_tokens)?;
+      add(document, &tokens, value)
+    }
+    PatchOperation::Copy { from, path } => {
+      let value = get_mut(document, &parse_pointer(from)?)
+        .ok_or_else(|| unprocessable(format!("{} does not exist", from)))?
+        .clone();
+      add(document, &parse_pointer(path)?, value)
+    }
+    PatchOperation::Test { path, value } => match get_mut(document, &parse_pointer(path)?) {
+      Some(current) if current == value => Ok(()),
+      _ => Err(AppError::Conflict(format!("test of {} failed", path))),
+    },
+  }
+}
+
+fn unprocessable(message: String) -> AppError {
+  AppError::ValidationError(message)
+}
+
+/// Split an RFC 6901 JSON Pointer into its unescaped reference tokens.
+fn parse_pointer(pointer: &str) -> Result<Vec<String>, AppError> {
+  if pointer.is_empty() {
+    return Ok(Vec::new());
+  }
+  let Some(pointer) = pointer.strip_prefix('/') else {
+    return Err(unprocessable(format!("invalid JSON pointer: {}", pointer)));
+  };
+  Ok(
+    pointer
+      .split('/')
+      .map(|token| token.replace("~1", "/").replace("~0", "~"))
+      .collect(),
+  )
+}
+
+/// Index of an existing array element, `len` itself only where `allow_end` is set.
+fn array_index(token: &str, len: usize, allow_end: bool) -> Result<usize, AppError> {
+  if allow_end && token == "-" {
+    return Ok(len);
+  }
+  let index = token
+    .parse::<usize>()
+    .ok()
+    .filter(|_| token == "0" || !token.starts_with('0'))
+    .ok_or_else(|| unprocessable(format!("invalid array index: {}", token)))?;
+  if index > len || (index == len && !allow_end) {
+    return Err(unprocessable(format!(
+      "array index out of bounds: {}",
+      index
+    )));
+  }
+  Ok(index)
+}
+
+fn get_mut<'a>(document: &'a mut Value, tokens: &[String]) -> Option<&'a mut Value> {
+  tokens
+    .iter()
+    .try_fold(document, |value, token| match value {
+      Value::Object(map) => map.get_mut(token),
+      Value::Array(items) => {
+        let index = array_index(token, items.len(), false).ok()?;
+        items.get_mut(index)
+      }
+      _ => None,
+    })
+}
+
+fn add(document: &mut Value, tokens: &[String], value: Value) -> Result<(), AppError> {
+  let Some((last, parent)) = tokens.split_last() else {
+    *document = value;
+    return Ok(());
+  };
+  match get_mut(document, parent) {
+    Some(Value::Object(map)) => {
+      map.insert(last.clone(), value);
+      Ok(())
+    }
+    Some(Value::Array(items)) => {
+      let index = array_index(last, items.len(), true)?;
+      items.insert(index, value);
+      Ok(())
+    }
+    _ => Err(unprocessable(format!(
+      "parent of /{} does not exist",
+      tokens.join("/")
+    ))),
+  }
+}
+
+fn remove(document: &mut Value, tokens: &[String]) -> Result<Value, AppError> {
+  let missing = || unprocessable(format!("/{} does not exist", tokens.join("/")));
+  let Some((last, parent)) = tokens.split_last() else {
+    return Err(unprocessable(
+      "cannot remove the whole document".to_string(),
+    ));
+  };
+  match get_mut(document, parent) {
+    Some(Value::Object(map)) => map.remove(last).ok_or_else(missing),
+    Some(Value::Array(items)) => {
+      let index = array_index(last, items.len(), false)?;
+      Ok(items.remove(index))
+    }
+    _ => Err(missing()),
+  }
+}
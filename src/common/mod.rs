@@ -1,5 +1,23 @@
 pub mod auth;
 pub mod config;
 pub mod errors;
+pub mod etag;
+pub mod json_patch;
+pub mod request_context;
+pub mod retry;
+pub mod storage;
+pub mod tenant;
 
-pub use auth::{hash_password, sign, verify_password};
+pub use auth::{hash_password, sign, sign_invitation, verify_invitation, verify_password};
+pub use etag::{EntityTag, EntityTags, etag};
+pub use json_patch::{PatchOperation, apply_patch, merge_patch};
+pub use request_context::{
+  REQUEST_ID_HEADER, RequestContext, current_request, request_context_middleware,
+  with_request_context,
+};
+pub use retry::retry_delay;
+pub use storage::{LocalStorage, Storage, StorageFuture, storage_from_config};
+pub use tenant::{
+  DEFAULT_ORGANIZATION_ID, across_tenants, across_tenants_middleware, current_tenant, set_tenant,
+  tenant_pool_options, with_tenant,
+};
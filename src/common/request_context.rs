@@ -0,0 +1,96 @@
+use std::future::Future;
+use std::net::SocketAddr;
+
+use axum::{
+  body::Body,
+  extract::{ConnectInfo, State},
+  http::{HeaderValue, Request, header},
+  middleware::Next,
+  response::Response,
+};
+
+use crate::AppState;
+
+/// Longest user agent kept, longer ones are cut.
+const MAX_USER_AGENT_CHARS: usize = 512;
+
+/// Header carrying the request id, taken from the client when usable and echoed in the response.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Who made the current request and from where, recorded with audit events and signins.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RequestContext {
+  pub request_id: Option<String>,
+  pub ip: Option<String>,
+  pub user_agent: Option<String>,
+  /// authenticated user, set by `auth_middleware`
+  pub actor_id: Option<i32>,
+}
+
+tokio::task_local! {
+  static REQUEST_CONTEXT: RequestContext;
+}
+
+/// Context of the current request, empty outside of a request (startup, background tasks).
+pub fn current_request() -> RequestContext {
+  REQUEST_CONTEXT
+    .try_with(|context| context.clone())
+    .unwrap_or_default()
+}
+
+/// Run `f` with `context` as the current request context.
+pub async fn with_request_context<F: Future>(context: RequestContext, f: F) -> F::Output {
+  REQUEST_CONTEXT.scope(context, f).await
+}
+
+/// Assign the request its id and client address for the rest of the request.
+pub async fn request_context_middleware(
+  State(state): State<AppState>,
+  req: Request<Body>,
+  next: Next,
+) -> Response {
+  let request_id = req
+    .headers()
+    .get(REQUEST_ID_HEADER)
+    .and_then(|value| value.to_str().ok())
+    .filter(|id| is_valid_request_id(id))
+    .map(|id| id.to_string())
+    .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+  let forwarded = req
+    .headers()
+    .get("x-forwarded-for")
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| value.split(',').next())
+    .map(|ip| ip.trim().to_string())
+    .filter(|_| state.config.server.trust_forwarded_for);
+  let peer = req
+    .extensions()
+    .get::<ConnectInfo<SocketAddr>>()
+    .map(|ConnectInfo(addr)| addr.ip().to_string());
+  let user_agent = req
+    .headers()
+    .get(header::USER_AGENT)
+    .and_then(|value| value.to_str().ok())
+    .map(|agent| agent.chars().take(MAX_USER_AGENT_CHARS).collect());
+  let context = RequestContext {
+    request_id: Some(request_id.clone()),
+    ip: forwarded.or(peer),
+    user_agent,
+    actor_id: None,
+  };
+
+  let mut response = with_request_context(context, next.run(req)).await;
+  if let Ok(value) = HeaderValue::from_str(&request_id) {
+    response.headers_mut().insert(REQUEST_ID_HEADER, value);
+  }
+  response
+}
+
+/// Client supplied ids are kept when short and printable, so they can be logged as is.
+fn is_valid_request_id(id: &str) -> bool {
+  !id.is_empty()
+    && id.len() <= 128
+    && id
+      .bytes()
+      .all(|byte| byte.is_ascii_alphanumeric() || b"-_.:".contains(&byte))
+}
@@ -0,0 +1,6 @@
+/// Seconds to wait before retrying something that failed `attempts` times: `base` doubled
+/// on every further failure, at most `max`.
+pub fn retry_delay(base: u64, max: u64, attempts: i32) -> u64 {
+  let doublings = attempts.saturating_sub(1).clamp(0, 32) as u32;
+  base.saturating_mul(1 << doublings).min(max)
+}
@@ -0,0 +1,91 @@
+use std::fmt::Debug;
+use std::future::Future;
+use std::path::{Component, Path, PathBuf};
+use std::pin::Pin;
+
+use crate::AppError;
+use crate::common::config::{StorageBackend, StorageConfig};
+
+pub type StorageFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, AppError>> + Send + 'a>>;
+
+/// Blob storage for uploaded files, addressed by `/` separated keys.
+pub trait Storage: Debug + Send + Sync {
+  fn put<'a>(&'a self, key: &'a str, bytes: Vec<u8>) -> StorageFuture<'a, ()>;
+  /// `None` when nothing is stored under `key`
+  fn get<'a>(&'a self, key: &'a str) -> StorageFuture<'a, Option<Vec<u8>>>;
+  /// Deleting a missing key succeeds.
+  fn delete<'a>(&'a self, key: &'a str) -> StorageFuture<'a, ()>;
+}
+
+/// Build the backend selected in `app.yaml`.
+pub fn storage_from_config(config: &StorageConfig) -> std::sync::Arc<dyn Storage> {
+  match config.backend {
+    StorageBackend::Local => std::sync::Arc::new(LocalStorage::new(&config.root)),
+  }
+}
+
+/// Stores every key as a file under `root`.
+#[derive(Debug, Clone)]
+pub struct LocalStorage {
+  root: PathBuf,
+}
+
+impl LocalStorage {
+  pub fn new(root: impl Into<PathBuf>) -> Self {
+    Self { root: root.into() }
+  }
+
+  /// Map a key to its file, refusing keys that would escape `root`.
+  fn path(&self, key: &str) -> Result<PathBuf, AppError> {
+    let relative = Path::new(key);
+    let is_plain = !key.is_empty()
+      && relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    if !is_plain {
+      return Err(AppError::BadRequest(format!(
+        "invalid storage key: {}",
+        key
+      )));
+    }
+    Ok(self.root.join(relative))
+  }
+}
+
+impl Storage for LocalStorage {
+  fn put<'a>(&'a self, key: &'a str, bytes: Vec<u8>) -> StorageFuture<'a, ()> {
+    Box::pin(async move {
+      let path = self.path(key)?;
+      if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+      }
+      // write aside and rename, readers never see a partial file
+      let partial = path.with_extension(format!("{}.partial", uuid::Uuid::new_v4()));
+      tokio::fs::write(&partial, bytes).await?;
+      if let Err(err) = tokio::fs::rename(&partial, &path).await {
+        let _ = tokio::fs::remove_file(&partial).await;
+        return Err(err.into());
+      }
+      Ok(())
+    })
+  }
+
+  fn get<'a>(&'a self, key: &'a str) -> StorageFuture<'a, Option<Vec<u8>>> {
+    Box::pin(async move {
+      match tokio::fs::read(self.path(key)?).await {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+      }
+    })
+  }
+
+  fn delete<'a>(&'a self, key: &'a str) -> StorageFuture<'a, ()> {
+    Box::pin(async move {
+      match tokio::fs::remove_file(self.path(key)?).await {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+      }
+    })
+  }
+}
@@ -0,0 +1,107 @@
+use std::future::Future;
+
+use axum::body::Body;
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use sqlx::postgres::PgPoolOptions;
+use sqlx::{Executor, PgConnection, Postgres, Transaction};
+use tracing::error;
+
+use crate::AppError;
+
+/// Organization every user belongs to when none is chosen, created by the migrations.
+pub const DEFAULT_ORGANIZATION_ID: i32 = 1;
+
+/// `app.tenant_id` lifting the row-level security policies, see `across_tenants`.
+const ALL_TENANTS: &str = "all";
+
+/// Whose rows the current task works on.
+#[derive(Clone, Copy, Debug)]
+enum Tenant {
+  Organization(i32),
+  All,
+}
+
+tokio::task_local! {
+  static TENANT: Tenant;
+}
+
+/// Active organization of the current request, set by `auth_middleware`. An error outside of
+/// `with_tenant`, work across organizations must not fall back to one of them.
+pub fn current_tenant() -> Result<i32, AppError> {
+  match TENANT.try_with(|tenant| *tenant) {
+    Ok(Tenant::Organization(organization_id)) => Ok(organization_id),
+    Ok(Tenant::All) | Err(_) => {
+      error!("no active organization");
+      Err(AppError::InternalServerError)
+    }
+  }
+}
+
+/// Run `f` with `organization_id` as the active organization.
+pub async fn with_tenant<F: Future>(organization_id: i32, f: F) -> F::Output {
+  TENANT.scope(Tenant::Organization(organization_id), f).await
+}
+
+/// Run `f` on the rows of every organization, for work done on behalf of none of them:
+/// signing in, signing up and the background tasks.
+pub async fn across_tenants<F: Future>(f: F) -> F::Output {
+  TENANT.scope(Tenant::All, f).await
+}
+
+/// `app.tenant_id` of the current task, empty outside of `with_tenant` and `across_tenants`
+/// so that the row-level security policies reject the queries.
+fn tenant_setting() -> String {
+  match TENANT.try_with(|tenant| *tenant) {
+    Ok(Tenant::Organization(organization_id)) => organization_id.to_string(),
+    Ok(Tenant::All) => ALL_TENANTS.to_string(),
+    Err(_) => String::new(),
+  }
+}
+
+/// Pool options giving every connection the tenant of the task acquiring it, so queries on
+/// the pool are restricted like those of transactions. Connections switch to `role` first
+/// when given: row-level security doesn't apply to superusers.
+pub fn tenant_pool_options(role: Option<String>) -> PgPoolOptions {
+  PgPoolOptions::new()
+    .after_connect(move |connection, _| {
+      let role = role.clone();
+      Box::pin(async move {
+        if let Some(role) = role {
+          let set_role = format!("SET ROLE \"{}\"", role.replace('"', "\"\""));
+          connection.execute(set_role.as_str()).await?;
+        }
+        apply_tenant(connection).await
+      })
+    })
+    .before_acquire(|connection, _| {
+      Box::pin(async move {
+        apply_tenant(connection).await?;
+        Ok(true)
+      })
+    })
+}
+
+async fn apply_tenant(connection: &mut PgConnection) -> Result<(), sqlx::Error> {
+  sqlx::query("SELECT set_config('app.tenant_id', $1, false)")
+    .bind(tenant_setting())
+    .execute(&mut *connection)
+    .await?;
+  Ok(())
+}
+
+/// Switch a transaction begun for another organization to the active one, until it ends.
+pub async fn set_tenant(transaction: &mut Transaction<'_, Postgres>) -> Result<(), AppError> {
+  sqlx::query("SELECT set_config('app.tenant_id', $1, true)")
+    .bind(tenant_setting())
+    .execute(&mut **transaction)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+  Ok(())
+}
+
+/// Run the rest of a request that isn't authenticated `across_tenants`.
+pub async fn across_tenants_middleware(req: Request<Body>, next: Next) -> Response {
+  across_tenants(next.run(req)).await
+}
@@ -1,6 +1,17 @@
 use anyhow::Result;
 use axum::Router;
-use axum::middleware::from_fn_with_state;
+use axum::middleware::{from_fn, from_fn_with_state};
+use common::{
+  Storage, across_tenants_middleware, request_context_middleware, storage_from_config,
+  tenant_pool_options,
+};
+use modules::auth::{AllowAllGuard, LogSigninNotifier, SigninNotifier, SignupRejections};
+use modules::events::EventHub;
+use modules::jobs::{
+  Job, JobRegistry, PruneOutbox, PurgeDeletedUsers, PurgeExpiredInvitations, SweepExpiredGrants,
+};
+use modules::outbox::{EventSink, LogEventSink};
+use modules::webhooks::WebhookSink;
 use sqlx::PgPool;
 use std::ops::Deref;
 use std::sync::Arc;
@@ -9,16 +20,46 @@ pub mod common;
 pub mod modules;
 pub use common::config::AppConfig;
 pub use common::errors::AppError;
-pub use modules::auth::{auth_middleware, auth_router};
+pub use modules::audit::audit_router;
+pub use modules::auth::{SignupGuard, auth_middleware, auth_router};
+pub use modules::authz::{authz_debug_middleware, authz_router};
+pub use modules::events::events_router;
+pub use modules::groups::groups_router;
 pub use modules::health::health_router;
+pub use modules::invitations::invitations_router;
+pub use modules::jobs::jobs_router;
+pub use modules::organizations::organizations_router;
+pub use modules::role_requests::role_requests_router;
+pub use modules::scheduler::scheduler_router;
+pub use modules::user_transfer::user_transfer_router;
 pub use modules::users::users_router;
+pub use modules::webhooks::webhooks_router;
 
 pub async fn get_router(state: AppState) -> Result<Router, AppError> {
   let router = Router::new()
     .merge(health_router(state.clone()))
-    .nest("/users", users_router(state.clone()))
+    .nest(
+      "/users",
+      users_router(state.clone()).merge(user_transfer_router(state.clone())),
+    )
+    .nest("/groups", groups_router(state.clone()))
+    .nest("/organizations", organizations_router(state.clone()))
+    .nest("/invitations", invitations_router(state.clone()))
+    .nest("/authz", authz_router(state.clone()))
+    .nest("/role-requests", role_requests_router(state.clone()))
+    .nest("/audit", audit_router(state.clone()))
+    .nest("/webhooks", webhooks_router(state.clone()))
+    .nest("/events", events_router(state.clone()))
+    .nest("/jobs", jobs_router(state.clone()))
+    .nest("/scheduler", scheduler_router(state.clone()))
+    .layer(from_fn_with_state(state.clone(), authz_debug_middleware))
     .layer(from_fn_with_state(state.clone(), auth_middleware))
-    .nest("/auth", auth_router(state.clone()));
+    .nest("/auth", auth_router(state.clone()))
+    .layer(from_fn(across_tenants_middleware))
+    .layer(from_fn_with_state(
+      state.clone(),
+      request_context_middleware,
+    ));
   Ok(router)
 }
 
@@ -27,6 +68,13 @@ pub async fn get_router(state: AppState) -> Result<Router, AppError> {
 pub struct AppStateInner {
   pub config: AppConfig,
   pub pool: PgPool,
+  pub signup_guard: Arc<dyn SignupGuard>,
+  pub signup_rejections: Arc<SignupRejections>,
+  pub signin_notifier: Arc<dyn SigninNotifier>,
+  pub storage: Arc<dyn Storage>,
+  pub event_sinks: Vec<Arc<dyn EventSink>>,
+  pub event_hub: Arc<EventHub>,
+  pub job_registry: JobRegistry,
 }
 
 #[derive(Clone, Debug)]
@@ -36,14 +84,89 @@ pub struct AppState {
 
 impl AppState {
   pub fn new(config: AppConfig, pool: PgPool) -> Self {
+    let storage = storage_from_config(&config.storage);
+    let event_hub = Arc::new(EventHub::new(config.events.buffer));
+    let mut job_registry = JobRegistry::default();
+    job_registry.register::<SweepExpiredGrants>();
+    job_registry.register::<PruneOutbox>();
+    job_registry.register::<PurgeExpiredInvitations>();
+    job_registry.register::<PurgeDeletedUsers>();
+    let event_sinks: Vec<Arc<dyn EventSink>> = vec![
+      Arc::new(LogEventSink),
+      Arc::new(WebhookSink::new(pool.clone())),
+    ];
     Self {
-      inner: Arc::new(AppStateInner { config, pool }),
+      inner: Arc::new(AppStateInner {
+        config,
+        pool,
+        signup_guard: Arc::new(AllowAllGuard),
+        signup_rejections: Arc::new(SignupRejections::default()),
+        signin_notifier: Arc::new(LogSigninNotifier),
+        storage,
+        event_sinks,
+        event_hub,
+        job_registry,
+      }),
+    }
+  }
+
+  /// Replace the verification run on every signup, e.g. with a captcha check.
+  pub fn with_signup_guard(self, signup_guard: Arc<dyn SignupGuard>) -> Self {
+    Self {
+      inner: Arc::new(AppStateInner {
+        signup_guard,
+        ..(*self.inner).clone()
+      }),
+    }
+  }
+
+  /// Replace the hook told about suspicious signins, e.g. with one emailing the user.
+  pub fn with_signin_notifier(self, signin_notifier: Arc<dyn SigninNotifier>) -> Self {
+    Self {
+      inner: Arc::new(AppStateInner {
+        signin_notifier,
+        ..(*self.inner).clone()
+      }),
+    }
+  }
+
+  /// Replace the backend uploaded files are stored in.
+  pub fn with_storage(self, storage: Arc<dyn Storage>) -> Self {
+    Self {
+      inner: Arc::new(AppStateInner {
+        storage,
+        ..(*self.inner).clone()
+      }),
+    }
+  }
+
+  /// Replace the sinks the outbox relay delivers domain events to, e.g. with a message broker.
+  pub fn with_event_sinks(self, event_sinks: Vec<Arc<dyn EventSink>>) -> Self {
+    Self {
+      inner: Arc::new(AppStateInner {
+        event_sinks,
+        ..(*self.inner).clone()
+      }),
+    }
+  }
+
+  /// Teach the workers to run jobs of type `J`.
+  pub fn with_job<J: Job>(self) -> Self {
+    let mut job_registry = self.job_registry.clone();
+    job_registry.register::<J>();
+    Self {
+      inner: Arc::new(AppStateInner {
+        job_registry,
+        ..(*self.inner).clone()
+      }),
     }
   }
 
   pub async fn init_state() -> Result<AppState> {
     let config = AppConfig::from_file("app.yaml")?;
-    let pool = PgPool::connect(&config.database.db_url).await?;
+    let pool = tenant_pool_options(config.database.role.clone())
+      .connect(&config.database.db_url)
+      .await?;
     let state = AppState::new(config, pool);
     Ok(state)
   }
@@ -62,8 +185,64 @@ impl Deref for AppState {
 #[cfg(test)]
 mod test_util {
   use super::*;
+  use crate::common::across_tenants;
+  use crate::modules::audit::record_audit;
+  use crate::modules::users::RoleIn;
+  use axum::http::StatusCode;
+  use reqwest::Client;
+  use serde_json::{Value, json};
   use sqlx::{Executor, PgPool};
   use sqlx_db_tester::TestPg;
+  use std::net::SocketAddr;
+  use tokio::net::TcpListener;
+  use tokio::sync::oneshot;
+
+  /// Serve the router of `state` like `main` on a free local port, returns its address and the
+  /// sender stopping it.
+  pub async fn spawn_app(state: AppState) -> Result<(String, oneshot::Sender<()>)> {
+    let app = get_router(state).await?;
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let (tx, rx) = oneshot::channel();
+    tokio::spawn(async move {
+      axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+      )
+      .with_graceful_shutdown(async {
+        rx.await.ok();
+      })
+      .await
+      .unwrap();
+    });
+    Ok((addr.to_string(), tx))
+  }
+
+  /// Sign in with `body` through `/auth/signin`, returns the token.
+  pub async fn sign_in(client: &Client, addr: &str, body: Value) -> Result<String> {
+    let response = client
+      .post(format!("http://{}/auth/signin", addr))
+      .json(&body)
+      .send()
+      .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let token: Value = response.json().await?;
+    Ok(token["token"].as_str().unwrap().to_string())
+  }
+
+  pub async fn get_token(
+    client: &Client,
+    addr: &str,
+    username: &str,
+    password: &str,
+  ) -> Result<String> {
+    sign_in(
+      client,
+      addr,
+      json!({"username": username, "password": password}),
+    )
+    .await
+  }
 
   impl AppState {
     pub async fn init_test_state() -> Result<(TestPg, AppState), AppError> {
@@ -71,11 +250,16 @@ mod test_util {
       let pos = config.database.db_url.rfind('/').unwrap();
       let db_url = config.database.db_url[..pos].to_string();
       let tdb = TestPg::new(db_url, std::path::Path::new("./migrations"));
-      let pool = tdb.get_pool().await;
+      let pool = tenant_pool_options(config.database.role.clone())
+        .connect(&tdb.url())
+        .await
+        .expect("connect to the test database failed");
 
       // test_data.sql start
       let sql = include_str!("../fixtures/test_data.sql").split(';');
-      let mut ts = pool.begin().await.expect("begin transaction failed");
+      let mut ts = across_tenants(pool.begin())
+        .await
+        .expect("begin transaction failed");
       for s in sql {
         if s.trim().is_empty() {
           continue;
@@ -85,10 +269,30 @@ mod test_util {
       ts.commit().await.expect("commit transaction failed");
       // test_data.sql end
 
-      let state = Self {
-        inner: Arc::new(AppStateInner { config, pool }),
-      };
+      let state = Self::new(config, pool);
       Ok((tdb, state))
     }
+
+    /// `update_roles` committed on its own, with its audit event.
+    pub async fn set_roles(
+      &self,
+      roles: Vec<RoleIn>,
+      user_id: i32,
+      granted_by: i32,
+    ) -> Result<(), AppError> {
+      let mut transaction = self
+        .pool
+        .begin()
+        .await
+        .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+      let audit = self
+        .update_roles(&mut transaction, roles, user_id, granted_by)
+        .await?;
+      record_audit(&mut transaction, audit).await?;
+      transaction
+        .commit()
+        .await
+        .map_err(|err| AppError::DatabaseError(err.to_string()))
+    }
   }
 }
@@ -1,7 +1,10 @@
+use axum_template::common::across_tenants;
 use axum_template::{AppState, get_router};
 
-use anyhow::Result;
+use anyhow::{Result, bail};
+use std::net::SocketAddr;
 use tokio::net::TcpListener;
+use tokio::sync::watch;
 use tracing::info;
 use tracing_appender::rolling;
 use tracing_subscriber::{
@@ -28,13 +31,96 @@ async fn main() -> Result<()> {
     .init();
 
   let state = AppState::init_state().await?;
+  let (serve, work) = match std::env::args().nth(1).as_deref() {
+    None | Some("all") => (true, true),
+    Some("serve") => (true, false),
+    Some("worker") => (false, true),
+    Some("verify-audit") => return verify_audit(state).await,
+    Some(command) => bail!(
+      "unknown command: {} (expected all, serve, worker or verify-audit)",
+      command
+    ),
+  };
+
+  let (shutdown_tx, shutdown_rx) = watch::channel(false);
+  tokio::spawn(async move {
+    shutdown_signal().await;
+    info!("Shutting down");
+    shutdown_tx.send_replace(true);
+  });
+  if work {
+    tokio::spawn(across_tenants(state.clone().run_scheduler()));
+  }
+  let worker = work.then(|| {
+    let shutdown = wait_shutdown(shutdown_rx.clone());
+    tokio::spawn(across_tenants(state.clone().run_job_worker(shutdown)))
+  });
+  if serve {
+    serve_api(state, wait_shutdown(shutdown_rx)).await?;
+  }
+  if let Some(worker) = worker {
+    worker.await?;
+  }
+  Ok(())
+}
+
+/// `serve`: the API and its background tasks, until `shutdown` completes.
+async fn serve_api(
+  state: AppState,
+  shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<()> {
+  tokio::spawn(across_tenants(state.clone().run_audit_anchorer()));
+  tokio::spawn(across_tenants(state.clone().run_outbox_relay()));
+  tokio::spawn(across_tenants(state.clone().run_webhook_dispatcher()));
+  tokio::spawn(across_tenants(state.clone().run_event_listener()));
   let app = get_router(state.clone()).await?;
 
   let addr = format!("0.0.0.0:{}", &state.config.server.port);
   let listener = TcpListener::bind(&addr).await?;
   info!("Listening on: {}", addr);
 
-  axum::serve(listener, app.into_make_service()).await?;
+  axum::serve(
+    listener,
+    app.into_make_service_with_connect_info::<SocketAddr>(),
+  )
+  .with_graceful_shutdown(shutdown)
+  .await?;
+
+  Ok(())
+}
+
+/// Ctrl-C, or SIGTERM on unix.
+async fn shutdown_signal() {
+  let ctrl_c = async {
+    tokio::signal::ctrl_c().await.ok();
+  };
+  #[cfg(unix)]
+  let terminate = async {
+    match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+      Ok(mut signal) => {
+        signal.recv().await;
+      }
+      Err(_) => std::future::pending().await,
+    }
+  };
+  #[cfg(not(unix))]
+  let terminate = std::future::pending::<()>();
+  tokio::select! {
+    _ = ctrl_c => (),
+    _ = terminate => (),
+  }
+}
+
+async fn wait_shutdown(mut shutdown: watch::Receiver<bool>) {
+  shutdown.wait_for(|stopping| *stopping).await.ok();
+}
 
+/// `verify-audit`: print the audit chain report, exit with 1 when the chain is broken.
+async fn verify_audit(state: AppState) -> Result<()> {
+  let report = state.verify_audit_chain().await?;
+  println!("{}", serde_json::to_string_pretty(&report)?);
+  if !report.valid {
+    std::process::exit(1);
+  }
   Ok(())
 }
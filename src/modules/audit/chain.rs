@@ -0,0 +1,353 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::{SecondsFormat, SubsecRound, Utc};
+use ed25519_dalek::{Signature, Signer, Verifier};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use sqlx::PgConnection;
+use tracing::{info, warn};
+
+use super::{AuditAnchor, AuditChainBreak, AuditChainReport, AuditEvent, NewAuditEvent};
+use crate::common::{current_request, current_tenant};
+use crate::{AppError, AppState};
+
+/// Advisory lock serializing appends to the chain, held until the appending transaction ends.
+const AUDIT_CHAIN_LOCK: i64 = 0x0041_5544_4954;
+
+/// Events read per query while verifying.
+const VERIFY_BATCH: i64 = 1000;
+
+/// Append `event` to the chain through `connection`, the transaction of the audited change so
+/// both commit or roll back together. Actor, client address and request id come from the request.
+///
+/// Appends are serialized until the transaction ends: each event hashes the one before it.
+pub(crate) async fn record_audit(
+  connection: &mut PgConnection,
+  event: NewAuditEvent,
+) -> Result<(), AppError> {
+  sqlx::query("SELECT pg_advisory_xact_lock($1)")
+    .bind(AUDIT_CHAIN_LOCK)
+    .execute(&mut *connection)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+  let prev_hash: Option<String> =
+    sqlx::query_scalar("SELECT hash FROM audit_events ORDER BY id DESC LIMIT 1")
+      .fetch_optional(&mut *connection)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?
+      .flatten();
+  let id: i64 = sqlx::query_scalar("SELECT nextval('audit_events_id_seq')")
+    .fetch_one(&mut *connection)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+  let request = current_request();
+  let mut event = AuditEvent {
+    id,
+    organization_id: current_tenant()?,
+    actor_id: event.actor_id.or(request.actor_id),
+    action: event.action.as_ref().to_string(),
+    target_type: event.target_type.to_string(),
+    target_id: event.target_id,
+    before: event.before,
+    after: event.after,
+    ip: request.ip,
+    request_id: request.request_id,
+    // the precision postgres keeps, the hash is recomputed from the stored row
+    created_at: Utc::now().trunc_subsecs(6),
+    prev_hash,
+    hash: None,
+  };
+  event.hash = Some(event_hash(&event));
+
+  sqlx::query(
+    r#"
+    INSERT INTO audit_events
+      (id, organization_id, actor_id, action, target_type, target_id, before, after, ip,
+       request_id, created_at, prev_hash, hash)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+    "#,
+  )
+  .bind(event.id)
+  .bind(event.organization_id)
+  .bind(event.actor_id)
+  .bind(&event.action)
+  .bind(&event.target_type)
+  .bind(event.target_id)
+  .bind(&event.before)
+  .bind(&event.after)
+  .bind(&event.ip)
+  .bind(&event.request_id)
+  .bind(event.created_at)
+  .bind(&event.prev_hash)
+  .bind(&event.hash)
+  .execute(&mut *connection)
+  .await
+  .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+  Ok(())
+}
+
+/// SHA-256 of the previous hash and the canonical JSON of the event's fields, hex encoded.
+pub fn event_hash(event: &AuditEvent) -> String {
+  let fields = json!([
+    event.id,
+    event.organization_id,
+    event.actor_id,
+    event.action,
+    event.target_type,
+    event.target_id,
+    event.before,
+    event.after,
+    event.ip,
+    event.request_id,
+    event
+      .created_at
+      .to_rfc3339_opts(SecondsFormat::Micros, true),
+  ]);
+  let mut canonical = String::new();
+  write_canonical(&fields, &mut canonical);
+
+  let mut hasher = Sha256::new();
+  hasher.update(event.prev_hash.as_deref().unwrap_or_default());
+  hasher.update(b"\n");
+  hasher.update(canonical);
+  hex::encode(hasher.finalize())
+}
+
+/// JSON with object members sorted by name, so the hash doesn't depend on how the
+/// database or serde order them.
+fn write_canonical(value: &Value, out: &mut String) {
+  match value {
+    Value::Array(items) => {
+      out.push('[');
+      for (index, item) in items.iter().enumerate() {
+        if index > 0 {
+          out.push(',');
+        }
+        write_canonical(item, out);
+      }
+      out.push(']');
+    }
+    Value::Object(members) => {
+      let mut members: Vec<_> = members.iter().collect();
+      members.sort_by_key(|(name, _)| *name);
+      out.push('{');
+      for (index, (name, value)) in members.into_iter().enumerate() {
+        if index > 0 {
+          out.push(',');
+        }
+        out.push_str(&Value::String(name.clone()).to_string());
+        out.push(':');
+        write_canonical(value, out);
+      }
+      out.push('}');
+    }
+    value => out.push_str(&value.to_string()),
+  }
+}
+
+/// What an anchor signs: the chain up to and including `event_id`.
+fn anchor_message(event_id: i64, hash: &str) -> String {
+  format!("audit-anchor:{}:{}", event_id, hash)
+}
+
+impl AppState {
+  /// Sign the head of the chain, `None` when nothing was appended since the last anchor.
+  pub async fn anchor_audit_chain(&self) -> Result<Option<AuditAnchor>, AppError> {
+    let mut transaction = self
+      .pool
+      .begin()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+      .bind(AUDIT_CHAIN_LOCK)
+      .execute(&mut *transaction)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    let head: Option<(i64, String)> = sqlx::query_as(
+      "SELECT id, hash FROM audit_events WHERE hash IS NOT NULL ORDER BY id DESC LIMIT 1",
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    let Some((event_id, hash)) = head else {
+      return Ok(None);
+    };
+    let anchored: Option<i64> = sqlx::query_scalar("SELECT MAX(event_id) FROM audit_anchors")
+      .fetch_one(&mut *transaction)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    if anchored.is_some_and(|anchored| anchored >= event_id) {
+      return Ok(None);
+    }
+
+    let signature = self
+      .config
+      .auth
+      .signing_key
+      .sign(anchor_message(event_id, &hash).as_bytes());
+    let anchor: AuditAnchor = sqlx::query_as(
+      r#"
+      INSERT INTO audit_anchors (event_id, hash, signature, created_at)
+      VALUES ($1, $2, $3, $4)
+      RETURNING id, event_id, hash, signature, created_at
+      "#,
+    )
+    .bind(event_id)
+    .bind(&hash)
+    .bind(hex::encode(signature.to_bytes()))
+    .bind(Utc::now())
+    .fetch_one(&mut *transaction)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    transaction
+      .commit()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    info!(event_id, "audit chain anchored");
+    Ok(Some(anchor))
+  }
+
+  pub async fn run_audit_anchorer(self) {
+    let mut interval =
+      tokio::time::interval(Duration::from_secs(self.config.audit.anchor_interval));
+    loop {
+      interval.tick().await;
+      if let Err(e) = self.anchor_audit_chain().await {
+        warn!(error = ?e, "anchor audit chain failed");
+      }
+    }
+  }
+
+  /// Walk the whole chain, of every organization, recomputing each hash and checking each
+  /// anchor's signature. Stops at the first break.
+  ///
+  /// Events removed from the end of the chain are only noticed up to the last anchor.
+  pub async fn verify_audit_chain(&self) -> Result<AuditChainReport, AppError> {
+    let anchors: Vec<AuditAnchor> = sqlx::query_as(
+      "SELECT id, event_id, hash, signature, created_at FROM audit_anchors ORDER BY event_id, id",
+    )
+    .fetch_all(&self.pool)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    let mut report = AuditChainReport {
+      valid: true,
+      events: 0,
+      unchained: 0,
+      anchors: 0,
+      last_anchor: anchors.last().cloned(),
+      first_break: None,
+    };
+    let mut pending: HashMap<i64, Vec<&AuditAnchor>> = HashMap::new();
+    for anchor in &anchors {
+      pending.entry(anchor.event_id).or_default().push(anchor);
+    }
+
+    let mut expected_prev: Option<String> = None;
+    let mut started = false;
+    let mut last_id = 0;
+    'walk: loop {
+      let events: Vec<AuditEvent> = sqlx::query_as(
+        r#"
+        SELECT id, organization_id, actor_id, action, target_type, target_id, before, after,
+               ip, request_id, created_at, prev_hash, hash
+        FROM audit_events
+        WHERE id > $1
+        ORDER BY id
+        LIMIT $2
+        "#,
+      )
+      .bind(last_id)
+      .bind(VERIFY_BATCH)
+      .fetch_all(&self.pool)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+      let Some(last) = events.last() else {
+        break;
+      };
+      last_id = last.id;
+
+      for event in &events {
+        if let Some(reason) = self.check_event(event, started, &expected_prev, &mut pending) {
+          report.first_break = Some(AuditChainBreak {
+            event_id: event.id,
+            reason,
+          });
+          break 'walk;
+        }
+        match &event.hash {
+          Some(hash) => {
+            started = true;
+            expected_prev = Some(hash.clone());
+            report.events += 1;
+          }
+          None => report.unchained += 1,
+        }
+        report.anchors += pending.remove(&event.id).map_or(0, |anchors| anchors.len()) as u64;
+      }
+    }
+
+    if report.first_break.is_none()
+      && let Some(event_id) = pending.keys().min()
+    {
+      report.first_break = Some(AuditChainBreak {
+        event_id: *event_id,
+        reason: "anchored event is missing".to_string(),
+      });
+    }
+    report.valid = report.first_break.is_none();
+    if let Some(chain_break) = &report.first_break {
+      warn!(
+        event_id = chain_break.event_id,
+        reason = %chain_break.reason,
+        "audit chain broken"
+      );
+    }
+    Ok(report)
+  }
+
+  /// Why `event` breaks the chain, if it does.
+  fn check_event(
+    &self,
+    event: &AuditEvent,
+    started: bool,
+    expected_prev: &Option<String>,
+    pending: &mut HashMap<i64, Vec<&AuditAnchor>>,
+  ) -> Option<String> {
+    let Some(hash) = &event.hash else {
+      return started.then(|| "event is not chained".to_string());
+    };
+    if event.prev_hash != *expected_prev {
+      return Some(match started {
+        true => "previous hash does not match, an event was removed or reordered".to_string(),
+        false => "chain does not start at its first event".to_string(),
+      });
+    }
+    if event_hash(event) != *hash {
+      return Some("hash does not match the event, it was modified".to_string());
+    }
+    for anchor in pending.get(&event.id).into_iter().flatten() {
+      if anchor.hash != *hash {
+        return Some(format!("anchor {} signed another hash", anchor.id));
+      }
+      let signature = hex::decode(&anchor.signature)
+        .ok()
+        .and_then(|bytes| Signature::from_slice(&bytes).ok());
+      let verified = signature.is_some_and(|signature| {
+        self
+          .config
+          .auth
+          .verifying_key
+          .verify(anchor_message(anchor.event_id, hash).as_bytes(), &signature)
+          .is_ok()
+      });
+      if !verified {
+        return Some(format!("anchor {} has an invalid signature", anchor.id));
+      }
+    }
+    None
+  }
+}
@@ -0,0 +1,160 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use validator::Validate;
+
+use super::{AuditAction, AuditAnchor, AuditEvent};
+
+/// Fields never written to the log as is, only whether they changed.
+const REDACTED_FIELDS: [&str; 1] = ["password"];
+
+/// audit event to record, the request context supplies the rest
+#[derive(Clone, Debug)]
+pub struct NewAuditEvent {
+  pub action: AuditAction,
+  pub target_type: &'static str,
+  pub target_id: Option<i32>,
+  /// overrides the authenticated user of the request, e.g. for signins
+  pub actor_id: Option<i32>,
+  pub before: Option<Value>,
+  pub after: Option<Value>,
+}
+
+impl NewAuditEvent {
+  /// An action on the user `user_id`.
+  pub fn user(action: AuditAction, user_id: Option<i32>) -> Self {
+    Self {
+      action,
+      target_type: "user",
+      target_id: user_id,
+      actor_id: None,
+      before: None,
+      after: None,
+    }
+  }
+
+  pub fn actor(self, actor_id: i32) -> Self {
+    Self {
+      actor_id: Some(actor_id),
+      ..self
+    }
+  }
+
+  /// Keep the fields of two snapshots that differ, `None` when the target didn't exist.
+  pub fn changes(self, before: Option<Value>, after: Option<Value>) -> Self {
+    let (before, after) = match (before, after) {
+      (Some(Value::Object(mut before)), Some(Value::Object(mut after))) => {
+        let unchanged: Vec<String> = before
+          .iter()
+          .filter(|(name, value)| after.get(*name) == Some(*value))
+          .map(|(name, _)| name.clone())
+          .collect();
+        for name in unchanged {
+          before.remove(&name);
+          after.remove(&name);
+        }
+        (Some(Value::Object(before)), Some(Value::Object(after)))
+      }
+      changes => changes,
+    };
+    Self {
+      before: before.map(redact),
+      after: after.map(redact),
+      ..self
+    }
+  }
+
+  /// Details of an action that changes nothing, e.g. why a signin failed.
+  pub fn details(self, details: Value) -> Self {
+    Self {
+      after: Some(redact(details)),
+      ..self
+    }
+  }
+}
+
+fn redact(value: Value) -> Value {
+  match value {
+    Value::Object(fields) => Value::Object(
+      fields
+        .into_iter()
+        .map(
+          |(name, value)| match REDACTED_FIELDS.contains(&name.as_str()) {
+            true => (name, Value::String("[redacted]".to_string())),
+            false => (name, value),
+          },
+        )
+        .collect::<Map<_, _>>(),
+    ),
+    value => value,
+  }
+}
+
+/// audit log filter input dto, newest events first
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct AuditFilter {
+  pub actor_id: Option<i32>,
+  #[validate(length(min = 1, max = 64))]
+  pub action: Option<String>,
+  #[validate(length(min = 1, max = 64))]
+  pub target_type: Option<String>,
+  pub target_id: Option<i32>,
+  #[validate(length(min = 1, max = 128))]
+  pub request_id: Option<String>,
+  pub since: Option<DateTime<Utc>>,
+  pub until: Option<DateTime<Utc>>,
+  /// the previous page's `next_before`
+  pub before: Option<i64>,
+  #[serde(default = "default_limit")]
+  #[validate(range(min = 1, max = 100))]
+  pub limit: i64,
+}
+
+fn default_limit() -> i64 {
+  50
+}
+
+impl Default for AuditFilter {
+  fn default() -> Self {
+    Self {
+      actor_id: None,
+      action: None,
+      target_type: None,
+      target_id: None,
+      request_id: None,
+      since: None,
+      until: None,
+      before: None,
+      limit: default_limit(),
+    }
+  }
+}
+
+/// audit log page output dto
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AuditPage {
+  pub events: Vec<AuditEvent>,
+  /// `before` of the next page, absent on the last one
+  pub next_before: Option<i64>,
+}
+
+/// where the audit hash chain stops being trustworthy
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct AuditChainBreak {
+  pub event_id: i64,
+  pub reason: String,
+}
+
+/// audit chain verification output dto
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AuditChainReport {
+  pub valid: bool,
+  /// chained events checked
+  pub events: u64,
+  /// events older than the chain, not covered by it
+  pub unchained: u64,
+  /// anchors whose signature was checked
+  pub anchors: u64,
+  pub last_anchor: Option<AuditAnchor>,
+  pub first_break: Option<AuditChainBreak>,
+}
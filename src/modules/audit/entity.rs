@@ -0,0 +1,93 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::FromRow;
+
+/// audit_events table
+#[derive(Clone, Debug, Deserialize, FromRow, Serialize)]
+pub struct AuditEvent {
+  pub id: i64,
+  pub organization_id: i32,
+  pub actor_id: Option<i32>,
+  pub action: String,
+  pub target_type: String,
+  pub target_id: Option<i32>,
+  /// changed fields before the action, absent for creations
+  pub before: Option<Value>,
+  /// changed fields after the action, absent for removals
+  pub after: Option<Value>,
+  pub ip: Option<String>,
+  pub request_id: Option<String>,
+  pub created_at: DateTime<Utc>,
+  /// hash of the previous event of the chain
+  pub prev_hash: Option<String>,
+  /// hash of this event and `prev_hash`, absent for events older than the chain
+  pub hash: Option<String>,
+}
+
+/// audit_anchors table, a signature of the chain up to `event_id`
+#[derive(Clone, Debug, Deserialize, FromRow, Serialize)]
+pub struct AuditAnchor {
+  pub id: i64,
+  pub event_id: i64,
+  pub hash: String,
+  pub signature: String,
+  pub created_at: DateTime<Utc>,
+}
+
+/// audited actions
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum AuditAction {
+  UserCreate,
+  UserUpdate,
+  UserUpdateRoles,
+  UserUpdatePermissions,
+  UserUpdateGroups,
+  UserSuspend,
+  UserRestore,
+  UserDelete,
+  UserPurge,
+  MemberRemove,
+  SigninSucceeded,
+  SigninFailed,
+}
+
+impl AuditAction {
+  #[allow(clippy::should_implement_trait)]
+  pub fn from_str(action: &str) -> Option<Self> {
+    match action {
+      "user.create" => Some(AuditAction::UserCreate),
+      "user.update" => Some(AuditAction::UserUpdate),
+      "user.update_roles" => Some(AuditAction::UserUpdateRoles),
+      "user.update_permissions" => Some(AuditAction::UserUpdatePermissions),
+      "user.update_groups" => Some(AuditAction::UserUpdateGroups),
+      "user.suspend" => Some(AuditAction::UserSuspend),
+      "user.restore" => Some(AuditAction::UserRestore),
+      "user.delete" => Some(AuditAction::UserDelete),
+      "user.purge" => Some(AuditAction::UserPurge),
+      "organization.remove_member" => Some(AuditAction::MemberRemove),
+      "auth.signin" => Some(AuditAction::SigninSucceeded),
+      "auth.signin_failed" => Some(AuditAction::SigninFailed),
+      _ => None,
+    }
+  }
+}
+
+impl AsRef<str> for AuditAction {
+  fn as_ref(&self) -> &str {
+    match *self {
+      AuditAction::UserCreate => "user.create",
+      AuditAction::UserUpdate => "user.update",
+      AuditAction::UserUpdateRoles => "user.update_roles",
+      AuditAction::UserUpdatePermissions => "user.update_permissions",
+      AuditAction::UserUpdateGroups => "user.update_groups",
+      AuditAction::UserSuspend => "user.suspend",
+      AuditAction::UserRestore => "user.restore",
+      AuditAction::UserDelete => "user.delete",
+      AuditAction::UserPurge => "user.purge",
+      AuditAction::MemberRemove => "organization.remove_member",
+      AuditAction::SigninSucceeded => "auth.signin",
+      AuditAction::SigninFailed => "auth.signin_failed",
+    }
+  }
+}
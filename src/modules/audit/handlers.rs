@@ -0,0 +1,35 @@
+use super::AuditFilter;
+use crate::modules::authz::{Action, Resource};
+use crate::modules::users::User;
+use crate::{AppError, AppState};
+
+use axum::{
+  Extension, Json,
+  extract::{Query, State},
+  http::StatusCode,
+  response::IntoResponse,
+};
+use tracing::info;
+use validator::Validate;
+
+pub async fn get_audit_events_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Query(filter): Query<AuditFilter>,
+) -> Result<impl IntoResponse, AppError> {
+  filter.validate()?;
+  info!("Audit Handler::get audit events: filter: {:?}", filter);
+  state.authorize(&claims, Action::ReadAudit, &Resource::audit())?;
+  let page = state.get_audit_events(&filter).await?;
+  Ok((StatusCode::OK, Json(page)))
+}
+
+pub async fn verify_audit_chain_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+  info!("Audit Handler::verify audit chain");
+  state.authorize(&claims, Action::VerifyAudit, &Resource::audit())?;
+  let report = state.verify_audit_chain().await?;
+  Ok((StatusCode::OK, Json(report)))
+}
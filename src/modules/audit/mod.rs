@@ -0,0 +1,23 @@
+pub mod chain;
+pub mod dto;
+pub mod entity;
+pub mod handlers;
+pub mod services;
+pub mod tests;
+
+pub use chain::event_hash;
+pub(crate) use chain::record_audit;
+pub use dto::{AuditChainBreak, AuditChainReport, AuditFilter, AuditPage, NewAuditEvent};
+pub use entity::{AuditAction, AuditAnchor, AuditEvent};
+pub use handlers::{get_audit_events_handler, verify_audit_chain_handler};
+
+use crate::AppState;
+use axum::Router;
+use axum::routing::get;
+
+pub fn audit_router(state: AppState) -> Router {
+  Router::new()
+    .route("/", get(get_audit_events_handler))
+    .route("/verify", get(verify_audit_chain_handler))
+    .with_state(state)
+}
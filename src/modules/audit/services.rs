@@ -0,0 +1,63 @@
+use sqlx::{Postgres, QueryBuilder};
+
+use super::{AuditEvent, AuditFilter, AuditPage};
+use crate::common::current_tenant;
+use crate::{AppError, AppState};
+
+impl AppState {
+  /// Events of the active organization matching `filter`, newest first.
+  pub async fn get_audit_events(&self, filter: &AuditFilter) -> Result<AuditPage, AppError> {
+    let mut query = QueryBuilder::<Postgres>::new(
+      r#"
+      SELECT id, organization_id, actor_id, action, target_type, target_id, before, after,
+             ip, request_id, created_at, prev_hash, hash
+      FROM audit_events
+      WHERE organization_id = "#,
+    );
+    query.push_bind(current_tenant()?);
+    if let Some(actor_id) = filter.actor_id {
+      query.push(" AND actor_id = ").push_bind(actor_id);
+    }
+    if let Some(action) = &filter.action {
+      query.push(" AND action = ").push_bind(action);
+    }
+    if let Some(target_type) = &filter.target_type {
+      query.push(" AND target_type = ").push_bind(target_type);
+    }
+    if let Some(target_id) = filter.target_id {
+      query.push(" AND target_id = ").push_bind(target_id);
+    }
+    if let Some(request_id) = &filter.request_id {
+      query.push(" AND request_id = ").push_bind(request_id);
+    }
+    if let Some(since) = filter.since {
+      query.push(" AND created_at >= ").push_bind(since);
+    }
+    if let Some(until) = filter.until {
+      query.push(" AND created_at < ").push_bind(until);
+    }
+    if let Some(before) = filter.before {
+      query.push(" AND id < ").push_bind(before);
+    }
+    // one extra row tells whether there is a next page
+    query
+      .push(" ORDER BY id DESC LIMIT ")
+      .push_bind(filter.limit + 1);
+
+    let mut events: Vec<AuditEvent> = query
+      .build_query_as()
+      .fetch_all(&self.pool)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    let next_before = if events.len() as i64 > filter.limit {
+      events.truncate(filter.limit as usize);
+      events.last().map(|event| event.id)
+    } else {
+      None
+    };
+    Ok(AuditPage {
+      events,
+      next_before,
+    })
+  }
+}
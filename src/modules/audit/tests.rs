@@ -0,0 +1,400 @@
+#[cfg(test)]
+mod util_tests {
+  use crate::common::{DEFAULT_ORGANIZATION_ID, RequestContext, with_request_context, with_tenant};
+  use crate::modules::audit::*;
+  use crate::modules::users::{CreateUser, UpdateUserOptions};
+  use crate::{AppError, AppState};
+  use anyhow::Result;
+  use serde_json::json;
+  use serial_test::serial;
+
+  #[test]
+  fn audit_changes_test() {
+    let before = json!({"username": "alice", "email": null, "password": "hash1", "roles": []});
+    let after =
+      json!({"username": "alice", "email": "a@example.com", "password": "hash2", "roles": []});
+    let event =
+      NewAuditEvent::user(AuditAction::UserUpdate, Some(2)).changes(Some(before), Some(after));
+    assert_eq!(
+      event.before,
+      Some(json!({"email": null, "password": "[redacted]"}))
+    );
+    assert_eq!(
+      event.after,
+      Some(json!({"email": "a@example.com", "password": "[redacted]"}))
+    );
+
+    let event = NewAuditEvent::user(AuditAction::UserCreate, Some(2))
+      .changes(None, Some(json!({"username": "alice", "password": "hash"})));
+    assert_eq!(event.before, None);
+    assert_eq!(
+      event.after,
+      Some(json!({"username": "alice", "password": "[redacted]"}))
+    );
+    assert_eq!(
+      AuditAction::from_str(AuditAction::SigninFailed.as_ref()),
+      Some(AuditAction::SigninFailed)
+    );
+  }
+
+  fn filter(action: AuditAction) -> AuditFilter {
+    AuditFilter {
+      action: Some(action.as_ref().to_string()),
+      ..Default::default()
+    }
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn audit_events_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let context = RequestContext {
+        request_id: Some("req-1".to_string()),
+        ip: Some("10.0.0.1".to_string()),
+        actor_id: Some(1),
+        ..Default::default()
+      };
+
+      let user = with_request_context(
+        context,
+        state.create_user(CreateUser::new("newton", "apple123")),
+      )
+      .await?;
+      let page = state
+        .get_audit_events(&filter(AuditAction::UserCreate))
+        .await?;
+      assert_eq!(page.events.len(), 1);
+      let event = &page.events[0];
+      assert_eq!(event.target_id, Some(user.user_info.id));
+      assert_eq!(event.actor_id, Some(1));
+      assert_eq!(event.ip.as_deref(), Some("10.0.0.1"));
+      assert_eq!(event.request_id.as_deref(), Some("req-1"));
+      assert_eq!(event.before, None);
+      let after = event.after.as_ref().unwrap();
+      assert_eq!(after["username"], "newton");
+      assert_eq!(after["password"], "[redacted]");
+      assert_eq!(after["roles"][0]["name"], "User");
+
+      // 2, alice, updates her own email
+      let alice = state.get_user_by_id(2).await?;
+      let input = UpdateUserOptions {
+        username: None,
+        password: None,
+        email: Some(Some("alice@example.com".to_string())),
+        roles: None,
+        permissions: None,
+      };
+      state.update_user(&alice, 2, input, None).await?;
+      let page = state
+        .get_audit_events(&filter(AuditAction::UserUpdate))
+        .await?;
+      assert_eq!(page.events[0].before, Some(json!({"email": null})));
+      assert_eq!(
+        page.events[0].after,
+        Some(json!({"email": "alice@example.com"}))
+      );
+
+      state.suspend_user(3).await?;
+      let page = state
+        .get_audit_events(&filter(AuditAction::UserSuspend))
+        .await?;
+      assert_eq!(page.events[0].target_id, Some(3));
+      assert_eq!(page.events[0].before, Some(json!({"status": "active"})));
+      assert_eq!(page.events[0].after, Some(json!({"status": "suspended"})));
+
+      // a change that is rolled back leaves no event
+      assert!(matches!(
+        state.suspend_user(1).await,
+        Err(AppError::InvariantViolation(_))
+      ));
+      let filter_target = AuditFilter {
+        target_id: Some(1),
+        ..Default::default()
+      };
+      assert!(
+        state
+          .get_audit_events(&filter_target)
+          .await?
+          .events
+          .is_empty()
+      );
+
+      state.purge_user(4, None).await?;
+      let page = state
+        .get_audit_events(&filter(AuditAction::UserPurge))
+        .await?;
+      assert_eq!(
+        page.events[0].before.as_ref().unwrap()["username"],
+        "charlie"
+      );
+      assert_eq!(page.events[0].after, None);
+
+      // the log can't be rewritten
+      let result = sqlx::query("UPDATE audit_events SET action = 'user.read'")
+        .execute(&state.pool)
+        .await;
+      assert!(result.is_err());
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn audit_signin_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      state.get_token("alice", "123456", None).await?;
+      assert!(state.get_token("alice", "wrong", None).await.is_err());
+      assert!(state.get_token("alice", "wrong-again", None).await.is_err());
+      assert!(state.get_token("nobody", "123456", None).await.is_err());
+
+      let page = state
+        .get_audit_events(&filter(AuditAction::SigninSucceeded))
+        .await?;
+      assert_eq!(page.events.len(), 1);
+      assert_eq!(page.events[0].actor_id, Some(2));
+      assert_eq!(page.events[0].target_id, Some(2));
+
+      let page = state
+        .get_audit_events(&filter(AuditAction::SigninFailed))
+        .await?;
+      // only the first failure of the window, and none for unknown usernames
+      assert_eq!(page.events.len(), 1);
+      assert_eq!(page.events[0].target_id, Some(2));
+      assert_eq!(
+        page.events[0].after,
+        Some(json!({"reason": "invalid password"}))
+      );
+      let attempts: Vec<(Option<i32>, String)> =
+        sqlx::query_as("SELECT user_id, username FROM signin_attempts WHERE NOT succeeded")
+          .fetch_all(&state.pool)
+          .await?;
+      assert_eq!(
+        attempts,
+        vec![(Some(2), "alice".into()), (Some(2), "alice".into())]
+      );
+
+      // pages of one event, newest first
+      let mut filter = AuditFilter {
+        limit: 1,
+        ..Default::default()
+      };
+      let mut ids = vec![];
+      loop {
+        let page = state.get_audit_events(&filter).await?;
+        ids.extend(page.events.iter().map(|event| event.id));
+        match page.next_before {
+          Some(before) => filter.before = Some(before),
+          None => break,
+        }
+      }
+      assert_eq!(ids.len(), 2);
+      assert!(ids.windows(2).all(|pair| pair[0] > pair[1]));
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  async fn set_append_only(state: &AppState, enabled: bool) -> Result<()> {
+    let action = if enabled { "ENABLE" } else { "DISABLE" };
+    // only the owner of the tables, not the application role, can switch the triggers
+    let mut transaction = state.pool.begin().await?;
+    sqlx::query("SET LOCAL ROLE NONE")
+      .execute(&mut *transaction)
+      .await?;
+    for table in ["audit_events", "audit_anchors"] {
+      sqlx::query(&format!("ALTER TABLE {} {} TRIGGER ALL", table, action))
+        .execute(&mut *transaction)
+        .await?;
+    }
+    transaction.commit().await?;
+    Ok(())
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn audit_chain_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      // recorded before the chain existed
+      sqlx::query(
+        "INSERT INTO audit_events (organization_id, action, target_type) VALUES (1, 'user.create', 'user')",
+      )
+      .execute(&state.pool)
+      .await?;
+      state.get_token("alice", "123456", None).await?;
+      state.suspend_user(3).await?;
+      state.restore_user(3).await?;
+      state.get_token("bob", "123456", None).await?;
+
+      let report = state.verify_audit_chain().await?;
+      assert!(report.valid);
+      assert_eq!(report.events, 4);
+      assert_eq!(report.unchained, 1);
+      assert!(report.last_anchor.is_none());
+
+      let anchor = state.anchor_audit_chain().await?.unwrap();
+      assert!(state.anchor_audit_chain().await?.is_none());
+      let report = state.verify_audit_chain().await?;
+      assert!(report.valid);
+      assert_eq!(report.anchors, 1);
+      assert_eq!(report.last_anchor.unwrap().event_id, anchor.event_id);
+
+      let events = state
+        .get_audit_events(&AuditFilter::default())
+        .await?
+        .events;
+      let ids: Vec<i64> = events.iter().rev().map(|event| event.id).collect();
+      assert_eq!(events[0].prev_hash, events[1].hash);
+      set_append_only(&state, false).await?;
+
+      // an edited event
+      sqlx::query("UPDATE audit_events SET actor_id = 4 WHERE id = $1")
+        .bind(ids[2])
+        .execute(&state.pool)
+        .await?;
+      let report = state.verify_audit_chain().await?;
+      assert!(!report.valid);
+      let chain_break = report.first_break.unwrap();
+      assert_eq!(chain_break.event_id, ids[2]);
+      assert!(chain_break.reason.contains("modified"));
+      sqlx::query("UPDATE audit_events SET actor_id = NULL WHERE id = $1")
+        .bind(ids[2])
+        .execute(&state.pool)
+        .await?;
+      assert!(state.verify_audit_chain().await?.valid);
+
+      // a forged anchor
+      sqlx::query("INSERT INTO audit_anchors (event_id, hash, signature) VALUES ($1, $2, $3)")
+        .bind(anchor.event_id)
+        .bind(&anchor.hash)
+        .bind("00".repeat(64))
+        .execute(&state.pool)
+        .await?;
+      let report = state.verify_audit_chain().await?;
+      assert!(
+        report
+          .first_break
+          .unwrap()
+          .reason
+          .contains("invalid signature")
+      );
+      sqlx::query("DELETE FROM audit_anchors WHERE id <> $1")
+        .bind(anchor.id)
+        .execute(&state.pool)
+        .await?;
+
+      // a removed event
+      sqlx::query("DELETE FROM audit_events WHERE id = $1")
+        .bind(ids[3])
+        .execute(&state.pool)
+        .await?;
+      let report = state.verify_audit_chain().await?;
+      let chain_break = report.first_break.unwrap();
+      assert_eq!(chain_break.event_id, ids[4]);
+      assert!(chain_break.reason.contains("removed"));
+
+      // the anchored end of the chain removed
+      sqlx::query("DELETE FROM audit_events WHERE id >= $1")
+        .bind(ids[3])
+        .execute(&state.pool)
+        .await?;
+      let report = state.verify_audit_chain().await?;
+      assert_eq!(
+        report.first_break.unwrap().reason,
+        "anchored event is missing"
+      );
+      set_append_only(&state, true).await?;
+      anyhow::Ok(())
+    })
+    .await
+  }
+}
+
+#[cfg(test)]
+mod integration_tests {
+  use crate::AppState;
+  use crate::common::{DEFAULT_ORGANIZATION_ID, REQUEST_ID_HEADER, with_tenant};
+  use crate::test_util::{get_token, spawn_app};
+  use anyhow::Result;
+  use axum::http::StatusCode;
+  use reqwest::Client;
+  use serde_json::json;
+  use serial_test::serial;
+
+  #[tokio::test]
+  #[serial]
+  async fn get_audit_events_handler_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let (addr, tx) = spawn_app(state).await?;
+
+      let client = Client::builder().no_proxy().build().unwrap();
+      let addr = addr.to_string();
+
+      let response = client
+        .post(format!("http://{}/auth/signin", addr))
+        .header(REQUEST_ID_HEADER, "signin-42")
+        .json(&json!({"username": "superman", "password": "wrong-password"}))
+        .send()
+        .await?;
+      assert_eq!(response.headers()[REQUEST_ID_HEADER], "signin-42");
+      let token = get_token(&client, &addr, "superman", "supermannofly").await?;
+
+      let response = client
+        .get(format!(
+          "http://{}/audit?action=auth.signin_failed&target_id=1",
+          addr
+        ))
+        .header("Authorization", format!("Bearer {}", &token))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+      // a request id is assigned when the client sends none
+      assert!(response.headers().contains_key(REQUEST_ID_HEADER));
+      let page: serde_json::Value = response.json().await?;
+      let events = page["events"].as_array().unwrap();
+      assert_eq!(events.len(), 1);
+      assert_eq!(events[0]["request_id"], "signin-42");
+      assert_eq!(events[0]["ip"], "127.0.0.1");
+
+      let response = client
+        .get(format!("http://{}/audit?limit=500", addr))
+        .header("Authorization", format!("Bearer {}", &token))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+      let response = client
+        .get(format!("http://{}/audit/verify", addr))
+        .header("Authorization", format!("Bearer {}", &token))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+      let report: serde_json::Value = response.json().await?;
+      assert_eq!(report["valid"], true);
+      assert_eq!(report["first_break"], serde_json::Value::Null);
+
+      // 2, alice, 123456 has no VIEW_REPORTS
+      let token = get_token(&client, &addr, "alice", "123456").await?;
+      let response = client
+        .get(format!("http://{}/audit", addr))
+        .header("Authorization", format!("Bearer {}", &token))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::FORBIDDEN);
+      let response = client
+        .get(format!("http://{}/audit/verify", addr))
+        .header("Authorization", format!("Bearer {}", &token))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+      tx.send(()).unwrap();
+      anyhow::Ok(())
+    })
+    .await
+  }
+}
@@ -1,3 +1,4 @@
+use super::SigninAttempt;
 use serde::{Deserialize, Serialize};
 use validator::Validate;
 
@@ -7,6 +8,9 @@ pub struct TokenRequest {
   pub username: String,
   #[validate(length(min = 6, max = 50))]
   pub password: String,
+  /// organization to act in, defaults to the user's first organization
+  #[serde(default)]
+  pub organization_id: Option<i32>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -15,6 +19,13 @@ pub struct TokenResponse {
   pub token_type: Option<String>,
 }
 
+/// signup query parameters
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct SignupParams {
+  /// invitation token from `POST /invitations`
+  pub invite: Option<String>,
+}
+
 impl Default for TokenResponse {
   fn default() -> Self {
     Self {
@@ -29,6 +40,7 @@ impl TokenRequest {
     Self {
       username: username.to_string(),
       password: password.to_string(),
+      organization_id: None,
     }
   }
 }
@@ -41,3 +53,34 @@ impl TokenResponse {
     }
   }
 }
+
+/// signin history query parameters, newest attempts first
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct SigninHistoryParams {
+  #[serde(default = "default_history_limit")]
+  #[validate(range(min = 1, max = 100))]
+  pub limit: i64,
+  /// the previous page's `next_before`
+  pub before: Option<i64>,
+}
+
+fn default_history_limit() -> i64 {
+  20
+}
+
+impl Default for SigninHistoryParams {
+  fn default() -> Self {
+    Self {
+      limit: default_history_limit(),
+      before: None,
+    }
+  }
+}
+
+/// signin history output dto
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SigninHistory {
+  pub attempts: Vec<SigninAttempt>,
+  /// `before` of the next page, absent on the last one
+  pub next_before: Option<i64>,
+}
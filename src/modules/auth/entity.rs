@@ -0,0 +1,43 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+/// signin_attempts table
+#[derive(Clone, Debug, Deserialize, FromRow, Serialize)]
+pub struct SigninAttempt {
+  pub id: i64,
+  /// absent when the username matched no account
+  pub user_id: Option<i32>,
+  pub username: String,
+  pub succeeded: bool,
+  pub failure_reason: Option<String>,
+  pub ip: Option<String>,
+  pub user_agent: Option<String>,
+  /// anomalies found in a successful signin, see `SigninFlag`
+  pub flags: Vec<String>,
+  pub created_at: DateTime<Utc>,
+}
+
+/// anomaly of a successful signin
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum SigninFlag {
+  /// from an address the user never signed in from
+  NewIp,
+  /// with a user agent the user never signed in with
+  NewUserAgent,
+  /// from another address than a signin moments before
+  RapidIpSwitch,
+  /// after `signins.failure_threshold` failed attempts
+  FailuresBeforeSuccess,
+}
+
+impl AsRef<str> for SigninFlag {
+  fn as_ref(&self) -> &str {
+    match *self {
+      SigninFlag::NewIp => "new_ip",
+      SigninFlag::NewUserAgent => "new_user_agent",
+      SigninFlag::RapidIpSwitch => "rapid_ip_switch",
+      SigninFlag::FailuresBeforeSuccess => "failures_before_success",
+    }
+  }
+}
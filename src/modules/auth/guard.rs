@@ -0,0 +1,112 @@
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::Serialize;
+
+/// Header carrying the captcha response or proof-of-work solution of a signup.
+pub const SIGNUP_PROOF_HEADER: &str = "x-signup-proof";
+
+pub type GuardFuture<'a> = Pin<Box<dyn Future<Output = Result<(), String>> + Send + 'a>>;
+
+/// What a `SignupGuard` gets to see of a signup.
+#[derive(Debug, Clone)]
+pub struct SignupAttempt {
+  pub username: String,
+  pub email: Option<String>,
+  /// value of the `X-Signup-Proof` header
+  pub proof: Option<String>,
+}
+
+/// Extra verification of signups, e.g. a captcha or proof-of-work check.
+/// Returns the reason on rejection.
+pub trait SignupGuard: Debug + Send + Sync {
+  fn check<'a>(&'a self, attempt: &'a SignupAttempt) -> GuardFuture<'a>;
+}
+
+/// Accepts every signup, the default.
+#[derive(Debug, Default)]
+pub struct AllowAllGuard;
+
+impl SignupGuard for AllowAllGuard {
+  fn check<'a>(&'a self, _attempt: &'a SignupAttempt) -> GuardFuture<'a> {
+    Box::pin(async { Ok(()) })
+  }
+}
+
+/// Accepts signups whose proof equals a fixed value, for tests.
+#[derive(Debug)]
+pub struct StubSignupGuard {
+  pub expected_proof: String,
+}
+
+impl SignupGuard for StubSignupGuard {
+  fn check<'a>(&'a self, attempt: &'a SignupAttempt) -> GuardFuture<'a> {
+    Box::pin(async move {
+      match attempt.proof.as_deref() {
+        Some(proof) if proof == self.expected_proof => Ok(()),
+        Some(_) => Err("invalid signup proof".to_string()),
+        None => Err("missing signup proof".to_string()),
+      }
+    })
+  }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SignupRejection {
+  Disabled,
+  InviteRequired,
+  EmailDomain,
+  Guard,
+}
+
+impl AsRef<str> for SignupRejection {
+  fn as_ref(&self) -> &str {
+    match self {
+      SignupRejection::Disabled => "disabled",
+      SignupRejection::InviteRequired => "invite_required",
+      SignupRejection::EmailDomain => "email_domain",
+      SignupRejection::Guard => "guard",
+    }
+  }
+}
+
+/// Rejected signups since startup, by reason.
+#[derive(Debug, Default)]
+pub struct SignupRejections {
+  disabled: AtomicU64,
+  invite_required: AtomicU64,
+  email_domain: AtomicU64,
+  guard: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, PartialEq, Eq)]
+pub struct SignupRejectionCounts {
+  pub disabled: u64,
+  pub invite_required: u64,
+  pub email_domain: u64,
+  pub guard: u64,
+}
+
+impl SignupRejections {
+  /// Count a rejection, returning the new total for its reason.
+  pub fn record(&self, reason: SignupRejection) -> u64 {
+    let counter = match reason {
+      SignupRejection::Disabled => &self.disabled,
+      SignupRejection::InviteRequired => &self.invite_required,
+      SignupRejection::EmailDomain => &self.email_domain,
+      SignupRejection::Guard => &self.guard,
+    };
+    counter.fetch_add(1, Ordering::Relaxed) + 1
+  }
+
+  pub fn counts(&self) -> SignupRejectionCounts {
+    SignupRejectionCounts {
+      disabled: self.disabled.load(Ordering::Relaxed),
+      invite_required: self.invite_required.load(Ordering::Relaxed),
+      email_domain: self.email_domain.load(Ordering::Relaxed),
+      guard: self.guard.load(Ordering::Relaxed),
+    }
+  }
+}
@@ -1,22 +1,36 @@
+use crate::common::{DEFAULT_ORGANIZATION_ID, with_tenant};
 use crate::modules::users::CreateUser;
 use crate::{AppError, AppState};
 use axum::{
-  extract::{Json, State},
-  http::StatusCode,
+  extract::{Json, Query, State},
+  http::{HeaderMap, StatusCode},
   response::IntoResponse,
 };
 use tracing::info;
 use validator::Validate;
 
-use super::TokenRequest;
+use super::{SIGNUP_PROOF_HEADER, SignupParams, TokenRequest};
 
 pub async fn signup_handler(
   State(state): State<AppState>,
+  Query(params): Query<SignupParams>,
+  headers: HeaderMap,
   Json(payload): Json<CreateUser>,
 ) -> Result<impl IntoResponse, AppError> {
   payload.validate()?;
   info!("Auth Handler::create user: input: {:?}", payload);
-  let user = state.create_user(payload).await?;
+  let proof = headers
+    .get(SIGNUP_PROOF_HEADER)
+    .and_then(|value| value.to_str().ok())
+    .map(|value| value.to_string());
+  state
+    .check_registration(&payload, params.invite.is_some(), proof)
+    .await?;
+  let user = match params.invite {
+    Some(token) => state.accept_invitation(&token, payload).await?,
+    // open signups join the default organization
+    None => with_tenant(DEFAULT_ORGANIZATION_ID, state.create_user(payload)).await?,
+  };
   Ok((StatusCode::CREATED, Json(user)))
 }
 
@@ -27,7 +41,11 @@ pub async fn signin_handler(
   payload.validate()?;
   info!("Auth Handler::get token: username: {:?}", payload.username);
   let token = state
-    .get_token(&payload.username, &payload.password)
+    .get_token(
+      &payload.username,
+      &payload.password,
+      payload.organization_id,
+    )
     .await?;
   Ok((StatusCode::OK, Json(token)))
 }
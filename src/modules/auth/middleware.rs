@@ -1,4 +1,8 @@
-use crate::{AppState, common::auth::verify};
+use crate::{
+  AppState,
+  common::{RequestContext, auth::verify, current_request, with_request_context, with_tenant},
+  modules::users::UserStatus,
+};
 use axum::{
   body::Body,
   extract::State,
@@ -8,6 +12,9 @@ use axum::{
 };
 use tracing::warn;
 
+/// Header selecting the active organization, overriding the one in the token.
+pub const ORGANIZATION_HEADER: &str = "x-organization-id";
+
 pub async fn auth_middleware(
   State(state): State<AppState>,
   req: Request<Body>,
@@ -18,21 +25,53 @@ pub async fn auth_middleware(
     .get("authorization")
     .and_then(|header| header.to_str().ok());
 
+  let organization_header = match headers.get(ORGANIZATION_HEADER) {
+    Some(value) => match value.to_str().ok().and_then(|id| id.parse::<i32>().ok()) {
+      Some(organization_id) => Some(organization_id),
+      None => {
+        warn!("Invalid organization header");
+        return (StatusCode::BAD_REQUEST, "Invalid X-Organization-Id header").into_response();
+      }
+    },
+    None => None,
+  };
+
   match auth_header {
     Some(auth_str) => {
       if let Some(bearer_str) = auth_str.strip_prefix("Bearer ") {
         match verify(bearer_str, &state.config) {
-          Ok(user_id) => {
-            let user = match state.get_user_by_id(user_id).await {
-              Ok(user) => user,
+          Ok(subject) => {
+            let user_id = subject.user_id;
+            let requested = organization_header.or(subject.organization_id);
+            let organization_id = match state.resolve_organization(user_id, requested).await {
+              Ok(organization_id) => organization_id,
               Err(e) => {
-                warn!(user_id, error = ?e, "user not exists or removed");
-                return (StatusCode::FORBIDDEN, "user not exists or removed").into_response();
+                warn!(user_id, error = ?e, "organization not accessible");
+                return (StatusCode::FORBIDDEN, "organization not accessible").into_response();
               }
             };
-            let mut req = req;
-            req.extensions_mut().insert(user);
-            next.run(req).await
+            with_tenant(organization_id, async move {
+              let user = match state.get_user_by_id(user_id).await {
+                Ok(user) => user,
+                Err(e) => {
+                  warn!(user_id, error = ?e, "user not exists or removed");
+                  return (StatusCode::FORBIDDEN, "user not exists or removed").into_response();
+                }
+              };
+              if let Some(reason) = inactive_reason(user.user_info.status) {
+                warn!(user_id, reason, "inactive user rejected");
+                return (StatusCode::FORBIDDEN, reason).into_response();
+              }
+              let mut req = req;
+              req.extensions_mut().insert(user);
+              req.extensions_mut().insert(subject);
+              let context = RequestContext {
+                actor_id: Some(user_id),
+                ..current_request()
+              };
+              with_request_context(context, next.run(req)).await
+            })
+            .await
           }
           Err(e) => {
             warn!(error = ?e, "verify token failed");
@@ -54,3 +93,12 @@ pub async fn auth_middleware(
     }
   }
 }
+
+/// Why a user can't authenticate, `None` for active users.
+pub fn inactive_reason(status: UserStatus) -> Option<&'static str> {
+  match status {
+    UserStatus::Active => None,
+    UserStatus::Suspended => Some("account is suspended"),
+    UserStatus::Deleted => Some("account is deleted"),
+  }
+}
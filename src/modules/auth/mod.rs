@@ -1,12 +1,22 @@
 pub mod dto;
+pub mod entity;
+pub mod guard;
 pub mod handlers;
 pub mod middleware;
+pub mod notifier;
 pub mod services;
+pub mod signins;
 pub mod tests;
 
-pub use dto::{TokenRequest, TokenResponse};
+pub use dto::{SigninHistory, SigninHistoryParams, SignupParams, TokenRequest, TokenResponse};
+pub use entity::{SigninAttempt, SigninFlag};
+pub use guard::{
+  AllowAllGuard, SIGNUP_PROOF_HEADER, SignupAttempt, SignupGuard, SignupRejection,
+  SignupRejectionCounts, SignupRejections, StubSignupGuard,
+};
 pub use handlers::{signin_handler, signup_handler};
-pub use middleware::auth_middleware;
+pub use middleware::{ORGANIZATION_HEADER, auth_middleware, inactive_reason};
+pub use notifier::{LogSigninNotifier, MemorySigninNotifier, NotifyFuture, SigninNotifier};
 
 use crate::AppState;
 use axum::Router;
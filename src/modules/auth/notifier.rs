@@ -0,0 +1,49 @@
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+
+use tracing::warn;
+
+use super::SigninAttempt;
+
+pub type NotifyFuture<'a> = Pin<Box<dyn Future<Output = ()> + Send + 'a>>;
+
+/// Told about every flagged signin, e.g. to email the user or page security.
+/// Runs before the signin responds: hand slow work off to a task.
+pub trait SigninNotifier: Debug + Send + Sync {
+  fn notify<'a>(&'a self, attempt: &'a SigninAttempt) -> NotifyFuture<'a>;
+}
+
+/// Logs flagged signins, the default.
+#[derive(Debug, Default)]
+pub struct LogSigninNotifier;
+
+impl SigninNotifier for LogSigninNotifier {
+  fn notify<'a>(&'a self, attempt: &'a SigninAttempt) -> NotifyFuture<'a> {
+    Box::pin(async move {
+      warn!(
+        user_id = attempt.user_id,
+        ip = attempt.ip,
+        flags = ?attempt.flags,
+        "suspicious signin"
+      );
+    })
+  }
+}
+
+/// Keeps the flagged signins it's told about, for tests.
+#[derive(Debug, Default)]
+pub struct MemorySigninNotifier {
+  pub attempts: Mutex<Vec<SigninAttempt>>,
+}
+
+impl SigninNotifier for MemorySigninNotifier {
+  fn notify<'a>(&'a self, attempt: &'a SigninAttempt) -> NotifyFuture<'a> {
+    Box::pin(async move {
+      if let Ok(mut attempts) = self.attempts.lock() {
+        attempts.push(attempt.clone());
+      }
+    })
+  }
+}
@@ -1,20 +1,120 @@
-use super::TokenResponse;
+use super::{SignupAttempt, SignupRejection, TokenResponse, inactive_reason};
 use crate::AppError;
 use crate::AppState;
-use crate::common::{sign, verify_password};
-use crate::modules::users::User;
+use crate::common::config::RegistrationMode;
+use crate::common::{sign, verify_password, with_tenant};
+use crate::modules::users::{CreateUser, User, UserInfo};
+use tracing::warn;
 
 impl AppState {
-  pub async fn get_token(&self, username: &str, password: &str) -> Result<TokenResponse, AppError> {
-    let user = self.verify_user(username, password).await?;
-    let token = sign(user.user_info.id, &self.config)?;
+  /// Issue a token acting in `organization_id`, or in the user's first organization.
+  pub async fn get_token(
+    &self,
+    username: &str,
+    password: &str,
+    organization_id: Option<i32>,
+  ) -> Result<TokenResponse, AppError> {
+    let user_info = match self.verify_credentials(username, password).await {
+      Ok(user_info) => user_info,
+      Err(err) => {
+        self
+          .record_signin_failure(username, organization_id, &err)
+          .await;
+        return Err(err);
+      }
+    };
+    let user_id = user_info.id;
+    let organization_id = self.resolve_organization(user_id, organization_id).await?;
+    let token = sign(user_id, organization_id, &self.config)?;
+    with_tenant(organization_id, async {
+      let user = self.get_user_obj_by_user_info(user_info).await?;
+      self.record_signin_success(&user).await
+    })
+    .await?;
     Ok(TokenResponse::new(&token))
   }
 
+  /// Apply the `registration` config and the signup guard to a signup,
+  /// counting and logging every rejection.
+  pub async fn check_registration(
+    &self,
+    input: &CreateUser,
+    invited: bool,
+    proof: Option<String>,
+  ) -> Result<(), AppError> {
+    let registration = &self.config.registration;
+    match registration.mode {
+      RegistrationMode::Disabled => {
+        return Err(self.reject_signup(input, SignupRejection::Disabled, "signup is disabled"));
+      }
+      RegistrationMode::InviteOnly if !invited => {
+        return Err(self.reject_signup(
+          input,
+          SignupRejection::InviteRequired,
+          "signup is by invitation only",
+        ));
+      }
+      _ => (),
+    }
+
+    // invitations are explicit, the domain allowlist only restricts open signups
+    if !invited && !registration.allowed_email_domains.is_empty() {
+      let domain = input
+        .email
+        .as_deref()
+        .and_then(|email| email.rsplit_once('@'))
+        .map(|(_, domain)| domain.to_lowercase());
+      let allowed = domain.is_some_and(|domain| {
+        registration
+          .allowed_email_domains
+          .iter()
+          .any(|allowed| allowed.eq_ignore_ascii_case(&domain))
+      });
+      if !allowed {
+        return Err(self.reject_signup(
+          input,
+          SignupRejection::EmailDomain,
+          "an email of an allowed domain is required",
+        ));
+      }
+    }
+
+    let attempt = SignupAttempt {
+      username: input.username.clone(),
+      email: input.email.clone(),
+      proof,
+    };
+    if let Err(reason) = self.signup_guard.check(&attempt).await {
+      return Err(self.reject_signup(input, SignupRejection::Guard, &reason));
+    }
+    Ok(())
+  }
+
+  fn reject_signup(&self, input: &CreateUser, reason: SignupRejection, message: &str) -> AppError {
+    let total = self.signup_rejections.record(reason);
+    warn!(
+      username = input.username,
+      reason = reason.as_ref(),
+      total,
+      "signup rejected: {}",
+      message
+    );
+    AppError::Forbidden(message.to_string())
+  }
+
   pub async fn verify_user(&self, username: &str, password: &str) -> Result<User, AppError> {
-    let user = self.verify_user_by_username(username).await?;
-    if verify_password(password, &user.user_info.password)? {
-      Ok(user)
+    let user_info = self.verify_credentials(username, password).await?;
+    self.get_user_obj_by_user_info(user_info).await
+  }
+
+  /// The account `username`, if `password` is its password and it's active.
+  async fn verify_credentials(&self, username: &str, password: &str) -> Result<UserInfo, AppError> {
+    let user_info = self.user_info_by_username(username).await?;
+    if verify_password(password, &user_info.password)? {
+      if let Some(reason) = inactive_reason(user_info.status) {
+        return Err(AppError::Forbidden(reason.to_string()));
+      }
+      Ok(user_info)
     } else {
       Err(AppError::PasswordError("Invalid password".to_string()))
     }
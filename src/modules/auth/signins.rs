@@ -0,0 +1,269 @@
+use chrono::{DateTime, Duration, Utc};
+use serde_json::json;
+use sqlx::{PgConnection, Postgres, QueryBuilder};
+use tracing::warn;
+
+use super::{SigninAttempt, SigninFlag, SigninHistory, SigninHistoryParams};
+use crate::common::{
+  DEFAULT_ORGANIZATION_ID, RequestContext, current_request, current_tenant, with_tenant,
+};
+use crate::modules::audit::{AuditAction, NewAuditEvent, record_audit};
+use crate::modules::outbox::{DomainEvent, publish_event};
+use crate::modules::users::User;
+use crate::{AppError, AppState};
+
+impl AppState {
+  /// Record a successful signin of `user` with its anomaly flags, together with its audit
+  /// event. Flagged signins are passed on to the signin notifier.
+  pub(crate) async fn record_signin_success(&self, user: &User) -> Result<SigninAttempt, AppError> {
+    let user_id = user.user_info.id;
+    let request = current_request();
+    let mut transaction = self
+      .pool
+      .begin()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    let flags = self
+      .signin_flags(&mut transaction, user_id, &request)
+      .await?;
+    let attempt = insert_attempt(
+      &mut transaction,
+      Some(user_id),
+      &user.user_info.username,
+      None,
+      &request,
+      &flags,
+    )
+    .await?;
+    let mut event = NewAuditEvent::user(AuditAction::SigninSucceeded, Some(user_id)).actor(user_id);
+    if !flags.is_empty() {
+      event = event.details(json!({"flags": attempt.flags}));
+      let suspicious = DomainEvent::SuspiciousSignin {
+        user_id,
+        ip: attempt.ip.clone(),
+        flags: attempt.flags.clone(),
+      };
+      publish_event(&mut transaction, suspicious).await?;
+    }
+    record_audit(&mut transaction, event).await?;
+    transaction
+      .commit()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    if !attempt.flags.is_empty() {
+      self.signin_notifier.notify(&attempt).await;
+    }
+    Ok(attempt)
+  }
+
+  /// Record a failed signin on an existing account in its organization. Only the first
+  /// failure within `signins.failure_window` is audited, the following ones are only counted
+  /// in `signin_attempts`. Attempts on unknown usernames are neither stored nor audited.
+  /// Failing to record it doesn't change the outcome of the signin.
+  pub(crate) async fn record_signin_failure(
+    &self,
+    username: &str,
+    organization_id: Option<i32>,
+    err: &AppError,
+  ) {
+    let reason = match err {
+      AppError::NotFound(_) => return,
+      AppError::PasswordError(_) => "invalid password",
+      AppError::Forbidden(reason) => reason.as_str(),
+      _ => return,
+    };
+    let account: Option<(i32, String)> =
+      sqlx::query_as("SELECT id, username FROM users WHERE username_normalized = lower($1)")
+        .bind(username)
+        .fetch_optional(&self.pool)
+        .await
+        .ok()
+        .flatten();
+    let Some((user_id, username)) = account else {
+      return;
+    };
+    let organization_id = self
+      .resolve_organization(user_id, organization_id)
+      .await
+      .unwrap_or(DEFAULT_ORGANIZATION_ID);
+    let since = Utc::now() - Duration::seconds(self.config.signins.failure_window as i64);
+    let recorded = with_tenant(organization_id, async {
+      let mut transaction = self
+        .pool
+        .begin()
+        .await
+        .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+      let repeated: bool = sqlx::query_scalar(
+        r#"
+        SELECT EXISTS (
+          SELECT 1 FROM signin_attempts WHERE user_id = $1 AND NOT succeeded AND created_at > $2
+        )
+        "#,
+      )
+      .bind(user_id)
+      .bind(since)
+      .fetch_one(&mut *transaction)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+      insert_attempt(
+        &mut transaction,
+        Some(user_id),
+        &username,
+        Some(reason),
+        &current_request(),
+        &[],
+      )
+      .await?;
+      if !repeated {
+        let event = NewAuditEvent::user(AuditAction::SigninFailed, Some(user_id))
+          .details(json!({"reason": reason}));
+        record_audit(&mut transaction, event).await?;
+      }
+      transaction
+        .commit()
+        .await
+        .map_err(|err| AppError::DatabaseError(err.to_string()))
+    })
+    .await;
+    if let Err(err) = recorded {
+      warn!(user_id, error = ?err, "failed to record signin failure");
+    }
+  }
+
+  /// Anomalies of a successful signin of `user_id` from `request`, given the earlier attempts.
+  /// A first signin has nothing to compare with and is never flagged.
+  async fn signin_flags(
+    &self,
+    connection: &mut PgConnection,
+    user_id: i32,
+    request: &RequestContext,
+  ) -> Result<Vec<SigninFlag>, AppError> {
+    let config = &self.config.signins;
+    let last_success: Option<(Option<String>, DateTime<Utc>)> = sqlx::query_as(
+      r#"
+      SELECT ip, created_at FROM signin_attempts
+      WHERE user_id = $1 AND succeeded
+      ORDER BY id DESC
+      LIMIT 1
+      "#,
+    )
+    .bind(user_id)
+    .fetch_optional(&mut *connection)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    let since = Utc::now() - Duration::seconds(config.failure_window as i64);
+    let since = last_success
+      .as_ref()
+      .map_or(since, |(_, created_at)| since.max(*created_at));
+    let (known_ip, known_agent, failures): (bool, bool, i64) = sqlx::query_as(
+      r#"
+      SELECT
+        EXISTS (SELECT 1 FROM signin_attempts WHERE user_id = $1 AND succeeded AND ip = $2),
+        EXISTS (SELECT 1 FROM signin_attempts WHERE user_id = $1 AND succeeded AND user_agent = $3),
+        (SELECT COUNT(*) FROM signin_attempts WHERE user_id = $1 AND NOT succeeded AND created_at > $4)
+      "#,
+    )
+    .bind(user_id)
+    .bind(&request.ip)
+    .bind(&request.user_agent)
+    .bind(since)
+    .fetch_one(&mut *connection)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    let mut flags = Vec::new();
+    if let Some((last_ip, last_at)) = &last_success {
+      if request.ip.is_some() && !known_ip {
+        flags.push(SigninFlag::NewIp);
+      }
+      if request.user_agent.is_some() && !known_agent {
+        flags.push(SigninFlag::NewUserAgent);
+      }
+      let window = Duration::seconds(config.rapid_switch_window as i64);
+      if let (Some(ip), Some(last_ip)) = (&request.ip, last_ip)
+        && ip != last_ip
+        && Utc::now() - *last_at < window
+      {
+        flags.push(SigninFlag::RapidIpSwitch);
+      }
+    }
+    if failures >= config.failure_threshold {
+      flags.push(SigninFlag::FailuresBeforeSuccess);
+    }
+    Ok(flags)
+  }
+
+  /// Signin attempts on the account `user_id` in the active organization, newest first.
+  pub async fn get_signin_history(
+    &self,
+    user_id: i32,
+    params: &SigninHistoryParams,
+  ) -> Result<SigninHistory, AppError> {
+    let mut query = QueryBuilder::<Postgres>::new(
+      r#"
+      SELECT id, user_id, username, succeeded, failure_reason, ip, user_agent, flags, created_at
+      FROM signin_attempts
+      WHERE user_id = "#,
+    );
+    query
+      .push_bind(user_id)
+      .push(" AND organization_id = ")
+      .push_bind(current_tenant()?);
+    if let Some(before) = params.before {
+      query.push(" AND id < ").push_bind(before);
+    }
+    // one extra row tells whether there is a next page
+    query
+      .push(" ORDER BY id DESC LIMIT ")
+      .push_bind(params.limit + 1);
+
+    let mut attempts: Vec<SigninAttempt> = query
+      .build_query_as()
+      .fetch_all(&self.pool)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    let next_before = if attempts.len() as i64 > params.limit {
+      attempts.truncate(params.limit as usize);
+      attempts.last().map(|attempt| attempt.id)
+    } else {
+      None
+    };
+    Ok(SigninHistory {
+      attempts,
+      next_before,
+    })
+  }
+}
+
+async fn insert_attempt(
+  connection: &mut PgConnection,
+  user_id: Option<i32>,
+  username: &str,
+  failure_reason: Option<&str>,
+  request: &RequestContext,
+  flags: &[SigninFlag],
+) -> Result<SigninAttempt, AppError> {
+  let flags: Vec<&str> = flags.iter().map(|flag| flag.as_ref()).collect();
+  sqlx::query_as(
+    r#"
+    INSERT INTO signin_attempts
+      (user_id, organization_id, username, succeeded, failure_reason, ip, user_agent, flags, created_at)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+    RETURNING id, user_id, username, succeeded, failure_reason, ip, user_agent, flags, created_at
+    "#,
+  )
+  .bind(user_id)
+  .bind(current_tenant()?)
+  .bind(username)
+  .bind(failure_reason.is_none())
+  .bind(failure_reason)
+  .bind(&request.ip)
+  .bind(&request.user_agent)
+  .bind(flags)
+  .bind(Utc::now())
+  .fetch_one(connection)
+  .await
+  .map_err(|err| AppError::DatabaseError(err.to_string()))
+}
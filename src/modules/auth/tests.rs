@@ -1,6 +1,7 @@
 #[cfg(test)]
 mod util_tests {
   pub use crate::AppState;
+  use crate::common::{DEFAULT_ORGANIZATION_ID, with_tenant};
   pub use anyhow::Result;
   use serial_test::serial;
 
@@ -8,23 +9,188 @@ mod util_tests {
   #[serial]
   async fn get_token_test() -> Result<()> {
     let (_tdb, state) = AppState::init_test_state().await?;
-    let token = state.get_token("alice", "123456").await?;
-    assert!(!token.token.is_empty());
-    Ok(())
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let token = state.get_token("alice", "123456", None).await?;
+      assert!(!token.token.is_empty());
+      anyhow::Ok(())
+    })
+    .await
   }
 
   #[tokio::test]
   #[serial]
   async fn verify_user_test() -> Result<()> {
     let (_tdb, state) = AppState::init_test_state().await?;
-    let user = state.verify_user("alice", "123456").await?;
-    assert_eq!(user.user_info.username, "alice");
-    Ok(())
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let user = state.verify_user("alice", "123456").await?;
+      assert_eq!(user.user_info.username, "alice");
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn registration_controls_test() -> Result<()> {
+    use crate::common::config::RegistrationMode;
+    use crate::modules::auth::{SignupRejectionCounts, StubSignupGuard};
+    use crate::modules::users::CreateUser;
+    use std::sync::Arc;
+
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let mut config = state.config.clone();
+      config.registration.allowed_email_domains = vec!["example.com".to_string()];
+      let state =
+        AppState::new(config, state.pool.clone()).with_signup_guard(Arc::new(StubSignupGuard {
+          expected_proof: "solved".to_string(),
+        }));
+      let proof = Some("solved".to_string());
+
+      let mut input = CreateUser::new("newbie", "123456");
+      assert!(
+        state
+          .check_registration(&input, false, proof.clone())
+          .await
+          .is_err()
+      );
+      input.email = Some("newbie@other.com".to_string());
+      assert!(
+        state
+          .check_registration(&input, false, proof.clone())
+          .await
+          .is_err()
+      );
+      input.email = Some("newbie@Example.com".to_string());
+      state
+        .check_registration(&input, false, proof.clone())
+        .await?;
+      assert!(state.check_registration(&input, false, None).await.is_err());
+      // the domain allowlist doesn't apply to invitations
+      state
+        .check_registration(&CreateUser::new("newbie", "123456"), true, proof.clone())
+        .await?;
+
+      let mut config = state.config.clone();
+      config.registration.mode = RegistrationMode::Disabled;
+      let disabled = AppState::new(config, state.pool.clone());
+      assert!(
+        disabled
+          .check_registration(&input, true, None)
+          .await
+          .is_err()
+      );
+
+      assert_eq!(
+        state.signup_rejections.counts(),
+        SignupRejectionCounts {
+          email_domain: 2,
+          guard: 1,
+          ..Default::default()
+        }
+      );
+      assert_eq!(disabled.signup_rejections.counts().disabled, 1);
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn signin_history_test() -> Result<()> {
+    use crate::common::{RequestContext, with_request_context};
+    use crate::modules::auth::{MemorySigninNotifier, SigninHistoryParams};
+    use std::sync::Arc;
+
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let notifier = Arc::new(MemorySigninNotifier::default());
+      let state = state.with_signin_notifier(notifier.clone());
+      let from = |ip: &str, user_agent: &str| RequestContext {
+        ip: Some(ip.to_string()),
+        user_agent: Some(user_agent.to_string()),
+        ..Default::default()
+      };
+      let signin = |context: RequestContext| {
+        let state = state.clone();
+        async move {
+          with_request_context(context, state.get_token("alice", "123456", None)).await?;
+          let history = state
+            .get_signin_history(2, &SigninHistoryParams::default())
+            .await?;
+          Ok::<_, anyhow::Error>(history.attempts[0].flags.clone())
+        }
+      };
+
+      // nothing to compare a first signin with
+      assert!(signin(from("10.0.0.1", "curl")).await?.is_empty());
+      assert!(signin(from("10.0.0.1", "curl")).await?.is_empty());
+      assert_eq!(
+        signin(from("10.0.0.2", "curl")).await?,
+        vec!["new_ip", "rapid_ip_switch"]
+      );
+
+      for _ in 0..5 {
+        let result = with_request_context(
+          from("10.0.0.9", "bot"),
+          state.get_token("alice", "wrong-password", None),
+        )
+        .await;
+        assert!(result.is_err());
+      }
+      assert_eq!(
+        signin(from("10.0.0.2", "firefox")).await?,
+        vec!["new_user_agent", "failures_before_success"]
+      );
+      assert_eq!(notifier.attempts.lock().unwrap().len(), 2);
+      // and told to other services
+      let suspicious: Vec<serde_json::Value> = sqlx::query_scalar(
+        "SELECT payload FROM outbox_events WHERE event_type = 'suspicious_signin' ORDER BY id",
+      )
+      .fetch_all(&state.pool)
+      .await?;
+      assert_eq!(suspicious.len(), 2);
+      assert_eq!(suspicious[0]["user_id"], 2);
+      assert_eq!(suspicious[0]["ip"], "10.0.0.2");
+
+      let history = state
+        .get_signin_history(2, &SigninHistoryParams::default())
+        .await?;
+      assert_eq!(history.attempts.len(), 9);
+      let failure = &history.attempts[1];
+      assert!(!failure.succeeded);
+      assert_eq!(failure.failure_reason.as_deref(), Some("invalid password"));
+      assert_eq!(failure.ip.as_deref(), Some("10.0.0.9"));
+
+      let page = state
+        .get_signin_history(
+          2,
+          &SigninHistoryParams {
+            limit: 5,
+            before: None,
+          },
+        )
+        .await?;
+      let next = state
+        .get_signin_history(
+          2,
+          &SigninHistoryParams {
+            limit: 5,
+            before: page.next_before,
+          },
+        )
+        .await?;
+      assert_eq!(next.attempts.len(), 4);
+      assert!(next.next_before.is_none());
+      anyhow::Ok(())
+    })
+    .await
   }
 }
 
 #[cfg(test)]
 mod integration_tests {
+  use crate::common::{DEFAULT_ORGANIZATION_ID, with_tenant};
   use crate::{AppState, get_router};
   use anyhow::Result;
   use axum::{Router, http::StatusCode};
@@ -65,6 +231,55 @@ mod integration_tests {
     Ok(())
   }
 
+  #[tokio::test]
+  #[serial]
+  async fn signup_guard_handler_test() -> Result<()> {
+    use crate::modules::auth::StubSignupGuard;
+    use std::sync::Arc;
+
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let state = state.with_signup_guard(Arc::new(StubSignupGuard {
+        expected_proof: "solved".to_string(),
+      }));
+      let app = get_router(state).await?;
+
+      let listener = TcpListener::bind("127.0.0.1:0").await?;
+      let addr = listener.local_addr()?;
+      let (tx, rx) = oneshot::channel();
+      tokio::spawn(async move {
+        axum::serve(listener, app.into_make_service())
+          .with_graceful_shutdown(async {
+            rx.await.ok();
+          })
+          .await
+          .unwrap();
+      });
+      tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+      let client = Client::builder().no_proxy().build().unwrap();
+      let new_user = json!({"username": "xuetrdi", "password": "123456"});
+      let response = client
+        .post(format!("http://{}/auth/signup", addr))
+        .json(&new_user)
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+      let response = client
+        .post(format!("http://{}/auth/signup", addr))
+        .json(&new_user)
+        .header("X-Signup-Proof", "solved")
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::CREATED);
+
+      tx.send(()).unwrap();
+      anyhow::Ok(())
+    })
+    .await
+  }
+
   #[tokio::test]
   #[serial]
   async fn signin_handler_test() -> Result<()> {
@@ -93,6 +308,39 @@ mod integration_tests {
     assert_eq!(response.status(), StatusCode::OK);
     assert!(response.text().await?.contains("token"));
 
+    let response = client
+      .post(format!("http://{}/auth/signin", addr))
+      .header("User-Agent", "signin-test/1.0")
+      .json(&json!({"username": "bob", "password": "123456"}))
+      .send()
+      .await?;
+    let token: serde_json::Value = response.json().await?;
+    let token = token["token"].as_str().unwrap().to_string();
+    let response = client
+      .get(format!("http://{}/users/me/logins", addr))
+      .header("Authorization", format!("Bearer {}", &token))
+      .send()
+      .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let history: serde_json::Value = response.json().await?;
+    assert_eq!(history["attempts"][0]["username"], "bob");
+    assert_eq!(history["attempts"][0]["succeeded"], true);
+    assert_eq!(history["attempts"][0]["user_agent"], "signin-test/1.0");
+
+    // 3, bob can't see the signins of 2, alice
+    let response = client
+      .get(format!("http://{}/users/2/logins", addr))
+      .header("Authorization", format!("Bearer {}", &token))
+      .send()
+      .await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = client
+      .get(format!("http://{}/users/3/logins?limit=1", addr))
+      .header("Authorization", format!("Bearer {}", &token))
+      .send()
+      .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
     tx.send(()).unwrap();
 
     Ok(())
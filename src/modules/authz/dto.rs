@@ -0,0 +1,31 @@
+use super::{Decision, Resource};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+/// access check input dto
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct AccessCheckRequest {
+  #[validate(range(min = 1))]
+  pub subject_id: i32,
+  #[validate(length(min = 1, max = 100))]
+  pub action: String,
+  #[validate(range(min = 1))]
+  pub target_id: Option<i32>,
+}
+
+/// access check output dto
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AccessCheckResponse {
+  pub subject: SubjectSummary,
+  pub resource: Resource,
+  pub decision: Decision,
+}
+
+/// roles and permissions the decision was based on
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SubjectSummary {
+  pub id: i32,
+  pub username: String,
+  pub roles: Vec<String>,
+  pub permissions: Vec<String>,
+}
@@ -0,0 +1,187 @@
+use serde::{Deserialize, Serialize};
+
+/// authorization policy file, see `policy.yaml`
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Policy {
+  #[serde(default)]
+  pub rules: Vec<Rule>,
+}
+
+/// a single policy rule
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Rule {
+  pub name: String,
+  pub effect: Effect,
+  pub actions: Vec<String>,
+  #[serde(default)]
+  pub subject: AttributeMatcher,
+  #[serde(default)]
+  pub resource: AttributeMatcher,
+  /// `true`: subject must own the resource, `false`: subject must not own it
+  #[serde(default)]
+  pub owner: Option<bool>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Effect {
+  Allow,
+  Deny,
+}
+
+/// conditions over the role and permission names of a subject or resource
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct AttributeMatcher {
+  #[serde(default)]
+  pub roles_any: Vec<String>,
+  #[serde(default)]
+  pub roles_all: Vec<String>,
+  #[serde(default)]
+  pub roles_none: Vec<String>,
+  #[serde(default)]
+  pub permissions_any: Vec<String>,
+  #[serde(default)]
+  pub permissions_all: Vec<String>,
+  #[serde(default)]
+  pub permissions_none: Vec<String>,
+}
+
+/// actions that can be authorized
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum Action {
+  ListUsers,
+  ReadUser,
+  UpdateUserInfo,
+  UpdateUserRoles,
+  UpdateUserPermissions,
+  DeleteUser,
+  ExplainAccess,
+  ReviewRoleRequests,
+  ReadGroups,
+  ManageGroups,
+  ManageOrganizations,
+  InviteUsers,
+  SuspendUser,
+  RestoreUser,
+  PurgeUser,
+  ImportUsers,
+  ExportUsers,
+  ReadAudit,
+  VerifyAudit,
+  ReadUserLogins,
+  ManageWebhooks,
+  ManageJobs,
+}
+
+/// the target of an action
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Resource {
+  pub kind: String,
+  pub owner_id: Option<i32>,
+  pub roles: Vec<String>,
+  pub permissions: Vec<String>,
+}
+
+/// result of evaluating the policy
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Decision {
+  pub allowed: bool,
+  pub action: String,
+  pub rule: Option<String>,
+  pub reason: String,
+  pub trace: Vec<RuleTrace>,
+}
+
+/// evaluation details of a rule covering the requested action
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RuleTrace {
+  pub rule: String,
+  pub effect: Effect,
+  pub matched: bool,
+  pub detail: String,
+}
+
+impl Action {
+  pub const ALL: [Action; 22] = [
+    Action::ListUsers,
+    Action::ReadUser,
+    Action::UpdateUserInfo,
+    Action::UpdateUserRoles,
+    Action::UpdateUserPermissions,
+    Action::DeleteUser,
+    Action::ExplainAccess,
+    Action::ReviewRoleRequests,
+    Action::ReadGroups,
+    Action::ManageGroups,
+    Action::ManageOrganizations,
+    Action::InviteUsers,
+    Action::SuspendUser,
+    Action::RestoreUser,
+    Action::PurgeUser,
+    Action::ImportUsers,
+    Action::ExportUsers,
+    Action::ReadAudit,
+    Action::VerifyAudit,
+    Action::ReadUserLogins,
+    Action::ManageWebhooks,
+    Action::ManageJobs,
+  ];
+
+  #[allow(clippy::should_implement_trait)]
+  pub fn from_str(name: &str) -> Option<Self> {
+    match name {
+      "users:list" => Some(Action::ListUsers),
+      "users:read" => Some(Action::ReadUser),
+      "users:update_info" => Some(Action::UpdateUserInfo),
+      "users:update_roles" => Some(Action::UpdateUserRoles),
+      "users:update_permissions" => Some(Action::UpdateUserPermissions),
+      "users:delete" => Some(Action::DeleteUser),
+      "authz:explain" => Some(Action::ExplainAccess),
+      "role_requests:review" => Some(Action::ReviewRoleRequests),
+      "groups:read" => Some(Action::ReadGroups),
+      "groups:manage" => Some(Action::ManageGroups),
+      "organizations:manage" => Some(Action::ManageOrganizations),
+      "users:invite" => Some(Action::InviteUsers),
+      "users:suspend" => Some(Action::SuspendUser),
+      "users:restore" => Some(Action::RestoreUser),
+      "users:purge" => Some(Action::PurgeUser),
+      "users:import" => Some(Action::ImportUsers),
+      "users:export" => Some(Action::ExportUsers),
+      "audit:read" => Some(Action::ReadAudit),
+      "audit:verify" => Some(Action::VerifyAudit),
+      "users:read_logins" => Some(Action::ReadUserLogins),
+      "webhooks:manage" => Some(Action::ManageWebhooks),
+      "jobs:manage" => Some(Action::ManageJobs),
+      _ => None,
+    }
+  }
+}
+
+impl AsRef<str> for Action {
+  fn as_ref(&self) -> &str {
+    match *self {
+      Action::ListUsers => "users:list",
+      Action::ReadUser => "users:read",
+      Action::UpdateUserInfo => "users:update_info",
+      Action::UpdateUserRoles => "users:update_roles",
+      Action::UpdateUserPermissions => "users:update_permissions",
+      Action::DeleteUser => "users:delete",
+      Action::ExplainAccess => "authz:explain",
+      Action::ReviewRoleRequests => "role_requests:review",
+      Action::ReadGroups => "groups:read",
+      Action::ManageGroups => "groups:manage",
+      Action::ManageOrganizations => "organizations:manage",
+      Action::InviteUsers => "users:invite",
+      Action::SuspendUser => "users:suspend",
+      Action::RestoreUser => "users:restore",
+      Action::PurgeUser => "users:purge",
+      Action::ImportUsers => "users:import",
+      Action::ExportUsers => "users:export",
+      Action::ReadAudit => "audit:read",
+      Action::VerifyAudit => "audit:verify",
+      Action::ReadUserLogins => "users:read_logins",
+      Action::ManageWebhooks => "webhooks:manage",
+      Action::ManageJobs => "jobs:manage",
+    }
+  }
+}
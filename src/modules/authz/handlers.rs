@@ -0,0 +1,22 @@
+use super::{AccessCheckRequest, Action};
+use crate::modules::users::User;
+use crate::{AppError, AppState};
+use axum::{Extension, Json, extract::State, http::StatusCode, response::IntoResponse};
+use tracing::info;
+use validator::Validate;
+
+pub async fn check_access_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Json(input): Json<AccessCheckRequest>,
+) -> Result<impl IntoResponse, AppError> {
+  input.validate()?;
+  info!("Authz Handler::check access: input: {:?}", input);
+  state
+    .authorize_user(&claims, Action::ExplainAccess, input.subject_id)
+    .await?;
+  let response = state
+    .explain_access(input.subject_id, &input.action, input.target_id)
+    .await?;
+  Ok((StatusCode::OK, Json(response)))
+}
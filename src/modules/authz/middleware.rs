@@ -0,0 +1,52 @@
+use super::{Action, Decision, Resource};
+use crate::AppState;
+use crate::modules::users::User;
+use axum::{
+  body::{Body, to_bytes},
+  extract::State,
+  http::{Request, StatusCode, header},
+  middleware::Next,
+  response::{IntoResponse, Response},
+};
+use tracing::warn;
+
+pub const AUTHZ_DEBUG_HEADER: &str = "x-authz-debug";
+
+/// Attach the policy decision to 403 responses when an admin sends `X-Authz-Debug`.
+pub async fn authz_debug_middleware(
+  State(state): State<AppState>,
+  req: Request<Body>,
+  next: Next,
+) -> Response {
+  let debug = req.headers().contains_key(AUTHZ_DEBUG_HEADER)
+    && req.extensions().get::<User>().is_some_and(|user| {
+      state
+        .check(user, Action::ExplainAccess, &Resource::user(user))
+        .allowed
+    });
+
+  let response = next.run(req).await;
+  if !debug || response.status() != StatusCode::FORBIDDEN {
+    return response;
+  }
+  let Some(decision) = response.extensions().get::<Decision>().cloned() else {
+    return response;
+  };
+
+  let (mut parts, body) = response.into_parts();
+  let mut output: serde_json::Value = match to_bytes(body, usize::MAX)
+    .await
+    .ok()
+    .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+  {
+    Some(output) => output,
+    None => {
+      warn!("failed to read 403 response body for authz debug output");
+      return (StatusCode::FORBIDDEN, "forbidden").into_response();
+    }
+  };
+  output["decision"] = serde_json::to_value(decision).unwrap_or_default();
+
+  parts.headers.remove(header::CONTENT_LENGTH);
+  Response::from_parts(parts, Body::from(output.to_string()))
+}
@@ -0,0 +1,21 @@
+pub mod dto;
+pub mod entity;
+pub mod handlers;
+pub mod middleware;
+pub mod services;
+pub mod tests;
+
+pub use dto::{AccessCheckRequest, AccessCheckResponse, SubjectSummary};
+pub use entity::{Action, AttributeMatcher, Decision, Effect, Policy, Resource, Rule, RuleTrace};
+pub use handlers::check_access_handler;
+pub use middleware::{AUTHZ_DEBUG_HEADER, authz_debug_middleware};
+
+use crate::AppState;
+use axum::Router;
+use axum::routing::post;
+
+pub fn authz_router(state: AppState) -> Router {
+  Router::new()
+    .route("/check", post(check_access_handler))
+    .with_state(state)
+}
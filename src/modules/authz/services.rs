@@ -0,0 +1,429 @@
+use std::fs::read_to_string;
+
+use anyhow::{Result, bail};
+use tracing::info;
+
+use super::{
+  AccessCheckResponse, Action, AttributeMatcher, Decision, Effect, Policy, Resource, Rule,
+  RuleTrace, SubjectSummary,
+};
+use crate::modules::users::{User, VecExtensions};
+use crate::{AppError, AppState};
+
+impl Policy {
+  pub fn from_file(file_path: &str) -> Result<Self> {
+    let policy_str = read_to_string(file_path)?;
+    Self::from_yaml(&policy_str)
+  }
+
+  pub fn from_yaml(policy_str: &str) -> Result<Self> {
+    let policy: Policy = serde_yaml_ng::from_str(policy_str)?;
+    for rule in &policy.rules {
+      for pattern in &rule.actions {
+        if !Action::ALL
+          .iter()
+          .any(|action| action_matches(pattern, action))
+        {
+          bail!("rule `{}`: unknown action `{}`", rule.name, pattern);
+        }
+      }
+    }
+    Ok(policy)
+  }
+
+  /// Evaluate the rules covering `action`. Deny rules take precedence over
+  /// allow rules, and an action no rule allows is denied.
+  pub fn authorize(&self, subject: &User, action: &Action, resource: &Resource) -> Decision {
+    self.evaluate(subject, action, resource, true)
+  }
+
+  /// Evaluate `action` before its target is loaded, only knowing the id of the user it's
+  /// about (`None` for a collection). Resource conditions are assumed to hold: rules that
+  /// need them still allow, deny rules that need them are left to `authorize`.
+  pub fn authorize_action(
+    &self,
+    subject: &User,
+    action: &Action,
+    owner_id: Option<i32>,
+  ) -> Decision {
+    let resource = Resource {
+      kind: "user".to_string(),
+      owner_id,
+      ..Default::default()
+    };
+    self.evaluate(subject, action, &resource, false)
+  }
+
+  fn evaluate(
+    &self,
+    subject: &User,
+    action: &Action,
+    resource: &Resource,
+    check_resource: bool,
+  ) -> Decision {
+    let mut trace = Vec::new();
+    let mut allowed_by = None;
+    let mut denied_by = None;
+
+    for rule in self.rules.iter().filter(|rule| rule.covers(action)) {
+      if !check_resource && rule.effect == Effect::Deny && !rule.resource.is_empty() {
+        continue;
+      }
+      let (matched, detail) = match rule.evaluate(subject, resource, check_resource) {
+        Ok(detail) => (true, detail),
+        Err(detail) => (false, detail),
+      };
+      if matched {
+        match rule.effect {
+          Effect::Deny if denied_by.is_none() => denied_by = Some(rule.name.clone()),
+          Effect::Allow if allowed_by.is_none() => allowed_by = Some(rule.name.clone()),
+          _ => (),
+        }
+      }
+      trace.push(RuleTrace {
+        rule: rule.name.clone(),
+        effect: rule.effect,
+        matched,
+        detail,
+      });
+    }
+
+    let (allowed, rule, reason) = match (denied_by, allowed_by) {
+      (Some(rule), _) => (
+        false,
+        Some(rule.clone()),
+        format!("denied by rule `{}`", rule),
+      ),
+      (None, Some(rule)) => (
+        true,
+        Some(rule.clone()),
+        format!("allowed by rule `{}`", rule),
+      ),
+      (None, None) => (false, None, "no rule allows this action".to_string()),
+    };
+
+    Decision {
+      allowed,
+      action: action.as_ref().to_string(),
+      rule,
+      reason,
+      trace,
+    }
+  }
+}
+
+impl Rule {
+  fn covers(&self, action: &Action) -> bool {
+    self
+      .actions
+      .iter()
+      .any(|pattern| action_matches(pattern, action))
+  }
+
+  /// Ok with a description of why the rule matched, Err with the first failed condition.
+  /// Without `check_resource` the resource conditions are skipped.
+  fn evaluate(
+    &self,
+    subject: &User,
+    resource: &Resource,
+    check_resource: bool,
+  ) -> Result<String, String> {
+    let subject_roles = subject.roles.extract_names();
+    let subject_permissions = subject.permissions.extract_names();
+    let mut details =
+      vec![
+        self
+          .subject
+          .evaluate("subject", &subject_roles, &subject_permissions)?,
+      ];
+    if check_resource {
+      details.push(
+        self
+          .resource
+          .evaluate("resource", &resource.roles, &resource.permissions)?,
+      );
+    }
+
+    if let Some(owner) = self.owner {
+      let is_owner = resource.owner_id == Some(subject.user_info.id);
+      if is_owner != owner {
+        return Err(format!(
+          "subject {} the owner of the resource",
+          if is_owner { "is" } else { "is not" }
+        ));
+      }
+      details.push(format!(
+        "subject {} the owner of the resource",
+        if owner { "is" } else { "is not" }
+      ));
+    }
+
+    details.retain(|detail| !detail.is_empty());
+    if details.is_empty() {
+      Ok("unconditional".to_string())
+    } else {
+      Ok(details.join("; "))
+    }
+  }
+}
+
+impl AttributeMatcher {
+  fn is_empty(&self) -> bool {
+    self.roles_any.is_empty()
+      && self.roles_all.is_empty()
+      && self.roles_none.is_empty()
+      && self.permissions_any.is_empty()
+      && self.permissions_all.is_empty()
+      && self.permissions_none.is_empty()
+  }
+
+  fn evaluate(
+    &self,
+    target: &str,
+    roles: &[String],
+    permissions: &[String],
+  ) -> Result<String, String> {
+    let mut details = Vec::new();
+    let roles_target = format!("{} roles {:?}", target, roles);
+    match_names(
+      &roles_target,
+      roles,
+      &self.roles_any,
+      &self.roles_all,
+      &self.roles_none,
+      &mut details,
+    )?;
+    let permissions_target = format!("{} permissions {:?}", target, permissions);
+    match_names(
+      &permissions_target,
+      permissions,
+      &self.permissions_any,
+      &self.permissions_all,
+      &self.permissions_none,
+      &mut details,
+    )?;
+    Ok(details.join("; "))
+  }
+}
+
+fn match_names(
+  target: &str,
+  values: &[String],
+  any: &[String],
+  all: &[String],
+  none: &[String],
+  details: &mut Vec<String>,
+) -> Result<(), String> {
+  if !any.is_empty() {
+    if !any.iter().any(|name| values.contains(name)) {
+      return Err(format!("{} has none of {:?}", target, any));
+    }
+    details.push(format!("{} has one of {:?}", target, any));
+  }
+  if !all.is_empty() {
+    if !all.iter().all(|name| values.contains(name)) {
+      return Err(format!("{} lacks some of {:?}", target, all));
+    }
+    details.push(format!("{} has all of {:?}", target, all));
+  }
+  if !none.is_empty() {
+    if let Some(name) = none.iter().find(|name| values.contains(name)) {
+      return Err(format!("{} contains excluded {}", target, name));
+    }
+    details.push(format!("{} has none of {:?}", target, none));
+  }
+  Ok(())
+}
+
+impl Resource {
+  /// a single user account, owned by the user itself
+  pub fn user(user: &User) -> Self {
+    Self {
+      kind: "user".to_string(),
+      owner_id: Some(user.user_info.id),
+      roles: user.roles.extract_names(),
+      permissions: user.permissions.extract_names(),
+    }
+  }
+
+  /// the collection of all user accounts
+  pub fn users() -> Self {
+    Self {
+      kind: "users".to_string(),
+      ..Default::default()
+    }
+  }
+
+  /// the collection of user groups
+  pub fn groups() -> Self {
+    Self {
+      kind: "groups".to_string(),
+      ..Default::default()
+    }
+  }
+
+  /// the audit log
+  pub fn audit() -> Self {
+    Self {
+      kind: "audit".to_string(),
+      ..Default::default()
+    }
+  }
+
+  /// the webhooks of the organization and their deliveries
+  pub fn webhooks() -> Self {
+    Self {
+      kind: "webhooks".to_string(),
+      ..Default::default()
+    }
+  }
+
+  /// the background jobs of the organization
+  pub fn jobs() -> Self {
+    Self {
+      kind: "jobs".to_string(),
+      ..Default::default()
+    }
+  }
+
+  /// the organizations and their memberships
+  pub fn organizations() -> Self {
+    Self {
+      kind: "organizations".to_string(),
+      ..Default::default()
+    }
+  }
+
+  /// the collection of pending invitations
+  pub fn invitations() -> Self {
+    Self {
+      kind: "invitations".to_string(),
+      ..Default::default()
+    }
+  }
+
+  /// the collection of role change requests
+  pub fn role_requests() -> Self {
+    Self {
+      kind: "role_requests".to_string(),
+      ..Default::default()
+    }
+  }
+}
+
+/// `*` matches every action, `users:*` every action of the `users` resource
+fn action_matches(pattern: &str, action: &Action) -> bool {
+  if pattern == "*" {
+    return true;
+  }
+  match pattern.strip_suffix('*') {
+    Some(prefix) => prefix.ends_with(':') && action.as_ref().starts_with(prefix),
+    None => pattern == action.as_ref(),
+  }
+}
+
+impl AppState {
+  /// Evaluate the configured policy and log the decision.
+  pub fn check(&self, subject: &User, action: Action, resource: &Resource) -> Decision {
+    let decision = self
+      .config
+      .authz
+      .policy
+      .authorize(subject, &action, resource);
+    info!(
+      subject = subject.user_info.id,
+      action = %decision.action,
+      resource = %resource.kind,
+      resource_owner = ?resource.owner_id,
+      allowed = decision.allowed,
+      rule = ?decision.rule,
+      reason = %decision.reason,
+      "authorization decision"
+    );
+    decision
+  }
+
+  /// Like `check`, but a denial becomes `AppError::AccessDenied`.
+  pub fn authorize(
+    &self,
+    subject: &User,
+    action: Action,
+    resource: &Resource,
+  ) -> Result<Decision, AppError> {
+    let decision = self.check(subject, action, resource);
+    if decision.allowed {
+      Ok(decision)
+    } else {
+      Err(AppError::AccessDenied(Box::new(decision)))
+    }
+  }
+
+  /// Check `action` on the user `owner_id` as far as possible without loading it, see
+  /// `Policy::authorize_action`. Done first, callers that may never act on a user
+  /// can't tell a missing one from an existing one.
+  pub fn authorize_action(
+    &self,
+    subject: &User,
+    action: Action,
+    owner_id: Option<i32>,
+  ) -> Result<Decision, AppError> {
+    let decision = self
+      .config
+      .authz
+      .policy
+      .authorize_action(subject, &action, owner_id);
+    if decision.allowed {
+      Ok(decision)
+    } else {
+      info!(
+        subject = subject.user_info.id,
+        action = %decision.action,
+        resource_owner = ?owner_id,
+        reason = %decision.reason,
+        "authorization denied before loading the resource"
+      );
+      Err(AppError::AccessDenied(Box::new(decision)))
+    }
+  }
+
+  /// Load the user `user_id` and authorize `action` on it, the action itself first.
+  pub async fn authorize_user(
+    &self,
+    subject: &User,
+    action: Action,
+    user_id: i32,
+  ) -> Result<User, AppError> {
+    self.authorize_action(subject, action.clone(), Some(user_id))?;
+    let user = self.get_user_by_id(user_id).await?;
+    self.authorize(subject, action, &Resource::user(&user))?;
+    Ok(user)
+  }
+
+  /// Evaluate `action` for another user, e.g. to find out why a request was denied.
+  pub async fn explain_access(
+    &self,
+    subject_id: i32,
+    action: &str,
+    target_id: Option<i32>,
+  ) -> Result<AccessCheckResponse, AppError> {
+    let action = Action::from_str(action)
+      .ok_or_else(|| AppError::BadRequest(format!("unknown action: {}", action)))?;
+    let subject = self.get_user_by_id(subject_id).await?;
+    let resource = match target_id {
+      Some(target_id) => Resource::user(&self.get_user_by_id(target_id).await?),
+      None => Resource::users(),
+    };
+    let decision = self.check(&subject, action, &resource);
+
+    Ok(AccessCheckResponse {
+      subject: SubjectSummary {
+        id: subject.user_info.id,
+        username: subject.user_info.username.clone(),
+        roles: subject.roles.extract_names(),
+        permissions: subject.permissions.extract_names(),
+      },
+      resource,
+      decision,
+    })
+  }
+}
@@ -0,0 +1,393 @@
+#[cfg(test)]
+mod util_tests {
+  use crate::AppState;
+  use crate::common::{DEFAULT_ORGANIZATION_ID, with_tenant};
+  use crate::modules::authz::*;
+  use crate::modules::users::{Role, RoleIn, UpdateUserOptions, User, UserInfo};
+  use anyhow::Result;
+  use serial_test::serial;
+
+  fn user_with_roles(id: i32, roles: &[&str]) -> User {
+    let mut user_info = UserInfo::new("someone", "password");
+    user_info.id = id;
+    let roles = roles
+      .iter()
+      .enumerate()
+      .map(|(i, name)| Role::new(i as i32 + 1, name))
+      .collect();
+    User::new(user_info, roles, vec![])
+  }
+
+  #[test]
+  fn moderator_update_permissions_test() -> Result<()> {
+    let policy = Policy::from_file("policy.yaml")?;
+    let moderator = user_with_roles(1, &["Moderator"]);
+
+    let target = user_with_roles(2, &["User"]);
+    let decision = policy.authorize(
+      &moderator,
+      &Action::UpdateUserPermissions,
+      &Resource::user(&target),
+    );
+    assert!(decision.allowed);
+    assert_eq!(
+      decision.rule.as_deref(),
+      Some("moderator-update-non-admin-permissions")
+    );
+
+    let target = user_with_roles(3, &["Admin"]);
+    let decision = policy.authorize(
+      &moderator,
+      &Action::UpdateUserPermissions,
+      &Resource::user(&target),
+    );
+    assert!(!decision.allowed);
+    assert_eq!(decision.rule, None);
+    assert!(
+      decision
+        .trace
+        .iter()
+        .any(|t| !t.matched && t.detail.contains("contains excluded Admin"))
+    );
+
+    let decision = policy.authorize(
+      &moderator,
+      &Action::UpdateUserRoles,
+      &Resource::user(&target),
+    );
+    assert!(!decision.allowed);
+    Ok(())
+  }
+
+  #[test]
+  fn owner_condition_test() -> Result<()> {
+    let policy = Policy::from_file("policy.yaml")?;
+    let user = user_with_roles(1, &["User"]);
+    let other = user_with_roles(2, &["User"]);
+
+    let own = policy.authorize(&user, &Action::UpdateUserInfo, &Resource::user(&user));
+    assert!(own.allowed);
+    let others = policy.authorize(&user, &Action::UpdateUserInfo, &Resource::user(&other));
+    assert!(!others.allowed);
+    let list = policy.authorize(&user, &Action::ListUsers, &Resource::users());
+    assert!(!list.allowed);
+    Ok(())
+  }
+
+  #[test]
+  fn deny_overrides_allow_test() -> Result<()> {
+    let policy = Policy::from_yaml(
+      r#"
+      rules:
+        - name: everyone
+          effect: allow
+          actions: ["users:*"]
+        - name: no-deleting-admins
+          effect: deny
+          actions: [users:delete]
+          resource:
+            roles_any: [Admin]
+      "#,
+    )?;
+    let subject = user_with_roles(1, &["Admin"]);
+    let target = user_with_roles(2, &["Admin"]);
+    let decision = policy.authorize(&subject, &Action::DeleteUser, &Resource::user(&target));
+    assert!(!decision.allowed);
+    assert_eq!(decision.rule.as_deref(), Some("no-deleting-admins"));
+    assert_eq!(decision.trace.len(), 2);
+
+    let decision = policy.authorize(&subject, &Action::ReadUser, &Resource::user(&target));
+    assert!(decision.allowed);
+    Ok(())
+  }
+
+  #[test]
+  fn authorize_action_test() -> Result<()> {
+    let policy = Policy::from_file("policy.yaml")?;
+    let user = user_with_roles(2, &["User"]);
+    let moderator = user_with_roles(3, &["Moderator"]);
+
+    assert!(
+      policy
+        .authorize_action(&user, &Action::ReadUser, Some(2))
+        .allowed
+    );
+    assert!(
+      !policy
+        .authorize_action(&user, &Action::ReadUser, Some(3))
+        .allowed
+    );
+    assert!(
+      !policy
+        .authorize_action(&user, &Action::ReadUser, None)
+        .allowed
+    );
+    // the resource condition of the moderator rule is left to `authorize`
+    let decision = policy.authorize_action(&moderator, &Action::UpdateUserPermissions, Some(1));
+    assert!(decision.allowed);
+
+    let policy = Policy::from_yaml(
+      r#"
+      rules:
+        - name: everyone
+          effect: allow
+          actions: ["users:*"]
+        - name: no-deleting-admins
+          effect: deny
+          actions: [users:delete]
+          resource:
+            roles_any: [Admin]
+        - name: no-purging
+          effect: deny
+          actions: [users:purge]
+      "#,
+    )?;
+    assert!(
+      policy
+        .authorize_action(&user, &Action::DeleteUser, Some(1))
+        .allowed
+    );
+    assert!(
+      !policy
+        .authorize_action(&user, &Action::PurgeUser, Some(1))
+        .allowed
+    );
+    Ok(())
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn explain_access_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let admin = state.get_user_by_id(1).await?;
+      let roles = vec![RoleIn {
+        id: 2,
+        name: "Moderator".to_string(),
+        expires_at: None,
+      }];
+      let options = UpdateUserOptions {
+        username: None,
+        password: None,
+        email: None,
+        roles: Some(roles),
+        permissions: None,
+      };
+      state.update_user(&admin, 3, options, None).await?;
+
+      let check = state
+        .explain_access(3, "users:update_permissions", Some(2))
+        .await?;
+      assert!(check.decision.allowed);
+      assert_eq!(check.subject.roles, vec!["Moderator".to_string()]);
+      assert!(
+        check
+          .subject
+          .permissions
+          .contains(&"MANAGE_PERMISSIONS".to_string())
+      );
+
+      let check = state
+        .explain_access(3, "users:update_roles", Some(2))
+        .await?;
+      assert!(!check.decision.allowed);
+      assert!(!check.decision.trace.is_empty());
+
+      let check = state.explain_access(3, "users:fly", None).await;
+      assert!(check.is_err());
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[test]
+  fn unknown_action_test() {
+    let policy = Policy::from_yaml(
+      r#"
+      rules:
+        - name: typo
+          effect: allow
+          actions: [users:updte_info]
+      "#,
+    );
+    assert!(policy.is_err());
+  }
+}
+
+#[cfg(test)]
+mod integration_tests {
+  use crate::AppState;
+  use crate::common::{DEFAULT_ORGANIZATION_ID, with_tenant};
+  use crate::modules::authz::Policy;
+  use crate::test_util::{get_token, spawn_app};
+  use anyhow::Result;
+  use axum::http::StatusCode;
+  use reqwest::Client;
+  use serde_json::json;
+  use serial_test::serial;
+
+  #[tokio::test]
+  #[serial]
+  async fn policy_denies_listing_users_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let (addr, tx) = spawn_app(state).await?;
+
+      let client = Client::builder().no_proxy().build().unwrap();
+      let token = get_token(&client, &addr.to_string(), "alice", "123456").await?;
+
+      let response = client
+        .get(format!("http://{}/users?limit=10&offset=0", addr))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+      // alice can still read herself
+      let response = client
+        .get(format!("http://{}/users/{}", addr, 2))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+
+      // but can't tell missing users from others
+      for path in ["users/3", "users/999", "users/3/logins", "users/999/logins"] {
+        let response = client
+          .get(format!("http://{}/{}", addr, path))
+          .header("Authorization", format!("Bearer {}", token))
+          .send()
+          .await?;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", path);
+      }
+      let response = client
+        .delete(format!("http://{}/users/999", addr))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::FORBIDDEN);
+      let response = client
+        .post(format!("http://{}/users/999/suspend", addr))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::FORBIDDEN);
+      for username in ["bob", "nobody"] {
+        let response = client
+          .get(format!("http://{}/users/by-username/{}", addr, username))
+          .header("Authorization", format!("Bearer {}", token))
+          .send()
+          .await?;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", username);
+      }
+      let response = client
+        .get(format!("http://{}/users/by-username/alice", addr))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+
+      // who may read users gets the 404
+      let token = get_token(&client, &addr, "superman", "supermannofly").await?;
+      let response = client
+        .get(format!("http://{}/users/999", addr))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+      tx.send(()).unwrap();
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn check_access_handler_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let (addr, tx) = spawn_app(state).await?;
+
+      let client = Client::builder().no_proxy().build().unwrap();
+      let token = get_token(&client, &addr.to_string(), "superman", "supermannofly").await?;
+
+      let response = client
+        .post(format!("http://{}/authz/check", addr))
+        .json(&json!({"subject_id": 2, "action": "users:list"}))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+      let check: serde_json::Value = response.json().await?;
+      assert_eq!(check["decision"]["allowed"], false);
+      assert_eq!(check["subject"]["username"], "alice");
+
+      // only admins may explain decisions
+      let token = get_token(&client, &addr.to_string(), "alice", "123456").await?;
+      let response = client
+        .post(format!("http://{}/authz/check", addr))
+        .json(&json!({"subject_id": 2, "action": "users:list"}))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+      tx.send(()).unwrap();
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn forbidden_response_trace_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let mut config = state.config.clone();
+      config.authz.policy = Policy::from_yaml(
+        r#"
+        rules:
+          - name: admin-all
+            effect: allow
+            actions: ["*"]
+            subject:
+              roles_any: [Admin]
+          - name: keep-admins
+            effect: deny
+            actions: [users:delete]
+            resource:
+              roles_any: [Admin]
+        "#,
+      )?;
+      let (addr, tx) = spawn_app(AppState::new(config, state.pool.clone())).await?;
+
+      let client = Client::builder().no_proxy().build().unwrap();
+      let token = get_token(&client, &addr.to_string(), "superman", "supermannofly").await?;
+
+      let response = client
+        .delete(format!("http://{}/users/{}", addr, 1))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::FORBIDDEN);
+      let output: serde_json::Value = response.json().await?;
+      assert!(output.get("decision").is_none());
+
+      let response = client
+        .delete(format!("http://{}/users/{}", addr, 1))
+        .header("Authorization", format!("Bearer {}", token))
+        .header("X-Authz-Debug", "1")
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::FORBIDDEN);
+      let output: serde_json::Value = response.json().await?;
+      assert_eq!(output["decision"]["rule"], "keep-admins");
+      assert_eq!(output["decision"]["trace"].as_array().unwrap().len(), 2);
+
+      tx.send(()).unwrap();
+      anyhow::Ok(())
+    })
+    .await
+  }
+}
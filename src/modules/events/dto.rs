@@ -0,0 +1,8 @@
+use serde::{Deserialize, Serialize};
+
+/// query parameters of the event streams, for clients that can't set `Last-Event-ID`
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct StreamParams {
+  /// resume after this event, as the `Last-Event-ID` header does
+  pub last_event_id: Option<i64>,
+}
@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+use super::StreamParams;
+use crate::common::auth::TokenSubject;
+use crate::common::current_tenant;
+use crate::modules::outbox::OutboxEvent;
+use crate::modules::users::User;
+use crate::{AppError, AppState};
+use axum::{
+  Extension,
+  extract::{
+    Query, State,
+    ws::{Message, WebSocket, WebSocketUpgrade},
+  },
+  http::HeaderMap,
+  response::{
+    IntoResponse,
+    sse::{Event, KeepAlive, Sse},
+  },
+};
+use futures_util::stream::{BoxStream, StreamExt};
+use tracing::info;
+
+/// Header of a reconnecting `EventSource` carrying the id of the last event it got.
+pub const LAST_EVENT_ID_HEADER: &str = "last-event-id";
+
+fn last_event_id(headers: &HeaderMap, params: &StreamParams) -> Result<Option<i64>, AppError> {
+  if params.last_event_id.is_some() {
+    return Ok(params.last_event_id);
+  }
+  headers
+    .get(LAST_EVENT_ID_HEADER)
+    .map(|value| {
+      value
+        .to_str()
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .ok_or(AppError::BadRequest("invalid Last-Event-ID".to_string()))
+    })
+    .transpose()
+}
+
+pub async fn event_stream_handler(
+  Extension(claims): Extension<User>,
+  Extension(subject): Extension<TokenSubject>,
+  State(state): State<AppState>,
+  Query(params): Query<StreamParams>,
+  headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+  let last_event_id = last_event_id(&headers, &params)?;
+  info!(
+    "Events Handler::stream: user: {:?}, last event: {:?}",
+    claims.user_info.id, last_event_id
+  );
+  let events = state
+    .subscribe_events(
+      &claims,
+      current_tenant()?,
+      last_event_id,
+      subject.expires_at,
+    )
+    .await?;
+  let events = events.map(|event| {
+    Event::default()
+      .id(event.id.to_string())
+      .event(&event.event_type)
+      .json_data(event.envelope())
+  });
+  let keep_alive = KeepAlive::new().interval(Duration::from_secs(state.config.events.keep_alive));
+  Ok(Sse::new(events).keep_alive(keep_alive))
+}
+
+pub async fn event_socket_handler(
+  Extension(claims): Extension<User>,
+  Extension(subject): Extension<TokenSubject>,
+  State(state): State<AppState>,
+  Query(params): Query<StreamParams>,
+  headers: HeaderMap,
+  upgrade: WebSocketUpgrade,
+) -> Result<impl IntoResponse, AppError> {
+  let last_event_id = last_event_id(&headers, &params)?;
+  info!(
+    "Events Handler::socket: user: {:?}, last event: {:?}",
+    claims.user_info.id, last_event_id
+  );
+  let events = state
+    .subscribe_events(
+      &claims,
+      current_tenant()?,
+      last_event_id,
+      subject.expires_at,
+    )
+    .await?;
+  Ok(upgrade.on_upgrade(move |socket| forward_events(socket, events)))
+}
+
+/// Send each event as a JSON text message until the stream ends or the client leaves.
+async fn forward_events(mut socket: WebSocket, mut events: BoxStream<'static, OutboxEvent>) {
+  loop {
+    tokio::select! {
+      event = events.next() => {
+        let Some(event) = event else {
+          break;
+        };
+        let message = Message::Text(event.envelope().to_string().into());
+        if socket.send(message).await.is_err() {
+          return;
+        }
+      }
+      message = socket.recv() => match message {
+        Some(Ok(Message::Close(_)) | Err(_)) | None => return,
+        // nothing is expected from subscribers
+        Some(Ok(_)) => (),
+      },
+    }
+  }
+  let _ = socket.send(Message::Close(None)).await;
+}
@@ -0,0 +1,19 @@
+pub mod dto;
+pub mod handlers;
+pub mod services;
+pub mod tests;
+
+pub use dto::StreamParams;
+pub use handlers::{LAST_EVENT_ID_HEADER, event_socket_handler, event_stream_handler};
+pub use services::EventHub;
+
+use crate::AppState;
+use axum::Router;
+use axum::routing::get;
+
+pub fn events_router(state: AppState) -> Router {
+  Router::new()
+    .route("/stream", get(event_stream_handler))
+    .route("/ws", get(event_socket_handler))
+    .with_state(state)
+}
@@ -0,0 +1,218 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures_util::stream::{self, BoxStream, StreamExt};
+use sqlx::postgres::PgListener;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{info, warn};
+
+use crate::common::with_tenant;
+use crate::modules::auth::inactive_reason;
+use crate::modules::authz::{Action, Resource};
+use crate::modules::outbox::{DomainEvent, OUTBOX_CHANNEL, OutboxEvent};
+use crate::modules::users::User;
+use crate::{AppError, AppState};
+
+/// Fans the events written by any instance out to the subscribers of this one.
+#[derive(Debug)]
+pub struct EventHub {
+  sender: broadcast::Sender<OutboxEvent>,
+}
+
+impl EventHub {
+  /// A subscriber falling `capacity` events behind loses its stream.
+  pub fn new(capacity: usize) -> Self {
+    let (sender, _) = broadcast::channel(capacity);
+    Self { sender }
+  }
+
+  pub fn subscribe(&self) -> broadcast::Receiver<OutboxEvent> {
+    self.sender.subscribe()
+  }
+
+  /// Hand `event` to the current subscribers, if any.
+  pub fn publish(&self, event: OutboxEvent) {
+    let _ = self.sender.send(event);
+  }
+}
+
+/// What a subscriber must be allowed to see events of `event_type`: security events
+/// are for readers of the audit log, lifecycle events for those who can list users.
+fn stream_permission(event_type: &str) -> (Action, Resource) {
+  match event_type {
+    "roles_changed" | "permissions_changed" | "suspicious_signin" => {
+      (Action::ReadAudit, Resource::audit())
+    }
+    _ => (Action::ListUsers, Resource::users()),
+  }
+}
+
+/// Whether `event` may change what anyone is allowed to see. Grants also come from
+/// groups and organization memberships, so who the event is about doesn't tell whose.
+fn changes_access(event: &OutboxEvent) -> bool {
+  matches!(
+    event.event_type.as_str(),
+    "roles_changed"
+      | "permissions_changed"
+      | "user_suspended"
+      | "user_deleted"
+      | "user_purged"
+      | "member_removed"
+  )
+}
+
+impl AppState {
+  /// Listen to `OUTBOX_CHANNEL` and publish every event written, by any instance, to the
+  /// event hub. Meant to be spawned at startup, reconnects when the connection is lost.
+  pub async fn run_event_listener(self) {
+    loop {
+      if let Err(e) = self.listen_events().await {
+        warn!(error = ?e, "listen for domain events failed");
+      }
+      tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+  }
+
+  async fn listen_events(&self) -> Result<(), AppError> {
+    let mut listener = PgListener::connect_with(&self.pool)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    listener
+      .listen(OUTBOX_CHANNEL)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    info!("listening for domain events");
+    loop {
+      let notification = listener
+        .recv()
+        .await
+        .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+      let Ok(id) = notification.payload().parse::<i64>() else {
+        continue;
+      };
+      let event: Option<OutboxEvent> = sqlx::query_as("SELECT * FROM outbox_events WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+      if let Some(event) = event {
+        self.event_hub.publish(event);
+      }
+    }
+  }
+
+  /// The events of `organization_id` `subscriber` may see: those after `last_event_id` first,
+  /// then live ones as any instance writes them.
+  ///
+  /// The stream ends after a replay of `events.replay_limit` events, or once the subscriber
+  /// falls `events.buffer` events behind; reconnecting with the last id received resumes it.
+  /// The token it was opened with is only checked here, so it also ends at `expires_at`,
+  /// when that token expires. Events changing anyone's access have the subscriber authorized
+  /// anew, the stream ends once they may see no event any more.
+  pub async fn subscribe_events(
+    &self,
+    subscriber: &User,
+    organization_id: i32,
+    last_event_id: Option<i64>,
+    expires_at: DateTime<Utc>,
+  ) -> Result<BoxStream<'static, OutboxEvent>, AppError> {
+    let expiry = tokio::time::sleep((expires_at - Utc::now()).to_std().unwrap_or_default());
+    let event_types = self.visible_event_types(subscriber);
+    if event_types.is_empty() {
+      return Err(AppError::Forbidden(
+        "not allowed to see any event".to_string(),
+      ));
+    }
+
+    // subscribed before replaying, so nothing written in between is missed
+    let receiver = self.event_hub.subscribe();
+    let replayed: Vec<OutboxEvent> = match last_event_id {
+      Some(last_event_id) => sqlx::query_as(
+        r#"
+        SELECT * FROM outbox_events
+        WHERE organization_id = $1 AND id > $2 AND event_type = ANY($3)
+        ORDER BY id
+        LIMIT $4
+        "#,
+      )
+      .bind(organization_id)
+      .bind(last_event_id)
+      .bind(&event_types)
+      .bind(self.config.events.replay_limit)
+      .fetch_all(&self.pool)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?,
+      None => vec![],
+    };
+    if replayed.len() as i64 >= self.config.events.replay_limit {
+      return Ok(stream::iter(replayed).take_until(expiry).boxed());
+    }
+
+    let replayed_ids: HashSet<i64> = replayed.iter().map(|event| event.id).collect();
+    let after = last_event_id.unwrap_or(0);
+    let subscriber_id = subscriber.user_info.id;
+    let state = self.clone();
+    let live = stream::unfold(Some((receiver, event_types)), move |subscription| {
+      let state = state.clone();
+      async move {
+        let (mut receiver, mut event_types) = subscription?;
+        let event = match receiver.recv().await {
+          Ok(event) => event,
+          // lagged too far behind to catch up from memory, or shutting down
+          Err(RecvError::Lagged(_) | RecvError::Closed) => return None,
+        };
+        if changes_access(&event) {
+          event_types = state
+            .reauthorize_subscriber(subscriber_id, organization_id)
+            .await;
+          if event_types.is_empty() {
+            return None;
+          }
+        }
+        let visible = event_types.contains(&event.event_type);
+        Some((visible.then_some(event), Some((receiver, event_types))))
+      }
+    })
+    .filter_map(move |event| {
+      let event = event.filter(|event| {
+        event.organization_id == organization_id
+          && event.id > after
+          && !replayed_ids.contains(&event.id)
+      });
+      async move { event }
+    });
+    Ok(
+      stream::iter(replayed)
+        .chain(live)
+        .take_until(expiry)
+        .boxed(),
+    )
+  }
+  /// The event types `subscriber` may see, see `stream_permission`.
+  fn visible_event_types(&self, subscriber: &User) -> Vec<String> {
+    DomainEvent::TYPES
+      .iter()
+      .filter(|event_type| {
+        let (action, resource) = stream_permission(event_type);
+        self.check(subscriber, action, &resource).allowed
+      })
+      .map(|event_type| event_type.to_string())
+      .collect()
+  }
+
+  /// `visible_event_types` for the subscriber `subscriber_id` as they are now in
+  /// `organization_id`: none once they left it, are inactive, or can't be loaded.
+  async fn reauthorize_subscriber(&self, subscriber_id: i32, organization_id: i32) -> Vec<String> {
+    match with_tenant(organization_id, self.get_user_by_id(subscriber_id)).await {
+      Ok(subscriber) if inactive_reason(subscriber.user_info.status).is_none() => {
+        self.visible_event_types(&subscriber)
+      }
+      Ok(_) => vec![],
+      Err(e) => {
+        warn!(error = ?e, subscriber_id, "reload event stream subscriber failed");
+        vec![]
+      }
+    }
+  }
+}
@@ -0,0 +1,240 @@
+#[cfg(test)]
+mod util_tests {
+  use crate::common::{DEFAULT_ORGANIZATION_ID, with_tenant};
+  use crate::modules::groups::{CreateGroup, UpdateGroup};
+  use crate::modules::outbox::OutboxEvent;
+  use crate::modules::users::{CreateUser, RoleIn};
+  use crate::{AppError, AppState};
+  use anyhow::Result;
+  use chrono::{DateTime, Utc};
+  use futures_util::stream::{BoxStream, StreamExt};
+  use serial_test::serial;
+  use tokio::time::{Duration, timeout};
+
+  fn in_an_hour() -> DateTime<Utc> {
+    Utc::now() + chrono::Duration::hours(1)
+  }
+
+  async fn next_event(events: &mut BoxStream<'static, OutboxEvent>) -> Result<OutboxEvent> {
+    let event = timeout(Duration::from_secs(5), events.next()).await?;
+    Ok(event.expect("stream ended"))
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn event_stream_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      tokio::spawn(state.clone().run_event_listener());
+      tokio::time::sleep(Duration::from_millis(200)).await;
+
+      // 1, superman, sees every event
+      let superman = state.get_user_by_id(1).await?;
+      let mut events = state
+        .subscribe_events(&superman, 1, None, in_an_hour())
+        .await?;
+
+      let newton = state
+        .create_user(CreateUser::new("newton", "apple123"))
+        .await?;
+      let created = next_event(&mut events).await?;
+      assert_eq!(created.event_type, "user_created");
+      assert_eq!(created.aggregate_id, newton.user_info.id);
+
+      // 3, bob, becomes a moderator: lifecycle events only
+      let roles = vec![RoleIn {
+        id: 2,
+        name: "Moderator".to_string(),
+        expires_at: None,
+      }];
+      state.set_roles(roles.clone(), 3, 1).await?;
+      let roles_changed = next_event(&mut events).await?;
+      assert_eq!(roles_changed.event_type, "roles_changed");
+
+      let bob = state.get_user_by_id(3).await?;
+      let mut bob_events = state
+        .subscribe_events(&bob, 1, Some(0), in_an_hour())
+        .await?;
+      let replayed = next_event(&mut bob_events).await?;
+      assert_eq!(replayed.id, created.id);
+
+      state.suspend_user(newton.user_info.id).await?;
+      let suspended = next_event(&mut bob_events).await?;
+      assert_eq!(suspended.event_type, "user_suspended");
+      assert_eq!(next_event(&mut events).await?.id, suspended.id);
+
+      // resumed after the last event received
+      let mut resumed = state
+        .subscribe_events(&superman, 1, Some(created.id), in_an_hour())
+        .await?;
+      assert_eq!(next_event(&mut resumed).await?.id, roles_changed.id);
+      assert_eq!(next_event(&mut resumed).await?.id, suspended.id);
+
+      // other organizations' events are left out
+      let mut other = state
+        .subscribe_events(&superman, 2, Some(0), in_an_hour())
+        .await?;
+      assert!(
+        timeout(Duration::from_millis(300), other.next())
+          .await
+          .is_err()
+      );
+
+      // closed once bob's own access changes, though roles_changed isn't visible to bob
+      state.set_roles(vec![], 3, 1).await?;
+      assert!(
+        timeout(Duration::from_secs(5), bob_events.next())
+          .await?
+          .is_none()
+      );
+
+      // and when the token it was opened with expires
+      let expires_at = Utc::now() + chrono::Duration::milliseconds(300);
+      let mut expiring = state
+        .subscribe_events(&superman, 1, None, expires_at)
+        .await?;
+      assert!(
+        timeout(Duration::from_secs(5), expiring.next())
+          .await?
+          .is_none()
+      );
+
+      // 4, charlie, is a moderator through a group, which keeps the stream open...
+      let group = state
+        .create_group(CreateGroup {
+          name: "moderators".to_string(),
+          description: None,
+        })
+        .await?;
+      let moderator = UpdateGroup {
+        role_ids: Some(vec![2]),
+        ..Default::default()
+      };
+      state.update_group(group.id, moderator, 1).await?;
+      state.add_group_member(group.id, 4).await?;
+      let charlie = state.get_user_by_id(4).await?;
+      let mut charlie_events = state
+        .subscribe_events(&charlie, 1, None, in_an_hour())
+        .await?;
+      state.set_roles(roles, newton.user_info.id, 1).await?;
+      state.restore_user(newton.user_info.id).await?;
+      assert_eq!(
+        next_event(&mut charlie_events).await?.event_type,
+        "user_restored"
+      );
+
+      // ...until the group loses the role
+      let no_roles = UpdateGroup {
+        role_ids: Some(vec![]),
+        ..Default::default()
+      };
+      state.update_group(group.id, no_roles, 1).await?;
+      assert!(
+        timeout(Duration::from_secs(5), charlie_events.next())
+          .await?
+          .is_none()
+      );
+
+      // 2, alice, may see nothing
+      let alice = state.get_user_by_id(2).await?;
+      assert!(matches!(
+        state.subscribe_events(&alice, 1, None, in_an_hour()).await,
+        Err(AppError::Forbidden(_))
+      ));
+      anyhow::Ok(())
+    })
+    .await
+  }
+}
+
+#[cfg(test)]
+mod integration_tests {
+  use crate::AppState;
+  use crate::common::{DEFAULT_ORGANIZATION_ID, with_tenant};
+  use crate::modules::users::CreateUser;
+  use crate::test_util::{get_token, spawn_app};
+  use anyhow::Result;
+  use axum::http::StatusCode;
+  use futures_util::StreamExt;
+  use reqwest::Client;
+  use serial_test::serial;
+  use tokio::time::{Duration, timeout};
+  use tokio_tungstenite::tungstenite::Message;
+  use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+  #[tokio::test]
+  #[serial]
+  async fn event_stream_handler_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      tokio::spawn(state.clone().run_event_listener());
+      state
+        .create_user(CreateUser::new("newton", "apple123"))
+        .await?;
+      let (addr, tx) = spawn_app(state.clone()).await?;
+
+      let client = Client::builder().no_proxy().build().unwrap();
+      let superman = get_token(&client, &addr.to_string(), "superman", "supermannofly").await?;
+      let alice = get_token(&client, &addr.to_string(), "alice", "123456").await?;
+
+      let response = client
+        .get(format!("http://{}/events/stream", addr))
+        .header("Authorization", format!("Bearer {}", alice))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+      let response = client
+        .get(format!("http://{}/events/stream", addr))
+        .header("Authorization", format!("Bearer {}", superman))
+        .header("Last-Event-ID", "latest")
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+      // replayed from the start
+      let mut response = client
+        .get(format!("http://{}/events/stream", addr))
+        .header("Authorization", format!("Bearer {}", superman))
+        .header("Last-Event-ID", "0")
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+      assert_eq!(
+        response.headers()["content-type"].to_str()?,
+        "text/event-stream"
+      );
+      let mut body = String::new();
+      while !body.contains("\n\n") {
+        let chunk = timeout(Duration::from_secs(5), response.chunk()).await??;
+        body.push_str(&String::from_utf8_lossy(&chunk.unwrap()));
+      }
+      assert!(body.contains("event: user_created\n"));
+      assert!(body.contains("\"username\":\"newton\""));
+      drop(response);
+
+      // live over a websocket
+      let mut request = format!("ws://{}/events/ws", addr).into_client_request()?;
+      request
+        .headers_mut()
+        .insert("Authorization", format!("Bearer {}", superman).parse()?);
+      let (mut socket, _) = tokio_tungstenite::connect_async(request).await?;
+      tokio::time::sleep(Duration::from_millis(200)).await;
+      state.suspend_user(3).await?;
+      let message = timeout(Duration::from_secs(5), socket.next())
+        .await?
+        .unwrap()?;
+      let Message::Text(text) = message else {
+        panic!("expected a text message, got {:?}", message);
+      };
+      let event: serde_json::Value = serde_json::from_str(&text)?;
+      assert_eq!(event["type"], "user_suspended");
+      assert_eq!(event["data"]["user_id"], 3);
+      socket.close(None).await?;
+
+      tx.send(()).unwrap();
+      anyhow::Ok(())
+    })
+    .await
+  }
+}
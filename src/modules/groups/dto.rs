@@ -0,0 +1,41 @@
+use super::{Group, GroupMember};
+use crate::modules::users::{Permission, Role};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+/// group create input dto
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct CreateGroup {
+  #[validate(length(
+    min = 3,
+    max = 50,
+    message = "group name length must be between 3 and 50 characters"
+  ))]
+  pub name: String,
+  #[validate(length(max = 500))]
+  pub description: Option<String>,
+}
+
+/// group update input dto, `role_ids` and `permission_ids` replace the group's grants
+#[derive(Debug, Default, Deserialize, Serialize, Validate)]
+pub struct UpdateGroup {
+  #[validate(length(
+    min = 3,
+    max = 50,
+    message = "group name length must be between 3 and 50 characters"
+  ))]
+  pub name: Option<String>,
+  #[validate(length(max = 500))]
+  pub description: Option<String>,
+  pub role_ids: Option<Vec<i32>>,
+  pub permission_ids: Option<Vec<i32>>,
+}
+
+/// group with its grants and members output dto
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GroupDetail {
+  pub group: Group,
+  pub roles: Vec<Role>,
+  pub permissions: Vec<Permission>,
+  pub members: Vec<GroupMember>,
+}
@@ -0,0 +1,28 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+/// groups table
+#[derive(Clone, Debug, Deserialize, FromRow, Serialize)]
+pub struct Group {
+  pub id: i32,
+  pub name: String,
+  pub description: String,
+  pub created_at: DateTime<Utc>,
+  pub updated_at: DateTime<Utc>,
+}
+
+/// group reference listed on a user
+#[derive(Clone, Debug, Deserialize, FromRow, Serialize)]
+pub struct GroupSummary {
+  pub id: i32,
+  pub name: String,
+}
+
+/// group_members table joined with users
+#[derive(Clone, Debug, Deserialize, FromRow, Serialize)]
+pub struct GroupMember {
+  pub user_id: i32,
+  pub username: String,
+  pub created_at: DateTime<Utc>,
+}
@@ -0,0 +1,104 @@
+use super::{CreateGroup, UpdateGroup};
+use crate::modules::authz::{Action, Resource};
+use crate::modules::users::User;
+use crate::{AppError, AppState};
+use axum::{
+  Extension, Json,
+  extract::{Path, State},
+  http::StatusCode,
+  response::IntoResponse,
+};
+use tracing::info;
+use validator::Validate;
+
+pub async fn get_groups_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+  info!("Groups Handler::get groups");
+  state.authorize(&claims, Action::ReadGroups, &Resource::groups())?;
+  let groups = state.get_groups().await?;
+  Ok((StatusCode::OK, Json(groups)))
+}
+
+pub async fn get_group_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Path(group_id): Path<i32>,
+) -> Result<impl IntoResponse, AppError> {
+  info!("Groups Handler::get group: {:?}", group_id);
+  state.authorize(&claims, Action::ReadGroups, &Resource::groups())?;
+  let group = state.get_group(group_id).await?;
+  Ok((StatusCode::OK, Json(group)))
+}
+
+pub async fn create_group_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Json(input): Json<CreateGroup>,
+) -> Result<impl IntoResponse, AppError> {
+  input.validate()?;
+  info!("Groups Handler::create group: input: {:?}", input);
+  state.authorize(&claims, Action::ManageGroups, &Resource::groups())?;
+  let group = state.create_group(input).await?;
+  Ok((StatusCode::CREATED, Json(group)))
+}
+
+pub async fn update_group_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Path(group_id): Path<i32>,
+  Json(input): Json<UpdateGroup>,
+) -> Result<impl IntoResponse, AppError> {
+  input.validate()?;
+  info!(
+    "Groups Handler::update group: {:?}, input: {:?}",
+    group_id, input
+  );
+  state.authorize(&claims, Action::ManageGroups, &Resource::groups())?;
+  let group = state
+    .update_group(group_id, input, claims.user_info.id)
+    .await?;
+  Ok((StatusCode::OK, Json(group)))
+}
+
+pub async fn delete_group_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Path(group_id): Path<i32>,
+) -> Result<impl IntoResponse, AppError> {
+  info!("Groups Handler::delete group: {:?}", group_id);
+  state.authorize(&claims, Action::ManageGroups, &Resource::groups())?;
+  state.delete_group(group_id, claims.user_info.id).await?;
+  Ok(StatusCode::OK)
+}
+
+pub async fn add_group_member_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Path((group_id, user_id)): Path<(i32, i32)>,
+) -> Result<impl IntoResponse, AppError> {
+  info!(
+    "Groups Handler::add member: {:?} to {:?}",
+    user_id, group_id
+  );
+  state.authorize(&claims, Action::ManageGroups, &Resource::groups())?;
+  let group = state.add_group_member(group_id, user_id).await?;
+  Ok((StatusCode::OK, Json(group)))
+}
+
+pub async fn remove_group_member_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Path((group_id, user_id)): Path<(i32, i32)>,
+) -> Result<impl IntoResponse, AppError> {
+  info!(
+    "Groups Handler::remove member: {:?} from {:?}",
+    user_id, group_id
+  );
+  state.authorize(&claims, Action::ManageGroups, &Resource::groups())?;
+  let group = state
+    .remove_group_member(group_id, user_id, claims.user_info.id)
+    .await?;
+  Ok((StatusCode::OK, Json(group)))
+}
@@ -0,0 +1,32 @@
+pub mod dto;
+pub mod entity;
+pub mod handlers;
+pub mod services;
+pub mod tests;
+
+pub use dto::{CreateGroup, GroupDetail, UpdateGroup};
+pub use entity::{Group, GroupMember, GroupSummary};
+pub use handlers::{
+  add_group_member_handler, create_group_handler, delete_group_handler, get_group_handler,
+  get_groups_handler, remove_group_member_handler, update_group_handler,
+};
+
+use crate::AppState;
+use axum::Router;
+use axum::routing::{get, put};
+
+pub fn groups_router(state: AppState) -> Router {
+  Router::new()
+    .route("/", get(get_groups_handler).post(create_group_handler))
+    .route(
+      "/{id}",
+      get(get_group_handler)
+        .patch(update_group_handler)
+        .delete(delete_group_handler),
+    )
+    .route(
+      "/{id}/members/{user_id}",
+      put(add_group_member_handler).delete(remove_group_member_handler),
+    )
+    .with_state(state)
+}
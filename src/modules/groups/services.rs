@@ -0,0 +1,454 @@
+use super::{CreateGroup, Group, GroupDetail, GroupMember, UpdateGroup};
+use crate::common::current_tenant;
+use crate::modules::audit::{AuditAction, NewAuditEvent, record_audit};
+use crate::modules::outbox::{DomainEvent, publish_event};
+use crate::modules::users::{
+  AdminGrants, Permission, Role, lock_admin_grants, snapshot_grants, user_snapshot,
+};
+use crate::{AppError, AppState};
+
+use chrono::Utc;
+use serde_json::Value;
+use sqlx::{Postgres, Transaction};
+
+impl AppState {
+  pub async fn create_group(&self, input: CreateGroup) -> Result<Group, AppError> {
+    if self.is_group_exists_by_name(&input.name).await? {
+      return Err(AppError::Conflict(format!(
+        "Group: {} already exists",
+        input.name
+      )));
+    }
+
+    let group = sqlx::query_as(
+      r#"
+      INSERT INTO groups (name, description, organization_id, created_at, updated_at)
+      VALUES ($1, $2, $4, $3, $3)
+      RETURNING *
+      "#,
+    )
+    .bind(&input.name)
+    .bind(input.description.unwrap_or_default())
+    .bind(Utc::now())
+    .bind(current_tenant()?)
+    .fetch_one(&self.pool)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    Ok(group)
+  }
+
+  pub async fn get_groups(&self) -> Result<Vec<Group>, AppError> {
+    let groups = sqlx::query_as("SELECT * FROM groups WHERE organization_id = $1 ORDER BY id")
+      .bind(current_tenant()?)
+      .fetch_all(&self.pool)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    Ok(groups)
+  }
+
+  pub async fn get_group(&self, group_id: i32) -> Result<GroupDetail, AppError> {
+    let group: Group =
+      sqlx::query_as("SELECT * FROM groups WHERE id = $1 AND organization_id = $2")
+        .bind(group_id)
+        .bind(current_tenant()?)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|err| AppError::DatabaseError(err.to_string()))?
+        .ok_or(AppError::NotFound(format!("Group: {} not found", group_id)))?;
+
+    let roles: Vec<Role> = sqlx::query_as(
+      r#"
+      SELECT r.id, r.name, r.created_at, r.updated_at
+      FROM roles r
+      INNER JOIN group_roles gr ON r.id = gr.role_id
+      WHERE gr.group_id = $1
+      ORDER BY r.id
+      "#,
+    )
+    .bind(group_id)
+    .fetch_all(&self.pool)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    let permissions: Vec<Permission> = sqlx::query_as(
+      r#"
+      SELECT p.id, p.name, p.created_at, p.updated_at
+      FROM permissions p
+      INNER JOIN group_permissions gp ON p.id = gp.permission_id
+      WHERE gp.group_id = $1
+      ORDER BY p.id
+      "#,
+    )
+    .bind(group_id)
+    .fetch_all(&self.pool)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    let members: Vec<GroupMember> = sqlx::query_as(
+      r#"
+      SELECT u.id AS user_id, u.username, gm.created_at
+      FROM group_members gm
+      INNER JOIN users u ON u.id = gm.user_id
+      WHERE gm.group_id = $1
+      ORDER BY u.id
+      "#,
+    )
+    .bind(group_id)
+    .fetch_all(&self.pool)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    Ok(GroupDetail {
+      group,
+      roles,
+      permissions,
+      members,
+    })
+  }
+
+  /// Update a group on behalf of `actor_id`. Its members' changed grants are audited and
+  /// published, and the group can't take the Admin role from the last admin or from `actor_id`.
+  pub async fn update_group(
+    &self,
+    group_id: i32,
+    input: UpdateGroup,
+    actor_id: i32,
+  ) -> Result<GroupDetail, AppError> {
+    let current = self.get_group(group_id).await?;
+    if let Some(name) = &input.name
+      && *name != current.group.name
+      && self.is_group_exists_by_name(name).await?
+    {
+      return Err(AppError::Conflict(format!(
+        "Group: {} already exists",
+        name
+      )));
+    }
+
+    let mut transaction = self
+      .pool
+      .begin()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    let admins = lock_admin_grants(&mut transaction).await?;
+    let members = member_snapshots(&mut transaction, group_id).await?;
+
+    sqlx::query(
+      r#"
+      UPDATE groups
+      SET name = $1, description = $2, updated_at = $3
+      WHERE id = $4
+      "#,
+    )
+    .bind(input.name.unwrap_or(current.group.name))
+    .bind(input.description.unwrap_or(current.group.description))
+    .bind(Utc::now())
+    .bind(group_id)
+    .execute(&mut *transaction)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    if let Some(role_ids) = input.role_ids {
+      ensure_all_exist(&mut transaction, "roles", &role_ids).await?;
+      let current_role_ids: Vec<i32> = current.roles.iter().map(|role| role.id).collect();
+      let added_role_ids: Vec<i32> = role_ids
+        .iter()
+        .filter(|id| !current_role_ids.contains(id))
+        .cloned()
+        .collect();
+      self
+        .ensure_no_privileged_roles(&mut transaction, &added_role_ids)
+        .await?;
+      sqlx::query("DELETE FROM group_roles WHERE group_id = $1")
+        .bind(group_id)
+        .execute(&mut *transaction)
+        .await
+        .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+      sqlx::query(
+        r#"
+        INSERT INTO group_roles (group_id, role_id)
+        SELECT $1, UNNEST($2::INTEGER[])
+        ON CONFLICT DO NOTHING
+        "#,
+      )
+      .bind(group_id)
+      .bind(&role_ids)
+      .execute(&mut *transaction)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    }
+
+    if let Some(permission_ids) = input.permission_ids {
+      ensure_all_exist(&mut transaction, "permissions", &permission_ids).await?;
+      sqlx::query("DELETE FROM group_permissions WHERE group_id = $1")
+        .bind(group_id)
+        .execute(&mut *transaction)
+        .await
+        .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+      sqlx::query(
+        r#"
+        INSERT INTO group_permissions (group_id, permission_id)
+        SELECT $1, UNNEST($2::INTEGER[])
+        ON CONFLICT DO NOTHING
+        "#,
+      )
+      .bind(group_id)
+      .bind(&permission_ids)
+      .execute(&mut *transaction)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    }
+
+    ensure_admins_remain(&mut transaction, &admins, actor_id).await?;
+    record_member_changes(&mut transaction, members).await?;
+    transaction
+      .commit()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    self.get_group(group_id).await
+  }
+
+  pub async fn delete_group(&self, group_id: i32, actor_id: i32) -> Result<(), AppError> {
+    let mut transaction = self
+      .pool
+      .begin()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    let admins = lock_admin_grants(&mut transaction).await?;
+    let members = member_snapshots(&mut transaction, group_id).await?;
+
+    let result = sqlx::query("DELETE FROM groups WHERE id = $1 AND organization_id = $2")
+      .bind(group_id)
+      .bind(current_tenant()?)
+      .execute(&mut *transaction)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    if result.rows_affected() == 0 {
+      return Err(AppError::NotFound(format!("Group: {} not found", group_id)));
+    }
+
+    ensure_admins_remain(&mut transaction, &admins, actor_id).await?;
+    record_member_changes(&mut transaction, members).await?;
+    transaction
+      .commit()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))
+  }
+
+  pub async fn add_group_member(
+    &self,
+    group_id: i32,
+    user_id: i32,
+  ) -> Result<GroupDetail, AppError> {
+    let group = self.get_group(group_id).await?;
+    if !self.is_user_exists_by_id(user_id).await? {
+      return Err(AppError::NotFound(format!("User: {} not found", user_id)));
+    }
+
+    let mut transaction = self
+      .pool
+      .begin()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    let role_ids: Vec<i32> = group.roles.iter().map(|role| role.id).collect();
+    self
+      .ensure_no_privileged_roles(&mut transaction, &role_ids)
+      .await?;
+    let before = user_snapshot(&mut transaction, user_id).await?;
+
+    sqlx::query(
+      r#"
+      INSERT INTO group_members (group_id, user_id, created_at)
+      VALUES ($1, $2, $3)
+      ON CONFLICT DO NOTHING
+      "#,
+    )
+    .bind(group_id)
+    .bind(user_id)
+    .bind(Utc::now())
+    .execute(&mut *transaction)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    record_member_changes(&mut transaction, vec![(user_id, before)]).await?;
+    transaction
+      .commit()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    self.get_group(group_id).await
+  }
+
+  /// Remove `user_id` from the group on behalf of `actor_id`, like `update_group` the last
+  /// admin and `actor_id` keep their Admin role.
+  pub async fn remove_group_member(
+    &self,
+    group_id: i32,
+    user_id: i32,
+    actor_id: i32,
+  ) -> Result<GroupDetail, AppError> {
+    self.get_group(group_id).await?;
+    let mut transaction = self
+      .pool
+      .begin()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    let admins = lock_admin_grants(&mut transaction).await?;
+    let before = user_snapshot(&mut transaction, user_id).await?;
+
+    let result = sqlx::query("DELETE FROM group_members WHERE group_id = $1 AND user_id = $2")
+      .bind(group_id)
+      .bind(user_id)
+      .execute(&mut *transaction)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    if result.rows_affected() == 0 {
+      return Err(AppError::NotFound(format!(
+        "User: {} is not a member of group: {}",
+        user_id, group_id
+      )));
+    }
+
+    ensure_admins_remain(&mut transaction, &admins, actor_id).await?;
+    record_member_changes(&mut transaction, vec![(user_id, before)]).await?;
+    transaction
+      .commit()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    self.get_group(group_id).await
+  }
+
+  pub async fn is_group_exists_by_name(&self, name: &str) -> Result<bool, AppError> {
+    let result = sqlx::query_scalar(
+      "SELECT EXISTS (SELECT 1 FROM groups WHERE name = $1 AND organization_id = $2)",
+    )
+    .bind(name)
+    .bind(current_tenant()?)
+    .fetch_one(&self.pool)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    Ok(result)
+  }
+
+  /// In four-eyes mode privileged roles must go through `/role-requests`, so
+  /// groups and invitations can't be used to hand them out.
+  pub(crate) async fn ensure_no_privileged_roles(
+    &self,
+    transaction: &mut Transaction<'_, Postgres>,
+    role_ids: &[i32],
+  ) -> Result<(), AppError> {
+    if !self.config.approvals.enabled || role_ids.is_empty() {
+      return Ok(());
+    }
+    let names: Vec<String> = sqlx::query_scalar("SELECT name FROM roles WHERE id = ANY($1)")
+      .bind(role_ids)
+      .fetch_all(&mut **transaction)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    if let Some(name) = names
+      .iter()
+      .find(|name| self.config.approvals.privileged_roles.contains(name))
+    {
+      return Err(AppError::Forbidden(format!(
+        "role {} requires approval and can't be granted through a group or invitation",
+        name
+      )));
+    }
+    Ok(())
+  }
+}
+
+/// `table` is either `roles` or `permissions`
+async fn ensure_all_exist(
+  transaction: &mut Transaction<'_, Postgres>,
+  table: &str,
+  ids: &[i32],
+) -> Result<(), AppError> {
+  let found: Vec<i32> = sqlx::query_scalar(&format!("SELECT id FROM {} WHERE id = ANY($1)", table))
+    .bind(ids)
+    .fetch_all(&mut **transaction)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+  if let Some(id) = ids.iter().find(|id| !found.contains(id)) {
+    return Err(AppError::BadRequest(format!(
+      "{}: {} does not exist",
+      table, id
+    )));
+  }
+  Ok(())
+}
+
+/// `user_snapshot`s of the members of `group_id`, taken before changing the group.
+async fn member_snapshots(
+  transaction: &mut Transaction<'_, Postgres>,
+  group_id: i32,
+) -> Result<Vec<(i32, Option<Value>)>, AppError> {
+  let user_ids: Vec<i32> =
+    sqlx::query_scalar("SELECT user_id FROM group_members WHERE group_id = $1 ORDER BY user_id")
+      .bind(group_id)
+      .fetch_all(&mut **transaction)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+  let mut snapshots = Vec::with_capacity(user_ids.len());
+  for user_id in user_ids {
+    snapshots.push((user_id, user_snapshot(transaction, user_id).await?));
+  }
+  Ok(snapshots)
+}
+
+/// Compare the Admin grants after a group change with `before`: neither the last admin
+/// nor `actor_id` may lose the role through it.
+async fn ensure_admins_remain(
+  transaction: &mut Transaction<'_, Postgres>,
+  before: &AdminGrants,
+  actor_id: i32,
+) -> Result<(), AppError> {
+  let after = lock_admin_grants(transaction).await?;
+  if before.is_admin(actor_id) && !after.is_admin(actor_id) {
+    return Err(AppError::InvariantViolation(
+      "admins cannot remove their own Admin role".to_string(),
+    ));
+  }
+  if before.has_permanent_admin() && !after.has_permanent_admin() {
+    return Err(AppError::InvariantViolation(
+      "cannot remove the Admin role of the last admin".to_string(),
+    ));
+  }
+  Ok(())
+}
+
+/// Publish and audit what a group change did to the members it was made with, from their
+/// snapshots before it. The audit events are appended last.
+async fn record_member_changes(
+  transaction: &mut Transaction<'_, Postgres>,
+  members: Vec<(i32, Option<Value>)>,
+) -> Result<(), AppError> {
+  let mut audits = vec![];
+  for (user_id, before) in members {
+    let after = user_snapshot(transaction, user_id).await?;
+    if after == before {
+      continue;
+    }
+    let roles = snapshot_grants(&after, "roles");
+    if roles != snapshot_grants(&before, "roles") {
+      publish_event(transaction, DomainEvent::RolesChanged { user_id, roles }).await?;
+    }
+    let permissions = snapshot_grants(&after, "permissions");
+    if permissions != snapshot_grants(&before, "permissions") {
+      publish_event(
+        transaction,
+        DomainEvent::PermissionsChanged {
+          user_id,
+          permissions,
+        },
+      )
+      .await?;
+    }
+    audits.push(
+      NewAuditEvent::user(AuditAction::UserUpdateGroups, Some(user_id)).changes(before, after),
+    );
+  }
+  for audit in audits {
+    record_audit(transaction, audit).await?;
+  }
+  Ok(())
+}
@@ -0,0 +1,266 @@
+#[cfg(test)]
+mod util_tests {
+  use crate::common::{DEFAULT_ORGANIZATION_ID, with_tenant};
+  use crate::modules::groups::{CreateGroup, UpdateGroup};
+  use crate::modules::users::{PermissionName, RoleName, VecExtensions};
+  use crate::{AppError, AppState};
+  use anyhow::Result;
+  use serial_test::serial;
+
+  #[tokio::test]
+  #[serial]
+  async fn group_grants_are_effective_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let group = state
+        .create_group(CreateGroup {
+          name: "moderators".to_string(),
+          description: None,
+        })
+        .await?;
+      let duplicate = state
+        .create_group(CreateGroup {
+          name: "moderators".to_string(),
+          description: None,
+        })
+        .await;
+      assert!(matches!(duplicate, Err(AppError::Conflict(_))));
+
+      let detail = state
+        .update_group(
+          group.id,
+          UpdateGroup {
+            name: None,
+            description: Some("shared moderator grants".to_string()),
+            role_ids: Some(vec![2]),
+            permission_ids: Some(vec![1]),
+          },
+          1,
+        )
+        .await?;
+      assert_eq!(detail.group.description, "shared moderator grants");
+      assert_eq!(detail.roles.len(), 1);
+
+      state.add_group_member(group.id, 2).await?;
+      let roles = state.get_user_roles(2).await?;
+      assert!(roles.contains_name(RoleName::Moderator));
+      let permissions = state.get_user_permissions(2).await?;
+      assert!(permissions.contains_name(PermissionName::Read));
+      let user = state.get_user_by_id(2).await?;
+      assert_eq!(user.groups.len(), 1);
+      assert_eq!(user.groups[0].name, "moderators");
+
+      // leaving the group drops the inherited grants
+      state.remove_group_member(group.id, 2, 1).await?;
+      assert!(state.get_user_roles(2).await?.is_empty());
+      assert!(state.get_user_permissions(2).await?.is_empty());
+
+      let invalid = state
+        .update_group(
+          group.id,
+          UpdateGroup {
+            name: None,
+            description: None,
+            role_ids: Some(vec![999]),
+            permission_ids: None,
+          },
+          1,
+        )
+        .await;
+      assert!(matches!(invalid, Err(AppError::BadRequest(_))));
+
+      state.delete_group(group.id, 1).await?;
+      assert!(matches!(
+        state.get_group(group.id).await,
+        Err(AppError::NotFound(_))
+      ));
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn group_admins_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let group = state
+        .create_group(CreateGroup {
+          name: "admins".to_string(),
+          description: None,
+        })
+        .await?;
+      let admin_role = UpdateGroup {
+        role_ids: Some(vec![3]),
+        ..Default::default()
+      };
+      state.update_group(group.id, admin_role, 1).await?;
+      state.add_group_member(group.id, 2).await?;
+      let event_type: String = sqlx::query_scalar(
+        "SELECT event_type FROM outbox_events WHERE aggregate_id = 2 ORDER BY id DESC LIMIT 1",
+      )
+      .fetch_one(&state.pool)
+      .await?;
+      assert_eq!(event_type, "roles_changed");
+      let action: String = sqlx::query_scalar(
+        "SELECT action FROM audit_events WHERE target_id = 2 ORDER BY id DESC LIMIT 1",
+      )
+      .fetch_one(&state.pool)
+      .await?;
+      assert_eq!(action, "user.update_groups");
+
+      // alice, Admin through the group, is enough of an admin for superman to step down
+      state.set_roles(vec![], 1, 2).await?;
+      let last_admin = |result: Result<_, AppError>| {
+        assert!(matches!(result, Err(AppError::InvariantViolation(_))));
+      };
+      last_admin(state.suspend_user(2).await.map(|_| ()));
+      last_admin(state.remove_group_member(group.id, 2, 1).await.map(|_| ()));
+      last_admin(state.remove_group_member(group.id, 2, 2).await.map(|_| ()));
+      let no_roles = UpdateGroup {
+        role_ids: Some(vec![]),
+        ..Default::default()
+      };
+      last_admin(state.update_group(group.id, no_roles, 1).await.map(|_| ()));
+      last_admin(state.delete_group(group.id, 1).await);
+      assert!(
+        state
+          .get_user_roles(2)
+          .await?
+          .contains_name(RoleName::Admin)
+      );
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn privileged_group_roles_need_approval_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let mut config = state.config.clone();
+      config.approvals.enabled = true;
+      let four_eyes = AppState::new(config, state.pool.clone());
+
+      let group = four_eyes
+        .create_group(CreateGroup {
+          name: "admins".to_string(),
+          description: None,
+        })
+        .await?;
+      let result = four_eyes
+        .update_group(
+          group.id,
+          UpdateGroup {
+            name: None,
+            description: None,
+            role_ids: Some(vec![3]),
+            permission_ids: None,
+          },
+          1,
+        )
+        .await;
+      assert!(matches!(result, Err(AppError::Forbidden(_))));
+
+      // a group set up before four-eyes mode can't be used to hand out Admin
+      state
+        .update_group(
+          group.id,
+          UpdateGroup {
+            name: None,
+            description: None,
+            role_ids: Some(vec![3]),
+            permission_ids: None,
+          },
+          1,
+        )
+        .await?;
+      let result = four_eyes.add_group_member(group.id, 2).await;
+      assert!(matches!(result, Err(AppError::Forbidden(_))));
+      anyhow::Ok(())
+    })
+    .await
+  }
+}
+
+#[cfg(test)]
+mod integration_tests {
+  use crate::AppState;
+  use crate::common::{DEFAULT_ORGANIZATION_ID, with_tenant};
+  use crate::test_util::{get_token, spawn_app};
+  use anyhow::Result;
+  use axum::http::StatusCode;
+  use reqwest::Client;
+  use serde_json::json;
+  use serial_test::serial;
+
+  #[tokio::test]
+  #[serial]
+  async fn group_handler_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let (addr, tx) = spawn_app(state).await?;
+
+      let client = Client::builder().no_proxy().build().unwrap();
+      let superman = get_token(&client, &addr.to_string(), "superman", "supermannofly").await?;
+      let alice = get_token(&client, &addr.to_string(), "alice", "123456").await?;
+
+      let response = client
+        .post(format!("http://{}/groups", addr))
+        .json(&json!({"name": "editors", "description": "can write"}))
+        .header("Authorization", format!("Bearer {}", alice))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+      let response = client
+        .post(format!("http://{}/groups", addr))
+        .json(&json!({"name": "editors", "description": "can write"}))
+        .header("Authorization", format!("Bearer {}", superman))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::CREATED);
+      let group: serde_json::Value = response.json().await?;
+      let group_id = group["id"].as_i64().unwrap();
+
+      let response = client
+        .patch(format!("http://{}/groups/{}", addr, group_id))
+        .json(&json!({"permission_ids": [2]}))
+        .header("Authorization", format!("Bearer {}", superman))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+
+      let response = client
+        .put(format!("http://{}/groups/{}/members/{}", addr, group_id, 2))
+        .header("Authorization", format!("Bearer {}", superman))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+      let detail: serde_json::Value = response.json().await?;
+      assert_eq!(detail["members"][0]["username"], "alice");
+      assert_eq!(detail["permissions"][0]["name"], "WRITE");
+
+      let response = client
+        .get(format!("http://{}/users/{}", addr, 2))
+        .header("Authorization", format!("Bearer {}", superman))
+        .send()
+        .await?;
+      let user: serde_json::Value = response.json().await?;
+      assert_eq!(user["groups"][0]["name"], "editors");
+      assert_eq!(user["permissions"][0]["name"], "WRITE");
+
+      let response = client
+        .delete(format!("http://{}/groups/{}", addr, group_id))
+        .header("Authorization", format!("Bearer {}", superman))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+
+      tx.send(()).unwrap();
+      anyhow::Ok(())
+    })
+    .await
+  }
+}
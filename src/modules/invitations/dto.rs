@@ -0,0 +1,29 @@
+use super::Invitation;
+use crate::modules::users::RoleIn;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+/// invitation create input dto
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct CreateInvitation {
+  /// where the invitation is meant to be sent, only this email address can accept it
+  #[validate(email)]
+  pub email: Option<String>,
+  /// roles the invited user signs up with
+  #[validate(nested, length(min = 1))]
+  pub roles: Vec<RoleIn>,
+}
+
+/// created invitation output dto, the token is only returned once
+#[derive(Debug, Deserialize, Serialize)]
+pub struct InvitationCreated {
+  pub invitation: Invitation,
+  pub token: String,
+}
+
+/// invitation accept input dto, for users who already have an account
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct AcceptInvitation {
+  #[validate(length(min = 1))]
+  pub token: String,
+}
@@ -0,0 +1,19 @@
+use crate::modules::users::RoleIn;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use sqlx::types::Json;
+
+/// invitations table
+#[derive(Clone, Debug, Deserialize, FromRow, Serialize)]
+pub struct Invitation {
+  pub id: i32,
+  pub organization_id: i32,
+  pub email: Option<String>,
+  pub roles: Json<Vec<RoleIn>>,
+  pub invited_by: Option<i32>,
+  pub accepted_by: Option<i32>,
+  pub accepted_at: Option<DateTime<Utc>>,
+  pub expires_at: DateTime<Utc>,
+  pub created_at: DateTime<Utc>,
+}
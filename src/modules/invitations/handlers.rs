@@ -0,0 +1,64 @@
+use super::{AcceptInvitation, CreateInvitation};
+use crate::modules::authz::{Action, Resource};
+use crate::modules::users::User;
+use crate::{AppError, AppState};
+use axum::{
+  Extension, Json,
+  extract::{Path, State},
+  http::StatusCode,
+  response::IntoResponse,
+};
+use tracing::info;
+use validator::Validate;
+
+pub async fn get_invitations_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+  info!("Invitations Handler::get invitations");
+  state.authorize(&claims, Action::InviteUsers, &Resource::invitations())?;
+  let invitations = state.get_invitations().await?;
+  Ok((StatusCode::OK, Json(invitations)))
+}
+
+pub async fn create_invitation_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Json(input): Json<CreateInvitation>,
+) -> Result<impl IntoResponse, AppError> {
+  input.validate()?;
+  info!("Invitations Handler::create invitation: input: {:?}", input);
+  state.authorize(&claims, Action::InviteUsers, &Resource::invitations())?;
+  let invitation = state.create_invitation(&claims, input).await?;
+  Ok((StatusCode::CREATED, Json(invitation)))
+}
+
+pub async fn delete_invitation_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Path(invitation_id): Path<i32>,
+) -> Result<impl IntoResponse, AppError> {
+  info!(
+    "Invitations Handler::delete invitation: {:?}",
+    invitation_id
+  );
+  state.authorize(&claims, Action::InviteUsers, &Resource::invitations())?;
+  state.delete_invitation(invitation_id).await?;
+  Ok(StatusCode::OK)
+}
+
+/// An existing user joins the inviting organization, no permission needed: the invitation is
+/// the organization's consent and accepting it the user's.
+pub async fn accept_invitation_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Json(input): Json<AcceptInvitation>,
+) -> Result<impl IntoResponse, AppError> {
+  input.validate()?;
+  info!(
+    "Invitations Handler::accept invitation: user: {:?}",
+    claims.user_info.id
+  );
+  let organization = state.join_organization(&claims, &input.token).await?;
+  Ok((StatusCode::OK, Json(organization)))
+}
@@ -0,0 +1,27 @@
+pub mod dto;
+pub mod entity;
+pub mod handlers;
+pub mod services;
+pub mod tests;
+
+pub use dto::{AcceptInvitation, CreateInvitation, InvitationCreated};
+pub use entity::Invitation;
+pub use handlers::{
+  accept_invitation_handler, create_invitation_handler, delete_invitation_handler,
+  get_invitations_handler,
+};
+
+use crate::AppState;
+use axum::Router;
+use axum::routing::{delete, get, post};
+
+pub fn invitations_router(state: AppState) -> Router {
+  Router::new()
+    .route(
+      "/",
+      get(get_invitations_handler).post(create_invitation_handler),
+    )
+    .route("/accept", post(accept_invitation_handler))
+    .route("/{id}", delete(delete_invitation_handler))
+    .with_state(state)
+}
@@ -0,0 +1,301 @@
+use super::{CreateInvitation, Invitation, InvitationCreated};
+use crate::common::{
+  across_tenants, current_tenant, set_tenant, sign_invitation, verify_invitation, with_tenant,
+};
+use crate::modules::audit::{AuditAction, NewAuditEvent, record_audit};
+use crate::modules::organizations::Organization;
+use crate::modules::outbox::publish_event;
+use crate::modules::users::{CreateUser, RoleIn, User, insert_user, user_created, user_snapshot};
+use crate::{AppError, AppState};
+
+use chrono::{Duration, Utc};
+use sqlx::types::Json;
+use sqlx::{Postgres, Transaction};
+use tracing::{info, warn};
+
+impl AppState {
+  /// Create an invitation into the active organization. The roles are checked
+  /// now, so accepting it later doesn't depend on the inviter's rights anymore.
+  pub async fn create_invitation(
+    &self,
+    actor: &User,
+    input: CreateInvitation,
+  ) -> Result<InvitationCreated, AppError> {
+    let mut transaction = self
+      .pool
+      .begin()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    let role_ids: Vec<i32> = input.roles.iter().map(|role| role.id).collect();
+    let known: Vec<(i32, String)> = sqlx::query_as("SELECT id, name FROM roles WHERE id = ANY($1)")
+      .bind(&role_ids)
+      .fetch_all(&mut *transaction)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    let roles = input
+      .roles
+      .into_iter()
+      .map(|role| match known.iter().find(|(id, _)| *id == role.id) {
+        Some((_, name)) => Ok(RoleIn {
+          name: name.clone(),
+          ..role
+        }),
+        None => Err(AppError::BadRequest(format!(
+          "roles: {} does not exist",
+          role.id
+        ))),
+      })
+      .collect::<Result<Vec<RoleIn>, AppError>>()?;
+    self
+      .ensure_no_privileged_roles(&mut transaction, &role_ids)
+      .await?;
+
+    let expires_at = Utc::now() + Duration::seconds(self.config.invitations.ttl as i64);
+    let invitation: Invitation = sqlx::query_as(
+      r#"
+      INSERT INTO invitations (organization_id, email, roles, invited_by, expires_at)
+      VALUES ($1, $2, $3, $4, $5)
+      RETURNING *
+      "#,
+    )
+    .bind(current_tenant()?)
+    .bind(&input.email)
+    .bind(Json(roles))
+    .bind(actor.user_info.id)
+    .bind(expires_at)
+    .fetch_one(&mut *transaction)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    let token = sign_invitation(invitation.id, invitation.expires_at, &self.config)?;
+    transaction
+      .commit()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    info!(
+      invitation_id = invitation.id,
+      invited_by = actor.user_info.id,
+      "invitation created"
+    );
+    Ok(InvitationCreated { invitation, token })
+  }
+
+  /// Invitations of the active organization that can still be accepted.
+  pub async fn get_invitations(&self) -> Result<Vec<Invitation>, AppError> {
+    let invitations = sqlx::query_as(
+      r#"
+      SELECT *
+      FROM invitations
+      WHERE organization_id = $1
+      AND accepted_at IS NULL
+      AND expires_at > NOW()
+      ORDER BY id DESC
+      "#,
+    )
+    .bind(current_tenant()?)
+    .fetch_all(&self.pool)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    Ok(invitations)
+  }
+
+  /// Revoke an invitation, its token stops working.
+  pub async fn delete_invitation(&self, invitation_id: i32) -> Result<(), AppError> {
+    let result = sqlx::query("DELETE FROM invitations WHERE id = $1 AND organization_id = $2")
+      .bind(invitation_id)
+      .bind(current_tenant()?)
+      .execute(&self.pool)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    if result.rows_affected() == 0 {
+      return Err(AppError::NotFound(format!(
+        "Invitation: {} not found",
+        invitation_id
+      )));
+    }
+    Ok(())
+  }
+
+  /// Delete the invitations, of every organization, that expired without being accepted.
+  pub async fn purge_expired_invitations(&self) -> Result<u64, AppError> {
+    let result = across_tenants(
+      sqlx::query("DELETE FROM invitations WHERE accepted_at IS NULL AND expires_at <= NOW()")
+        .execute(&self.pool),
+    )
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    Ok(result.rows_affected())
+  }
+
+  /// Sign up with an invitation token: the user joins the inviting organization
+  /// with the invited roles. Each invitation can be used once.
+  pub async fn accept_invitation(&self, token: &str, input: CreateUser) -> Result<User, AppError> {
+    let invitation_id = self.verify_invitation_token(token)?;
+    let mut transaction = self
+      .pool
+      .begin()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    self
+      .ensure_username_available(&mut transaction, &input.username, None)
+      .await?;
+
+    let invitation = claim_invitation(&mut transaction, invitation_id).await?;
+    // an invitation sent to an email address can't sign up another one
+    if let Some(email) = &input.email {
+      ensure_invited_email(&invitation, email)?;
+    }
+    let input = CreateUser {
+      email: input.email.or(invitation.email.clone()),
+      ..input
+    };
+    let organization_id = invitation.organization_id;
+    let user_info = with_tenant(organization_id, async {
+      set_tenant(&mut transaction).await?;
+      let user_info = insert_user(&mut transaction, &input).await?;
+      let granted_by = invitation.invited_by.unwrap_or(user_info.id);
+      self
+        .apply_roles(
+          &mut transaction,
+          invitation.roles.0.clone(),
+          user_info.id,
+          granted_by,
+        )
+        .await?;
+      let after = user_snapshot(&mut transaction, user_info.id).await?;
+      publish_event(&mut transaction, user_created(&user_info, &after)).await?;
+      record_audit(
+        &mut transaction,
+        NewAuditEvent::user(AuditAction::UserCreate, Some(user_info.id)).changes(None, after),
+      )
+      .await?;
+      Ok::<_, AppError>(user_info)
+    })
+    .await?;
+
+    mark_accepted(&mut transaction, invitation.id, user_info.id).await?;
+
+    transaction
+      .commit()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    info!(
+      invitation_id = invitation.id,
+      user_id = user_info.id,
+      "invitation accepted"
+    );
+    with_tenant(organization_id, self.get_user_by_id(user_info.id)).await
+  }
+
+  /// Accept an invitation as `user`, who already has an account: the user joins the inviting
+  /// organization with the invited roles. Each invitation can be used once.
+  pub async fn join_organization(
+    &self,
+    user: &User,
+    token: &str,
+  ) -> Result<Organization, AppError> {
+    let invitation_id = self.verify_invitation_token(token)?;
+    let user_id = user.user_info.id;
+    // the invitation belongs to another organization than the active one
+    let mut transaction = across_tenants(self.pool.begin())
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    let invitation = claim_invitation(&mut transaction, invitation_id).await?;
+    if invitation.email.is_some() {
+      ensure_invited_email(
+        &invitation,
+        user.user_info.email.as_deref().unwrap_or_default(),
+      )?;
+    }
+    mark_accepted(&mut transaction, invitation.id, user_id).await?;
+    let organization_id = invitation.organization_id;
+    with_tenant(organization_id, async {
+      set_tenant(&mut transaction).await?;
+      self
+        .insert_organization_member(
+          &mut transaction,
+          user_id,
+          invitation.roles.0.clone(),
+          invitation.invited_by.unwrap_or(user_id),
+        )
+        .await
+    })
+    .await?;
+
+    transaction
+      .commit()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    info!(
+      invitation_id = invitation.id,
+      user_id, "invitation accepted"
+    );
+    self
+      .get_user_organizations(user_id)
+      .await?
+      .into_iter()
+      .find(|organization| organization.id == organization_id)
+      .ok_or(AppError::InternalServerError)
+  }
+
+  fn verify_invitation_token(&self, token: &str) -> Result<i32, AppError> {
+    verify_invitation(token, &self.config).map_err(|e| {
+      warn!(error = ?e, "verify invitation failed");
+      AppError::Forbidden("invalid or expired invitation".to_string())
+    })
+  }
+}
+
+/// Lock the invitation `invitation_id` in `transaction`, if it can still be accepted.
+async fn claim_invitation(
+  transaction: &mut Transaction<'_, Postgres>,
+  invitation_id: i32,
+) -> Result<Invitation, AppError> {
+  let invitation: Invitation = sqlx::query_as("SELECT * FROM invitations WHERE id = $1 FOR UPDATE")
+    .bind(invitation_id)
+    .fetch_optional(&mut **transaction)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?
+    .ok_or(AppError::Forbidden(
+      "invalid or expired invitation".to_string(),
+    ))?;
+  if invitation.accepted_at.is_some() {
+    return Err(AppError::Conflict(format!(
+      "Invitation: {} has already been used",
+      invitation.id
+    )));
+  }
+  if invitation.expires_at <= Utc::now() {
+    return Err(AppError::Forbidden(
+      "invalid or expired invitation".to_string(),
+    ));
+  }
+  Ok(invitation)
+}
+
+/// An invitation sent to an email address is only for the owner of that address.
+fn ensure_invited_email(invitation: &Invitation, email: &str) -> Result<(), AppError> {
+  match &invitation.email {
+    Some(invited) if !invited.eq_ignore_ascii_case(email) => Err(AppError::Forbidden(
+      "invitation was sent to another email address".to_string(),
+    )),
+    _ => Ok(()),
+  }
+}
+
+async fn mark_accepted(
+  transaction: &mut Transaction<'_, Postgres>,
+  invitation_id: i32,
+  user_id: i32,
+) -> Result<(), AppError> {
+  sqlx::query("UPDATE invitations SET accepted_by = $1, accepted_at = $2 WHERE id = $3")
+    .bind(user_id)
+    .bind(Utc::now())
+    .bind(invitation_id)
+    .execute(&mut **transaction)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+  Ok(())
+}
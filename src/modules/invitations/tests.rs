@@ -0,0 +1,178 @@
+#[cfg(test)]
+mod util_tests {
+  use crate::common::{DEFAULT_ORGANIZATION_ID, with_tenant};
+  use crate::modules::invitations::CreateInvitation;
+  use crate::modules::users::{CreateUser, RoleIn, RoleName, VecExtensions};
+  use crate::{AppError, AppState};
+  use anyhow::Result;
+  use serial_test::serial;
+
+  fn invitation(role_id: i32) -> CreateInvitation {
+    CreateInvitation {
+      email: Some("new@example.com".to_string()),
+      roles: vec![RoleIn {
+        id: role_id,
+        name: "ignored".to_string(),
+        expires_at: None,
+      }],
+    }
+  }
+
+  fn new_user(username: &str) -> CreateUser {
+    CreateUser::new(username, "123456")
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn accept_invitation_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let superman = state.get_user_by_id(1).await?;
+
+      let created = state.create_invitation(&superman, invitation(2)).await?;
+      assert_eq!(created.invitation.roles.0[0].name, "Moderator");
+      assert_eq!(state.get_invitations().await?.len(), 1);
+
+      // the invitation is for new@example.com only
+      let other_email = CreateUser {
+        email: Some("other@example.com".to_string()),
+        ..new_user("newbie")
+      };
+      let mismatch = state.accept_invitation(&created.token, other_email).await;
+      assert!(matches!(mismatch, Err(AppError::Forbidden(_))));
+
+      let user = state
+        .accept_invitation(&created.token, new_user("newbie"))
+        .await?;
+      assert_eq!(user.user_info.email.as_deref(), Some("new@example.com"));
+      assert!(user.roles.contains_name(RoleName::Moderator));
+      assert!(!user.roles.contains_name(RoleName::User));
+      assert!(state.get_invitations().await?.is_empty());
+
+      let reused = state
+        .accept_invitation(&created.token, new_user("another"))
+        .await;
+      assert!(matches!(reused, Err(AppError::Conflict(_))));
+
+      let forged = state
+        .accept_invitation(&format!("{}x", created.token), new_user("another"))
+        .await;
+      assert!(matches!(forged, Err(AppError::Forbidden(_))));
+
+      let unknown_role = state.create_invitation(&superman, invitation(99)).await;
+      assert!(matches!(unknown_role, Err(AppError::BadRequest(_))));
+
+      // a revoked invitation can't be accepted
+      let created = state.create_invitation(&superman, invitation(1)).await?;
+      state.delete_invitation(created.invitation.id).await?;
+      let revoked = state
+        .accept_invitation(&created.token, new_user("another"))
+        .await;
+      assert!(matches!(revoked, Err(AppError::Forbidden(_))));
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn expired_and_privileged_invitation_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let superman = state.get_user_by_id(1).await?;
+      let mut config = state.config.clone();
+      config.invitations.ttl = 0;
+      config.approvals.enabled = true;
+      let restricted = AppState::new(config, state.pool.clone());
+
+      let admin = restricted.create_invitation(&superman, invitation(3)).await;
+      assert!(matches!(admin, Err(AppError::Forbidden(_))));
+
+      let created = restricted
+        .create_invitation(&superman, invitation(1))
+        .await?;
+      let expired = restricted
+        .accept_invitation(&created.token, new_user("newbie"))
+        .await;
+      assert!(matches!(expired, Err(AppError::Forbidden(_))));
+      anyhow::Ok(())
+    })
+    .await
+  }
+}
+
+#[cfg(test)]
+mod integration_tests {
+  use crate::AppState;
+  use crate::common::config::RegistrationMode;
+  use crate::common::{DEFAULT_ORGANIZATION_ID, with_tenant};
+  use crate::test_util::{get_token, spawn_app};
+  use anyhow::Result;
+  use axum::http::StatusCode;
+  use reqwest::Client;
+  use serde_json::json;
+  use serial_test::serial;
+
+  #[tokio::test]
+  #[serial]
+  async fn invitation_signup_handler_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let mut config = state.config.clone();
+      config.registration.mode = RegistrationMode::InviteOnly;
+      let (addr, tx) = spawn_app(AppState::new(config, state.pool.clone())).await?;
+
+      let client = Client::builder().no_proxy().build().unwrap();
+      let superman = get_token(&client, &addr.to_string(), "superman", "supermannofly").await?;
+      let alice = get_token(&client, &addr.to_string(), "alice", "123456").await?;
+      let new_user = json!({"username": "newbie", "password": "123456"});
+
+      let response = client
+        .post(format!("http://{}/auth/signup", addr))
+        .json(&new_user)
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+      let invitation = json!({"roles": [{"id": 2, "name": "Moderator"}]});
+      let response = client
+        .post(format!("http://{}/invitations", addr))
+        .json(&invitation)
+        .header("Authorization", format!("Bearer {}", alice))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+      let response = client
+        .post(format!("http://{}/invitations", addr))
+        .json(&invitation)
+        .header("Authorization", format!("Bearer {}", superman))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::CREATED);
+      let created: serde_json::Value = response.json().await?;
+      let token = created["token"].as_str().unwrap();
+
+      // an invitation isn't an access token
+      let response = client
+        .get(format!("http://{}/users/1", addr))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+      let response = client
+        .post(format!("http://{}/auth/signup?invite={}", addr, token))
+        .json(&new_user)
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::CREATED);
+      let user: serde_json::Value = response.json().await?;
+      assert_eq!(user["roles"][0]["name"], "Moderator");
+
+      tx.send(()).unwrap();
+      anyhow::Ok(())
+    })
+    .await
+  }
+}
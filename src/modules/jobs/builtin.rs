@@ -0,0 +1,75 @@
+use serde::{Deserialize, Serialize};
+
+use super::Job;
+use crate::AppState;
+
+/// `sweep_expired_grants`: remove role and permission grants past their `expires_at`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct SweepExpiredGrants {}
+
+impl Job for SweepExpiredGrants {
+  const KIND: &'static str = "sweep_expired_grants";
+
+  async fn run(self, state: AppState) -> Result<(), String> {
+    state
+      .sweep_expired_grants()
+      .await
+      .map(|_| ())
+      .map_err(|err| err.to_string())
+  }
+}
+
+/// `prune_outbox`: delete outbox events delivered more than `older_than` seconds ago.
+/// Event streams can't resume from before the oldest event kept.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PruneOutbox {
+  pub older_than: u64,
+}
+
+impl Job for PruneOutbox {
+  const KIND: &'static str = "prune_outbox";
+
+  async fn run(self, state: AppState) -> Result<(), String> {
+    sqlx::query("DELETE FROM outbox_events WHERE delivered_at < NOW() - make_interval(secs => $1)")
+      .bind(self.older_than as f64)
+      .execute(&state.pool)
+      .await
+      .map(|_| ())
+      .map_err(|err| err.to_string())
+  }
+}
+
+/// `purge_expired_invitations`: delete invitations that expired without being accepted.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct PurgeExpiredInvitations {}
+
+impl Job for PurgeExpiredInvitations {
+  const KIND: &'static str = "purge_expired_invitations";
+
+  async fn run(self, state: AppState) -> Result<(), String> {
+    state
+      .purge_expired_invitations()
+      .await
+      .map(|_| ())
+      .map_err(|err| err.to_string())
+  }
+}
+
+/// `purge_deleted_users`: purge the users of every organization soft deleted more than
+/// `retention` seconds ago.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PurgeDeletedUsers {
+  pub retention: u64,
+}
+
+impl Job for PurgeDeletedUsers {
+  const KIND: &'static str = "purge_deleted_users";
+
+  async fn run(self, state: AppState) -> Result<(), String> {
+    state
+      .purge_deleted_users(self.retention)
+      .await
+      .map(|_| ())
+      .map_err(|err| err.to_string())
+  }
+}
@@ -0,0 +1,39 @@
+use super::{JobRecord, JobStatus};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+/// query parameters of `GET /jobs`, newest first
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct JobFilter {
+  pub status: Option<JobStatus>,
+  #[validate(length(min = 1, max = 64))]
+  pub kind: Option<String>,
+  /// the previous page's `next_before`
+  pub before: Option<i64>,
+  #[serde(default = "default_limit")]
+  #[validate(range(min = 1, max = 100))]
+  pub limit: i64,
+}
+
+impl Default for JobFilter {
+  fn default() -> Self {
+    Self {
+      status: None,
+      kind: None,
+      before: None,
+      limit: default_limit(),
+    }
+  }
+}
+
+/// a page of jobs output dto
+#[derive(Debug, Deserialize, Serialize)]
+pub struct JobPage {
+  pub jobs: Vec<JobRecord>,
+  /// pass as `before` for the next page, absent on the last one
+  pub next_before: Option<i64>,
+}
+
+fn default_limit() -> i64 {
+  20
+}
@@ -0,0 +1,50 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::FromRow;
+
+/// jobs table
+#[derive(Clone, Debug, Deserialize, FromRow, Serialize)]
+pub struct JobRecord {
+  pub id: i64,
+  /// the tenant the job runs as
+  pub organization_id: i32,
+  /// `Job::KIND` of the handler
+  pub kind: String,
+  pub payload: Value,
+  pub status: String,
+  /// attempts started so far, the running one included
+  pub attempts: i32,
+  pub max_attempts: i32,
+  /// when the job is due, or its retry
+  pub run_at: DateTime<Utc>,
+  pub locked_by: Option<String>,
+  pub locked_until: Option<DateTime<Utc>>,
+  pub last_error: Option<String>,
+  pub finished_at: Option<DateTime<Utc>>,
+  pub created_at: DateTime<Utc>,
+}
+
+/// job states
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+  /// waiting to be due or for a free worker
+  Queued,
+  /// claimed by a worker until `locked_until`
+  Running,
+  Succeeded,
+  /// failed `max_attempts` times, left for an admin to retry
+  Dead,
+}
+
+impl AsRef<str> for JobStatus {
+  fn as_ref(&self) -> &str {
+    match *self {
+      JobStatus::Queued => "queued",
+      JobStatus::Running => "running",
+      JobStatus::Succeeded => "succeeded",
+      JobStatus::Dead => "dead",
+    }
+  }
+}
@@ -0,0 +1,46 @@
+use super::JobFilter;
+use crate::modules::authz::{Action, Resource};
+use crate::modules::users::User;
+use crate::{AppError, AppState};
+use axum::{
+  Extension, Json,
+  extract::{Path, Query, State},
+  http::StatusCode,
+  response::IntoResponse,
+};
+use tracing::info;
+use validator::Validate;
+
+pub async fn get_jobs_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Query(filter): Query<JobFilter>,
+) -> Result<impl IntoResponse, AppError> {
+  filter.validate()?;
+  info!("Jobs Handler::get jobs: filter: {:?}", filter);
+  state.authorize(&claims, Action::ManageJobs, &Resource::jobs())?;
+  let page = state.get_jobs(&filter).await?;
+  Ok((StatusCode::OK, Json(page)))
+}
+
+pub async fn get_job_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Path(job_id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+  info!("Jobs Handler::get job: {:?}", job_id);
+  state.authorize(&claims, Action::ManageJobs, &Resource::jobs())?;
+  let job = state.get_job(job_id).await?;
+  Ok((StatusCode::OK, Json(job)))
+}
+
+pub async fn retry_job_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Path(job_id): Path<i64>,
+) -> Result<impl IntoResponse, AppError> {
+  info!("Jobs Handler::retry job: {:?}", job_id);
+  state.authorize(&claims, Action::ManageJobs, &Resource::jobs())?;
+  let job = state.retry_job(job_id).await?;
+  Ok((StatusCode::OK, Json(job)))
+}
@@ -0,0 +1,26 @@
+pub mod builtin;
+pub mod dto;
+pub mod entity;
+pub mod handlers;
+pub mod registry;
+pub mod services;
+pub mod tests;
+
+pub use builtin::{PruneOutbox, PurgeDeletedUsers, PurgeExpiredInvitations, SweepExpiredGrants};
+pub use dto::{JobFilter, JobPage};
+pub use entity::{JobRecord, JobStatus};
+pub use handlers::{get_job_handler, get_jobs_handler, retry_job_handler};
+pub use registry::{Job, JobFuture, JobRegistry};
+pub(crate) use services::insert_job;
+
+use crate::AppState;
+use axum::Router;
+use axum::routing::{get, post};
+
+pub fn jobs_router(state: AppState) -> Router {
+  Router::new()
+    .route("/", get(get_jobs_handler))
+    .route("/{id}", get(get_job_handler))
+    .route("/{id}/retry", post(retry_job_handler))
+    .with_state(state)
+}
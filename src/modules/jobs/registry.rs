@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::AppState;
+
+pub type JobFuture = Pin<Box<dyn Future<Output = Result<(), String>> + Send>>;
+
+type JobHandler = Arc<dyn Fn(AppState, Value) -> JobFuture + Send + Sync>;
+
+/// A kind of background job: its payload, and what running it does.
+///
+/// A job may run more than once, after a failure or when its worker died mid-run,
+/// so `run` should be idempotent.
+pub trait Job: Serialize + DeserializeOwned + Send + Sync + 'static {
+  /// stored in `jobs.kind`, unique among the registered jobs
+  const KIND: &'static str;
+
+  /// Returns the reason of a failure, the job is then retried.
+  fn run(self, state: AppState) -> impl Future<Output = Result<(), String>> + Send;
+}
+
+/// The jobs a worker knows how to run, by kind.
+#[derive(Clone, Default)]
+pub struct JobRegistry {
+  handlers: HashMap<&'static str, JobHandler>,
+}
+
+impl JobRegistry {
+  pub fn register<J: Job>(&mut self) {
+    let handler: JobHandler = Arc::new(|state, payload| {
+      Box::pin(async move {
+        let job: J =
+          serde_json::from_value(payload).map_err(|err| format!("invalid payload: {}", err))?;
+        job.run(state).await
+      })
+    });
+    self.handlers.insert(J::KIND, handler);
+  }
+
+  /// Start running a job of `kind`, `None` when no such job is registered.
+  pub fn run(&self, kind: &str, state: AppState, payload: Value) -> Option<JobFuture> {
+    self
+      .handlers
+      .get(kind)
+      .map(|handler| handler(state, payload))
+  }
+
+  pub fn kinds(&self) -> Vec<&'static str> {
+    let mut kinds: Vec<&'static str> = self.handlers.keys().copied().collect();
+    kinds.sort_unstable();
+    kinds
+  }
+}
+
+impl Debug for JobRegistry {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("JobRegistry")
+      .field("kinds", &self.kinds())
+      .finish()
+  }
+}
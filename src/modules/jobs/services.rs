@@ -0,0 +1,311 @@
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures_util::FutureExt;
+use serde_json::Value;
+use sqlx::{PgConnection, Postgres, QueryBuilder};
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use super::{Job, JobFilter, JobPage, JobRecord, JobStatus};
+use crate::common::{current_tenant, retry_delay, with_tenant};
+use crate::{AppError, AppState};
+
+/// Queue `job` through `connection`, e.g. the transaction of the change it follows up on:
+/// the job only exists if that commits. Runs as the active organization.
+pub(crate) async fn enqueue_job<J: Job>(
+  connection: &mut PgConnection,
+  job: &J,
+  run_at: DateTime<Utc>,
+  max_attempts: i32,
+) -> Result<i64, AppError> {
+  let payload = serde_json::to_value(job).map_err(|_| AppError::InternalServerError)?;
+  insert_job(
+    connection,
+    current_tenant()?,
+    J::KIND,
+    &payload,
+    run_at,
+    max_attempts,
+  )
+  .await
+}
+
+/// Queue a job by kind, for callers that don't know its type, like the scheduler.
+pub(crate) async fn insert_job(
+  connection: &mut PgConnection,
+  organization_id: i32,
+  kind: &str,
+  payload: &Value,
+  run_at: DateTime<Utc>,
+  max_attempts: i32,
+) -> Result<i64, AppError> {
+  sqlx::query_scalar(
+    r#"
+    INSERT INTO jobs (organization_id, kind, payload, max_attempts, run_at)
+    VALUES ($1, $2, $3, $4, $5)
+    RETURNING id
+    "#,
+  )
+  .bind(organization_id)
+  .bind(kind)
+  .bind(payload)
+  .bind(max_attempts)
+  .bind(run_at)
+  .fetch_one(connection)
+  .await
+  .map_err(|err| AppError::DatabaseError(err.to_string()))
+}
+
+impl AppState {
+  /// Queue `job` to run as soon as a worker is free.
+  pub async fn enqueue<J: Job>(&self, job: &J) -> Result<i64, AppError> {
+    self.enqueue_at(job, Utc::now()).await
+  }
+
+  /// Queue `job` to run once `run_at` has passed.
+  pub async fn enqueue_at<J: Job>(&self, job: &J, run_at: DateTime<Utc>) -> Result<i64, AppError> {
+    let mut connection = self
+      .pool
+      .acquire()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    enqueue_job(&mut connection, job, run_at, self.config.jobs.max_attempts).await
+  }
+
+  pub async fn get_jobs(&self, filter: &JobFilter) -> Result<JobPage, AppError> {
+    let mut query = QueryBuilder::<Postgres>::new("SELECT * FROM jobs WHERE organization_id = ");
+    query.push_bind(current_tenant()?);
+    if let Some(status) = filter.status {
+      query
+        .push(" AND status = ")
+        .push_bind(status.as_ref().to_string());
+    }
+    if let Some(kind) = &filter.kind {
+      query.push(" AND kind = ").push_bind(kind.clone());
+    }
+    if let Some(before) = filter.before {
+      query.push(" AND id < ").push_bind(before);
+    }
+    // one extra row tells whether there is a next page
+    query
+      .push(" ORDER BY id DESC LIMIT ")
+      .push_bind(filter.limit + 1);
+
+    let mut jobs: Vec<JobRecord> = query
+      .build_query_as()
+      .fetch_all(&self.pool)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    let next_before = if jobs.len() as i64 > filter.limit {
+      jobs.truncate(filter.limit as usize);
+      jobs.last().map(|job| job.id)
+    } else {
+      None
+    };
+    Ok(JobPage { jobs, next_before })
+  }
+
+  pub async fn get_job(&self, job_id: i64) -> Result<JobRecord, AppError> {
+    sqlx::query_as("SELECT * FROM jobs WHERE id = $1 AND organization_id = $2")
+      .bind(job_id)
+      .bind(current_tenant()?)
+      .fetch_optional(&self.pool)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?
+      .ok_or(AppError::NotFound(format!("Job: {} not found", job_id)))
+  }
+
+  /// Queue a dead job again, with a fresh set of attempts.
+  pub async fn retry_job(&self, job_id: i64) -> Result<JobRecord, AppError> {
+    let job = self.get_job(job_id).await?;
+    sqlx::query_as(
+      r#"
+      UPDATE jobs
+      SET status = 'queued', attempts = 0, run_at = NOW(), finished_at = NULL
+      WHERE id = $1 AND status = 'dead'
+      RETURNING *
+      "#,
+    )
+    .bind(job_id)
+    .fetch_optional(&self.pool)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?
+    .ok_or(AppError::Conflict(format!(
+      "Job: {} is {}, only dead jobs can be retried",
+      job_id, job.status
+    )))
+  }
+
+  /// Claim up to `limit` due jobs, of every organization, for `worker_id`: queued ones and
+  /// running ones whose worker let `jobs.visibility_timeout` pass. Each claim is an attempt.
+  pub async fn claim_jobs(&self, limit: i64, worker_id: &str) -> Result<Vec<JobRecord>, AppError> {
+    sqlx::query_as(
+      r#"
+      UPDATE jobs
+      SET status = 'running', attempts = attempts + 1, locked_by = $2,
+          locked_until = NOW() + make_interval(secs => $3)
+      WHERE id IN (
+        SELECT id FROM jobs
+        WHERE (status = 'queued' AND run_at <= NOW())
+           OR (status = 'running' AND locked_until < NOW())
+        ORDER BY run_at, id
+        LIMIT $1
+        FOR UPDATE SKIP LOCKED
+      )
+      RETURNING *
+      "#,
+    )
+    .bind(limit)
+    .bind(worker_id)
+    .bind(self.config.jobs.visibility_timeout as f64)
+    .fetch_all(&self.pool)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))
+  }
+
+  /// Run a job claimed by `worker_id` as its organization and record the outcome: succeeded,
+  /// queued for a retry with backoff, or dead after its last attempt. A panic or running past
+  /// the visibility timeout is a failure. `None` when the job was claimed by another worker
+  /// meanwhile.
+  pub async fn execute_job(
+    &self,
+    job: JobRecord,
+    worker_id: &str,
+  ) -> Result<Option<JobRecord>, AppError> {
+    let config = &self.config.jobs;
+    let outcome = if job.attempts > job.max_attempts {
+      // its workers kept dying or timing out
+      Err("visibility timeout expired on the last attempt".to_string())
+    } else {
+      match self
+        .job_registry
+        .run(&job.kind, self.clone(), job.payload.clone())
+      {
+        None => Err(format!("no handler for job kind {}", job.kind)),
+        Some(run) => {
+          let run = AssertUnwindSafe(with_tenant(job.organization_id, run)).catch_unwind();
+          match timeout(Duration::from_secs(config.visibility_timeout), run).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err("job panicked".to_string()),
+            Err(_) => Err("visibility timeout expired".to_string()),
+          }
+        }
+      }
+    };
+
+    let (status, error) = match outcome {
+      Ok(()) => (JobStatus::Succeeded, None),
+      Err(error) if job.attempts >= job.max_attempts => (JobStatus::Dead, Some(error)),
+      Err(error) => (JobStatus::Queued, Some(error)),
+    };
+    if let Some(error) = &error {
+      warn!(
+        job_id = job.id,
+        kind = job.kind,
+        attempts = job.attempts,
+        status = status.as_ref(),
+        error,
+        "job failed"
+      );
+    }
+    let delay = retry_delay(config.retry_base, config.retry_max, job.attempts);
+    let finished = sqlx::query_as(
+      r#"
+      UPDATE jobs
+      SET status = $1, last_error = $2, locked_by = NULL, locked_until = NULL,
+          run_at = CASE WHEN $1 = 'queued' THEN NOW() + make_interval(secs => $3) ELSE run_at END,
+          finished_at = CASE WHEN $1 = 'queued' THEN NULL ELSE NOW() END
+      WHERE id = $4 AND status = 'running' AND locked_by = $5
+      RETURNING *
+      "#,
+    )
+    .bind(status.as_ref())
+    .bind(error)
+    .bind(delay as f64)
+    .bind(job.id)
+    .bind(worker_id)
+    .fetch_optional(&self.pool)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    if finished.is_none() {
+      warn!(
+        job_id = job.id,
+        worker_id, "job was claimed by another worker"
+      );
+    }
+    Ok(finished)
+  }
+
+  /// Put the jobs `worker_id` is running back in the queue, the attempt doesn't count.
+  pub async fn release_jobs(&self, worker_id: &str) -> Result<u64, AppError> {
+    let result = sqlx::query(
+      r#"
+      UPDATE jobs
+      SET status = 'queued', attempts = attempts - 1, run_at = NOW(),
+          locked_by = NULL, locked_until = NULL
+      WHERE status = 'running' AND locked_by = $1
+      "#,
+    )
+    .bind(worker_id)
+    .execute(&self.pool)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    Ok(result.rows_affected())
+  }
+
+  /// Run jobs, `jobs.concurrency` at a time, until `shutdown` completes. Then stop claiming,
+  /// give the running jobs `jobs.shutdown_timeout` to finish and put the others back.
+  pub async fn run_job_worker(self, shutdown: impl Future<Output = ()>) {
+    let config = self.config.jobs.clone();
+    let worker_id = format!("worker-{}", Uuid::new_v4().simple());
+    info!(worker_id, kinds = ?self.job_registry.kinds(), "job worker started");
+    let mut running = JoinSet::new();
+    let mut poll = tokio::time::interval(Duration::from_secs(config.poll_interval));
+    let mut shutdown = std::pin::pin!(shutdown);
+    loop {
+      tokio::select! {
+        _ = &mut shutdown => break,
+        _ = poll.tick() => (),
+        // a slot is free again
+        Some(_) = running.join_next() => (),
+      }
+      let free = config.concurrency.saturating_sub(running.len());
+      if free == 0 {
+        continue;
+      }
+      match self.claim_jobs(free as i64, &worker_id).await {
+        Ok(jobs) => {
+          for job in jobs {
+            let state = self.clone();
+            let worker_id = worker_id.clone();
+            running.spawn(async move {
+              if let Err(e) = state.execute_job(job, &worker_id).await {
+                warn!(error = ?e, "record job outcome failed");
+              }
+            });
+          }
+        }
+        Err(e) => warn!(error = ?e, "claim jobs failed"),
+      }
+    }
+
+    info!(worker_id, running = running.len(), "job worker stopping");
+    let drain = async { while running.join_next().await.is_some() {} };
+    if timeout(Duration::from_secs(config.shutdown_timeout), drain)
+      .await
+      .is_err()
+    {
+      running.shutdown().await;
+      match self.release_jobs(&worker_id).await {
+        Ok(count) => info!(worker_id, count, "put unfinished jobs back"),
+        Err(e) => warn!(error = ?e, "release jobs failed"),
+      }
+    }
+    info!(worker_id, "job worker stopped");
+  }
+}
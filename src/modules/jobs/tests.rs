@@ -0,0 +1,314 @@
+#[cfg(test)]
+mod util_tests {
+  use crate::common::{DEFAULT_ORGANIZATION_ID, with_tenant};
+  use crate::modules::jobs::*;
+  use crate::{AppError, AppState};
+  use anyhow::Result;
+  use serde::{Deserialize, Serialize};
+  use serial_test::serial;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+  use tokio::sync::oneshot;
+  use tokio::time::{Duration, sleep};
+
+  static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+  /// Fails with `error` when set, after sleeping `sleep_ms`.
+  #[derive(Debug, Default, Deserialize, Serialize)]
+  struct TestJob {
+    error: Option<String>,
+    sleep_ms: u64,
+  }
+
+  impl Job for TestJob {
+    const KIND: &'static str = "test";
+
+    async fn run(self, _state: AppState) -> Result<(), String> {
+      RUNS.fetch_add(1, Ordering::SeqCst);
+      sleep(Duration::from_millis(self.sleep_ms)).await;
+      self.error.map_or(Ok(()), Err)
+    }
+  }
+
+  fn failing() -> TestJob {
+    TestJob {
+      error: Some("boom".to_string()),
+      sleep_ms: 0,
+    }
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn job_registry_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      assert_eq!(
+        state.job_registry.kinds(),
+        vec![
+          "prune_outbox",
+          "purge_deleted_users",
+          "purge_expired_invitations",
+          "sweep_expired_grants"
+        ]
+      );
+      let state = state.with_job::<TestJob>();
+      assert_eq!(state.job_registry.kinds().len(), 5);
+      assert!(
+        state
+          .job_registry
+          .run("missing", state.clone(), serde_json::json!({}))
+          .is_none()
+      );
+      let invalid = state
+        .job_registry
+        .run("test", state.clone(), serde_json::json!({"sleep_ms": "x"}))
+        .unwrap();
+      assert!(invalid.await.unwrap_err().starts_with("invalid payload"));
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn job_retry_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let state = state.with_job::<TestJob>();
+
+      let ok_id = state.enqueue(&TestJob::default()).await?;
+      let jobs = state.claim_jobs(10, "w1").await?;
+      assert_eq!(jobs.len(), 1);
+      assert_eq!(jobs[0].status, "running");
+      assert_eq!(jobs[0].attempts, 1);
+      let job = state.execute_job(jobs[0].clone(), "w1").await?.unwrap();
+      assert_eq!((job.id, job.status.as_str()), (ok_id, "succeeded"));
+      assert!(job.finished_at.is_some());
+      assert!(job.locked_by.is_none());
+
+      // a failure is retried with backoff, then the job is dead
+      let mut connection = state.pool.acquire().await?;
+      let id = services::enqueue_job(&mut connection, &failing(), chrono::Utc::now(), 2).await?;
+      let job = state.claim_jobs(10, "w1").await?.remove(0);
+      let job = state.execute_job(job, "w1").await?.unwrap();
+      assert_eq!(job.status, "queued");
+      assert_eq!(job.last_error.as_deref(), Some("boom"));
+      assert!(job.run_at > chrono::Utc::now());
+      assert!(state.claim_jobs(10, "w1").await?.is_empty());
+
+      sqlx::query("UPDATE jobs SET run_at = NOW() WHERE id = $1")
+        .bind(id)
+        .execute(&state.pool)
+        .await?;
+      let job = state.claim_jobs(10, "w1").await?.remove(0);
+      assert_eq!(job.attempts, 2);
+      let job = state.execute_job(job, "w1").await?.unwrap();
+      assert_eq!(job.status, "dead");
+      assert!(state.claim_jobs(10, "w1").await?.is_empty());
+
+      let dead = state
+        .get_jobs(&JobFilter {
+          status: Some(JobStatus::Dead),
+          ..Default::default()
+        })
+        .await?;
+      assert_eq!(dead.jobs.len(), 1);
+      assert_eq!(dead.jobs[0].id, id);
+
+      // an admin gives it a fresh set of attempts
+      let job = state.retry_job(id).await?;
+      assert_eq!((job.status.as_str(), job.attempts), ("queued", 0));
+      assert!(matches!(
+        state.retry_job(id).await,
+        Err(AppError::Conflict(_))
+      ));
+      assert!(matches!(
+        state.retry_job(ok_id).await,
+        Err(AppError::Conflict(_))
+      ));
+      assert!(matches!(
+        state.get_job(-1).await,
+        Err(AppError::NotFound(_))
+      ));
+
+      // unknown kinds fail like any job
+      sqlx::query("UPDATE jobs SET kind = 'gone' WHERE id = $1")
+        .bind(id)
+        .execute(&state.pool)
+        .await?;
+      let job = state.claim_jobs(10, "w1").await?.remove(0);
+      let job = state.execute_job(job, "w1").await?.unwrap();
+      assert_eq!(
+        job.last_error.as_deref(),
+        Some("no handler for job kind gone")
+      );
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn job_visibility_timeout_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let state = state.with_job::<TestJob>();
+      let id = state.enqueue(&TestJob::default()).await?;
+      let job = state.claim_jobs(10, "w1").await?.remove(0);
+      // claimed jobs aren't claimed again until their lease expires
+      assert!(state.claim_jobs(10, "w2").await?.is_empty());
+
+      sqlx::query("UPDATE jobs SET locked_until = NOW() - INTERVAL '1 second' WHERE id = $1")
+        .bind(id)
+        .execute(&state.pool)
+        .await?;
+      let reclaimed = state.claim_jobs(10, "w2").await?.remove(0);
+      assert_eq!(reclaimed.attempts, 2);
+      assert_eq!(reclaimed.locked_by.as_deref(), Some("w2"));
+
+      // the first worker lost the job
+      assert!(state.execute_job(job, "w1").await?.is_none());
+      let job = state.execute_job(reclaimed, "w2").await?.unwrap();
+      assert_eq!(job.status, "succeeded");
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn job_worker_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let mut config = state.config.clone();
+      config.jobs.poll_interval = 1;
+      config.jobs.shutdown_timeout = 1;
+      let state = AppState::new(config, state.pool.clone()).with_job::<TestJob>();
+      RUNS.store(0, Ordering::SeqCst);
+
+      let quick = state.enqueue(&TestJob::default()).await?;
+      let slow = state
+        .enqueue(&TestJob {
+          error: None,
+          sleep_ms: 60_000,
+        })
+        .await?;
+      let (tx, rx) = oneshot::channel();
+      let worker = tokio::spawn(state.clone().run_job_worker(async {
+        rx.await.ok();
+      }));
+      for _ in 0..50 {
+        if state.get_job(quick).await?.status == "succeeded" {
+          break;
+        }
+        sleep(Duration::from_millis(100)).await;
+      }
+      assert_eq!(state.get_job(quick).await?.status, "succeeded");
+      assert_eq!(state.get_job(slow).await?.status, "running");
+      assert_eq!(RUNS.load(Ordering::SeqCst), 2);
+
+      // the slow job doesn't finish within the shutdown timeout and goes back to the queue
+      tx.send(()).unwrap();
+      tokio::time::timeout(Duration::from_secs(5), worker).await??;
+      let job = state.get_job(slow).await?;
+      assert_eq!((job.status.as_str(), job.attempts), ("queued", 0));
+      assert!(job.locked_by.is_none());
+      anyhow::Ok(())
+    })
+    .await
+  }
+}
+
+#[cfg(test)]
+mod integration_tests {
+  use crate::AppState;
+  use crate::common::{DEFAULT_ORGANIZATION_ID, with_tenant};
+  use crate::modules::jobs::SweepExpiredGrants;
+  use crate::test_util::{get_token, spawn_app};
+  use anyhow::Result;
+  use axum::http::StatusCode;
+  use reqwest::Client;
+  use serial_test::serial;
+
+  #[tokio::test]
+  #[serial]
+  async fn job_handler_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let first = state.enqueue(&SweepExpiredGrants {}).await?;
+      let second = state.enqueue(&SweepExpiredGrants {}).await?;
+      sqlx::query("UPDATE jobs SET status = 'dead', attempts = 5 WHERE id = $1")
+        .bind(first)
+        .execute(&state.pool)
+        .await?;
+      let (addr, tx) = spawn_app(state).await?;
+
+      let client = Client::new();
+      let addr = addr.to_string();
+      let token = get_token(&client, &addr, "superman", "supermannofly").await?;
+
+      let response = client
+        .get(format!("http://{}/jobs?limit=1", addr))
+        .bearer_auth(&token)
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+      let page: serde_json::Value = response.json().await?;
+      assert_eq!(page["jobs"][0]["id"], second);
+      assert_eq!(page["next_before"], second);
+
+      let response = client
+        .get(format!("http://{}/jobs?status=dead", addr))
+        .bearer_auth(&token)
+        .send()
+        .await?;
+      let page: serde_json::Value = response.json().await?;
+      assert_eq!(page["jobs"].as_array().unwrap().len(), 1);
+      assert_eq!(page["jobs"][0]["kind"], "sweep_expired_grants");
+      assert!(page["next_before"].is_null());
+
+      let response = client
+        .get(format!("http://{}/jobs?limit=0", addr))
+        .bearer_auth(&token)
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+      let response = client
+        .post(format!("http://{}/jobs/{}/retry", addr, first))
+        .bearer_auth(&token)
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+      let job: serde_json::Value = response.json().await?;
+      assert_eq!(job["status"], "queued");
+      assert_eq!(job["attempts"], 0);
+
+      let response = client
+        .post(format!("http://{}/jobs/{}/retry", addr, second))
+        .bearer_auth(&token)
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::CONFLICT);
+
+      let response = client
+        .get(format!("http://{}/jobs/{}", addr, second))
+        .bearer_auth(&token)
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+
+      // jobs are for admins only
+      let token = get_token(&client, &addr, "alice", "123456").await?;
+      let response = client
+        .get(format!("http://{}/jobs", addr))
+        .bearer_auth(&token)
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+      tx.send(()).unwrap();
+      anyhow::Ok(())
+    })
+    .await
+  }
+}
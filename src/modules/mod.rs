@@ -1,3 +1,15 @@
+pub mod audit;
 pub mod auth;
+pub mod authz;
+pub mod events;
+pub mod groups;
 pub mod health;
+pub mod invitations;
+pub mod jobs;
+pub mod organizations;
+pub mod outbox;
+pub mod role_requests;
+pub mod scheduler;
+pub mod user_transfer;
 pub mod users;
+pub mod webhooks;
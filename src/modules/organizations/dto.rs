@@ -0,0 +1,13 @@
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+/// organization create input dto
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct CreateOrganization {
+  #[validate(length(
+    min = 3,
+    max = 50,
+    message = "organization name length must be between 3 and 50 characters"
+  ))]
+  pub name: String,
+}
@@ -0,0 +1,20 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+/// organizations table
+#[derive(Clone, Debug, Deserialize, FromRow, Serialize)]
+pub struct Organization {
+  pub id: i32,
+  pub name: String,
+  pub created_at: DateTime<Utc>,
+  pub updated_at: DateTime<Utc>,
+}
+
+/// organization_members table joined with users
+#[derive(Clone, Debug, Deserialize, FromRow, Serialize)]
+pub struct OrganizationMember {
+  pub user_id: i32,
+  pub username: String,
+  pub created_at: DateTime<Utc>,
+}
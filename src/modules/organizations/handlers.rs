@@ -0,0 +1,88 @@
+use super::CreateOrganization;
+use crate::common::current_tenant;
+use crate::modules::authz::{Action, Resource};
+use crate::modules::users::User;
+use crate::{AppError, AppState};
+use axum::{
+  Extension, Json,
+  extract::{Path, State},
+  http::StatusCode,
+  response::IntoResponse,
+};
+use tracing::info;
+use validator::Validate;
+
+pub async fn get_organizations_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+  info!("Organizations Handler::get organizations");
+  let organizations = state.get_user_organizations(claims.user_info.id).await?;
+  Ok((StatusCode::OK, Json(organizations)))
+}
+
+pub async fn create_organization_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Json(input): Json<CreateOrganization>,
+) -> Result<impl IntoResponse, AppError> {
+  input.validate()?;
+  info!(
+    "Organizations Handler::create organization: input: {:?}",
+    input
+  );
+  state.authorize(
+    &claims,
+    Action::ManageOrganizations,
+    &Resource::organizations(),
+  )?;
+  let organization = state.create_organization(&claims, input).await?;
+  Ok((StatusCode::CREATED, Json(organization)))
+}
+
+pub async fn get_organization_members_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Path(organization_id): Path<i32>,
+) -> Result<impl IntoResponse, AppError> {
+  info!(
+    "Organizations Handler::get organization members: {:?}",
+    organization_id
+  );
+  ensure_active_organization(organization_id)?;
+  state.authorize(&claims, Action::ListUsers, &Resource::users())?;
+  let members = state.get_organization_members(organization_id).await?;
+  Ok((StatusCode::OK, Json(members)))
+}
+
+pub async fn remove_organization_member_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Path((organization_id, user_id)): Path<(i32, i32)>,
+) -> Result<impl IntoResponse, AppError> {
+  info!(
+    "Organizations Handler::remove member: {:?} from organization: {:?}",
+    user_id, organization_id
+  );
+  ensure_active_organization(organization_id)?;
+  state.authorize(
+    &claims,
+    Action::ManageOrganizations,
+    &Resource::organizations(),
+  )?;
+  let members = state
+    .remove_organization_member(organization_id, user_id, None)
+    .await?;
+  Ok((StatusCode::OK, Json(members)))
+}
+
+/// Roles are per organization, so members can only be managed from within the organization.
+fn ensure_active_organization(organization_id: i32) -> Result<(), AppError> {
+  if organization_id != current_tenant()? {
+    return Err(AppError::Forbidden(format!(
+      "organization: {} is not the active organization",
+      organization_id
+    )));
+  }
+  Ok(())
+}
@@ -0,0 +1,30 @@
+pub mod dto;
+pub mod entity;
+pub mod handlers;
+pub mod services;
+pub mod tests;
+
+pub use dto::CreateOrganization;
+pub use entity::{Organization, OrganizationMember};
+pub use handlers::{
+  create_organization_handler, get_organization_members_handler, get_organizations_handler,
+  remove_organization_member_handler,
+};
+
+use crate::AppState;
+use axum::Router;
+use axum::routing::{delete, get};
+
+pub fn organizations_router(state: AppState) -> Router {
+  Router::new()
+    .route(
+      "/",
+      get(get_organizations_handler).post(create_organization_handler),
+    )
+    .route("/{id}/members", get(get_organization_members_handler))
+    .route(
+      "/{id}/members/{user_id}",
+      delete(remove_organization_member_handler),
+    )
+    .with_state(state)
+}
@@ -0,0 +1,293 @@
+use super::{CreateOrganization, Organization, OrganizationMember};
+use crate::common::{EntityTags, across_tenants, current_tenant, set_tenant, with_tenant};
+use crate::modules::audit::{AuditAction, NewAuditEvent, record_audit};
+use crate::modules::outbox::{DomainEvent, publish_event};
+use crate::modules::users::{RoleIn, RoleName, User, lock_admins, user_snapshot};
+use crate::{AppError, AppState};
+
+use chrono::Utc;
+use sqlx::{Postgres, Transaction};
+
+impl AppState {
+  /// Organizations `user_id` belongs to, the first one is the default at sign in.
+  pub async fn get_user_organizations(&self, user_id: i32) -> Result<Vec<Organization>, AppError> {
+    let organizations = across_tenants(
+      sqlx::query_as(
+        r#"
+        SELECT o.id, o.name, o.created_at, o.updated_at
+        FROM organizations o
+        INNER JOIN organization_members m ON o.id = m.organization_id
+        WHERE m.user_id = $1
+        ORDER BY o.id
+        "#,
+      )
+      .bind(user_id)
+      .fetch_all(&self.pool),
+    )
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    Ok(organizations)
+  }
+
+  pub async fn is_organization_member(
+    &self,
+    organization_id: i32,
+    user_id: i32,
+  ) -> Result<bool, AppError> {
+    let result = across_tenants(
+      sqlx::query_scalar(
+        r#"
+        SELECT EXISTS (
+          SELECT 1
+          FROM organization_members
+          WHERE organization_id = $1 AND user_id = $2
+        )
+        "#,
+      )
+      .bind(organization_id)
+      .bind(user_id)
+      .fetch_one(&self.pool),
+    )
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    Ok(result)
+  }
+
+  /// Pick the organization a request of `user_id` acts in: the requested one if
+  /// the user is a member, otherwise the user's first organization.
+  pub async fn resolve_organization(
+    &self,
+    user_id: i32,
+    requested: Option<i32>,
+  ) -> Result<i32, AppError> {
+    match requested {
+      Some(organization_id) => {
+        if self
+          .is_organization_member(organization_id, user_id)
+          .await?
+        {
+          Ok(organization_id)
+        } else {
+          Err(AppError::Forbidden(format!(
+            "not a member of organization: {}",
+            organization_id
+          )))
+        }
+      }
+      None => self
+        .get_user_organizations(user_id)
+        .await?
+        .first()
+        .map(|organization| organization.id)
+        .ok_or(AppError::Forbidden(
+          "not a member of any organization".to_string(),
+        )),
+    }
+  }
+
+  /// Create an organization with `actor` as its first member and Admin.
+  pub async fn create_organization(
+    &self,
+    actor: &User,
+    input: CreateOrganization,
+  ) -> Result<Organization, AppError> {
+    let exists: bool =
+      sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM organizations WHERE name = $1)")
+        .bind(&input.name)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    if exists {
+      return Err(AppError::Conflict(format!(
+        "Organization: {} already exists",
+        input.name
+      )));
+    }
+
+    let mut transaction = self
+      .pool
+      .begin()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    let organization: Organization = sqlx::query_as(
+      r#"
+      INSERT INTO organizations (name, created_at, updated_at)
+      VALUES ($1, $2, $2)
+      RETURNING id, name, created_at, updated_at
+      "#,
+    )
+    .bind(&input.name)
+    .bind(Utc::now())
+    .fetch_one(&mut *transaction)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    let admin = self.role_in(RoleName::Admin).await?;
+    with_tenant(organization.id, async {
+      set_tenant(&mut transaction).await?;
+      self
+        .insert_organization_member(
+          &mut transaction,
+          actor.user_info.id,
+          vec![admin],
+          actor.user_info.id,
+        )
+        .await
+    })
+    .await?;
+
+    transaction
+      .commit()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    Ok(organization)
+  }
+
+  pub async fn get_organization_members(
+    &self,
+    organization_id: i32,
+  ) -> Result<Vec<OrganizationMember>, AppError> {
+    let members = sqlx::query_as(
+      r#"
+      SELECT u.id AS user_id, u.username, m.created_at
+      FROM organization_members m
+      INNER JOIN users u ON u.id = m.user_id
+      WHERE m.organization_id = $1
+      ORDER BY u.id
+      "#,
+    )
+    .bind(organization_id)
+    .fetch_all(&self.pool)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    Ok(members)
+  }
+
+  /// Make `user_id` a member of the active organization with `roles`, within a `transaction`
+  /// switched to it. Users only join by creating an organization or accepting an invitation,
+  /// see `join_organization`: an organization can't pull in a user of another one.
+  pub(crate) async fn insert_organization_member(
+    &self,
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: i32,
+    roles: Vec<RoleIn>,
+    granted_by: i32,
+  ) -> Result<(), AppError> {
+    let organization_id = current_tenant()?;
+    let result = sqlx::query(
+      r#"
+      INSERT INTO organization_members (organization_id, user_id, created_at)
+      VALUES ($1, $2, $3)
+      ON CONFLICT DO NOTHING
+      "#,
+    )
+    .bind(organization_id)
+    .bind(user_id)
+    .bind(Utc::now())
+    .execute(&mut **transaction)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    if result.rows_affected() == 0 {
+      return Err(AppError::Conflict(format!(
+        "User: {} is already a member of organization: {}",
+        user_id, organization_id
+      )));
+    }
+    let audit = self
+      .update_roles(transaction, roles, user_id, granted_by)
+      .await?;
+    record_audit(transaction, audit).await
+  }
+
+  /// Remove a user from the organization along with their grants in it.
+  /// The user itself, and their memberships elsewhere, are kept. With `if_match` it only
+  /// goes ahead when the user's version still matches it.
+  pub async fn remove_organization_member(
+    &self,
+    organization_id: i32,
+    user_id: i32,
+    if_match: Option<&EntityTags>,
+  ) -> Result<Vec<OrganizationMember>, AppError> {
+    let mut transaction = self
+      .pool
+      .begin()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    with_tenant(organization_id, async {
+      set_tenant(&mut transaction).await?;
+      if let Some(if_match) = if_match {
+        self
+          .lock_user_version(&mut transaction, user_id, if_match)
+          .await?;
+      }
+      let admins = lock_admins(&mut transaction, user_id).await?;
+      if admins.is_last_permanent_admin(user_id) {
+        return Err(AppError::InvariantViolation(
+          "cannot remove the last admin of the organization".to_string(),
+        ));
+      }
+      // taken while still a member, the user isn't visible in the organization afterwards
+      let before = user_snapshot(&mut transaction, user_id).await?;
+
+      let result =
+        sqlx::query("DELETE FROM organization_members WHERE organization_id = $1 AND user_id = $2")
+          .bind(organization_id)
+          .bind(user_id)
+          .execute(&mut *transaction)
+          .await
+          .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+      if result.rows_affected() == 0 {
+        return Err(AppError::NotFound(format!(
+          "User: {} is not a member of organization: {}",
+          user_id, organization_id
+        )));
+      }
+
+      for query in [
+        "DELETE FROM user_roles WHERE organization_id = $1 AND user_id = $2",
+        "DELETE FROM user_permissions WHERE organization_id = $1 AND user_id = $2",
+        r#"
+        DELETE FROM group_members gm
+        USING groups g
+        WHERE g.id = gm.group_id AND g.organization_id = $1 AND gm.user_id = $2
+        "#,
+      ] {
+        sqlx::query(query)
+          .bind(organization_id)
+          .bind(user_id)
+          .execute(&mut *transaction)
+          .await
+          .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+      }
+
+      publish_event(&mut transaction, DomainEvent::MemberRemoved { user_id }).await?;
+      record_audit(
+        &mut transaction,
+        NewAuditEvent::user(AuditAction::MemberRemove, Some(user_id)).changes(before, None),
+      )
+      .await
+    })
+    .await?;
+
+    transaction
+      .commit()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    self.get_organization_members(organization_id).await
+  }
+
+  async fn role_in(&self, name: RoleName) -> Result<RoleIn, AppError> {
+    let id: i32 = sqlx::query_scalar("SELECT id FROM roles WHERE name = $1")
+      .bind(name.as_ref())
+      .fetch_one(&self.pool)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    Ok(RoleIn {
+      id,
+      name: name.as_ref().to_string(),
+      expires_at: None,
+    })
+  }
+}
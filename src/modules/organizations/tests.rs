@@ -0,0 +1,346 @@
+#[cfg(test)]
+mod util_tests {
+  use crate::common::{DEFAULT_ORGANIZATION_ID, across_tenants, current_tenant, with_tenant};
+  use crate::modules::invitations::CreateInvitation;
+  use crate::modules::organizations::CreateOrganization;
+  use crate::modules::users::{RoleIn, RoleName, VecExtensions};
+  use crate::{AppError, AppState};
+  use anyhow::Result;
+  use serial_test::serial;
+  use sqlx::PgPool;
+
+  #[tokio::test]
+  #[serial]
+  async fn organization_scoping_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let superman = state.get_user_by_id(1).await?;
+      let acme = state
+        .create_organization(
+          &superman,
+          CreateOrganization {
+            name: "acme".to_string(),
+          },
+        )
+        .await?;
+      let duplicate = state
+        .create_organization(
+          &superman,
+          CreateOrganization {
+            name: "acme".to_string(),
+          },
+        )
+        .await;
+      assert!(matches!(duplicate, Err(AppError::Conflict(_))));
+      assert_eq!(state.get_user_organizations(1).await?.len(), 2);
+
+      with_tenant(acme.id, async {
+        let users = state.get_users(10, 0).await?;
+        assert_eq!(users.total_count, Some(1));
+        assert!(users.users[0].roles.contains_name(RoleName::Admin));
+        assert!(matches!(
+          state.get_user_by_id(2).await,
+          Err(AppError::NotFound(_))
+        ));
+
+        // alice joins by accepting an invitation, not one sent to another email address
+        let alice = with_tenant(DEFAULT_ORGANIZATION_ID, state.get_user_by_id(2)).await?;
+        let invitation = |email: Option<&str>| CreateInvitation {
+          email: email.map(str::to_string),
+          roles: vec![RoleIn {
+            id: 1,
+            name: "User".to_string(),
+            expires_at: None,
+          }],
+        };
+        let other = state
+          .create_invitation(&superman, invitation(Some("other@example.com")))
+          .await?;
+        assert!(matches!(
+          state.join_organization(&alice, &other.token).await,
+          Err(AppError::Forbidden(_))
+        ));
+        let created = state.create_invitation(&superman, invitation(None)).await?;
+        let joined = state.join_organization(&alice, &created.token).await?;
+        assert_eq!(joined.id, acme.id);
+        let again = state.create_invitation(&superman, invitation(None)).await?;
+        assert!(matches!(
+          state.join_organization(&alice, &again.token).await,
+          Err(AppError::Conflict(_))
+        ));
+        // the Admin of the creator and the User role of the new member are audited
+        let audits: i64 = sqlx::query_scalar(
+          "SELECT COUNT(*) FROM audit_events WHERE organization_id = $1 AND action = 'user.update_roles'",
+        )
+        .bind(acme.id)
+        .fetch_one(&state.pool)
+        .await?;
+        let events: i64 = sqlx::query_scalar(
+          "SELECT COUNT(*) FROM outbox_events WHERE organization_id = $1 AND event_type = 'roles_changed'",
+        )
+        .bind(acme.id)
+        .fetch_one(&state.pool)
+        .await?;
+        assert_eq!((audits, events), (2, 2));
+        let moderator = vec![RoleIn {
+          id: 2,
+          name: "Moderator".to_string(),
+          expires_at: None,
+        }];
+        state.set_roles(moderator, 2, 1).await?;
+        let alice = state.get_user_by_id(2).await?;
+        assert!(alice.roles.contains_name(RoleName::Moderator));
+
+        let last_admin = state.remove_organization_member(acme.id, 1, None).await;
+        assert!(matches!(last_admin, Err(AppError::InvariantViolation(_))));
+        anyhow::Ok(())
+      })
+      .await?;
+
+      // roles granted in acme don't leak into the default organization
+      assert!(state.get_user_roles(2).await?.is_empty());
+
+      // the status is shared by every organization, neither of them may change it alone
+      assert!(matches!(
+        state.suspend_user(2).await,
+        Err(AppError::Conflict(_))
+      ));
+      assert!(matches!(
+        with_tenant(acme.id, state.restore_user(2)).await,
+        Err(AppError::Conflict(_))
+      ));
+
+      // deleting a user shared with another organization only removes the membership
+      with_tenant(acme.id, state.delete_user(2, None)).await?;
+      assert_eq!(state.get_user_organizations(2).await?.len(), 1);
+      assert!(state.is_user_exists_by_id(2).await?);
+      // with the grants it had there, audited and published in acme
+      let before: serde_json::Value = sqlx::query_scalar(
+        "SELECT before FROM audit_events WHERE organization_id = $1 AND action = 'organization.remove_member' AND target_id = 2",
+      )
+      .bind(acme.id)
+      .fetch_one(&state.pool)
+      .await?;
+      assert_eq!(before["roles"][0]["name"], "Moderator");
+      let events: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM outbox_events WHERE organization_id = $1 AND event_type = 'member_removed' AND aggregate_id = 2",
+      )
+      .bind(acme.id)
+      .fetch_one(&state.pool)
+      .await?;
+      assert_eq!(events, 1);
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  async fn usernames(pool: &PgPool) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar("SELECT username FROM users ORDER BY id")
+      .fetch_all(pool)
+      .await
+  }
+
+  async fn current_tenant_outside_of_scope() -> Result<i32, AppError> {
+    tokio::spawn(async { current_tenant() }).await.unwrap()
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn row_level_security_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let superman = state.get_user_by_id(1).await?;
+      let acme = state
+        .create_organization(
+          &superman,
+          CreateOrganization {
+            name: "acme".to_string(),
+          },
+        )
+        .await?;
+
+      // the connections switch to the application role, which doesn't bypass the policies
+      let role: String = sqlx::query_scalar("SELECT current_user::TEXT")
+        .fetch_one(&state.pool)
+        .await?;
+      assert_eq!(Some(role), state.config.database.role);
+
+      // the pool sets the tenant of the task on the connection, no filter in the queries
+      let users = with_tenant(acme.id, usernames(&state.pool)).await?;
+      assert_eq!(users, vec!["superman".to_string()]);
+      let organizations: Vec<i32> = with_tenant(
+        acme.id,
+        sqlx::query_scalar("SELECT DISTINCT organization_id FROM user_roles")
+          .fetch_all(&state.pool),
+      )
+      .await?;
+      assert_eq!(organizations, vec![acme.id]);
+
+      let users = across_tenants(usernames(&state.pool)).await?;
+      assert!(users.len() > 1);
+      assert!(users.contains(&"alice".to_string()));
+
+      // spawned tasks don't inherit the tenant, their queries fail instead of seeing everything
+      let pool = state.pool.clone();
+      let err = tokio::spawn(async move { usernames(&pool).await })
+        .await?
+        .unwrap_err();
+      assert!(err.to_string().contains("no tenant set on the connection"));
+      assert!(current_tenant_outside_of_scope().await.is_err());
+      anyhow::Ok(())
+    })
+    .await
+  }
+}
+
+#[cfg(test)]
+mod integration_tests {
+  use crate::AppState;
+  use crate::common::{DEFAULT_ORGANIZATION_ID, with_tenant};
+  use crate::test_util::{get_token, sign_in, spawn_app};
+  use anyhow::Result;
+  use axum::http::StatusCode;
+  use reqwest::Client;
+  use serde_json::json;
+  use serial_test::serial;
+
+  #[tokio::test]
+  #[serial]
+  async fn organization_handler_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let (addr, tx) = spawn_app(state).await?;
+
+      let client = Client::builder().no_proxy().build().unwrap();
+      let addr = addr.to_string();
+      let superman = get_token(&client, &addr, "superman", "supermannofly").await?;
+
+      let response = client
+        .post(format!("http://{}/organizations", addr))
+        .json(&json!({"name": "acme"}))
+        .header("Authorization", format!("Bearer {}", superman))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::CREATED);
+      let acme: serde_json::Value = response.json().await?;
+      let acme_id = acme["id"].as_i64().unwrap();
+
+      let response = client
+        .get(format!("http://{}/organizations", addr))
+        .header("Authorization", format!("Bearer {}", superman))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+      let organizations: serde_json::Value = response.json().await?;
+      assert_eq!(organizations.as_array().unwrap().len(), 2);
+
+      // users of other organizations can't be added directly, only invited
+      let response = client
+        .put(format!(
+          "http://{}/organizations/{}/members/{}",
+          addr, acme_id, 2
+        ))
+        .header("Authorization", format!("Bearer {}", superman))
+        .header("X-Organization-Id", acme_id.to_string())
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+      let response = client
+        .post(format!("http://{}/invitations", addr))
+        .json(&json!({"roles": [{"id": 1, "name": "User"}]}))
+        .header("Authorization", format!("Bearer {}", superman))
+        .header("X-Organization-Id", acme_id.to_string())
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::CREATED);
+      let invitation: serde_json::Value = response.json().await?;
+
+      let alice = get_token(&client, &addr, "alice", "123456").await?;
+      let response = client
+        .post(format!("http://{}/invitations/accept", addr))
+        .json(&json!({"token": invitation["token"]}))
+        .header("Authorization", format!("Bearer {}", alice))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+      let joined: serde_json::Value = response.json().await?;
+      assert_eq!(joined["id"], acme_id);
+
+      // members are managed from within the organization
+      let response = client
+        .get(format!("http://{}/organizations/{}/members", addr, acme_id))
+        .header("Authorization", format!("Bearer {}", superman))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::FORBIDDEN);
+      let response = client
+        .get(format!("http://{}/organizations/{}/members", addr, acme_id))
+        .header("Authorization", format!("Bearer {}", superman))
+        .header("X-Organization-Id", acme_id.to_string())
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+      let members: serde_json::Value = response.json().await?;
+      assert_eq!(members.as_array().unwrap().len(), 2);
+
+      let response = client
+        .get(format!("http://{}/users?limit=10&offset=0", addr))
+        .header("Authorization", format!("Bearer {}", superman))
+        .header("X-Organization-Id", acme_id.to_string())
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+      let users: serde_json::Value = response.json().await?;
+      assert_eq!(users["total_count"], 2);
+
+      // bob isn't a member of acme
+      let bob = get_token(&client, &addr, "bob", "123456").await?;
+      let response = client
+        .get(format!("http://{}/users/{}", addr, 3))
+        .header("Authorization", format!("Bearer {}", bob))
+        .header("X-Organization-Id", acme_id.to_string())
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+      // alice signs in to acme, where she only has the role User
+      let alice = sign_in(
+        &client,
+        &addr,
+        json!({"username": "alice", "password": "123456", "organization_id": acme_id}),
+      )
+      .await?;
+      let response = client
+        .get(format!("http://{}/users/{}", addr, 2))
+        .header("Authorization", format!("Bearer {}", alice))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+      let user: serde_json::Value = response.json().await?;
+      assert_eq!(user["roles"][0]["name"], "User");
+
+      // acme only sees the signins to acme, not those to the default organization
+      let response = client
+        .get(format!("http://{}/users/2/logins", addr))
+        .header("Authorization", format!("Bearer {}", superman))
+        .header("X-Organization-Id", acme_id.to_string())
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+      let history: serde_json::Value = response.json().await?;
+      assert_eq!(history["attempts"].as_array().unwrap().len(), 1);
+      let response = client
+        .get(format!("http://{}/users/2/logins", addr))
+        .header("Authorization", format!("Bearer {}", superman))
+        .send()
+        .await?;
+      let history: serde_json::Value = response.json().await?;
+      assert_eq!(history["attempts"].as_array().unwrap().len(), 1);
+
+      tx.send(()).unwrap();
+      anyhow::Ok(())
+    })
+    .await
+  }
+}
@@ -0,0 +1,144 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sqlx::FromRow;
+
+/// outbox_events table
+#[derive(Clone, Debug, Deserialize, FromRow, Serialize)]
+pub struct OutboxEvent {
+  pub id: i64,
+  pub organization_id: i32,
+  pub aggregate_type: String,
+  pub aggregate_id: i32,
+  pub event_type: String,
+  /// the `DomainEvent`, tagged with its `type`
+  pub payload: Value,
+  /// failed and successful deliveries so far
+  pub attempts: i32,
+  pub next_attempt_at: DateTime<Utc>,
+  pub last_error: Option<String>,
+  pub delivered_at: Option<DateTime<Utc>>,
+  pub created_at: DateTime<Utc>,
+}
+
+impl OutboxEvent {
+  /// The typed event, `None` for a payload this version doesn't know.
+  pub fn domain_event(&self) -> Option<DomainEvent> {
+    serde_json::from_value(self.payload.clone()).ok()
+  }
+
+  /// The event as sent to webhooks and streamed to subscribers.
+  pub fn envelope(&self) -> Value {
+    json!({
+      "id": self.id,
+      "type": self.event_type,
+      "organization_id": self.organization_id,
+      "created_at": self.created_at,
+      "data": self.payload,
+    })
+  }
+}
+
+/// What happened to a user, as other services get told about it.
+/// `SuspiciousSignin` is a signin flagged by the anomaly rules.
+/// Role and permission names are the user's direct grants in the event's organization.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DomainEvent {
+  UserCreated {
+    user_id: i32,
+    username: String,
+    email: Option<String>,
+    roles: Vec<String>,
+  },
+  UserUpdated {
+    user_id: i32,
+    /// names of the changed account fields
+    fields: Vec<String>,
+  },
+  UserSuspended {
+    user_id: i32,
+  },
+  UserRestored {
+    user_id: i32,
+  },
+  UserDeleted {
+    user_id: i32,
+  },
+  UserPurged {
+    user_id: i32,
+  },
+  /// the user left the organization of the event, with its grants there
+  MemberRemoved {
+    user_id: i32,
+  },
+  RolesChanged {
+    user_id: i32,
+    roles: Vec<String>,
+  },
+  PermissionsChanged {
+    user_id: i32,
+    permissions: Vec<String>,
+  },
+  SuspiciousSignin {
+    user_id: i32,
+    ip: Option<String>,
+    flags: Vec<String>,
+  },
+}
+
+impl DomainEvent {
+  /// every `event_type`
+  pub const TYPES: [&'static str; 10] = [
+    "user_created",
+    "user_updated",
+    "user_suspended",
+    "user_restored",
+    "user_deleted",
+    "user_purged",
+    "member_removed",
+    "roles_changed",
+    "permissions_changed",
+    "suspicious_signin",
+  ];
+
+  /// `(aggregate_type, aggregate_id)`, events of one aggregate are delivered in order.
+  pub fn aggregate(&self) -> (&'static str, i32) {
+    match *self {
+      DomainEvent::UserCreated { user_id, .. }
+      | DomainEvent::UserUpdated { user_id, .. }
+      | DomainEvent::UserSuspended { user_id }
+      | DomainEvent::UserRestored { user_id }
+      | DomainEvent::UserDeleted { user_id }
+      | DomainEvent::UserPurged { user_id }
+      | DomainEvent::MemberRemoved { user_id }
+      | DomainEvent::RolesChanged { user_id, .. }
+      | DomainEvent::PermissionsChanged { user_id, .. }
+      | DomainEvent::SuspiciousSignin { user_id, .. } => ("user", user_id),
+    }
+  }
+}
+
+impl AsRef<str> for DomainEvent {
+  fn as_ref(&self) -> &str {
+    match *self {
+      DomainEvent::UserCreated { .. } => "user_created",
+      DomainEvent::UserUpdated { .. } => "user_updated",
+      DomainEvent::UserSuspended { .. } => "user_suspended",
+      DomainEvent::UserRestored { .. } => "user_restored",
+      DomainEvent::UserDeleted { .. } => "user_deleted",
+      DomainEvent::UserPurged { .. } => "user_purged",
+      DomainEvent::MemberRemoved { .. } => "member_removed",
+      DomainEvent::RolesChanged { .. } => "roles_changed",
+      DomainEvent::PermissionsChanged { .. } => "permissions_changed",
+      DomainEvent::SuspiciousSignin { .. } => "suspicious_signin",
+    }
+  }
+}
+
+/// What a pass of the relay did.
+#[derive(Clone, Debug, Default, Serialize, PartialEq, Eq)]
+pub struct RelayReport {
+  pub delivered: u64,
+  pub failed: u64,
+}
@@ -0,0 +1,9 @@
+pub mod entity;
+pub mod services;
+pub mod sinks;
+pub mod tests;
+
+pub use entity::{DomainEvent, OutboxEvent, RelayReport};
+pub use services::OUTBOX_CHANNEL;
+pub(crate) use services::publish_event;
+pub use sinks::{EventSink, LogEventSink, MemoryEventSink, SinkFuture};
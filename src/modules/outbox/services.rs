@@ -0,0 +1,179 @@
+use std::time::Duration;
+
+use sqlx::PgConnection;
+use tracing::{info, warn};
+
+use super::{DomainEvent, OutboxEvent, RelayReport};
+use crate::common::{current_tenant, retry_delay};
+use crate::{AppError, AppState};
+
+/// Channel notified with the id of every event written, once its transaction commits.
+pub const OUTBOX_CHANNEL: &str = "outbox_events";
+
+/// Advisory lock making one relay at a time deliver, held for a whole pass.
+const OUTBOX_RELAY_LOCK: i64 = 0x004f_5554_424f;
+
+/// Write `event` to the outbox through `connection`, the transaction of the change it describes,
+/// so the event exists exactly when the change does. Listeners of `OUTBOX_CHANNEL` hear of it
+/// on commit.
+pub(crate) async fn publish_event(
+  connection: &mut PgConnection,
+  event: DomainEvent,
+) -> Result<(), AppError> {
+  let (aggregate_type, aggregate_id) = event.aggregate();
+  let payload = serde_json::to_value(&event).map_err(|_| AppError::InternalServerError)?;
+  let id: i64 = sqlx::query_scalar(
+    r#"
+    INSERT INTO outbox_events (organization_id, aggregate_type, aggregate_id, event_type, payload)
+    VALUES ($1, $2, $3, $4, $5)
+    RETURNING id
+    "#,
+  )
+  .bind(current_tenant()?)
+  .bind(aggregate_type)
+  .bind(aggregate_id)
+  .bind(event.as_ref())
+  .bind(payload)
+  .fetch_one(&mut *connection)
+  .await
+  .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+  sqlx::query("SELECT pg_notify($1, $2)")
+    .bind(OUTBOX_CHANNEL)
+    .bind(id.to_string())
+    .execute(connection)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+  Ok(())
+}
+
+impl AppState {
+  /// Deliver the pending events to every sink, of every organization.
+  ///
+  /// Only the oldest pending event of an aggregate is ever tried, so a user's events arrive
+  /// in the order they were written; a failed one holds back the rest of its aggregate until
+  /// its retry succeeds. Returns once nothing more is due, at once when another relay is busy.
+  pub async fn relay_outbox(&self) -> Result<RelayReport, AppError> {
+    let mut lock = self
+      .pool
+      .begin()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    let locked: bool = sqlx::query_scalar("SELECT pg_try_advisory_xact_lock($1)")
+      .bind(OUTBOX_RELAY_LOCK)
+      .fetch_one(&mut *lock)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    let mut report = RelayReport::default();
+    if !locked {
+      return Ok(report);
+    }
+
+    loop {
+      let events: Vec<OutboxEvent> = sqlx::query_as(
+        r#"
+        SELECT * FROM (
+          SELECT DISTINCT ON (aggregate_type, aggregate_id)
+            id, organization_id, aggregate_type, aggregate_id, event_type, payload,
+            attempts, next_attempt_at, last_error, delivered_at, created_at
+          FROM outbox_events
+          WHERE delivered_at IS NULL
+          ORDER BY aggregate_type, aggregate_id, id
+        ) heads
+        WHERE next_attempt_at <= NOW()
+        ORDER BY id
+        LIMIT $1
+        "#,
+      )
+      .bind(self.config.outbox.batch_size)
+      .fetch_all(&self.pool)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+      if events.is_empty() {
+        break;
+      }
+
+      let delivered = report.delivered;
+      for event in events {
+        match self.deliver_event(&event).await {
+          Ok(()) => {
+            sqlx::query(
+              r#"
+              UPDATE outbox_events
+              SET delivered_at = NOW(), attempts = attempts + 1, last_error = NULL
+              WHERE id = $1
+              "#,
+            )
+            .bind(event.id)
+            .execute(&self.pool)
+            .await
+            .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+            report.delivered += 1;
+          }
+          Err(error) => {
+            let attempts = event.attempts + 1;
+            let outbox = &self.config.outbox;
+            let delay = retry_delay(outbox.retry_base, outbox.retry_max, attempts);
+            sqlx::query(
+              r#"
+              UPDATE outbox_events
+              SET attempts = $1, last_error = $2,
+                  next_attempt_at = NOW() + make_interval(secs => $3)
+              WHERE id = $4
+              "#,
+            )
+            .bind(attempts)
+            .bind(&error)
+            .bind(delay as f64)
+            .bind(event.id)
+            .execute(&self.pool)
+            .await
+            .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+            warn!(
+              id = event.id,
+              attempts, delay, error, "deliver domain event failed"
+            );
+            report.failed += 1;
+          }
+        }
+      }
+      // only failures left, they wait for their retry
+      if report.delivered == delivered {
+        break;
+      }
+    }
+
+    lock
+      .commit()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    Ok(report)
+  }
+
+  /// Hand `event` to every sink in turn, stopping at the first failure.
+  async fn deliver_event(&self, event: &OutboxEvent) -> Result<(), String> {
+    for sink in self.event_sinks.iter() {
+      sink.deliver(event).await?;
+    }
+    Ok(())
+  }
+
+  /// Periodically run `relay_outbox`, meant to be spawned at startup.
+  pub async fn run_outbox_relay(self) {
+    let mut interval = tokio::time::interval(Duration::from_secs(self.config.outbox.poll_interval));
+    loop {
+      interval.tick().await;
+      match self.relay_outbox().await {
+        Ok(RelayReport {
+          delivered: 0,
+          failed: 0,
+        }) => (),
+        Ok(report) => info!(
+          delivered = report.delivered,
+          failed = report.failed,
+          "relayed domain events"
+        ),
+        Err(e) => warn!(error = ?e, "relay outbox failed"),
+      }
+    }
+  }
+}
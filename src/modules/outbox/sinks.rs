@@ -0,0 +1,63 @@
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tracing::info;
+
+use super::OutboxEvent;
+
+pub type SinkFuture<'a> = Pin<Box<dyn Future<Output = Result<(), String>> + Send + 'a>>;
+
+/// Where the relay delivers domain events, e.g. a message broker.
+/// Returns the reason when the event should be retried later.
+///
+/// Delivery is at least once: an event may be delivered again after a failure or a crash,
+/// `OutboxEvent::id` tells the copies apart.
+pub trait EventSink: Debug + Send + Sync {
+  fn deliver<'a>(&'a self, event: &'a OutboxEvent) -> SinkFuture<'a>;
+}
+
+/// Logs every event, the default.
+#[derive(Debug, Default)]
+pub struct LogEventSink;
+
+impl EventSink for LogEventSink {
+  fn deliver<'a>(&'a self, event: &'a OutboxEvent) -> SinkFuture<'a> {
+    Box::pin(async move {
+      info!(
+        id = event.id,
+        event_type = event.event_type,
+        aggregate_id = event.aggregate_id,
+        "domain event"
+      );
+      Ok(())
+    })
+  }
+}
+
+/// Keeps the events delivered to it, for tests. Fails the next `failures` deliveries.
+#[derive(Debug, Default)]
+pub struct MemoryEventSink {
+  pub events: Mutex<Vec<OutboxEvent>>,
+  pub failures: AtomicUsize,
+}
+
+impl EventSink for MemoryEventSink {
+  fn deliver<'a>(&'a self, event: &'a OutboxEvent) -> SinkFuture<'a> {
+    Box::pin(async move {
+      let failing = self
+        .failures
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+        .is_ok();
+      if failing {
+        return Err("sink unavailable".to_string());
+      }
+      if let Ok(mut events) = self.events.lock() {
+        events.push(event.clone());
+      }
+      Ok(())
+    })
+  }
+}
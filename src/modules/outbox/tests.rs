@@ -0,0 +1,154 @@
+#[cfg(test)]
+mod util_tests {
+  use crate::common::{DEFAULT_ORGANIZATION_ID, retry_delay, with_tenant};
+  use crate::modules::outbox::*;
+  use crate::modules::users::{CreateUser, RoleIn};
+  use crate::{AppError, AppState};
+  use anyhow::Result;
+  use serial_test::serial;
+  use std::sync::Arc;
+  use std::sync::atomic::Ordering;
+
+  #[test]
+  fn retry_delay_test() {
+    let config = crate::AppConfig::from_file("app.yaml").unwrap().outbox;
+    let delay = |attempts| retry_delay(config.retry_base, config.retry_max, attempts);
+    assert_eq!(delay(1), config.retry_base);
+    assert_eq!(delay(2), config.retry_base * 2);
+    assert_eq!(delay(3), config.retry_base * 4);
+    assert_eq!(delay(1000), config.retry_max);
+  }
+
+  async fn pending_events(state: &AppState, user_id: i32) -> Result<Vec<OutboxEvent>> {
+    let events = sqlx::query_as(
+      "SELECT * FROM outbox_events WHERE aggregate_id = $1 AND delivered_at IS NULL ORDER BY id",
+    )
+    .bind(user_id)
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(events)
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn outbox_events_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let sink = Arc::new(MemoryEventSink::default());
+      let state = state.with_event_sinks(vec![sink.clone()]);
+
+      let user = state
+        .create_user(CreateUser::new("newton", "apple123"))
+        .await?;
+      let user_id = user.user_info.id;
+      let roles = vec![RoleIn {
+        id: 2,
+        name: "Moderator".to_string(),
+        expires_at: None,
+      }];
+      state.set_roles(roles, user_id, 1).await?;
+      state.suspend_user(user_id).await?;
+
+      let events = pending_events(&state, user_id).await?;
+      let types: Vec<&str> = events.iter().map(|e| e.event_type.as_str()).collect();
+      assert_eq!(types, ["user_created", "roles_changed", "user_suspended"]);
+      assert_eq!(
+        events[0].domain_event(),
+        Some(DomainEvent::UserCreated {
+          user_id,
+          username: "newton".to_string(),
+          email: None,
+          roles: vec!["User".to_string()],
+        })
+      );
+      assert_eq!(
+        events[1].domain_event(),
+        Some(DomainEvent::RolesChanged {
+          user_id,
+          roles: vec!["Moderator".to_string()],
+        })
+      );
+
+      // a change that is rolled back leaves no event
+      assert!(matches!(
+        state.suspend_user(1).await,
+        Err(AppError::InvariantViolation(_))
+      ));
+      assert!(pending_events(&state, 1).await?.is_empty());
+
+      let report = state.relay_outbox().await?;
+      assert_eq!(
+        report,
+        RelayReport {
+          delivered: 3,
+          failed: 0
+        }
+      );
+      let delivered: Vec<i64> = sink.events.lock().unwrap().iter().map(|e| e.id).collect();
+      assert_eq!(delivered, events.iter().map(|e| e.id).collect::<Vec<_>>());
+      assert!(pending_events(&state, user_id).await?.is_empty());
+      assert_eq!(state.relay_outbox().await?, RelayReport::default());
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn outbox_retry_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let sink = Arc::new(MemoryEventSink::default());
+      let state = state.with_event_sinks(vec![sink.clone()]);
+
+      let user = state
+        .create_user(CreateUser::new("newton", "apple123"))
+        .await?;
+      let user_id = user.user_info.id;
+      state.suspend_user(user_id).await?;
+      // 3, bob
+      state.suspend_user(3).await?;
+
+      // the first delivery fails: newton's events wait, bob's go through
+      sink.failures.store(1, Ordering::SeqCst);
+      let report = state.relay_outbox().await?;
+      assert_eq!(
+        report,
+        RelayReport {
+          delivered: 1,
+          failed: 1
+        }
+      );
+      let events = pending_events(&state, user_id).await?;
+      assert_eq!(events.len(), 2);
+      assert_eq!(events[0].attempts, 1);
+      assert_eq!(events[0].last_error.as_deref(), Some("sink unavailable"));
+      assert!(events[0].next_attempt_at > events[0].created_at);
+      {
+        let delivered = sink.events.lock().unwrap();
+        assert_eq!(delivered.len(), 1);
+        assert_eq!(delivered[0].aggregate_id, 3);
+      }
+
+      // not due yet
+      assert_eq!(state.relay_outbox().await?, RelayReport::default());
+
+      sqlx::query("UPDATE outbox_events SET next_attempt_at = NOW()")
+        .execute(&state.pool)
+        .await?;
+      let report = state.relay_outbox().await?;
+      assert_eq!(report.delivered, 2);
+      let delivered: Vec<String> = sink
+        .events
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|e| e.aggregate_id == user_id)
+        .map(|e| e.event_type.clone())
+        .collect();
+      assert_eq!(delivered, ["user_created", "user_suspended"]);
+      anyhow::Ok(())
+    })
+    .await
+  }
+}
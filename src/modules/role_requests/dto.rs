@@ -0,0 +1,32 @@
+use super::{RoleRequest, RoleRequestEvent};
+use crate::modules::users::User;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+/// role request list filter input dto
+#[derive(Debug, Default, Deserialize, Serialize, Validate)]
+pub struct RoleRequestFilter {
+  #[validate(length(min = 1, max = 20))]
+  pub status: Option<String>,
+}
+
+/// approve or reject input dto
+#[derive(Debug, Default, Deserialize, Serialize, Validate)]
+pub struct ReviewRoleRequest {
+  #[validate(length(max = 500))]
+  pub comment: Option<String>,
+}
+
+/// role request with its history output dto
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RoleRequestDetail {
+  pub request: RoleRequest,
+  pub history: Vec<RoleRequestEvent>,
+}
+
+/// user update output dto when role changes await approval
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PendingUserUpdate {
+  pub user: User,
+  pub role_request: RoleRequest,
+}
@@ -0,0 +1,64 @@
+use crate::modules::users::RoleIn;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use sqlx::types::Json;
+
+/// role_requests table
+#[derive(Clone, Debug, Deserialize, FromRow, Serialize)]
+pub struct RoleRequest {
+  pub id: i32,
+  pub user_id: i32,
+  pub requested_by: Option<i32>,
+  pub roles: Json<Vec<RoleIn>>,
+  pub status: String,
+  pub reviewed_by: Option<i32>,
+  pub reviewed_at: Option<DateTime<Utc>>,
+  pub expires_at: DateTime<Utc>,
+  pub created_at: DateTime<Utc>,
+  pub updated_at: DateTime<Utc>,
+}
+
+/// role_request_events table
+#[derive(Clone, Debug, Deserialize, FromRow, Serialize)]
+pub struct RoleRequestEvent {
+  pub id: i32,
+  pub request_id: i32,
+  pub actor_id: Option<i32>,
+  pub event: String,
+  pub comment: Option<String>,
+  pub created_at: DateTime<Utc>,
+}
+
+/// role request status
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum RoleRequestStatus {
+  Pending,
+  Approved,
+  Rejected,
+  Expired,
+}
+
+impl RoleRequestStatus {
+  #[allow(clippy::should_implement_trait)]
+  pub fn from_str(status: &str) -> Option<Self> {
+    match status {
+      "pending" => Some(RoleRequestStatus::Pending),
+      "approved" => Some(RoleRequestStatus::Approved),
+      "rejected" => Some(RoleRequestStatus::Rejected),
+      "expired" => Some(RoleRequestStatus::Expired),
+      _ => None,
+    }
+  }
+}
+
+impl AsRef<str> for RoleRequestStatus {
+  fn as_ref(&self) -> &str {
+    match *self {
+      RoleRequestStatus::Pending => "pending",
+      RoleRequestStatus::Approved => "approved",
+      RoleRequestStatus::Rejected => "rejected",
+      RoleRequestStatus::Expired => "expired",
+    }
+  }
+}
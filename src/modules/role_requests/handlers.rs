@@ -0,0 +1,85 @@
+use super::{ReviewRoleRequest, RoleRequestFilter};
+use crate::modules::authz::{Action, Resource};
+use crate::modules::users::User;
+use crate::{AppError, AppState};
+use axum::{
+  Extension, Json,
+  extract::{Path, Query, State},
+  http::StatusCode,
+  response::IntoResponse,
+};
+use tracing::info;
+use validator::Validate;
+
+pub async fn get_role_requests_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Query(filter): Query<RoleRequestFilter>,
+) -> Result<impl IntoResponse, AppError> {
+  filter.validate()?;
+  info!("Role Requests Handler::get role requests: {:?}", filter);
+  state.authorize(
+    &claims,
+    Action::ReviewRoleRequests,
+    &Resource::role_requests(),
+  )?;
+  let requests = state.get_role_requests(filter.status.as_deref()).await?;
+  Ok((StatusCode::OK, Json(requests)))
+}
+
+pub async fn get_role_request_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Path(request_id): Path<i32>,
+) -> Result<impl IntoResponse, AppError> {
+  info!("Role Requests Handler::get role request: {:?}", request_id);
+  state.authorize(
+    &claims,
+    Action::ReviewRoleRequests,
+    &Resource::role_requests(),
+  )?;
+  let request = state.get_role_request(request_id).await?;
+  Ok((StatusCode::OK, Json(request)))
+}
+
+pub async fn approve_role_request_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Path(request_id): Path<i32>,
+  input: Option<Json<ReviewRoleRequest>>,
+) -> Result<impl IntoResponse, AppError> {
+  review_role_request(claims, state, request_id, input, true).await
+}
+
+pub async fn reject_role_request_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Path(request_id): Path<i32>,
+  input: Option<Json<ReviewRoleRequest>>,
+) -> Result<impl IntoResponse, AppError> {
+  review_role_request(claims, state, request_id, input, false).await
+}
+
+async fn review_role_request(
+  claims: User,
+  state: AppState,
+  request_id: i32,
+  input: Option<Json<ReviewRoleRequest>>,
+  approve: bool,
+) -> Result<impl IntoResponse, AppError> {
+  let Json(input) = input.unwrap_or_default();
+  input.validate()?;
+  info!(
+    "Role Requests Handler::review role request: {:?}, approve: {:?}",
+    request_id, approve
+  );
+  state.authorize_action(&claims, Action::ReviewRoleRequests, None)?;
+  let detail = state.get_role_request(request_id).await?;
+  state
+    .authorize_user(&claims, Action::ReviewRoleRequests, detail.request.user_id)
+    .await?;
+  let request = state
+    .review_role_request(&claims, request_id, approve, input.comment)
+    .await?;
+  Ok((StatusCode::OK, Json(request)))
+}
@@ -0,0 +1,25 @@
+pub mod dto;
+pub mod entity;
+pub mod handlers;
+pub mod services;
+pub mod tests;
+
+pub use dto::{PendingUserUpdate, ReviewRoleRequest, RoleRequestDetail, RoleRequestFilter};
+pub use entity::{RoleRequest, RoleRequestEvent, RoleRequestStatus};
+pub use handlers::{
+  approve_role_request_handler, get_role_request_handler, get_role_requests_handler,
+  reject_role_request_handler,
+};
+
+use crate::AppState;
+use axum::Router;
+use axum::routing::{get, post};
+
+pub fn role_requests_router(state: AppState) -> Router {
+  Router::new()
+    .route("/", get(get_role_requests_handler))
+    .route("/{id}", get(get_role_request_handler))
+    .route("/{id}/approve", post(approve_role_request_handler))
+    .route("/{id}/reject", post(reject_role_request_handler))
+    .with_state(state)
+}
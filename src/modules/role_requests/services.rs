@@ -0,0 +1,349 @@
+use super::{RoleRequest, RoleRequestDetail, RoleRequestEvent, RoleRequestStatus};
+use crate::common::current_tenant;
+use crate::modules::audit::record_audit;
+use crate::modules::users::{RoleIn, User, direct_roles};
+use crate::{AppError, AppState};
+
+use chrono::{Duration, Utc};
+use sqlx::types::Json;
+use sqlx::{Postgres, Transaction};
+use tracing::info;
+
+impl AppState {
+  /// In four-eyes mode, split the roles `actor_id` sets on `user_id` within `transaction`.
+  /// Privileged roles the user doesn't hold directly, or holds until an earlier time, are filed
+  /// as a pending request for another admin and keep their current grant meanwhile.
+  ///
+  /// Returns the roles to apply now, `None` when they don't change, and the request if any.
+  pub(crate) async fn intercept_role_escalation(
+    &self,
+    transaction: &mut Transaction<'_, Postgres>,
+    actor_id: i32,
+    user_id: i32,
+    roles: Vec<RoleIn>,
+  ) -> Result<(Option<Vec<RoleIn>>, Option<RoleRequest>), AppError> {
+    if !self.config.approvals.enabled {
+      return Ok((Some(roles), None));
+    }
+    let current = direct_roles(transaction, user_id).await?;
+    let privileged_role_ids: Vec<i32> =
+      sqlx::query_scalar("SELECT id FROM roles WHERE name = ANY($1)")
+        .bind(&self.config.approvals.privileged_roles)
+        .fetch_all(&mut **transaction)
+        .await
+        .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    let held = |role_id: i32| current.iter().find(|role| role.id == role_id);
+
+    let (escalated, mut granted): (Vec<RoleIn>, Vec<RoleIn>) =
+      roles.into_iter().partition(|role| {
+        privileged_role_ids.contains(&role.id) && is_escalation(held(role.id), role)
+      });
+    if escalated.is_empty() {
+      return Ok((Some(granted), None));
+    }
+    granted.extend(escalated.iter().filter_map(|role| held(role.id).cloned()));
+    let unchanged = granted.len() == current.len()
+      && granted
+        .iter()
+        .all(|role| held(role.id).is_some_and(|grant| grant.expires_at == role.expires_at));
+
+    let request = self
+      .create_role_request(transaction, actor_id, user_id, escalated)
+      .await?;
+    info!(
+      request_id = request.id,
+      user_id,
+      requested_by = actor_id,
+      "role escalation awaits approval"
+    );
+    Ok(((!unchanged).then_some(granted), Some(request)))
+  }
+
+  /// File a pending request for the role grants `roles`, added to the user's direct grants
+  /// once approved.
+  pub(crate) async fn create_role_request(
+    &self,
+    transaction: &mut Transaction<'_, Postgres>,
+    requested_by: i32,
+    user_id: i32,
+    roles: Vec<RoleIn>,
+  ) -> Result<RoleRequest, AppError> {
+    let expires_at = Utc::now() + Duration::seconds(self.config.approvals.request_ttl as i64);
+    let request: RoleRequest = sqlx::query_as(
+      r#"
+      INSERT INTO role_requests (user_id, requested_by, roles, status, expires_at, organization_id)
+      VALUES ($1, $2, $3, $4, $5, $6)
+      RETURNING *
+      "#,
+    )
+    .bind(user_id)
+    .bind(requested_by)
+    .bind(Json(roles))
+    .bind(RoleRequestStatus::Pending.as_ref())
+    .bind(expires_at)
+    .bind(current_tenant()?)
+    .fetch_one(&mut **transaction)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    record_event(transaction, request.id, Some(requested_by), "created", None).await?;
+    Ok(request)
+  }
+
+  pub async fn get_role_requests(
+    &self,
+    status: Option<&str>,
+  ) -> Result<Vec<RoleRequest>, AppError> {
+    if let Some(status) = status {
+      RoleRequestStatus::from_str(status)
+        .ok_or_else(|| AppError::BadRequest(format!("unknown role request status: {}", status)))?;
+    }
+    self.expire_role_requests().await?;
+
+    let requests = sqlx::query_as(
+      r#"
+      SELECT *
+      FROM role_requests
+      WHERE organization_id = $2
+      AND ($1::VARCHAR IS NULL OR status = $1)
+      ORDER BY id DESC
+      "#,
+    )
+    .bind(status)
+    .bind(current_tenant()?)
+    .fetch_all(&self.pool)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    Ok(requests)
+  }
+
+  pub async fn get_role_request(&self, request_id: i32) -> Result<RoleRequestDetail, AppError> {
+    self.expire_role_requests().await?;
+
+    let request: RoleRequest =
+      sqlx::query_as("SELECT * FROM role_requests WHERE id = $1 AND organization_id = $2")
+        .bind(request_id)
+        .bind(current_tenant()?)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|err| AppError::DatabaseError(err.to_string()))?
+        .ok_or(AppError::NotFound(format!(
+          "Role request: {} not found",
+          request_id
+        )))?;
+
+    let history: Vec<RoleRequestEvent> = sqlx::query_as(
+      r#"
+      SELECT *
+      FROM role_request_events
+      WHERE request_id = $1
+      ORDER BY id
+      "#,
+    )
+    .bind(request_id)
+    .fetch_all(&self.pool)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    Ok(RoleRequestDetail { request, history })
+  }
+
+  /// Approve or reject a pending request. Approval grants the requested roles
+  /// in the same transaction; neither the requester nor the user whose roles it changes
+  /// can review it.
+  pub async fn review_role_request(
+    &self,
+    reviewer: &User,
+    request_id: i32,
+    approve: bool,
+    comment: Option<String>,
+  ) -> Result<RoleRequest, AppError> {
+    let mut transaction = self
+      .pool
+      .begin()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    let request: RoleRequest = sqlx::query_as(
+      "SELECT * FROM role_requests WHERE id = $1 AND organization_id = $2 FOR UPDATE",
+    )
+    .bind(request_id)
+    .bind(current_tenant()?)
+    .fetch_optional(&mut *transaction)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?
+    .ok_or(AppError::NotFound(format!(
+      "Role request: {} not found",
+      request_id
+    )))?;
+
+    if request.status != RoleRequestStatus::Pending.as_ref() {
+      return Err(AppError::Conflict(format!(
+        "Role request: {} is already {}",
+        request_id, request.status
+      )));
+    }
+
+    if request.expires_at <= Utc::now() {
+      set_status(
+        &mut transaction,
+        request_id,
+        RoleRequestStatus::Expired,
+        None,
+      )
+      .await?;
+      record_event(&mut transaction, request_id, None, "expired", None).await?;
+      transaction
+        .commit()
+        .await
+        .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+      return Err(AppError::Conflict(format!(
+        "Role request: {} has expired",
+        request_id
+      )));
+    }
+
+    if request.requested_by == Some(reviewer.user_info.id) {
+      return Err(AppError::Forbidden(
+        "role request must be reviewed by a different admin".to_string(),
+      ));
+    }
+    if request.user_id == reviewer.user_info.id {
+      return Err(AppError::Forbidden(
+        "role request can't be reviewed by the admin it grants roles to".to_string(),
+      ));
+    }
+
+    let (status, audit) = if approve {
+      // the requested grants replace those of the same roles, other changes made since stay
+      let mut roles = direct_roles(&mut transaction, request.user_id).await?;
+      roles.retain(|role| {
+        !request
+          .roles
+          .iter()
+          .any(|requested| requested.id == role.id)
+      });
+      roles.extend(request.roles.0.iter().cloned());
+      let audit = self
+        .update_roles(
+          &mut transaction,
+          roles,
+          request.user_id,
+          reviewer.user_info.id,
+        )
+        .await?;
+      (RoleRequestStatus::Approved, Some(audit))
+    } else {
+      (RoleRequestStatus::Rejected, None)
+    };
+
+    let request = set_status(
+      &mut transaction,
+      request_id,
+      status.clone(),
+      Some(reviewer.user_info.id),
+    )
+    .await?;
+    record_event(
+      &mut transaction,
+      request_id,
+      Some(reviewer.user_info.id),
+      status.as_ref(),
+      comment,
+    )
+    .await?;
+    if let Some(audit) = audit {
+      record_audit(&mut transaction, audit).await?;
+    }
+
+    transaction
+      .commit()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    info!(
+      request_id,
+      reviewer = reviewer.user_info.id,
+      status = status.as_ref(),
+      "role request reviewed"
+    );
+    Ok(request)
+  }
+
+  /// Mark pending requests past their `expires_at` as expired.
+  pub async fn expire_role_requests(&self) -> Result<u64, AppError> {
+    let result = sqlx::query(
+      r#"
+      WITH expired AS (
+        UPDATE role_requests
+        SET status = 'expired', updated_at = NOW()
+        WHERE status = 'pending' AND expires_at <= NOW()
+        RETURNING id
+      )
+      INSERT INTO role_request_events (request_id, event)
+      SELECT id, 'expired' FROM expired
+      "#,
+    )
+    .execute(&self.pool)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    Ok(result.rows_affected())
+  }
+}
+
+async fn set_status(
+  transaction: &mut Transaction<'_, Postgres>,
+  request_id: i32,
+  status: RoleRequestStatus,
+  reviewed_by: Option<i32>,
+) -> Result<RoleRequest, AppError> {
+  let request = sqlx::query_as(
+    r#"
+    UPDATE role_requests
+    SET status = $1, reviewed_by = $2, reviewed_at = CASE WHEN $2 IS NULL THEN NULL ELSE $3 END, updated_at = $3
+    WHERE id = $4
+    RETURNING *
+    "#,
+  )
+  .bind(status.as_ref())
+  .bind(reviewed_by)
+  .bind(Utc::now())
+  .bind(request_id)
+  .fetch_one(&mut **transaction)
+  .await
+  .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+  Ok(request)
+}
+
+async fn record_event(
+  transaction: &mut Transaction<'_, Postgres>,
+  request_id: i32,
+  actor_id: Option<i32>,
+  event: &str,
+  comment: Option<String>,
+) -> Result<(), AppError> {
+  sqlx::query(
+    r#"
+    INSERT INTO role_request_events (request_id, actor_id, event, comment)
+    VALUES ($1, $2, $3, $4)
+    "#,
+  )
+  .bind(request_id)
+  .bind(actor_id)
+  .bind(event)
+  .bind(comment)
+  .execute(&mut **transaction)
+  .await
+  .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+  Ok(())
+}
+
+/// Whether granting `requested` extends `held`, the direct grant of the same role if any.
+fn is_escalation(held: Option<&RoleIn>, requested: &RoleIn) -> bool {
+  match held.map(|role| role.expires_at) {
+    None => true,
+    Some(None) => false,
+    Some(Some(held_until)) => requested
+      .expires_at
+      .is_none_or(|requested_until| requested_until > held_until),
+  }
+}
@@ -0,0 +1,309 @@
+#[cfg(test)]
+mod util_tests {
+  use crate::common::{DEFAULT_ORGANIZATION_ID, with_tenant};
+  use crate::modules::groups::CreateGroup;
+  use crate::modules::users::{RoleIn, RoleName, UpdateUserOptions, VecExtensions};
+  use crate::{AppError, AppState};
+  use anyhow::Result;
+  use chrono::{DateTime, Duration, SubsecRound, Utc};
+  use serial_test::serial;
+
+  async fn four_eyes_state() -> Result<(sqlx_db_tester::TestPg, AppState, AppState)> {
+    let (tdb, state) = AppState::init_test_state().await?;
+    let mut config = state.config.clone();
+    config.approvals.enabled = true;
+    let four_eyes = AppState::new(config, state.pool.clone());
+    Ok((tdb, state, four_eyes))
+  }
+
+  fn role(id: i32, name: &str, expires_at: Option<DateTime<Utc>>) -> RoleIn {
+    RoleIn {
+      id,
+      name: name.to_string(),
+      expires_at,
+    }
+  }
+
+  fn admin_role() -> Vec<RoleIn> {
+    vec![role(3, "Admin", None)]
+  }
+
+  fn set_roles(roles: Vec<RoleIn>) -> UpdateUserOptions {
+    UpdateUserOptions {
+      roles: Some(roles),
+      ..Default::default()
+    }
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn approve_role_request_test() -> Result<()> {
+    let (_tdb, state, four_eyes) = four_eyes_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      // bob becomes a second admin before four-eyes mode applies
+      state.set_roles(admin_role(), 3, 1).await?;
+      let superman = state.get_user_by_id(1).await?;
+      let bob = state.get_user_by_id(3).await?;
+
+      let (_, request) = four_eyes
+        .update_user(&superman, 2, set_roles(admin_role()), None)
+        .await?;
+      let request = request.expect("granting Admin needs approval");
+      assert_eq!(request.status, "pending");
+      assert!(state.get_user_roles(2).await?.is_empty());
+
+      // role changes made meanwhile survive the approval
+      let (_, other) = four_eyes
+        .update_user(&superman, 2, set_roles(vec![role(1, "User", None)]), None)
+        .await?;
+      assert!(other.is_none());
+
+      let own_review = four_eyes
+        .review_role_request(&superman, request.id, true, None)
+        .await;
+      assert!(own_review.is_err());
+
+      let request = four_eyes
+        .review_role_request(&bob, request.id, true, Some("ok".to_string()))
+        .await?;
+      assert_eq!(request.status, "approved");
+      assert_eq!(request.reviewed_by, Some(3));
+      let roles = state.get_user_roles(2).await?;
+      assert!(roles.contains_name(RoleName::Admin));
+      assert!(roles.contains_name(RoleName::User));
+      // with the User grant made meanwhile, each audited and published
+      let audits: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM audit_events WHERE target_id = 2 AND action = 'user.update_roles'",
+      )
+      .fetch_one(&state.pool)
+      .await?;
+      let events: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM outbox_events WHERE aggregate_id = 2 AND event_type = 'roles_changed'",
+      )
+      .fetch_one(&state.pool)
+      .await?;
+      assert_eq!((audits, events), (2, 2));
+
+      let detail = four_eyes.get_role_request(request.id).await?;
+      let events: Vec<&str> = detail.history.iter().map(|e| e.event.as_str()).collect();
+      assert_eq!(events, vec!["created", "approved"]);
+
+      // a decided request can't be reviewed again
+      let again = four_eyes
+        .review_role_request(&bob, request.id, false, None)
+        .await;
+      assert!(again.is_err());
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn role_escalation_test() -> Result<()> {
+    let (_tdb, state, four_eyes) = four_eyes_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let superman = state.get_user_by_id(1).await?;
+      let until = (Utc::now() + Duration::days(1)).trunc_subsecs(6);
+      state
+        .set_roles(vec![role(3, "Admin", Some(until))], 2, 1)
+        .await?;
+
+      // making a time-limited Admin grant permanent, or longer, is an escalation
+      for expires_at in [None, Some(until + Duration::days(1))] {
+        let (alice, request) = four_eyes
+          .update_user(
+            &superman,
+            2,
+            set_roles(vec![role(3, "Admin", expires_at)]),
+            None,
+          )
+          .await?;
+        let document = four_eyes.get_user_document(&alice).await?;
+        assert_eq!(document.roles[0].expires_at, Some(until));
+
+        // alice is an admin, but not a reviewer of their own escalation
+        let review = four_eyes
+          .review_role_request(&alice, request.expect("needs approval").id, true, None)
+          .await;
+        assert!(matches!(review, Err(AppError::Forbidden(_))));
+      }
+      let earlier = until - Duration::hours(1);
+      let (alice, request) = four_eyes
+        .update_user(
+          &superman,
+          2,
+          set_roles(vec![role(3, "Admin", Some(earlier))]),
+          None,
+        )
+        .await?;
+      assert!(request.is_none());
+      let document = four_eyes.get_user_document(&alice).await?;
+      assert_eq!(document.roles[0].expires_at, Some(earlier));
+
+      // Admin through a group doesn't make a direct grant any less of an escalation
+      let group = state
+        .create_group(CreateGroup {
+          name: "admins".to_string(),
+          description: None,
+        })
+        .await?;
+      sqlx::query("INSERT INTO group_roles (group_id, role_id) VALUES ($1, 3)")
+        .bind(group.id)
+        .execute(&state.pool)
+        .await?;
+      state.add_group_member(group.id, 4).await?;
+      let (_, request) = four_eyes
+        .update_user(&superman, 4, set_roles(admin_role()), None)
+        .await?;
+      assert!(request.is_some());
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn reject_and_expire_role_request_test() -> Result<()> {
+    let (_tdb, state, four_eyes) = four_eyes_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      state.set_roles(admin_role(), 3, 1).await?;
+      let superman = state.get_user_by_id(1).await?;
+      let bob = state.get_user_by_id(3).await?;
+
+      // granting a non-privileged role needs no approval
+      let (alice, request) = four_eyes
+        .update_user(&superman, 2, set_roles(vec![role(1, "User", None)]), None)
+        .await?;
+      assert!(request.is_none());
+      assert!(alice.roles.contains_name(RoleName::User));
+
+      let (_, request) = four_eyes
+        .update_user(&superman, 2, set_roles(admin_role()), None)
+        .await?;
+      let request = four_eyes
+        .review_role_request(&bob, request.unwrap().id, false, Some("no".to_string()))
+        .await?;
+      assert_eq!(request.status, "rejected");
+      assert!(
+        !state
+          .get_user_roles(2)
+          .await?
+          .contains_name(RoleName::Admin)
+      );
+
+      let (_, request) = four_eyes
+        .update_user(&superman, 4, set_roles(admin_role()), None)
+        .await?;
+      let request = request.unwrap();
+      sqlx::query("UPDATE role_requests SET expires_at = NOW() - INTERVAL '1 hour' WHERE id = $1")
+        .bind(request.id)
+        .execute(&state.pool)
+        .await?;
+      let pending = four_eyes.get_role_requests(Some("pending")).await?;
+      assert!(pending.is_empty());
+      let expired = four_eyes.get_role_requests(Some("expired")).await?;
+      assert_eq!(expired.len(), 1);
+      let review = four_eyes
+        .review_role_request(&bob, request.id, true, None)
+        .await;
+      assert!(review.is_err());
+      anyhow::Ok(())
+    })
+    .await
+  }
+}
+
+#[cfg(test)]
+mod integration_tests {
+  use crate::AppState;
+  use crate::common::{DEFAULT_ORGANIZATION_ID, with_tenant};
+  use crate::test_util::{get_token, spawn_app};
+  use anyhow::Result;
+  use axum::http::StatusCode;
+  use reqwest::Client;
+  use serde_json::json;
+  use serial_test::serial;
+
+  #[tokio::test]
+  #[serial]
+  async fn role_request_handler_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let admin_role = json!([{"id": 3, "name": "Admin"}]);
+      state
+        .set_roles(serde_json::from_value(admin_role.clone())?, 3, 1)
+        .await?;
+      let mut config = state.config.clone();
+      config.approvals.enabled = true;
+      let (addr, tx) = spawn_app(AppState::new(config, state.pool.clone())).await?;
+
+      let client = Client::builder().no_proxy().build().unwrap();
+      let superman = get_token(&client, &addr.to_string(), "superman", "supermannofly").await?;
+      let bob = get_token(&client, &addr.to_string(), "bob", "123456").await?;
+
+      // a failed update files no request
+      let response = client
+        .patch(format!("http://{}/users/{}", addr, 2))
+        .json(&json!({"roles": admin_role}))
+        .header("Authorization", format!("Bearer {}", superman))
+        .header("If-Match", "\"999\"")
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+
+      let response = client
+        .patch(format!("http://{}/users/{}", addr, 2))
+        .json(&json!({"roles": admin_role}))
+        .header("Authorization", format!("Bearer {}", superman))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::ACCEPTED);
+      let pending: serde_json::Value = response.json().await?;
+      assert_eq!(pending["user"]["roles"].as_array().unwrap().len(), 0);
+      let request_id = pending["role_request"]["id"].as_i64().unwrap();
+
+      let response = client
+        .get(format!("http://{}/role-requests?status=pending", addr))
+        .header("Authorization", format!("Bearer {}", bob))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+      let requests: serde_json::Value = response.json().await?;
+      assert_eq!(requests.as_array().unwrap().len(), 1);
+
+      let response = client
+        .post(format!(
+          "http://{}/role-requests/{}/approve",
+          addr, request_id
+        ))
+        .header("Authorization", format!("Bearer {}", superman))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+      let response = client
+        .post(format!(
+          "http://{}/role-requests/{}/approve",
+          addr, request_id
+        ))
+        .json(&json!({"comment": "approved by bob"}))
+        .header("Authorization", format!("Bearer {}", bob))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+
+      let response = client
+        .get(format!("http://{}/users/{}", addr, 2))
+        .header("Authorization", format!("Bearer {}", superman))
+        .send()
+        .await?;
+      let user: serde_json::Value = response.json().await?;
+      assert_eq!(user["roles"][0]["name"], "Admin");
+
+      tx.send(()).unwrap();
+      anyhow::Ok(())
+    })
+    .await
+  }
+}
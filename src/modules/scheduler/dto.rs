@@ -0,0 +1,50 @@
+use super::ScheduledRun;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use validator::Validate;
+
+/// a schedule of `app.yaml` output dto
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ScheduleInfo {
+  pub name: String,
+  pub cron: String,
+  pub job: String,
+  pub payload: Value,
+  pub next_run: Option<DateTime<Utc>>,
+  pub last_run: Option<ScheduledRun>,
+}
+
+/// query parameters of `GET /scheduler/runs`, newest first
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct RunsFilter {
+  #[validate(length(min = 1, max = 64))]
+  pub schedule: Option<String>,
+  /// the previous page's `next_before`
+  pub before: Option<i64>,
+  #[serde(default = "default_limit")]
+  #[validate(range(min = 1, max = 100))]
+  pub limit: i64,
+}
+
+impl Default for RunsFilter {
+  fn default() -> Self {
+    Self {
+      schedule: None,
+      before: None,
+      limit: default_limit(),
+    }
+  }
+}
+
+/// a page of scheduled runs output dto
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RunsPage {
+  pub runs: Vec<ScheduledRun>,
+  /// pass as `before` for the next page, absent on the last one
+  pub next_before: Option<i64>,
+}
+
+fn default_limit() -> i64 {
+  20
+}
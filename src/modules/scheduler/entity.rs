@@ -0,0 +1,23 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+/// scheduled_runs table, with the outcome of the enqueued job
+#[derive(Clone, Debug, Deserialize, FromRow, Serialize)]
+pub struct ScheduledRun {
+  pub id: i64,
+  pub organization_id: i32,
+  pub schedule: String,
+  /// the tick of the cron expression
+  pub scheduled_for: DateTime<Utc>,
+  /// absent once the job has been deleted
+  pub job_id: Option<i64>,
+  /// the instance that won the tick
+  pub instance: String,
+  pub created_at: DateTime<Utc>,
+  /// status of the job: queued, running, succeeded or dead
+  pub job_status: Option<String>,
+  pub attempts: Option<i32>,
+  pub last_error: Option<String>,
+  pub finished_at: Option<DateTime<Utc>>,
+}
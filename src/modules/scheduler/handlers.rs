@@ -0,0 +1,34 @@
+use super::RunsFilter;
+use crate::modules::authz::{Action, Resource};
+use crate::modules::users::User;
+use crate::{AppError, AppState};
+use axum::{
+  Extension, Json,
+  extract::{Query, State},
+  http::StatusCode,
+  response::IntoResponse,
+};
+use tracing::info;
+use validator::Validate;
+
+pub async fn get_schedules_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+  info!("Scheduler Handler::get schedules");
+  state.authorize(&claims, Action::ManageJobs, &Resource::jobs())?;
+  let schedules = state.get_schedules().await?;
+  Ok((StatusCode::OK, Json(schedules)))
+}
+
+pub async fn get_scheduled_runs_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Query(filter): Query<RunsFilter>,
+) -> Result<impl IntoResponse, AppError> {
+  filter.validate()?;
+  info!("Scheduler Handler::get runs: filter: {:?}", filter);
+  state.authorize(&claims, Action::ManageJobs, &Resource::jobs())?;
+  let page = state.get_scheduled_runs(&filter).await?;
+  Ok((StatusCode::OK, Json(page)))
+}
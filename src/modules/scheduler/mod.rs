@@ -0,0 +1,20 @@
+pub mod dto;
+pub mod entity;
+pub mod handlers;
+pub mod services;
+pub mod tests;
+
+pub use dto::{RunsFilter, RunsPage, ScheduleInfo};
+pub use entity::ScheduledRun;
+pub use handlers::{get_scheduled_runs_handler, get_schedules_handler};
+
+use crate::AppState;
+use axum::Router;
+use axum::routing::get;
+
+pub fn scheduler_router(state: AppState) -> Router {
+  Router::new()
+    .route("/schedules", get(get_schedules_handler))
+    .route("/runs", get(get_scheduled_runs_handler))
+    .with_state(state)
+}
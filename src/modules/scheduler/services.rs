@@ -0,0 +1,200 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use sqlx::{Postgres, QueryBuilder};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use super::{RunsFilter, RunsPage, ScheduleInfo, ScheduledRun};
+use crate::common::config::ScheduleConfig;
+use crate::common::current_tenant;
+use crate::modules::jobs::insert_job;
+use crate::{AppError, AppState};
+
+/// first key of the per schedule advisory locks, the second is the hash of its name
+const SCHEDULER_LOCK: i32 = 0x5343;
+
+const RUN_COLUMNS: &str = r#"
+  SELECT r.*, j.status AS job_status, j.attempts, j.last_error, j.finished_at
+  FROM scheduled_runs r
+  LEFT JOIN jobs j ON j.id = r.job_id
+"#;
+
+impl AppState {
+  /// Enqueue the job of `schedule` for its tick at `scheduled_for` on behalf of `instance`.
+  /// `None` when another instance holds the tick or already enqueued it.
+  pub async fn fire_schedule(
+    &self,
+    schedule: &ScheduleConfig,
+    scheduled_for: DateTime<Utc>,
+    instance: &str,
+  ) -> Result<Option<i64>, AppError> {
+    let mut transaction = self
+      .pool
+      .begin()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    let locked: bool = sqlx::query_scalar("SELECT pg_try_advisory_xact_lock($1, hashtext($2))")
+      .bind(SCHEDULER_LOCK)
+      .bind(&schedule.name)
+      .fetch_one(&mut *transaction)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    if !locked {
+      return Ok(None);
+    }
+
+    let job_id = insert_job(
+      &mut transaction,
+      schedule.organization_id,
+      &schedule.job,
+      &schedule.payload,
+      Utc::now(),
+      self.config.jobs.max_attempts,
+    )
+    .await?;
+    // the lock is gone once an instance commits, the history catches the late ones
+    let run_id: Option<i64> = sqlx::query_scalar(
+      r#"
+      INSERT INTO scheduled_runs (organization_id, schedule, scheduled_for, job_id, instance)
+      VALUES ($1, $2, $3, $4, $5)
+      ON CONFLICT (schedule, scheduled_for) DO NOTHING
+      RETURNING id
+      "#,
+    )
+    .bind(schedule.organization_id)
+    .bind(&schedule.name)
+    .bind(scheduled_for)
+    .bind(job_id)
+    .bind(instance)
+    .fetch_optional(&mut *transaction)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    if run_id.is_none() {
+      return Ok(None);
+    }
+
+    transaction
+      .commit()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    info!(
+      schedule = schedule.name,
+      %scheduled_for,
+      job_id,
+      "schedule fired"
+    );
+    Ok(run_id)
+  }
+
+  /// Fire the schedules that ticked in `(since, until]`. A schedule that ticked several
+  /// times, e.g. after a pause, only fires once, for its last tick. Returns the runs created.
+  pub async fn fire_due_schedules(
+    &self,
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+    instance: &str,
+  ) -> Vec<i64> {
+    let mut runs = Vec::new();
+    for schedule in &self.config.scheduler.schedules {
+      let Some(tick) = schedule
+        .cron
+        .after(&since)
+        .take_while(|tick| *tick <= until)
+        .last()
+      else {
+        continue;
+      };
+      match self.fire_schedule(schedule, tick, instance).await {
+        Ok(Some(run_id)) => runs.push(run_id),
+        Ok(None) => (),
+        Err(e) => warn!(schedule = schedule.name, error = ?e, "fire schedule failed"),
+      }
+    }
+    runs
+  }
+
+  /// Fire the schedules of `scheduler.schedules` as they come due, meant to be spawned
+  /// at startup. Every instance may run it, each tick is only enqueued once.
+  pub async fn run_scheduler(self) {
+    let instance = format!("scheduler-{}", Uuid::new_v4().simple());
+    let kinds = self.job_registry.kinds();
+    for schedule in &self.config.scheduler.schedules {
+      if !kinds.contains(&schedule.job.as_str()) {
+        warn!(
+          schedule = schedule.name,
+          job = schedule.job,
+          "schedule of an unknown job kind, its jobs will fail"
+        );
+      }
+    }
+    let mut interval =
+      tokio::time::interval(Duration::from_secs(self.config.scheduler.poll_interval));
+    let mut since = Utc::now();
+    loop {
+      interval.tick().await;
+      let until = Utc::now();
+      self.fire_due_schedules(since, until, &instance).await;
+      since = until;
+    }
+  }
+
+  /// The schedules running as the active organization, with their next and last run.
+  pub async fn get_schedules(&self) -> Result<Vec<ScheduleInfo>, AppError> {
+    let tenant = current_tenant()?;
+    let mut schedules = Vec::new();
+    for schedule in &self.config.scheduler.schedules {
+      if schedule.organization_id != tenant {
+        continue;
+      }
+      let last_run = self
+        .get_scheduled_runs(&RunsFilter {
+          schedule: Some(schedule.name.clone()),
+          limit: 1,
+          ..Default::default()
+        })
+        .await?
+        .runs
+        .pop();
+      schedules.push(ScheduleInfo {
+        name: schedule.name.clone(),
+        cron: schedule.cron.to_string(),
+        job: schedule.job.clone(),
+        payload: schedule.payload.clone(),
+        next_run: schedule.cron.upcoming(Utc).next(),
+        last_run,
+      });
+    }
+    Ok(schedules)
+  }
+
+  pub async fn get_scheduled_runs(&self, filter: &RunsFilter) -> Result<RunsPage, AppError> {
+    let mut query = QueryBuilder::<Postgres>::new(RUN_COLUMNS);
+    query
+      .push(" WHERE r.organization_id = ")
+      .push_bind(current_tenant()?);
+    if let Some(schedule) = &filter.schedule {
+      query.push(" AND r.schedule = ").push_bind(schedule.clone());
+    }
+    if let Some(before) = filter.before {
+      query.push(" AND r.id < ").push_bind(before);
+    }
+    // one extra row tells whether there is a next page
+    query
+      .push(" ORDER BY r.id DESC LIMIT ")
+      .push_bind(filter.limit + 1);
+
+    let mut runs: Vec<ScheduledRun> = query
+      .build_query_as()
+      .fetch_all(&self.pool)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    let next_before = if runs.len() as i64 > filter.limit {
+      runs.truncate(filter.limit as usize);
+      runs.last().map(|run| run.id)
+    } else {
+      None
+    };
+    Ok(RunsPage { runs, next_before })
+  }
+}
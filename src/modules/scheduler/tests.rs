@@ -0,0 +1,229 @@
+#[cfg(test)]
+mod util_tests {
+  use crate::AppState;
+  use crate::common::config::{ScheduleConfig, SchedulerConfig};
+  use crate::common::{DEFAULT_ORGANIZATION_ID, across_tenants, with_tenant};
+  use crate::modules::organizations::CreateOrganization;
+  use crate::modules::scheduler::*;
+  use crate::modules::users::CreateUser;
+  use anyhow::Result;
+  use chrono::{Duration, Utc};
+  use serial_test::serial;
+
+  fn schedule(name: &str, cron: &str, job: &str) -> Result<ScheduleConfig> {
+    Ok(serde_yaml_ng::from_str(&format!(
+      "{{name: {}, cron: \"{}\", job: {}}}",
+      name, cron, job
+    ))?)
+  }
+
+  #[test]
+  fn schedule_config_test() -> Result<()> {
+    let every_minute = schedule("a", "* * * * *", "prune_outbox")?;
+    let every_second = schedule("b", "* * * * * *", "prune_outbox")?;
+    let now = Utc::now();
+    let ticks: Vec<_> = every_minute.cron.after(&now).take(2).collect();
+    assert_eq!(ticks[1] - ticks[0], Duration::minutes(1));
+    let ticks: Vec<_> = every_second.cron.after(&now).take(2).collect();
+    assert_eq!(ticks[1] - ticks[0], Duration::seconds(1));
+    assert_eq!(every_minute.payload, serde_json::json!({}));
+    assert_eq!(every_minute.organization_id, 1);
+
+    assert!(schedule("c", "61 * * * *", "prune_outbox").is_err());
+    assert!(schedule("c", "nonsense", "prune_outbox").is_err());
+
+    let config: SchedulerConfig = serde_yaml_ng::from_str(
+      r#"
+      poll_interval: 5
+      schedules:
+        - {name: a, cron: "0 3 * * *", job: prune_outbox, payload: {older_than: 60}}
+      "#,
+    )?;
+    assert_eq!(config.schedules[0].payload["older_than"], 60);
+    Ok(())
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn fire_schedule_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let mut config = state.config.clone();
+      config.scheduler.schedules = vec![schedule("sweep", "* * * * *", "sweep_expired_grants")?];
+      let state = AppState::new(config, state.pool.clone());
+
+      // missed ticks are fired once, for the last one
+      let now = Utc::now();
+      let runs = state
+        .fire_due_schedules(now - Duration::minutes(3), now, "a")
+        .await;
+      assert_eq!(runs.len(), 1);
+      // another instance polling the same window finds the tick taken
+      let runs = state
+        .fire_due_schedules(now - Duration::minutes(2), now, "b")
+        .await;
+      assert!(runs.is_empty());
+
+      // an instance holding the lock of a schedule wins its tick
+      let schedule = &state.config.scheduler.schedules[0];
+      let next = schedule.cron.after(&now).next().unwrap();
+      let mut transaction = state.pool.begin().await?;
+      sqlx::query("SELECT pg_advisory_xact_lock($1, hashtext($2))")
+        .bind(0x5343)
+        .bind("sweep")
+        .execute(&mut *transaction)
+        .await?;
+      assert_eq!(state.fire_schedule(schedule, next, "b").await?, None);
+      transaction.commit().await?;
+      assert!(state.fire_schedule(schedule, next, "b").await?.is_some());
+
+      let page = state.get_scheduled_runs(&RunsFilter::default()).await?;
+      assert_eq!(page.runs.len(), 2);
+      assert_eq!(page.runs[0].scheduled_for, next);
+      assert_eq!(page.runs[0].instance, "b");
+      assert_eq!(page.runs[1].instance, "a");
+      assert_eq!(page.runs[1].job_status.as_deref(), Some("queued"));
+      assert!(page.next_before.is_none());
+
+      // the history shows the outcome of the jobs
+      for job in state.claim_jobs(10, "w").await? {
+        state.execute_job(job, "w").await?;
+      }
+      let schedules = state.get_schedules().await?;
+      assert_eq!(schedules.len(), 1);
+      assert_eq!(schedules[0].cron, "0 * * * * *");
+      assert!(schedules[0].next_run.unwrap() > Utc::now());
+      let last_run = schedules[0].last_run.as_ref().unwrap();
+      assert_eq!(last_run.scheduled_for, next);
+      assert_eq!(last_run.job_status.as_deref(), Some("succeeded"));
+      assert_eq!(last_run.attempts, Some(1));
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn cleanup_jobs_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let superman = state.get_user_by_id(1).await?;
+      let acme = state
+        .create_organization(
+          &superman,
+          CreateOrganization {
+            name: "acme".to_string(),
+          },
+        )
+        .await?;
+      let newton = with_tenant(acme.id, async {
+        let newton = state
+          .create_user(CreateUser::new("newton", "apple123"))
+          .await?;
+        state.delete_user(newton.user_info.id, None).await?;
+        anyhow::Ok(newton.user_info.id)
+      })
+      .await?;
+      state.delete_user(2, None).await?;
+      state.delete_user(3, None).await?;
+      across_tenants(
+        sqlx::query(
+          "UPDATE users SET deleted_at = NOW() - INTERVAL '40 days' WHERE id = 2 OR id = $1",
+        )
+        .bind(newton)
+        .execute(&state.pool),
+      )
+      .await?;
+      // the users of every organization are purged, not only those of the schedule's
+      assert_eq!(state.purge_deleted_users(30 * 86400).await?, 2);
+      assert!(!state.is_user_exists_by_id(2).await?);
+      assert!(state.is_user_exists_by_id(3).await?);
+      assert!(!with_tenant(acme.id, state.is_user_exists_by_id(newton)).await?);
+
+      sqlx::query(
+        r#"
+        INSERT INTO invitations (organization_id, roles, expires_at, accepted_at)
+        VALUES (1, '[]', NOW() - INTERVAL '1 day', NULL),
+               (1, '[]', NOW() - INTERVAL '1 day', NOW() - INTERVAL '2 days'),
+               (1, '[]', NOW() + INTERVAL '1 day', NULL)
+        "#,
+      )
+      .execute(&state.pool)
+      .await?;
+      assert_eq!(state.purge_expired_invitations().await?, 1);
+      assert_eq!(state.purge_expired_invitations().await?, 0);
+      anyhow::Ok(())
+    })
+    .await
+  }
+}
+
+#[cfg(test)]
+mod integration_tests {
+  use crate::AppState;
+  use crate::common::{DEFAULT_ORGANIZATION_ID, with_tenant};
+  use crate::test_util::{get_token, spawn_app};
+  use anyhow::Result;
+  use axum::http::StatusCode;
+  use chrono::Utc;
+  use reqwest::Client;
+  use serial_test::serial;
+
+  #[tokio::test]
+  #[serial]
+  async fn scheduler_handler_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let schedule = state.config.scheduler.schedules[0].clone();
+      state.fire_schedule(&schedule, Utc::now(), "a").await?;
+      let (addr, tx) = spawn_app(state).await?;
+
+      let client = Client::new();
+      let addr = addr.to_string();
+      let token = get_token(&client, &addr, "superman", "supermannofly").await?;
+
+      let response = client
+        .get(format!("http://{}/scheduler/schedules", addr))
+        .bearer_auth(&token)
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+      let schedules: serde_json::Value = response.json().await?;
+      assert_eq!(schedules[0]["name"], schedule.name.as_str());
+      assert_eq!(schedules[0]["last_run"]["job_status"], "queued");
+      assert!(schedules[1]["last_run"].is_null());
+
+      let response = client
+        .get(format!(
+          "http://{}/scheduler/runs?schedule={}",
+          addr, schedule.name
+        ))
+        .bearer_auth(&token)
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+      let page: serde_json::Value = response.json().await?;
+      assert_eq!(page["runs"].as_array().unwrap().len(), 1);
+      assert_eq!(page["runs"][0]["instance"], "a");
+
+      let response = client
+        .get(format!("http://{}/scheduler/runs?limit=101", addr))
+        .bearer_auth(&token)
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+      let token = get_token(&client, &addr, "alice", "123456").await?;
+      let response = client
+        .get(format!("http://{}/scheduler/runs", addr))
+        .bearer_auth(&token)
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+      tx.send(()).unwrap();
+      anyhow::Ok(())
+    })
+    .await
+  }
+}
@@ -0,0 +1,190 @@
+use crate::modules::users::{User, UserStatus};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+/// file formats of imports and exports
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferFormat {
+  /// comma separated with a header row, roles separated by `;`
+  Csv,
+  /// one JSON object per line
+  Ndjson,
+}
+
+impl TransferFormat {
+  /// Pick the format from a `Content-Type` or `Accept` header value.
+  pub fn from_media_type(media_type: &str) -> Option<Self> {
+    let essence = media_type.split(';').next().unwrap_or_default().trim();
+    match essence.to_ascii_lowercase().as_str() {
+      "text/csv" => Some(TransferFormat::Csv),
+      "application/x-ndjson" | "application/jsonl" | "application/json-lines" => {
+        Some(TransferFormat::Ndjson)
+      }
+      _ => None,
+    }
+  }
+
+  pub fn media_type(&self) -> &'static str {
+    match self {
+      TransferFormat::Csv => "text/csv; charset=utf-8",
+      TransferFormat::Ndjson => "application/x-ndjson",
+    }
+  }
+
+  pub fn extension(&self) -> &'static str {
+    match self {
+      TransferFormat::Csv => "csv",
+      TransferFormat::Ndjson => "ndjson",
+    }
+  }
+}
+
+/// user import input dto, the format defaults to the request's `Content-Type`
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ImportParams {
+  /// check every row without saving anything
+  #[serde(default)]
+  pub dry_run: bool,
+  pub format: Option<TransferFormat>,
+}
+
+/// user export input dto, the format defaults to the request's `Accept`, then CSV
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ExportParams {
+  pub format: Option<TransferFormat>,
+}
+
+/// one user of an import, exactly one of `password` and `password_hash` is required
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+pub struct ImportRow {
+  #[validate(length(
+    min = 3,
+    max = 50,
+    message = "username length must be between 3 and 50 characters"
+  ))]
+  pub username: String,
+  #[validate(length(
+    min = 6,
+    max = 50,
+    message = "password length must be between 6 and 50 characters"
+  ))]
+  pub password: Option<String>,
+  /// argon2 PHC string from the previous system
+  pub password_hash: Option<String>,
+  #[validate(email(message = "invalid email"))]
+  pub email: Option<String>,
+  /// role names, `User` when empty
+  #[serde(default)]
+  pub roles: Vec<String>,
+}
+
+/// CSV shape of `ImportRow`
+#[derive(Debug, Deserialize)]
+pub struct CsvImportRow {
+  pub username: String,
+  pub password: Option<String>,
+  pub password_hash: Option<String>,
+  pub email: Option<String>,
+  pub roles: Option<String>,
+}
+
+impl From<CsvImportRow> for ImportRow {
+  fn from(row: CsvImportRow) -> Self {
+    Self {
+      username: row.username,
+      password: row.password,
+      password_hash: row.password_hash,
+      email: row.email,
+      roles: split_names(row.roles.as_deref().unwrap_or_default()),
+    }
+  }
+}
+
+/// Split a `;` separated list, dropping empty entries.
+pub fn split_names(names: &str) -> Vec<String> {
+  names
+    .split(';')
+    .map(str::trim)
+    .filter(|name| !name.is_empty())
+    .map(str::to_string)
+    .collect()
+}
+
+/// result of an import
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ImportReport {
+  pub dry_run: bool,
+  /// data rows read, the CSV header and blank lines excluded
+  pub rows: usize,
+  /// rows imported, or that would be without `dry_run`
+  pub imported: usize,
+  pub failed: usize,
+  pub errors: Vec<ImportRowError>,
+}
+
+/// why a row of an import was rejected
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ImportRowError {
+  /// 1-based line of the input
+  pub line: usize,
+  pub username: Option<String>,
+  pub error: String,
+}
+
+/// one user of an export
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExportRow {
+  pub id: i32,
+  pub username: String,
+  pub email: Option<String>,
+  pub status: UserStatus,
+  pub roles: Vec<String>,
+  pub permissions: Vec<String>,
+  pub created_at: DateTime<Utc>,
+}
+
+impl From<User> for ExportRow {
+  fn from(user: User) -> Self {
+    Self {
+      id: user.user_info.id,
+      username: user.user_info.username,
+      email: user.user_info.email,
+      status: user.user_info.status,
+      roles: user.roles.into_iter().map(|role| role.name).collect(),
+      permissions: user
+        .permissions
+        .into_iter()
+        .map(|permission| permission.name)
+        .collect(),
+      created_at: user.user_info.created_at,
+    }
+  }
+}
+
+/// CSV shape of `ExportRow`, roles and permissions separated by `;`
+#[derive(Debug, Serialize)]
+pub struct CsvExportRow {
+  pub id: i32,
+  pub username: String,
+  pub email: Option<String>,
+  pub status: UserStatus,
+  pub roles: String,
+  pub permissions: String,
+  pub created_at: DateTime<Utc>,
+}
+
+impl From<ExportRow> for CsvExportRow {
+  fn from(row: ExportRow) -> Self {
+    Self {
+      id: row.id,
+      username: row.username,
+      email: row.email,
+      status: row.status,
+      roles: row.roles.join(";"),
+      permissions: row.permissions.join(";"),
+      created_at: row.created_at,
+    }
+  }
+}
@@ -0,0 +1,76 @@
+use super::{ExportParams, ImportParams, TransferFormat};
+use crate::AppState;
+use crate::common::errors::AppError;
+use crate::modules::authz::{Action, Resource};
+use crate::modules::users::User;
+
+use axum::{
+  Extension, Json,
+  body::Body,
+  extract::{Query, State},
+  http::{HeaderMap, StatusCode, header},
+  response::IntoResponse,
+};
+use tracing::info;
+
+pub async fn import_users_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Query(params): Query<ImportParams>,
+  headers: HeaderMap,
+  body: Body,
+) -> Result<impl IntoResponse, AppError> {
+  state.authorize(&claims, Action::ImportUsers, &Resource::users())?;
+  let format = params
+    .format
+    .or_else(|| media_type_format(&headers, header::CONTENT_TYPE))
+    .ok_or_else(|| {
+      AppError::UnsupportedMediaType("imports must be text/csv or application/x-ndjson".to_string())
+    })?;
+  info!(
+    "User Transfer Handler::import users: {:?}, dry run: {}",
+    format, params.dry_run
+  );
+  let report = state
+    .import_users(
+      claims.user_info.id,
+      format,
+      params.dry_run,
+      body.into_data_stream(),
+    )
+    .await?;
+  Ok((StatusCode::OK, Json(report)))
+}
+
+pub async fn export_users_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Query(params): Query<ExportParams>,
+  headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+  state.authorize(&claims, Action::ExportUsers, &Resource::users())?;
+  let format = params
+    .format
+    .or_else(|| media_type_format(&headers, header::ACCEPT))
+    .unwrap_or(TransferFormat::Csv);
+  info!("User Transfer Handler::export users: {:?}", format);
+  let users = state.export_users(format)?;
+  Ok((
+    StatusCode::OK,
+    [
+      (header::CONTENT_TYPE, format.media_type().to_string()),
+      (
+        header::CONTENT_DISPOSITION,
+        format!("attachment; filename=\"users.{}\"", format.extension()),
+      ),
+    ],
+    Body::from_stream(users),
+  ))
+}
+
+fn media_type_format(headers: &HeaderMap, name: header::HeaderName) -> Option<TransferFormat> {
+  headers
+    .get(name)
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| value.split(',').find_map(TransferFormat::from_media_type))
+}
@@ -0,0 +1,22 @@
+pub mod dto;
+pub mod handlers;
+pub mod services;
+pub mod tests;
+
+pub use dto::{
+  CsvExportRow, CsvImportRow, ExportParams, ExportRow, ImportParams, ImportReport, ImportRow,
+  ImportRowError, TransferFormat,
+};
+pub use handlers::{export_users_handler, import_users_handler};
+
+use crate::AppState;
+use axum::Router;
+use axum::routing::{get, post};
+
+/// Bulk import and export, served under `/users`.
+pub fn user_transfer_router(state: AppState) -> Router {
+  Router::new()
+    .route("/import", post(import_users_handler))
+    .route("/export", get(export_users_handler))
+    .with_state(state)
+}
@@ -0,0 +1,477 @@
+use std::collections::{HashMap, HashSet};
+
+use argon2::password_hash::PasswordHash;
+use axum::body::Bytes;
+use futures_util::{Stream, StreamExt};
+use sqlx::{Acquire, Postgres, Transaction};
+use validator::Validate;
+
+use super::{
+  CsvExportRow, CsvImportRow, ExportRow, ImportReport, ImportRow, ImportRowError, TransferFormat,
+};
+use crate::common::{current_tenant, hash_password, with_tenant};
+use crate::modules::audit::{AuditAction, NewAuditEvent, record_audit};
+use crate::modules::outbox::publish_event;
+use crate::modules::users::{
+  CreateUser, PaginationParams, RoleIn, RoleName, User, UserFilter, insert_user_hashed,
+  user_created, user_snapshot,
+};
+use crate::{AppError, AppState};
+
+/// Longest accepted line of an import, to bound the memory of a request.
+const MAX_LINE_BYTES: usize = 64 * 1024;
+
+const EXPORT_CSV_HEADER: [&str; 7] = [
+  "id",
+  "username",
+  "email",
+  "status",
+  "roles",
+  "permissions",
+  "created_at",
+];
+
+impl AppState {
+  /// Import users line by line from `body` into the active organization.
+  ///
+  /// Every `user_transfer.batch_size` rows are saved in one transaction, a rejected
+  /// row only rolls back its own savepoint. With `dry_run` every batch is rolled back.
+  pub async fn import_users<S, E>(
+    &self,
+    actor_id: i32,
+    format: TransferFormat,
+    dry_run: bool,
+    body: S,
+  ) -> Result<ImportReport, AppError>
+  where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: std::fmt::Display,
+  {
+    let role_ids: HashMap<String, i32> = sqlx::query_as("SELECT name, id FROM roles")
+      .fetch_all(&self.pool)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?
+      .into_iter()
+      .collect();
+    let batch_size = self.config.user_transfer.batch_size.max(1);
+
+    let mut report = ImportReport {
+      dry_run,
+      ..Default::default()
+    };
+    let mut lines = Lines::new(body);
+    let mut header = None;
+    let mut usernames = HashSet::new();
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut line = 0;
+    while let Some(bytes) = lines.next_line().await? {
+      line += 1;
+      let text = match String::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(_) => {
+          report.rows += 1;
+          report.reject(line, None, "line is not valid UTF-8".to_string());
+          continue;
+        }
+      };
+      if text.trim().is_empty() {
+        continue;
+      }
+      let row = match (format, &header) {
+        (TransferFormat::Csv, None) => {
+          header = Some(parse_csv_header(&text)?);
+          continue;
+        }
+        (TransferFormat::Csv, Some(header)) => parse_csv_row(&text, header),
+        (TransferFormat::Ndjson, _) => {
+          serde_json::from_str::<ImportRow>(&text).map_err(|err| err.to_string())
+        }
+      };
+      report.rows += 1;
+
+      let row = match row.and_then(check_row) {
+        Ok(row) => row,
+        Err(error) => {
+          report.reject(line, None, error);
+          continue;
+        }
+      };
+      if !usernames.insert(row.username.to_lowercase()) {
+        let error = "username appears more than once in the import".to_string();
+        report.reject(line, Some(row.username), error);
+        continue;
+      }
+      batch.push((line, row));
+      if batch.len() >= batch_size {
+        let rows = std::mem::take(&mut batch);
+        self
+          .import_batch(actor_id, &role_ids, rows, &mut report)
+          .await?;
+      }
+    }
+    if !batch.is_empty() {
+      self
+        .import_batch(actor_id, &role_ids, batch, &mut report)
+        .await?;
+    }
+    // rows rejected before reaching the database are reported ahead of their batch
+    report.errors.sort_by_key(|error| error.line);
+    Ok(report)
+  }
+
+  async fn import_batch(
+    &self,
+    actor_id: i32,
+    role_ids: &HashMap<String, i32>,
+    rows: Vec<(usize, ImportRow)>,
+    report: &mut ImportReport,
+  ) -> Result<(), AppError> {
+    // argon2 is slow, hash before the transaction takes any lock, a few rows at a time
+    let hashed: Vec<_> = futures_util::stream::iter(rows)
+      .map(|(line, mut row)| async move {
+        let password = row_password(&mut row).await;
+        (line, row, password)
+      })
+      .buffered(self.config.user_transfer.hash_concurrency.max(1))
+      .collect()
+      .await;
+
+    let mut transaction = self
+      .pool
+      .begin()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    let mut audits = vec![];
+    for (line, row, password) in hashed {
+      let username = row.username.clone();
+      let hashed_password = match password {
+        Ok(hashed_password) => hashed_password,
+        Err(err) => {
+          report.reject(line, Some(username), row_error(err));
+          continue;
+        }
+      };
+      let mut savepoint = transaction
+        .begin()
+        .await
+        .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+      match self
+        .import_row(&mut savepoint, actor_id, role_ids, row, hashed_password)
+        .await
+      {
+        Ok(audit) => {
+          savepoint
+            .commit()
+            .await
+            .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+          audits.push(audit);
+          report.imported += 1;
+        }
+        Err(err) => {
+          savepoint
+            .rollback()
+            .await
+            .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+          report.reject(line, Some(username), row_error(err));
+        }
+      }
+    }
+
+    if report.dry_run {
+      return transaction
+        .rollback()
+        .await
+        .map_err(|err| AppError::DatabaseError(err.to_string()));
+    }
+    // appends to the audit chain are serialized, hold their lock for the commit only
+    for audit in audits {
+      record_audit(&mut transaction, audit).await?;
+    }
+    transaction
+      .commit()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))
+  }
+
+  async fn import_row(
+    &self,
+    transaction: &mut Transaction<'_, Postgres>,
+    actor_id: i32,
+    role_ids: &HashMap<String, i32>,
+    row: ImportRow,
+    hashed_password: String,
+  ) -> Result<NewAuditEvent, AppError> {
+    self
+      .ensure_username_available(transaction, &row.username, None)
+      .await?;
+
+    let mut names = row.roles;
+    if names.is_empty() {
+      names.push(RoleName::User.as_ref().to_string());
+    }
+    let mut roles: Vec<RoleIn> = Vec::with_capacity(names.len());
+    for name in names {
+      let id = *role_ids
+        .get(&name)
+        .ok_or_else(|| AppError::BadRequest(format!("unknown role: {}", name)))?;
+      if roles.iter().all(|role| role.id != id) {
+        roles.push(RoleIn {
+          id,
+          name,
+          expires_at: None,
+        });
+      }
+    }
+    let ids: Vec<i32> = roles.iter().map(|role| role.id).collect();
+    self.ensure_no_privileged_roles(transaction, &ids).await?;
+
+    let input = CreateUser {
+      username: row.username,
+      password: String::new(),
+      email: row.email,
+    };
+    let user_info = insert_user_hashed(transaction, &input, hashed_password).await?;
+    self
+      .apply_roles(transaction, roles, user_info.id, actor_id)
+      .await?;
+    let after = user_snapshot(transaction, user_info.id).await?;
+    publish_event(transaction, user_created(&user_info, &after)).await?;
+    Ok(
+      NewAuditEvent::user(AuditAction::UserCreate, Some(user_info.id))
+        .actor(actor_id)
+        .changes(None, after),
+    )
+  }
+
+  /// Stream the users of the active organization with their roles and permissions,
+  /// one `user_transfer.batch_size` page at a time. Deleted users are left out.
+  pub fn export_users(
+    &self,
+    format: TransferFormat,
+  ) -> Result<impl Stream<Item = Result<Bytes, AppError>> + Send + 'static, AppError> {
+    let state = self.clone();
+    // the stream outlives the request and its tenant scope
+    let tenant = current_tenant()?;
+    let limit = self.config.user_transfer.batch_size.max(1) as i64;
+    Ok(futures_util::stream::try_unfold(
+      Some((None, true)),
+      move |page| {
+        let state = state.clone();
+        async move {
+          let Some((after, first)) = page else {
+            return Ok(None);
+          };
+          let params = PaginationParams {
+            limit,
+            after,
+            ..Default::default()
+          };
+          let users =
+            with_tenant(tenant, state.search_users(&UserFilter::default(), &params)).await?;
+          let chunk = encode_export(format, first, users.users)?;
+          let next = users.next_cursor.map(|cursor| (Some(cursor), false));
+          Ok(Some((chunk, next)))
+        }
+      },
+    ))
+  }
+}
+
+/// The password hash of `row`, given or hashed from its password.
+async fn row_password(row: &mut ImportRow) -> Result<String, AppError> {
+  match (row.password.take(), row.password_hash.take()) {
+    (Some(password), None) => tokio::task::spawn_blocking(move || hash_password(&password))
+      .await
+      .map_err(|_| AppError::InternalServerError)?,
+    (None, Some(password_hash)) => Ok(password_hash),
+    _ => Err(AppError::BadRequest(
+      "exactly one of password and password_hash is required".to_string(),
+    )),
+  }
+}
+
+impl ImportReport {
+  fn reject(&mut self, line: usize, username: Option<String>, error: String) {
+    self.failed += 1;
+    self.errors.push(ImportRowError {
+      line,
+      username,
+      error,
+    });
+  }
+}
+
+/// Splits a byte stream into lines, without the line terminators.
+struct Lines<S> {
+  stream: S,
+  buffer: Vec<u8>,
+  done: bool,
+}
+
+impl<S, E> Lines<S>
+where
+  S: Stream<Item = Result<Bytes, E>> + Unpin,
+  E: std::fmt::Display,
+{
+  fn new(stream: S) -> Self {
+    Self {
+      stream,
+      buffer: Vec::new(),
+      done: false,
+    }
+  }
+
+  async fn next_line(&mut self) -> Result<Option<Vec<u8>>, AppError> {
+    loop {
+      if let Some(end) = self.buffer.iter().position(|byte| *byte == b'\n') {
+        let mut line: Vec<u8> = self.buffer.drain(..=end).collect();
+        line.pop();
+        if line.last() == Some(&b'\r') {
+          line.pop();
+        }
+        return Ok(Some(line));
+      }
+      if self.buffer.len() > MAX_LINE_BYTES {
+        return Err(AppError::PayloadTooLarge(format!(
+          "import lines must be at most {} bytes",
+          MAX_LINE_BYTES
+        )));
+      }
+      if self.done {
+        return Ok((!self.buffer.is_empty()).then(|| std::mem::take(&mut self.buffer)));
+      }
+      match self.stream.next().await {
+        Some(Ok(chunk)) => self.buffer.extend_from_slice(&chunk),
+        Some(Err(err)) => {
+          return Err(AppError::BadRequest(format!(
+            "failed to read the import: {}",
+            err
+          )));
+        }
+        None => self.done = true,
+      }
+    }
+  }
+}
+
+/// Rows are read line by line, so quoted CSV fields can't span lines.
+fn read_csv_record(line: &str) -> Result<csv::StringRecord, csv::Error> {
+  csv::ReaderBuilder::new()
+    .has_headers(false)
+    .from_reader(line.as_bytes())
+    .records()
+    .next()
+    .unwrap_or_else(|| Ok(csv::StringRecord::new()))
+}
+
+fn parse_csv_header(line: &str) -> Result<csv::StringRecord, AppError> {
+  let header = read_csv_record(line)
+    .map_err(|err| AppError::BadRequest(format!("invalid CSV header: {}", err)))?;
+  let header: csv::StringRecord = header.iter().map(str::trim).collect();
+  if let Some(column) = header.iter().find(|column| {
+    !matches!(
+      *column,
+      "username" | "password" | "password_hash" | "email" | "roles"
+    )
+  }) {
+    return Err(AppError::BadRequest(format!(
+      "unknown CSV column: {}",
+      column
+    )));
+  }
+  if !header.iter().any(|column| column == "username") {
+    return Err(AppError::BadRequest(
+      "CSV header must have a username column".to_string(),
+    ));
+  }
+  Ok(header)
+}
+
+fn parse_csv_row(line: &str, header: &csv::StringRecord) -> Result<ImportRow, String> {
+  let record = read_csv_record(line).map_err(|err| err.to_string())?;
+  if record.len() != header.len() {
+    return Err(format!(
+      "expected {} fields, found {}",
+      header.len(),
+      record.len()
+    ));
+  }
+  record
+    .deserialize::<CsvImportRow>(Some(header))
+    .map(ImportRow::from)
+    .map_err(|err| err.to_string())
+}
+
+/// Checks of a row that don't need the database.
+fn check_row(row: ImportRow) -> Result<ImportRow, String> {
+  row
+    .validate()
+    .map_err(|err| row_error(AppError::from(err)))?;
+  match (&row.password, &row.password_hash) {
+    (Some(_), None) => {}
+    (None, Some(password_hash)) => {
+      let is_argon2 = PasswordHash::new(password_hash)
+        .is_ok_and(|hash| hash.algorithm.as_str().starts_with("argon2"));
+      if !is_argon2 {
+        return Err("password_hash must be an argon2 PHC string".to_string());
+      }
+    }
+    _ => return Err("exactly one of password and password_hash is required".to_string()),
+  }
+  Ok(row)
+}
+
+/// Message of a rejected row, internal errors stay in the logs.
+fn row_error(err: AppError) -> String {
+  match err {
+    AppError::NotFound(message)
+    | AppError::Forbidden(message)
+    | AppError::BadRequest(message)
+    | AppError::ValidationError(message)
+    | AppError::Conflict(message)
+    | AppError::UserExisted(message)
+    | AppError::InvariantViolation(message) => message,
+    err => {
+      tracing::error!(error = %err, "failed to import a user");
+      "internal server error".to_string()
+    }
+  }
+}
+
+fn encode_export(
+  format: TransferFormat,
+  with_header: bool,
+  users: Vec<User>,
+) -> Result<Bytes, AppError> {
+  let rows = users.into_iter().map(ExportRow::from);
+  match format {
+    TransferFormat::Csv => {
+      let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+      if with_header {
+        writer
+          .write_record(EXPORT_CSV_HEADER)
+          .map_err(|_| AppError::InternalServerError)?;
+      }
+      for row in rows {
+        writer
+          .serialize(CsvExportRow::from(row))
+          .map_err(|_| AppError::InternalServerError)?;
+      }
+      let bytes = writer
+        .into_inner()
+        .map_err(|_| AppError::InternalServerError)?;
+      Ok(Bytes::from(bytes))
+    }
+    TransferFormat::Ndjson => {
+      let mut bytes = Vec::new();
+      for row in rows {
+        serde_json::to_writer(&mut bytes, &row).map_err(|_| AppError::InternalServerError)?;
+        bytes.push(b'\n');
+      }
+      Ok(Bytes::from(bytes))
+    }
+  }
+}
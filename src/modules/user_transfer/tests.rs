@@ -0,0 +1,236 @@
+#[cfg(test)]
+mod util_tests {
+  use crate::common::{DEFAULT_ORGANIZATION_ID, verify_password, with_tenant};
+  use crate::modules::user_transfer::*;
+  use crate::{AppError, AppState};
+  use anyhow::Result;
+  use axum::body::Bytes;
+  use futures_util::{StreamExt, TryStreamExt, stream};
+  use serial_test::serial;
+  use std::convert::Infallible;
+
+  /// Body stream that delivers `input` in chunks of `size` bytes.
+  fn chunks(
+    input: &str,
+    size: usize,
+  ) -> impl futures_util::Stream<Item = Result<Bytes, Infallible>> {
+    let chunks: Vec<_> = input
+      .as_bytes()
+      .chunks(size)
+      .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+      .collect();
+    stream::iter(chunks)
+  }
+
+  const CSV_IMPORT: &str = "username,password,email,roles\r\n\
+    newton,apple123,newton@example.com,User;Moderator\r\n\
+    \r\n\
+    ab,short1,,\r\n\
+    alice,123456,,\r\n\
+    leibniz,calculus,,Wizard\r\n\
+    Newton,apple123,,\r\n\
+    gauss,prince1,gauss@example.com,\r\n";
+
+  #[tokio::test]
+  #[serial]
+  async fn import_csv_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let report = state
+        .import_users(1, TransferFormat::Csv, true, chunks(CSV_IMPORT, 7))
+        .await?;
+      assert!(report.dry_run);
+      assert_eq!(report.rows, 6);
+      assert_eq!(report.imported, 2);
+      assert_eq!(report.failed, 4);
+      let lines: Vec<usize> = report.errors.iter().map(|error| error.line).collect();
+      assert_eq!(lines, vec![4, 5, 6, 7]);
+      assert!(report.errors[1].error.contains("already exists"));
+      assert!(report.errors[2].error.contains("unknown role"));
+      // usernames differing in case only are duplicates
+      assert!(report.errors[3].error.contains("more than once"));
+      // a dry run saves nothing
+      assert!(matches!(
+        state.get_user_by_username("newton").await,
+        Err(AppError::NotFound(_))
+      ));
+
+      let report = state
+        .import_users(1, TransferFormat::Csv, false, chunks(CSV_IMPORT, 1024))
+        .await?;
+      assert_eq!(report.imported, 2);
+      let audits: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM audit_events WHERE action = 'user.create'")
+          .fetch_one(&state.pool)
+          .await?;
+      assert_eq!(audits, 2);
+      let newton = state.get_user_by_username("newton").await?;
+      assert_eq!(
+        newton.user_info.email.as_deref(),
+        Some("newton@example.com")
+      );
+      let mut roles: Vec<_> = newton.roles.iter().map(|role| role.name.as_str()).collect();
+      roles.sort();
+      assert_eq!(roles, vec!["Moderator", "User"]);
+      let gauss = state.get_user_by_username("gauss").await?;
+      assert_eq!(gauss.roles[0].name, "User");
+      state.verify_user("gauss", "prince1").await?;
+
+      let result = state
+        .import_users(
+          1,
+          TransferFormat::Csv,
+          true,
+          chunks("username,nickname\n", 64),
+        )
+        .await;
+      assert!(matches!(result, Err(AppError::BadRequest(_))));
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn import_ndjson_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let password_hash = crate::common::hash_password("migrated")?;
+      let input = format!(
+        "{}\n{}\n{}",
+        serde_json::json!({"username": "euler", "password_hash": password_hash, "roles": ["User"]}),
+        serde_json::json!({"username": "fermat", "password_hash": "$2b$10$notargon2"}),
+        serde_json::json!({"username": "riemann", "password": "zeta123", "password_hash": password_hash}),
+      );
+
+      let report = state
+        .import_users(1, TransferFormat::Ndjson, false, chunks(&input, 16))
+        .await?;
+      assert_eq!(report.rows, 3);
+      assert_eq!(report.imported, 1);
+      assert!(report.errors[0].error.contains("argon2"));
+      assert!(report.errors[1].error.contains("exactly one"));
+
+      let euler = state.verify_user("euler", "migrated").await?;
+      assert!(verify_password("migrated", &euler.user_info.password)?);
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn export_users_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let mut config = state.config.clone();
+      config.user_transfer.batch_size = 4;
+      let state = AppState::new(config, state.pool.clone());
+      state.delete_user(11, None).await?;
+
+      let chunks: Vec<Bytes> = state
+        .export_users(TransferFormat::Csv)?
+        .try_collect()
+        .await?;
+      // 10 users in pages of 4
+      assert_eq!(chunks.len(), 3);
+      let csv = String::from_utf8(chunks.concat())?;
+      let lines: Vec<&str> = csv.lines().collect();
+      assert_eq!(
+        lines[0],
+        "id,username,email,status,roles,permissions,created_at"
+      );
+      assert_eq!(lines.len(), 11);
+      assert!(lines[1].starts_with("1,superman,"));
+      assert!(lines[1].contains(",active,Admin,"));
+      assert!(!csv.contains("judy"));
+
+      let rows: Vec<ExportRow> = state
+        .export_users(TransferFormat::Ndjson)?
+        .map(|chunk| chunk.map(|chunk| String::from_utf8(chunk.to_vec()).unwrap()))
+        .try_collect::<Vec<_>>()
+        .await?
+        .concat()
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+      assert_eq!(rows.len(), 10);
+      assert_eq!(rows[1].username, "alice");
+      assert!(rows[1].roles.is_empty());
+      anyhow::Ok(())
+    })
+    .await
+  }
+}
+
+#[cfg(test)]
+mod integration_tests {
+  use crate::AppState;
+  use crate::common::{DEFAULT_ORGANIZATION_ID, with_tenant};
+  use crate::test_util::{get_token, spawn_app};
+  use anyhow::Result;
+  use axum::http::StatusCode;
+  use reqwest::Client;
+  use serial_test::serial;
+
+  #[tokio::test]
+  #[serial]
+  async fn import_and_export_handler_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let (addr, tx) = spawn_app(state).await?;
+
+      let client = Client::builder().no_proxy().build().unwrap();
+      let addr = addr.to_string();
+      let token = get_token(&client, &addr, "superman", "supermannofly").await?;
+
+      let response = client
+        .post(format!("http://{}/users/import?dry_run=true", addr))
+        .header("Authorization", format!("Bearer {}", &token))
+        .body("username,password\nnewton,apple123\n")
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+      let response = client
+        .post(format!("http://{}/users/import", addr))
+        .header("Authorization", format!("Bearer {}", &token))
+        .header("Content-Type", "text/csv")
+        .body("username,password\nnewton,apple123\n")
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+      let report: serde_json::Value = response.json().await?;
+      assert_eq!(report["imported"], 1);
+      assert_eq!(report["dry_run"], false);
+
+      let response = client
+        .get(format!("http://{}/users/export", addr))
+        .header("Authorization", format!("Bearer {}", &token))
+        .header("Accept", "application/x-ndjson")
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+      assert_eq!(
+        response.headers()["content-disposition"],
+        "attachment; filename=\"users.ndjson\""
+      );
+      let body = response.text().await?;
+      assert_eq!(body.lines().count(), 12);
+      assert!(body.contains("\"username\":\"newton\""));
+
+      // 2, alice, 123456
+      let token = get_token(&client, &addr, "alice", "123456").await?;
+      let response = client
+        .get(format!("http://{}/users/export", addr))
+        .header("Authorization", format!("Bearer {}", &token))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+      tx.send(()).unwrap();
+      anyhow::Ok(())
+    })
+    .await
+  }
+}
@@ -1,6 +1,13 @@
-use super::{Permission, PermissionName, Role, RoleName, UserInfo, VecExtensions};
+use super::{
+  Permission, PermissionName, Role, RoleName, UserInfo, UserProfile, UserStatus, VecExtensions,
+};
+use crate::AppError;
+use crate::modules::groups::GroupSummary;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use validator::Validate;
+use validator::{Validate, ValidationError};
 
 /// Input Dto
 /// user create input dto
@@ -18,47 +25,218 @@ pub struct CreateUser {
     message = "password length must be between 8 and 50 characters"
   ))]
   pub password: String,
+  #[serde(default)]
+  #[validate(email)]
+  pub email: Option<String>,
 }
 
-/// user update input dto with is_who
-#[derive(Debug, Deserialize, Serialize, Validate)]
-pub struct UpdateUser {
+/// user update input dto
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
+pub struct UpdateUserOptions {
+  #[validate(length(
+    min = 3,
+    max = 50,
+    message = "username length must be between 3 and 50 characters"
+  ))]
   pub username: Option<String>,
+  #[validate(length(
+    min = 6,
+    max = 50,
+    message = "password length must be between 8 and 50 characters"
+  ))]
   pub password: Option<String>,
+  /// `Some(None)` clears the email
+  #[validate(email(message = "invalid email"))]
+  pub email: Option<Option<String>>,
+  #[validate(nested)]
   pub roles: Option<Vec<RoleIn>>,
+  #[validate(nested)]
   pub permissions: Option<Vec<PermissionIn>>,
-  pub is_own_user: bool,
-  pub is_moderator: bool,
-  pub is_admin: bool,
 }
 
-/// user update input dto
+impl UpdateUserOptions {
+  /// Names of the fields this update changes.
+  pub fn fields(&self) -> Vec<&'static str> {
+    [
+      ("username", self.username.is_some()),
+      ("email", self.email.is_some()),
+      ("password", self.password.is_some()),
+      ("roles", self.roles.is_some()),
+      ("permissions", self.permissions.is_some()),
+    ]
+    .into_iter()
+    .filter_map(|(name, set)| set.then_some(name))
+    .collect()
+  }
+}
+
+/// body formats of `PATCH /users/{id}`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PatchFormat {
+  /// plain `application/json`, an `UpdateUserOptions`: like a merge patch, except that a
+  /// `null` member leaves the field unchanged instead of removing it, `email` aside
+  Json,
+  /// RFC 7396 `application/merge-patch+json`
+  MergePatch,
+  /// RFC 6902 `application/json-patch+json`
+  JsonPatch,
+}
+
+impl PatchFormat {
+  pub fn from_media_type(media_type: &str) -> Option<Self> {
+    let essence = media_type.split(';').next().unwrap_or_default().trim();
+    match essence.to_ascii_lowercase().as_str() {
+      "application/json" => Some(PatchFormat::Json),
+      "application/merge-patch+json" => Some(PatchFormat::MergePatch),
+      "application/json-patch+json" => Some(PatchFormat::JsonPatch),
+      _ => None,
+    }
+  }
+}
+
+/// The editable view of a user that `PATCH /users/{id}` applies patches to.
+///
+/// `roles` and `permissions` are the direct grants only, `password` is write only
+/// and always `null` here.
 #[derive(Clone, Debug, Deserialize, Serialize, Validate)]
-pub struct UpdateUserOptions {
+#[serde(deny_unknown_fields)]
+pub struct UserDocument {
   #[validate(length(
     min = 3,
     max = 50,
     message = "username length must be between 3 and 50 characters"
   ))]
-  pub username: Option<String>,
+  pub username: String,
+  #[serde(default)]
+  #[validate(email(message = "invalid email"))]
+  pub email: Option<String>,
+  #[serde(default)]
   #[validate(length(
     min = 6,
     max = 50,
     message = "password length must be between 8 and 50 characters"
   ))]
   pub password: Option<String>,
+  #[serde(default)]
   #[validate(nested)]
-  pub roles: Option<Vec<RoleIn>>,
+  pub roles: Vec<RoleIn>,
+  #[serde(default)]
   #[validate(nested)]
-  pub permissions: Option<Vec<PermissionIn>>,
+  pub permissions: Vec<PermissionIn>,
 }
 
-/// current user role input dto
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct IsWho {
-  pub is_own_user: bool,
-  pub is_moderator: bool,
-  pub is_admin: bool,
+impl UserDocument {
+  /// Apply a patch body, the result still has to be validated.
+  pub fn patch(&self, format: PatchFormat, body: &[u8]) -> Result<UserDocument, AppError> {
+    let mut document = serde_json::to_value(self).map_err(|_| AppError::InternalServerError)?;
+    match format {
+      PatchFormat::Json => {
+        let mut patch: serde_json::Value = serde_json::from_slice(body)
+          .map_err(|err| AppError::BadRequest(format!("invalid JSON body: {}", err)))?;
+        // `"roles": null` has always meant unchanged, not revoking every grant
+        if let Some(members) = patch.as_object_mut() {
+          members.retain(|name, value| !value.is_null() || name == "email");
+        }
+        crate::common::merge_patch(&mut document, &patch);
+      }
+      PatchFormat::MergePatch => {
+        let patch: serde_json::Value = serde_json::from_slice(body)
+          .map_err(|err| AppError::BadRequest(format!("invalid merge patch: {}", err)))?;
+        crate::common::merge_patch(&mut document, &patch);
+      }
+      PatchFormat::JsonPatch => {
+        let operations: Vec<crate::common::PatchOperation> = serde_json::from_slice(body)
+          .map_err(|err| AppError::BadRequest(format!("invalid JSON patch: {}", err)))?;
+        crate::common::apply_patch(&mut document, &operations)?;
+      }
+    }
+    serde_json::from_value(document).map_err(|err| AppError::ValidationError(err.to_string()))
+  }
+
+  /// The update turning `self` into `patched`, unchanged fields are left out.
+  pub fn changes(&self, patched: UserDocument) -> UpdateUserOptions {
+    let role_grants = |roles: &[RoleIn]| {
+      let mut grants: Vec<_> = roles
+        .iter()
+        .map(|role| (role.id, role.expires_at))
+        .collect();
+      grants.sort();
+      grants.dedup();
+      grants
+    };
+    let permission_grants = |permissions: &[PermissionIn]| {
+      let mut grants: Vec<_> = permissions
+        .iter()
+        .map(|permission| (permission.id, permission.expires_at))
+        .collect();
+      grants.sort();
+      grants.dedup();
+      grants
+    };
+    UpdateUserOptions {
+      username: Some(patched.username).filter(|username| *username != self.username),
+      password: patched.password,
+      email: Some(patched.email).filter(|email| *email != self.email),
+      roles: Some(patched.roles).filter(|roles| role_grants(roles) != role_grants(&self.roles)),
+      permissions: Some(patched.permissions).filter(|permissions| {
+        permission_grants(permissions) != permission_grants(&self.permissions)
+      }),
+    }
+  }
+}
+
+/// user profile input dto, replaces every field
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
+pub struct UpdateProfile {
+  #[validate(length(min = 1, max = 100))]
+  pub display_name: Option<String>,
+  #[validate(custom(function = "validate_locale"))]
+  pub locale: Option<String>,
+  #[validate(custom(function = "validate_timezone"))]
+  pub timezone: Option<String>,
+  #[validate(length(max = 2000))]
+  pub bio: Option<String>,
+}
+
+/// Accept BCP 47 shaped tags: a 2-3 letter language then alphanumeric subtags.
+fn validate_locale(locale: &str) -> Result<(), ValidationError> {
+  let mut subtags = locale.split('-');
+  let language = subtags.next().unwrap_or_default();
+  let valid = locale.len() <= 35
+    && (2..=3).contains(&language.len())
+    && language.chars().all(|c| c.is_ascii_alphabetic())
+    && subtags
+      .all(|tag| (1..=8).contains(&tag.len()) && tag.chars().all(|c| c.is_ascii_alphanumeric()));
+  if valid {
+    Ok(())
+  } else {
+    Err(
+      ValidationError::new("locale")
+        .with_message("locale must be a language tag like zh-CN".into()),
+    )
+  }
+}
+
+/// Accept `UTC` and IANA shaped names like `America/Argentina/Buenos_Aires`.
+fn validate_timezone(timezone: &str) -> Result<(), ValidationError> {
+  let valid = timezone == "UTC"
+    || (timezone.len() <= 64
+      && timezone.split('/').count() >= 2
+      && timezone.split('/').all(|part| {
+        !part.is_empty()
+          && part.chars().next().is_some_and(|c| c.is_ascii_alphabetic())
+          && part
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '+'))
+      }));
+  if valid {
+    Ok(())
+  } else {
+    Err(
+      ValidationError::new("timezone")
+        .with_message("timezone must be an IANA name like Asia/Shanghai".into()),
+    )
+  }
 }
 
 /// update role input dto
@@ -68,6 +246,9 @@ pub struct RoleIn {
   pub id: i32,
   #[validate(length(min = 3, max = 50))]
   pub name: String,
+  /// grant the role until this time only
+  #[serde(default)]
+  pub expires_at: Option<DateTime<Utc>>,
 }
 
 /// update permission input dto
@@ -77,30 +258,9 @@ pub struct PermissionIn {
   pub id: i32,
   #[validate(length(min = 3, max = 50))]
   pub name: String,
-}
-
-impl IsWho {
-  pub fn new(is_own_user: bool, is_moderator: bool, is_admin: bool) -> Self {
-    Self {
-      is_own_user,
-      is_moderator,
-      is_admin,
-    }
-  }
-}
-
-impl UpdateUser {
-  pub fn new(input: UpdateUserOptions, is_who: IsWho) -> Self {
-    Self {
-      username: input.username,
-      password: input.password,
-      roles: input.roles,
-      permissions: input.permissions,
-      is_own_user: is_who.is_own_user,
-      is_moderator: is_who.is_moderator,
-      is_admin: is_who.is_admin,
-    }
-  }
+  /// grant the permission until this time only
+  #[serde(default)]
+  pub expires_at: Option<DateTime<Utc>>,
 }
 
 /// implement `VecExtensions` trait for `Vec<RoleIn>``
@@ -140,29 +300,200 @@ impl VecExtensions<PermissionName> for Vec<PermissionIn> {
 }
 
 /// user pagination input dto
+///
+/// Pages by keyset: pass the previous page's `next_cursor` as `after`.
+/// `offset` is still accepted for older clients and always counts the total.
 #[derive(Debug, Deserialize, Serialize, Validate)]
 pub struct PaginationParams {
   #[validate(range(min = 1, max = 100))]
   pub limit: i64,
   #[validate(range(min = 0))]
-  pub offset: i64,
+  pub offset: Option<i64>,
+  #[validate(length(min = 1, max = 512))]
+  pub after: Option<String>,
+  #[serde(default)]
+  pub include_total: bool,
 }
 
 impl Default for PaginationParams {
   fn default() -> Self {
     Self {
       limit: 10,
-      offset: 0,
+      offset: None,
+      after: None,
+      include_total: false,
+    }
+  }
+}
+
+impl PaginationParams {
+  pub fn offset(limit: i64, offset: i64) -> Self {
+    Self {
+      limit,
+      offset: Some(offset),
+      ..Default::default()
+    }
+  }
+}
+
+/// Position of the last user of a page, for the listing's sort order.
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct UserCursor {
+  /// the `sort` the page was listed with, a cursor can't be reused with another
+  pub sort: String,
+  /// sort key of the last user, absent when sorting by id
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub key: Option<serde_json::Value>,
+  pub id: i32,
+}
+
+impl UserCursor {
+  pub fn new(field: UserSortField, order: SortOrder, user: &UserInfo) -> Self {
+    let key = match field {
+      UserSortField::Id => None,
+      UserSortField::Username => Some(serde_json::json!(user.username)),
+      UserSortField::CreatedAt => Some(serde_json::json!(user.created_at)),
+      UserSortField::UpdatedAt => Some(serde_json::json!(user.updated_at)),
+    };
+    Self {
+      sort: sort_spec(field, order),
+      key,
+      id: user.id,
+    }
+  }
+
+  /// Encode as an opaque url-safe token.
+  pub fn encode(&self) -> String {
+    let json = serde_json::to_vec(self).expect("cursor is always serializable");
+    URL_SAFE_NO_PAD.encode(json)
+  }
+
+  /// Decode a token from `encode`, it must have been issued for the same sort.
+  pub fn decode(token: &str, field: UserSortField, order: SortOrder) -> Result<Self, AppError> {
+    let invalid = || AppError::BadRequest("invalid cursor".to_string());
+    let json = URL_SAFE_NO_PAD.decode(token).map_err(|_| invalid())?;
+    let cursor: Self = serde_json::from_slice(&json).map_err(|_| invalid())?;
+    if cursor.sort != sort_spec(field, order) {
+      return Err(AppError::BadRequest(
+        "cursor was issued for another sort order".to_string(),
+      ));
+    }
+    if cursor.key.is_some() == (field == UserSortField::Id) {
+      return Err(invalid());
+    }
+    Ok(cursor)
+  }
+}
+
+fn sort_spec(field: UserSortField, order: SortOrder) -> String {
+  format!("{}:{}", field.as_ref(), order.as_ref().to_ascii_lowercase())
+}
+
+/// user deletion input dto
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct DeleteUserParams {
+  /// remove the user permanently instead of a soft delete
+  #[serde(default)]
+  pub hard: bool,
+}
+
+/// user search input dto, every filter is optional
+#[derive(Debug, Default, Deserialize, Serialize, Validate)]
+pub struct UserFilter {
+  /// username substring, case insensitive
+  #[validate(length(min = 1, max = 50))]
+  pub username: Option<String>,
+  /// username prefix, case insensitive
+  #[validate(length(min = 1, max = 50))]
+  pub username_prefix: Option<String>,
+  /// role name, direct or through a group
+  #[validate(length(min = 1, max = 50))]
+  pub role: Option<String>,
+  /// permission name, direct or through a group
+  #[validate(length(min = 1, max = 50))]
+  pub permission: Option<String>,
+  /// deleted users are only listed when asked for
+  pub status: Option<UserStatus>,
+  pub created_after: Option<DateTime<Utc>>,
+  pub created_before: Option<DateTime<Utc>>,
+  /// `field:asc|desc`, field one of `id`, `username`, `created_at`, `updated_at`
+  pub sort: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserSortField {
+  Id,
+  Username,
+  CreatedAt,
+  UpdatedAt,
+}
+
+impl AsRef<str> for UserSortField {
+  fn as_ref(&self) -> &str {
+    match self {
+      UserSortField::Id => "id",
+      UserSortField::Username => "username",
+      UserSortField::CreatedAt => "created_at",
+      UserSortField::UpdatedAt => "updated_at",
     }
   }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+  Asc,
+  Desc,
+}
+
+impl AsRef<str> for SortOrder {
+  fn as_ref(&self) -> &str {
+    match self {
+      SortOrder::Asc => "ASC",
+      SortOrder::Desc => "DESC",
+    }
+  }
+}
+
+impl UserFilter {
+  /// Parse `sort` against the allowlist, defaults to `id:asc`.
+  pub fn sort(&self) -> Result<(UserSortField, SortOrder), AppError> {
+    let Some(sort) = &self.sort else {
+      return Ok((UserSortField::Id, SortOrder::Asc));
+    };
+    let (field, order) = sort.split_once(':').unwrap_or((sort, "asc"));
+    let field = match field {
+      "id" => UserSortField::Id,
+      "username" => UserSortField::Username,
+      "created_at" => UserSortField::CreatedAt,
+      "updated_at" => UserSortField::UpdatedAt,
+      _ => return Err(AppError::BadRequest(format!("cannot sort by: {}", field))),
+    };
+    let order = match order {
+      "asc" => SortOrder::Asc,
+      "desc" => SortOrder::Desc,
+      _ => {
+        return Err(AppError::BadRequest(format!(
+          "unknown sort order: {}",
+          order
+        )));
+      }
+    };
+    Ok((field, order))
+  }
+}
 /// Output Dto
 /// user output dto
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct User {
   pub user_info: UserInfo,
+  /// direct and group roles
   pub roles: Vec<Role>,
+  /// direct, group and group role permissions
   pub permissions: Vec<Permission>,
+  #[serde(default)]
+  pub groups: Vec<GroupSummary>,
+  #[serde(default)]
+  pub profile: UserProfile,
 }
 
 impl User {
@@ -171,13 +502,30 @@ impl User {
       user_info,
       roles,
       permissions,
+      groups: vec![],
+      profile: UserProfile::default(),
     }
   }
+
+  pub fn with_groups(mut self, groups: Vec<GroupSummary>) -> Self {
+    self.groups = groups;
+    self
+  }
+
+  pub fn with_profile(mut self, profile: UserProfile) -> Self {
+    self.profile = profile;
+    self
+  }
 }
 
 /// paginated users output dto
 #[derive(Debug, Deserialize, Serialize)]
 pub struct PaginatedUsers {
   pub users: Vec<User>,
-  pub total_count: i64,
+  /// only counted for offset pages or with `include_total=true`
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub total_count: Option<i64>,
+  /// cursor of the next page, absent on the last page
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub next_cursor: Option<String>,
 }
@@ -11,6 +11,7 @@ pub struct UserRoleRow {
   pub name: String,
   pub created_at: DateTime<Utc>,
   pub updated_at: DateTime<Utc>,
+  pub expires_at: Option<DateTime<Utc>>,
 }
 
 /// Helper row type for batch fetching permissions with user_id
@@ -21,6 +22,15 @@ pub struct UserPermissionRow {
   pub name: String,
   pub created_at: DateTime<Utc>,
   pub updated_at: DateTime<Utc>,
+  pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Helper row type for batch fetching group memberships with user_id
+#[derive(Clone, Debug, FromRow)]
+pub struct UserGroupRow {
+  pub user_id: i32,
+  pub id: i32,
+  pub name: String,
 }
 
 pub trait VecExtensions<T: AsRef<str> + Eq> {
@@ -37,10 +47,86 @@ pub struct UserInfo {
   #[sqlx(default)]
   #[serde(skip)]
   pub password: String,
+  #[sqlx(default)]
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub email: Option<String>,
+  #[sqlx(default, try_from = "String")]
+  #[serde(default)]
+  pub status: UserStatus,
+  #[sqlx(default)]
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub deleted_at: Option<DateTime<Utc>>,
+  /// bumped by every change to the user, its grants or profile, served as the ETag
+  #[sqlx(default)]
+  #[serde(default)]
+  pub version: i64,
   pub created_at: DateTime<Utc>,
   pub updated_at: DateTime<Utc>,
 }
 
+/// username_history table, one rename of a user
+#[derive(Clone, Debug, Deserialize, FromRow, Serialize)]
+pub struct UsernameChange {
+  pub id: i32,
+  pub old_username: String,
+  pub new_username: String,
+  /// `None` once the renaming user is purged
+  pub changed_by: Option<i32>,
+  pub changed_at: DateTime<Utc>,
+}
+
+/// account status, only active users can sign in
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UserStatus {
+  #[default]
+  Active,
+  Suspended,
+  Deleted,
+}
+
+impl AsRef<str> for UserStatus {
+  fn as_ref(&self) -> &str {
+    match self {
+      UserStatus::Active => "active",
+      UserStatus::Suspended => "suspended",
+      UserStatus::Deleted => "deleted",
+    }
+  }
+}
+
+impl TryFrom<String> for UserStatus {
+  type Error = String;
+
+  fn try_from(status: String) -> Result<Self, Self::Error> {
+    match status.as_str() {
+      "active" => Ok(UserStatus::Active),
+      "suspended" => Ok(UserStatus::Suspended),
+      "deleted" => Ok(UserStatus::Deleted),
+      _ => Err(format!("unknown user status: {}", status)),
+    }
+  }
+}
+
+/// user_profiles table
+#[derive(Clone, Debug, Default, Deserialize, FromRow, Serialize, PartialEq)]
+pub struct UserProfile {
+  #[serde(skip)]
+  pub user_id: i32,
+  pub display_name: Option<String>,
+  /// BCP 47 language tag, e.g. `zh-CN`
+  pub locale: Option<String>,
+  /// IANA time zone, e.g. `Asia/Shanghai`
+  pub timezone: Option<String>,
+  pub bio: Option<String>,
+  #[serde(skip)]
+  pub avatar_key: Option<String>,
+  /// where the avatar is served, set once one was uploaded
+  #[sqlx(default)]
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub avatar_url: Option<String>,
+}
+
 /// roles table
 #[derive(Clone, Debug, Deserialize, FromRow, Serialize)]
 pub struct Role {
@@ -48,6 +134,10 @@ pub struct Role {
   pub name: String,
   pub created_at: DateTime<Utc>,
   pub updated_at: DateTime<Utc>,
+  /// set when the role is granted to a user for a limited time
+  #[sqlx(default)]
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub expires_at: Option<DateTime<Utc>>,
 }
 
 /// permissions table
@@ -57,6 +147,10 @@ pub struct Permission {
   pub name: String,
   pub created_at: DateTime<Utc>,
   pub updated_at: DateTime<Utc>,
+  /// set when the permission is granted to a user for a limited time
+  #[sqlx(default)]
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub expires_at: Option<DateTime<Utc>>,
 }
 
 /// role names
@@ -103,6 +197,10 @@ impl UserInfo {
       id: 0,
       username: username.to_string(),
       password: password.to_string(),
+      email: None,
+      status: UserStatus::Active,
+      deleted_at: None,
+      version: 1,
       created_at: Utc::now(),
       updated_at: Utc::now(),
     }
@@ -116,6 +214,7 @@ impl Role {
       name: role.to_string(),
       created_at: Utc::now(),
       updated_at: Utc::now(),
+      expires_at: None,
     }
   }
 }
@@ -127,6 +226,7 @@ impl Permission {
       name: permission.to_string(),
       created_at: Utc::now(),
       updated_at: Utc::now(),
+      expires_at: None,
     }
   }
 }
@@ -1,12 +1,19 @@
-use super::{PaginationParams, UpdateUser, UpdateUserOptions, User};
+use super::{
+  DeleteUserParams, PaginatedUsers, PaginationParams, PatchFormat, UpdateProfile, User, UserFilter,
+};
 use crate::AppState;
 use crate::common::errors::AppError;
+use crate::common::{EntityTags, etag};
+use crate::modules::auth::SigninHistoryParams;
+use crate::modules::authz::{Action, Resource};
+use crate::modules::role_requests::PendingUserUpdate;
 
 use axum::{
   Extension, Json,
-  extract::{Path, Query, State},
-  http::StatusCode,
-  response::IntoResponse,
+  body::Bytes,
+  extract::{Multipart, OriginalUri, Path, Query, State, multipart::MultipartError},
+  http::{HeaderMap, HeaderValue, StatusCode, Uri, header},
+  response::{IntoResponse, Response},
 };
 use tracing::info;
 use validator::Validate;
@@ -15,71 +22,343 @@ pub async fn delete_user_handler(
   Extension(claims): Extension<User>,
   State(state): State<AppState>,
   Path(user_id): Path<i32>,
+  Query(params): Query<DeleteUserParams>,
+  headers: HeaderMap,
 ) -> Result<impl IntoResponse, AppError> {
-  info!("Users Handler::delete user: {:?}", user_id);
-  let is_who = state.get_role_by_claim(&claims, user_id).await?;
-  if !is_who.is_admin {
-    return Err(AppError::Forbidden(
-      "only admin can delete users".to_string(),
-    ));
+  info!("Users Handler::delete user: {:?}, {:?}", user_id, params);
+  let if_match = if_match(&state, &headers)?;
+  if params.hard {
+    state.authorize_action(&claims, Action::PurgeUser, Some(user_id))?;
+  }
+  let user = state
+    .authorize_user(&claims, Action::DeleteUser, user_id)
+    .await?;
+  if params.hard {
+    state.authorize(&claims, Action::PurgeUser, &Resource::user(&user))?;
+  }
+  if params.hard {
+    state.purge_user(user_id, if_match.as_ref()).await?;
+  } else {
+    state.delete_user(user_id, if_match.as_ref()).await?;
   }
-  state.delete_user(user_id).await?;
   Ok(StatusCode::OK)
 }
 
-pub async fn update_user_handler(
+pub async fn suspend_user_handler(
   Extension(claims): Extension<User>,
   State(state): State<AppState>,
   Path(user_id): Path<i32>,
-  Json(input): Json<UpdateUserOptions>,
 ) -> Result<impl IntoResponse, AppError> {
-  input.validate()?;
-  info!("Users Handler::update user: user_id: {:?}", user_id);
-  info!("Users Handler::update user: input: {:?}", input);
-  let is_who = state.get_role_by_claim(&claims, user_id).await?;
-  info!("Users Handler::update user: is_who: {:?}", is_who);
-  let input = UpdateUser::new(input, is_who);
+  info!("Users Handler::suspend user: {:?}", user_id);
+  state
+    .authorize_user(&claims, Action::SuspendUser, user_id)
+    .await?;
+  let user = state.suspend_user(user_id).await?;
+  Ok((StatusCode::OK, Json(user)))
+}
 
-  let user = state.update_user(user_id, input).await?;
+pub async fn restore_user_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Path(user_id): Path<i32>,
+) -> Result<impl IntoResponse, AppError> {
+  info!("Users Handler::restore user: {:?}", user_id);
+  state
+    .authorize_user(&claims, Action::RestoreUser, user_id)
+    .await?;
+  let user = state.restore_user(user_id).await?;
   Ok((StatusCode::OK, Json(user)))
 }
 
+/// `application/merge-patch+json` (or `application/json`) and `application/json-patch+json`
+/// bodies, applied to the user's `UserDocument`.
+pub async fn update_user_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Path(user_id): Path<i32>,
+  headers: HeaderMap,
+  body: Bytes,
+) -> Result<Response, AppError> {
+  info!("Users Handler::update user: user_id: {:?}", user_id);
+  let format = headers
+    .get(header::CONTENT_TYPE)
+    .and_then(|value| value.to_str().ok())
+    .and_then(PatchFormat::from_media_type)
+    .ok_or_else(|| {
+      AppError::UnsupportedMediaType(
+        "use application/merge-patch+json or application/json-patch+json".to_string(),
+      )
+    })?;
+  let if_match = if_match(&state, &headers)?;
+  // each field is authorized by `update_user`, the caller needs one of the update actions
+  state
+    .authorize_action(&claims, Action::UpdateUserInfo, Some(user_id))
+    .or_else(|_| state.authorize_action(&claims, Action::UpdateUserRoles, Some(user_id)))
+    .or_else(|_| state.authorize_action(&claims, Action::UpdateUserPermissions, Some(user_id)))?;
+  let user = state.get_user_by_id(user_id).await?;
+
+  let document = state.get_user_document(&user).await?;
+  let patched = document.patch(format, &body)?;
+  patched.validate()?;
+  let input = document.changes(patched);
+  info!("Users Handler::update user: fields: {:?}", input.fields());
+
+  let (user, role_request) = state
+    .update_user(&claims, user_id, input, if_match.as_ref())
+    .await?;
+  let etag = [(header::ETAG, etag(user.user_info.version))];
+  match role_request {
+    Some(role_request) => Ok(
+      (
+        StatusCode::ACCEPTED,
+        etag,
+        Json(PendingUserUpdate { user, role_request }),
+      )
+        .into_response(),
+    ),
+    None => Ok((StatusCode::OK, etag, Json(user)).into_response()),
+  }
+}
+
 pub async fn get_user_handler(
   Extension(claims): Extension<User>,
   State(state): State<AppState>,
   Path(user_id): Path<i32>,
-) -> Result<impl IntoResponse, AppError> {
+  headers: HeaderMap,
+) -> Result<Response, AppError> {
   info!("Users Handler::get user: user_id: {:?}", user_id);
   info!(
     "Users Handler::get user: claims user_id: {:?}",
     claims.user_info.id
   );
-  let is_who = state.get_role_by_claim(&claims, user_id).await?;
-  info!("Users Handler::get user: is_who: {:?}", is_who);
-  if !is_who.is_own_user && !is_who.is_admin && !is_who.is_moderator {
-    return Err(AppError::BadRequest("Permission denied".to_string()));
+  let if_none_match = EntityTags::from_headers(&headers, header::IF_NONE_MATCH)?;
+  let user = state
+    .authorize_user(&claims, Action::ReadUser, user_id)
+    .await?;
+  let etag = [(header::ETAG, etag(user.user_info.version))];
+  if if_none_match.is_some_and(|tags| tags.matches_weak(user.user_info.version)) {
+    return Ok((StatusCode::NOT_MODIFIED, etag).into_response());
   }
-  let user = state.get_user_by_id(user_id).await?;
+  Ok((StatusCode::OK, etag, Json(user)).into_response())
+}
+
+/// Look a user up by name, old names lead to the user that was renamed from them.
+pub async fn get_user_by_username_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Path(username): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+  info!("Users Handler::get user by username: {:?}", username);
+  // the owner is only known after the lookup: callers that may only read themselves
+  // learn nothing about other names
+  let user = match state.authorize_action(&claims, Action::ReadUser, None) {
+    Ok(_) => state.find_user_by_username(&username).await?,
+    Err(denied) => match state.find_user_by_username(&username).await {
+      Ok(user) if user.user_info.id == claims.user_info.id => user,
+      _ => return Err(denied),
+    },
+  };
+  state.authorize(&claims, Action::ReadUser, &Resource::user(&user))?;
+  Ok((
+    StatusCode::OK,
+    [(
+      header::CONTENT_LOCATION,
+      format!("/users/{}", user.user_info.id),
+    )],
+    Json(user),
+  ))
+}
+
+pub async fn get_username_history_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Path(user_id): Path<i32>,
+) -> Result<impl IntoResponse, AppError> {
+  state
+    .authorize_user(&claims, Action::ReadUser, user_id)
+    .await?;
+  let history = state.get_username_history(user_id).await?;
+  Ok((StatusCode::OK, Json(history)))
+}
+
+pub async fn get_user_logins_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Path(user_id): Path<i32>,
+  Query(params): Query<SigninHistoryParams>,
+) -> Result<impl IntoResponse, AppError> {
+  params.validate()?;
+  state
+    .authorize_user(&claims, Action::ReadUserLogins, user_id)
+    .await?;
+  let history = state.get_signin_history(user_id, &params).await?;
+  Ok((StatusCode::OK, Json(history)))
+}
+
+/// Signin history of the authenticated user.
+pub async fn get_my_logins_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Query(params): Query<SigninHistoryParams>,
+) -> Result<impl IntoResponse, AppError> {
+  params.validate()?;
+  let history = state
+    .get_signin_history(claims.user_info.id, &params)
+    .await?;
+  Ok((StatusCode::OK, Json(history)))
+}
+
+/// `If-Match` of a write, required when `concurrency.require_if_match` is set.
+fn if_match(state: &AppState, headers: &HeaderMap) -> Result<Option<EntityTags>, AppError> {
+  let if_match = EntityTags::from_headers(headers, header::IF_MATCH)?;
+  if if_match.is_none() && state.config.concurrency.require_if_match {
+    return Err(AppError::PreconditionRequired(
+      "If-Match header is required".to_string(),
+    ));
+  }
+  Ok(if_match)
+}
+
+pub async fn update_profile_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Path(user_id): Path<i32>,
+  Json(input): Json<UpdateProfile>,
+) -> Result<impl IntoResponse, AppError> {
+  input.validate()?;
+  info!("Users Handler::update profile: user_id: {:?}", user_id);
+  state
+    .authorize_user(&claims, Action::UpdateUserInfo, user_id)
+    .await?;
+  let user = state.update_user_profile(user_id, input).await?;
   Ok((StatusCode::OK, Json(user)))
 }
 
+/// Multipart form with the image in an `avatar` field.
+pub async fn upload_avatar_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Path(user_id): Path<i32>,
+  mut multipart: Multipart,
+) -> Result<impl IntoResponse, AppError> {
+  info!("Users Handler::upload avatar: user_id: {:?}", user_id);
+  state
+    .authorize_user(&claims, Action::UpdateUserInfo, user_id)
+    .await?;
+
+  let max_bytes = state.config.avatars.max_bytes;
+  let too_large =
+    || AppError::PayloadTooLarge(format!("avatar must be at most {} bytes", max_bytes));
+  let multipart_error = |err: MultipartError| match err.status() {
+    StatusCode::PAYLOAD_TOO_LARGE => too_large(),
+    _ => AppError::BadRequest(err.body_text()),
+  };
+  let mut upload = None;
+  while let Some(mut field) = multipart.next_field().await.map_err(multipart_error)? {
+    if field.name() != Some("avatar") {
+      continue;
+    }
+    // the declared content type isn't trusted, the bytes are sniffed later
+    let mut bytes = Vec::new();
+    while let Some(chunk) = field.chunk().await.map_err(multipart_error)? {
+      if bytes.len() + chunk.len() > max_bytes {
+        return Err(too_large());
+      }
+      bytes.extend_from_slice(&chunk);
+    }
+    upload = Some(bytes);
+    break;
+  }
+  let upload = upload.ok_or_else(|| AppError::BadRequest("missing avatar field".to_string()))?;
+
+  let user = state.set_user_avatar(user_id, upload).await?;
+  Ok((StatusCode::OK, Json(user)))
+}
+
+pub async fn get_avatar_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Path(user_id): Path<i32>,
+) -> Result<impl IntoResponse, AppError> {
+  state
+    .authorize_user(&claims, Action::ReadUser, user_id)
+    .await?;
+  let avatar = state
+    .get_user_avatar(user_id)
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("User: {} has no avatar", user_id)))?;
+  Ok((
+    StatusCode::OK,
+    [
+      (header::CONTENT_TYPE, "image/png"),
+      (header::CACHE_CONTROL, "private, max-age=300"),
+      (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+    ],
+    avatar,
+  ))
+}
+
 pub async fn get_users_handler(
   Extension(claims): Extension<User>,
   State(state): State<AppState>,
+  OriginalUri(uri): OriginalUri,
   Query(params): Query<PaginationParams>,
+  Query(filter): Query<UserFilter>,
 ) -> Result<impl IntoResponse, AppError> {
   params.validate()?;
-  info!("Users Handler::get users");
-  let is_who = state
-    .get_role_by_claim(&claims, claims.user_info.id)
-    .await?;
-  // Only admin and moderator can list all users
-  if !is_who.is_admin && !is_who.is_moderator {
-    return Err(AppError::Forbidden(
-      "only admin or moderator can list users".to_string(),
-    ));
+  filter.validate()?;
+  info!("Users Handler::get users: filter: {:?}", filter);
+  state.authorize(&claims, Action::ListUsers, &Resource::users())?;
+  let users = state.search_users(&filter, &params).await?;
+  let mut headers = HeaderMap::new();
+  if let Some(link) = page_links(&uri, &params, &users) {
+    headers.insert(header::LINK, link);
+  }
+  Ok((StatusCode::OK, headers, Json(users)))
+}
+
+/// RFC 8288 `Link` header pointing at the first and next (and for offset pages,
+/// previous) pages, keeping the other query params of the request.
+fn page_links(uri: &Uri, params: &PaginationParams, page: &PaginatedUsers) -> Option<HeaderValue> {
+  let base: Vec<&str> = uri
+    .query()
+    .unwrap_or_default()
+    .split('&')
+    .filter(|pair| {
+      let key = pair.split('=').next().unwrap_or_default();
+      !pair.is_empty() && key != "after" && key != "offset"
+    })
+    .collect();
+  let link = |extra: Option<String>, rel: &str| {
+    let mut query = base.clone();
+    if let Some(extra) = &extra {
+      query.push(extra);
+    }
+    format!("<{}?{}>; rel=\"{}\"", uri.path(), query.join("&"), rel)
+  };
+
+  let mut links = vec![];
+  match params.offset {
+    Some(offset) => {
+      links.push(link(Some("offset=0".to_string()), "first"));
+      if offset > 0 {
+        let prev = (offset - params.limit).max(0);
+        links.push(link(Some(format!("offset={}", prev)), "prev"));
+      }
+      if page
+        .total_count
+        .is_some_and(|total| offset + params.limit < total)
+      {
+        let next = offset + params.limit;
+        links.push(link(Some(format!("offset={}", next)), "next"));
+      }
+    }
+    None => {
+      links.push(link(None, "first"));
+      if let Some(cursor) = &page.next_cursor {
+        links.push(link(Some(format!("after={}", cursor)), "next"));
+      }
+    }
   }
-  let PaginationParams { limit, offset } = params;
-  let users = state.get_users(limit, offset).await?;
-  Ok((StatusCode::OK, Json(users)))
+  HeaderValue::from_str(&links.join(", ")).ok()
 }
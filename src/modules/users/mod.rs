@@ -3,17 +3,34 @@ pub mod entity;
 pub mod handlers;
 pub mod services;
 pub mod tests;
+pub mod usernames;
 
 pub use dto::{
-  CreateUser, IsWho, PaginationParams, PermissionIn, RoleIn, UpdateUser, UpdateUserOptions, User,
+  CreateUser, DeleteUserParams, PaginatedUsers, PaginationParams, PatchFormat, PermissionIn,
+  RoleIn, SortOrder, UpdateProfile, UpdateUserOptions, User, UserCursor, UserDocument, UserFilter,
+  UserSortField,
+};
+pub use entity::{
+  Permission, PermissionName, Role, RoleName, UserInfo, UserProfile, UserStatus, UsernameChange,
+  VecExtensions,
+};
+
+pub use handlers::{
+  delete_user_handler, get_avatar_handler, get_my_logins_handler, get_user_by_username_handler,
+  get_user_handler, get_user_logins_handler, get_username_history_handler, get_users_handler,
+  restore_user_handler, suspend_user_handler, update_profile_handler, update_user_handler,
+  upload_avatar_handler,
+};
+pub(crate) use services::{
+  AdminGrants, direct_roles, insert_user, insert_user_hashed, lock_admin_grants, lock_admins,
+  snapshot_grants, user_created, user_snapshot,
 };
-pub use entity::{Permission, PermissionName, Role, RoleName, UserInfo, VecExtensions};
-pub use handlers::{delete_user_handler, get_user_handler, get_users_handler, update_user_handler};
 
 use crate::AppState;
 
 use axum::Router;
-use axum::routing::get;
+use axum::extract::DefaultBodyLimit;
+use axum::routing::{get, post, put};
 
 pub fn users_router(state: AppState) -> Router {
   Router::new()
@@ -24,5 +41,21 @@ pub fn users_router(state: AppState) -> Router {
         .patch(update_user_handler)
         .delete(delete_user_handler),
     )
+    .route("/by-username/{username}", get(get_user_by_username_handler))
+    .route("/{id}/username-history", get(get_username_history_handler))
+    .route("/me/logins", get(get_my_logins_handler))
+    .route("/{id}/logins", get(get_user_logins_handler))
+    .route("/{id}/suspend", post(suspend_user_handler))
+    .route("/{id}/restore", post(restore_user_handler))
+    .route("/{id}/profile", put(update_profile_handler))
+    .route(
+      "/{id}/avatar",
+      get(get_avatar_handler)
+        .put(upload_avatar_handler)
+        // room for the multipart framing, the image itself is capped in the handler
+        .layer(DefaultBodyLimit::max(
+          state.config.avatars.max_bytes + 64 * 1024,
+        )),
+    )
     .with_state(state)
 }
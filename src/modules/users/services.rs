@@ -1,54 +1,53 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::io::Cursor;
 
 use crate::AppState;
+use crate::common::config::AvatarsConfig;
 use crate::common::errors::AppError;
-use crate::modules::users::dto::{CreateUser, IsWho, UpdateUser, User};
+use crate::common::{EntityTags, across_tenants, current_tenant, with_tenant};
+use crate::modules::audit::{AuditAction, NewAuditEvent, record_audit};
+use crate::modules::authz::{Action, Resource};
+use crate::modules::groups::GroupSummary;
+use crate::modules::outbox::{DomainEvent, publish_event};
+use crate::modules::role_requests::RoleRequest;
+use crate::modules::users::dto::{
+  CreateUser, PaginationParams, PermissionIn, RoleIn, SortOrder, UpdateProfile, UpdateUserOptions,
+  User, UserCursor, UserDocument, UserFilter, UserSortField,
+};
 use crate::modules::users::entity::{
-  Permission, Role, RoleName, UserInfo, UserPermissionRow, UserRoleRow, VecExtensions,
+  Permission, Role, RoleName, UserGroupRow, UserInfo, UserPermissionRow, UserProfile, UserRoleRow,
+  UserStatus,
 };
 
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use image::imageops::FilterType;
+use image::{ImageError, ImageFormat, ImageReader, Limits};
+use serde_json::Value;
+use sqlx::{PgConnection, Postgres, QueryBuilder, Transaction};
+use tracing::{info, warn};
 
 use super::dto::PaginatedUsers;
+use super::usernames::username_error;
 use crate::common::hash_password;
 
 impl AppState {
   pub async fn create_user(&self, input: CreateUser) -> Result<User, AppError> {
-    if self.is_user_exists_by_username(&input.username).await? {
-      return Err(AppError::UserExisted(format!(
-        "User: {} already exists",
-        input.username
-      )));
-    }
-
-    let hashed_password = hash_password(&input.password)?;
-
     let mut transaction = self
       .pool
       .begin()
       .await
       .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    self
+      .ensure_username_available(&mut transaction, &input.username, None)
+      .await?;
 
-    let user_info = sqlx::query_as::<_, UserInfo>(
-      r#"
-      INSERT INTO users (username, password, created_at, updated_at)
-      VALUES ($1, $2, $3, $4)
-      RETURNING id, username, password, created_at, updated_at
-      "#,
-    )
-    .bind(&input.username)
-    .bind(hashed_password)
-    .bind(Utc::now())
-    .bind(Utc::now())
-    .fetch_one(&mut *transaction)
-    .await
-    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    let user_info = insert_user(&mut transaction, &input).await?;
 
     let roles: Vec<Role> = sqlx::query_as(
       r#"
       WITH inserted AS (
-          INSERT INTO user_roles (user_id, role_id, created_at, updated_at)
-          VALUES ($1, 1, $2, $3) -- default role User, ID: 1
+          INSERT INTO user_roles (user_id, role_id, organization_id, created_at, updated_at)
+          VALUES ($1, 1, $4, $2, $3) -- default role User, ID: 1
           RETURNING role_id
       )
       SELECT r.id, r.name, r.created_at, r.updated_at
@@ -59,6 +58,7 @@ impl AppState {
     .bind(user_info.id)
     .bind(Utc::now())
     .bind(Utc::now())
+    .bind(current_tenant()?)
     .fetch_all(&mut *transaction)
     .await?;
 
@@ -69,9 +69,11 @@ impl AppState {
       JOIN role_permissions rp ON rp.permission_id = p.id
       JOIN user_roles ur ON ur.role_id = rp.role_id
       WHERE ur.user_id = $1
+      AND ur.organization_id = $2
       "#,
     )
     .bind(user_info.id)
+    .bind(current_tenant()?)
     .fetch_all(&mut *transaction)
     .await?;
 
@@ -79,12 +81,13 @@ impl AppState {
     for permission in &permissions {
       sqlx::query(
         r#"
-        INSERT INTO user_permissions (user_id, permission_id, created_at, updated_at)
-        VALUES ($1, $2, $3, $4)
+        INSERT INTO user_permissions (user_id, permission_id, organization_id, created_at, updated_at)
+        VALUES ($1, $2, $3, $4, $5)
         "#,
       )
       .bind(user_info.id)
       .bind(permission.id)
+      .bind(current_tenant()?)
       .bind(Utc::now())
       .bind(Utc::now())
       .execute(&mut *transaction)
@@ -92,6 +95,14 @@ impl AppState {
       .map_err(|err| AppError::DatabaseError(err.to_string()))?;
     }
 
+    let after = user_snapshot(&mut transaction, user_info.id).await?;
+    publish_event(&mut transaction, user_created(&user_info, &after)).await?;
+    record_audit(
+      &mut transaction,
+      NewAuditEvent::user(AuditAction::UserCreate, Some(user_info.id)).changes(None, after),
+    )
+    .await?;
+
     transaction
       .commit()
       .await
@@ -102,7 +113,150 @@ impl AppState {
     Ok(user)
   }
 
-  pub async fn delete_user(&self, user_id: i32) -> Result<(), AppError> {
+  /// Soft delete: the account can't sign in any more but keeps its history and can be restored.
+  /// With `if_match` it only goes ahead when the user's version still matches it, checked
+  /// in the transaction of the delete.
+  pub async fn delete_user(
+    &self,
+    user_id: i32,
+    if_match: Option<&EntityTags>,
+  ) -> Result<(), AppError> {
+    if !self.is_user_exists_by_id(user_id).await? {
+      return Err(AppError::NotFound(format!(
+        "User with id {} not found",
+        user_id
+      )));
+    }
+    // a user shared with other organizations only leaves the active one
+    if self.get_user_organizations(user_id).await?.len() > 1 {
+      self
+        .remove_organization_member(current_tenant()?, user_id, if_match)
+        .await?;
+      return Ok(());
+    }
+    self
+      .set_user_status(user_id, UserStatus::Deleted, if_match)
+      .await?;
+    Ok(())
+  }
+
+  pub async fn suspend_user(&self, user_id: i32) -> Result<User, AppError> {
+    let user = self.get_user_by_id(user_id).await?;
+    if user.user_info.status == UserStatus::Deleted {
+      return Err(AppError::Conflict(format!(
+        "User with id {} is deleted, restore it first",
+        user_id
+      )));
+    }
+    self.ensure_single_organization(user_id).await?;
+    self
+      .set_user_status(user_id, UserStatus::Suspended, None)
+      .await
+  }
+
+  /// Reactivate a suspended or soft deleted user.
+  pub async fn restore_user(&self, user_id: i32) -> Result<User, AppError> {
+    self.get_user_by_id(user_id).await?;
+    self.ensure_single_organization(user_id).await?;
+    self
+      .set_user_status(user_id, UserStatus::Active, None)
+      .await
+  }
+
+  /// The status of a user applies to every organization, so only one of them may change it.
+  async fn ensure_single_organization(&self, user_id: i32) -> Result<(), AppError> {
+    if self.get_user_organizations(user_id).await?.len() > 1 {
+      return Err(AppError::Conflict(format!(
+        "User with id {} belongs to other organizations",
+        user_id
+      )));
+    }
+    Ok(())
+  }
+
+  async fn set_user_status(
+    &self,
+    user_id: i32,
+    status: UserStatus,
+    if_match: Option<&EntityTags>,
+  ) -> Result<User, AppError> {
+    let mut transaction = self
+      .pool
+      .begin()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    if let Some(if_match) = if_match {
+      self
+        .lock_user_version(&mut transaction, user_id, if_match)
+        .await?;
+    }
+
+    let admins = lock_admins(&mut transaction, user_id).await?;
+    if admins.is_last_permanent_admin(user_id) {
+      let message = match status {
+        UserStatus::Active => None,
+        UserStatus::Suspended => Some("cannot suspend the last admin"),
+        UserStatus::Deleted => Some("cannot delete the last admin"),
+      };
+      if let Some(message) = message {
+        return Err(AppError::InvariantViolation(message.to_string()));
+      }
+    }
+    let before = user_snapshot(&mut transaction, user_id).await?;
+
+    sqlx::query(
+      r#"
+      UPDATE users
+      SET status = $1,
+          deleted_at = CASE WHEN $1 = 'deleted' THEN COALESCE(deleted_at, $2) END,
+          updated_at = $2
+      WHERE id = $3
+      "#,
+    )
+    .bind(status.as_ref())
+    .bind(Utc::now())
+    .bind(user_id)
+    .execute(&mut *transaction)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    let (action, event) = match status {
+      UserStatus::Active => (
+        AuditAction::UserRestore,
+        DomainEvent::UserRestored { user_id },
+      ),
+      UserStatus::Suspended => (
+        AuditAction::UserSuspend,
+        DomainEvent::UserSuspended { user_id },
+      ),
+      UserStatus::Deleted => (
+        AuditAction::UserDelete,
+        DomainEvent::UserDeleted { user_id },
+      ),
+    };
+    let after = user_snapshot(&mut transaction, user_id).await?;
+    publish_event(&mut transaction, event).await?;
+    record_audit(
+      &mut transaction,
+      NewAuditEvent::user(action, Some(user_id)).changes(before, after),
+    )
+    .await?;
+
+    transaction
+      .commit()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    info!(user_id, status = status.as_ref(), "user status changed");
+
+    self.get_user_by_id(user_id).await
+  }
+
+  /// Permanently remove the user and its grants, with `if_match` like `delete_user`.
+  pub async fn purge_user(
+    &self,
+    user_id: i32,
+    if_match: Option<&EntityTags>,
+  ) -> Result<(), AppError> {
     match self.is_user_exists_by_id(user_id).await? {
       true => (),
       false => {
@@ -112,20 +266,43 @@ impl AppState {
         )));
       }
     }
+    // a user shared with other organizations only leaves the active one
+    if self.get_user_organizations(user_id).await?.len() > 1 {
+      self
+        .remove_organization_member(current_tenant()?, user_id, if_match)
+        .await?;
+      return Ok(());
+    }
+
     let mut transaction = self
       .pool
       .begin()
       .await
       .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    if let Some(if_match) = if_match {
+      self
+        .lock_user_version(&mut transaction, user_id, if_match)
+        .await?;
+    }
+
+    let admins = lock_admins(&mut transaction, user_id).await?;
+    if admins.is_last_permanent_admin(user_id) {
+      return Err(AppError::InvariantViolation(
+        "cannot delete the last admin".to_string(),
+      ));
+    }
+    let before = user_snapshot(&mut transaction, user_id).await?;
 
     // delete user roles in user_roles
     sqlx::query(
       r#"
       DELETE FROM user_roles
       WHERE user_id = $1
+      AND organization_id = $2
       "#,
     )
     .bind(user_id)
+    .bind(current_tenant()?)
     .execute(&mut *transaction)
     .await
     .map_err(|err| AppError::DatabaseError(err.to_string()))?;
@@ -135,9 +312,11 @@ impl AppState {
       r#"
       DELETE FROM user_permissions
       WHERE user_id = $1
+      AND organization_id = $2
       "#,
     )
     .bind(user_id)
+    .bind(current_tenant()?)
     .execute(&mut *transaction)
     .await
     .map_err(|err| AppError::DatabaseError(err.to_string()))?;
@@ -155,6 +334,15 @@ impl AppState {
     .await
     .map_err(|err| AppError::DatabaseError(err.to_string()))?;
 
+    if result.is_some() {
+      publish_event(&mut transaction, DomainEvent::UserPurged { user_id }).await?;
+    }
+    record_audit(
+      &mut transaction,
+      NewAuditEvent::user(AuditAction::UserPurge, Some(user_id)).changes(before, None),
+    )
+    .await?;
+
     // commit the transaction
     transaction
       .commit()
@@ -170,105 +358,258 @@ impl AppState {
     }
   }
 
-  pub async fn update_user(&self, user_id: i32, input: UpdateUser) -> Result<User, AppError> {
-    if !input.is_own_user && !input.is_admin && !input.is_moderator {
-      return Err(AppError::Forbidden("Permission denied".to_string()));
-    }
-
-    match self.is_user_exists_by_id(user_id).await? {
-      true => (),
-      false => {
-        return Err(AppError::NotFound(format!(
-          "User with id {} not found",
-          user_id
-        )));
-      }
-    }
-
+  /// Apply `input` on behalf of `actor`, each field needs its own permission: a 403 lists
+  /// the fields `actor` may not change, with the decision that denied the first of them,
+  /// and nothing is applied.
+  ///
+  /// With `if_match` the update only goes ahead when the user's version still matches it.
+  /// The version check and all the fields commit in one transaction, or none of them do.
+  ///
+  /// Role escalations awaiting approval are filed in that transaction too and returned
+  /// with the updated user.
+  pub async fn update_user(
+    &self,
+    actor: &User,
+    user_id: i32,
+    input: UpdateUserOptions,
+    if_match: Option<&EntityTags>,
+  ) -> Result<(User, Option<RoleRequest>), AppError> {
     let user = self.get_user_by_id(user_id).await?;
 
-    // Admin: can update roles, permissions, and own info when updating self
-    if input.is_admin {
-      if input.is_own_user {
-        let hashed_password = if let Some(password) = input.password {
-          hash_password(&password)?
-        } else {
-          user.user_info.password.clone()
-        };
-        let _updated_user_info: UserInfo = sqlx::query_as(
-          r#"
-          UPDATE users
-          SET username = $1, password = $2, updated_at = $3
-          WHERE id = $4
-          RETURNING id, username, created_at, updated_at
-          "#,
-        )
-        .bind(input.username.unwrap_or(user.user_info.username.clone()))
-        .bind(hashed_password)
-        .bind(Utc::now())
-        .bind(user_id)
-        .fetch_one(&self.pool)
-        .await
-        .map_err(|err| AppError::DatabaseError(err.to_string()))?;
-      }
-      if let Some(roles) = input.roles {
-        self.update_roles(roles.extract_ids(), user_id).await?;
-      }
-      if let Some(permissions) = input.permissions {
-        self
-          .update_permissions(permissions.extract_ids(), user_id)
-          .await?;
-      }
-      return self.get_user_by_id(user_id).await;
+    let resource = Resource::user(&user);
+    let info_decision = self.check(actor, Action::UpdateUserInfo, &resource);
+    let roles_decision = self.check(actor, Action::UpdateUserRoles, &resource);
+    let permissions_decision = self.check(actor, Action::UpdateUserPermissions, &resource);
+    if !info_decision.allowed && !roles_decision.allowed && !permissions_decision.allowed {
+      return Err(AppError::AccessDenied(Box::new(info_decision)));
     }
-
-    // Moderator: can update user permissions only, not info or roles
-    if input.is_moderator {
-      if let Some(permissions) = input.permissions {
-        self
-          .update_permissions(permissions.extract_ids(), user_id)
-          .await?;
-      }
-      return self.get_user_by_id(user_id).await;
+    let decision_for = |field: &str| match field {
+      "roles" => &roles_decision,
+      "permissions" => &permissions_decision,
+      _ => &info_decision,
+    };
+    let forbidden: Vec<&str> = input
+      .fields()
+      .into_iter()
+      .filter(|field| !decision_for(field).allowed)
+      .collect();
+    if let Some(field) = forbidden.first() {
+      // the first field's denial stands for the request, the reason names them all
+      let mut decision = decision_for(field).clone();
+      decision.reason = format!(
+        "{}, cannot update: {}",
+        decision.reason,
+        forbidden.join(", ")
+      );
+      return Err(AppError::AccessDenied(Box::new(decision)));
+    }
+    // hashed up front, the transaction locks the user
+    let hashed_password = match &input.password {
+      Some(password) => hash_password(password)?,
+      None => user.user_info.password.clone(),
+    };
+    let mut transaction = self
+      .pool
+      .begin()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    if let Some(if_match) = if_match {
+      self
+        .lock_user_version(&mut transaction, user_id, if_match)
+        .await?;
     }
 
-    // Own user: can update own info (username/password) only
-    if input.is_own_user {
-      let hashed_password = if let Some(password) = input.password {
-        hash_password(&password)?
-      } else {
-        user.user_info.password
+    // appended last, once the transaction holds all the locks of the update
+    let mut audits = vec![];
+    if input.username.is_some() || input.password.is_some() || input.email.is_some() {
+      let before = user_snapshot(&mut transaction, user_id).await?;
+      let username = match input.username {
+        Some(username) => {
+          self
+            .rename_user(
+              &mut transaction,
+              &user.user_info,
+              &username,
+              actor.user_info.id,
+            )
+            .await?;
+          username
+        }
+        None => user.user_info.username.clone(),
       };
-      let updated_user_info: UserInfo = sqlx::query_as(
+      sqlx::query(
         r#"
         UPDATE users
-        SET username = $1, password = $2, updated_at = $3
-        WHERE id = $4
-        RETURNING id, username, created_at, updated_at
+        SET username = $1, password = $2, email = $3, updated_at = $4
+        WHERE id = $5
         "#,
       )
-      .bind(input.username.unwrap_or(user.user_info.username))
+      .bind(&username)
       .bind(hashed_password)
+      .bind(input.email.unwrap_or(user.user_info.email.clone()))
       .bind(Utc::now())
       .bind(user_id)
-      .fetch_one(&self.pool)
+      .execute(&mut *transaction)
       .await
-      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
-      return self.get_user_obj_by_user_info(updated_user_info).await;
+      .map_err(|err| username_error(err, &username))?;
+      let after = user_snapshot(&mut transaction, user_id).await?;
+      let fields = snapshot_changes(&before, &after);
+      if !fields.is_empty() {
+        publish_event(
+          &mut transaction,
+          DomainEvent::UserUpdated { user_id, fields },
+        )
+        .await?;
+      }
+      audits
+        .push(NewAuditEvent::user(AuditAction::UserUpdate, Some(user_id)).changes(before, after));
     }
 
-    Ok(user)
+    let mut role_request = None;
+    if let Some(roles) = input.roles {
+      let (roles, request) = self
+        .intercept_role_escalation(&mut transaction, actor.user_info.id, user_id, roles)
+        .await?;
+      role_request = request;
+      if let Some(roles) = roles {
+        let audit = self
+          .update_roles(&mut transaction, roles, user_id, actor.user_info.id)
+          .await?;
+        audits.push(audit);
+      }
+    }
+
+    if let Some(permissions) = input.permissions {
+      let audit = self
+        .update_permissions(&mut transaction, permissions, user_id, actor.user_info.id)
+        .await?;
+      audits.push(audit);
+    }
+
+    for audit in audits {
+      record_audit(&mut transaction, audit).await?;
+    }
+    transaction
+      .commit()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    Ok((self.get_user_by_id(user_id).await?, role_request))
+  }
+
+  /// The user as `PATCH /users/{id}` sees it, with its direct, unexpired grants only.
+  pub async fn get_user_document(&self, user: &User) -> Result<UserDocument, AppError> {
+    let mut connection = self
+      .pool
+      .acquire()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    let roles = direct_roles(&mut connection, user.user_info.id).await?;
+    let permissions: Vec<(i32, String, Option<DateTime<Utc>>)> = sqlx::query_as(
+      r#"
+      SELECT p.id, p.name, up.expires_at
+      FROM user_permissions up
+      JOIN permissions p ON p.id = up.permission_id
+      WHERE up.user_id = $1
+      AND up.organization_id = $2
+      AND (up.expires_at IS NULL OR up.expires_at > NOW())
+      ORDER BY p.id
+      "#,
+    )
+    .bind(user.user_info.id)
+    .bind(current_tenant()?)
+    .fetch_all(&self.pool)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    Ok(UserDocument {
+      username: user.user_info.username.clone(),
+      email: user.user_info.email.clone(),
+      password: None,
+      roles,
+      permissions: permissions
+        .into_iter()
+        .map(|(id, name, expires_at)| PermissionIn {
+          id,
+          name,
+          expires_at,
+        })
+        .collect(),
+    })
+  }
+
+  /// Check `If-Match` against the user's version and bump it in one statement, so of two
+  /// writers holding the same ETag only the first one gets through.
+  pub async fn claim_user_version(
+    &self,
+    user_id: i32,
+    if_match: &EntityTags,
+  ) -> Result<(), AppError> {
+    let mut transaction = self
+      .pool
+      .begin()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    self
+      .lock_user_version(&mut transaction, user_id, if_match)
+      .await?;
+    transaction
+      .commit()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    Ok(())
+  }
+
+  /// `claim_user_version` within a transaction owned by the caller. The user row stays
+  /// locked until it ends, concurrent writers holding the same ETag then fail the check.
+  pub(crate) async fn lock_user_version(
+    &self,
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: i32,
+    if_match: &EntityTags,
+  ) -> Result<(), AppError> {
+    // the users trigger bumps the version
+    let claimed: Option<i64> = sqlx::query_scalar(
+      r#"
+      UPDATE users
+      SET version = version
+      WHERE id = $1
+      AND ($2::BIGINT[] IS NULL OR version = ANY($2))
+      RETURNING version
+      "#,
+    )
+    .bind(user_id)
+    .bind(if_match.strong_versions())
+    .fetch_optional(&mut **transaction)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    match claimed {
+      Some(_) => Ok(()),
+      None if self.is_user_exists_by_id(user_id).await? => Err(AppError::PreconditionFailed(
+        format!("User with id {} has been modified", user_id),
+      )),
+      None => Err(AppError::NotFound(format!(
+        "User with id {} not found",
+        user_id
+      ))),
+    }
   }
 
   pub async fn get_user_by_id(&self, user_id: i32) -> Result<User, AppError> {
     let user_info: UserInfo = sqlx::query_as(
       r#"
-      SELECT id, username, password, created_at, updated_at
+      SELECT id, username, password, email, status, deleted_at, version, created_at, updated_at
       FROM users
       WHERE id = $1
+      AND EXISTS (
+        SELECT 1 FROM organization_members m
+        WHERE m.user_id = users.id AND m.organization_id = $2
+      )
       "#,
     )
     .bind(user_id)
+    .bind(current_tenant()?)
     .fetch_optional(&self.pool)
     .await
     .map_err(|err| AppError::DatabaseError(err.to_string()))?
@@ -282,12 +623,17 @@ impl AppState {
   pub async fn get_user_by_username(&self, username: &str) -> Result<User, AppError> {
     let user_info: UserInfo = sqlx::query_as(
       r#"
-      SELECT id, username, created_at, updated_at
+      SELECT id, username, email, status, deleted_at, version, created_at, updated_at
       FROM users
-      WHERE username = $1
+      WHERE username_normalized = lower($1)
+      AND EXISTS (
+        SELECT 1 FROM organization_members m
+        WHERE m.user_id = users.id AND m.organization_id = $2
+      )
       "#,
     )
     .bind(username)
+    .bind(current_tenant()?)
     .fetch_one(&self.pool)
     .await
     .map_err(|_| AppError::NotFound(format!("User: {} not found", username)))?;
@@ -297,49 +643,113 @@ impl AppState {
   }
 
   pub async fn verify_user_by_username(&self, username: &str) -> Result<User, AppError> {
-    let user_info: UserInfo = sqlx::query_as(
-      r#"
-      SELECT id, username, password, created_at, updated_at
-      FROM users
-      WHERE username = $1
-      "#,
-    )
-    .bind(username)
-    .fetch_one(&self.pool)
-    .await
-    .map_err(|_| AppError::NotFound(format!("User: {} not found", username)))?;
-
+    let user_info = self.user_info_by_username(username).await?;
     let user = self.get_user_obj_by_user_info(user_info).await?;
     Ok(user)
   }
 
-  pub async fn get_users(&self, limit: i64, offset: i64) -> Result<PaginatedUsers, AppError> {
-    // Optimized approach: fetch users with their basic info and then batch fetch roles/permissions
-    let users_info = sqlx::query_as::<_, UserInfo>(
-      r#"
-      SELECT id, username, created_at, updated_at
-      FROM users
-      ORDER BY id
-      LIMIT $1
-      OFFSET $2
-      "#,
+  /// The account `username`, whichever organizations it belongs to.
+  pub(crate) async fn user_info_by_username(&self, username: &str) -> Result<UserInfo, AppError> {
+    across_tenants(
+      sqlx::query_as(
+        r#"
+        SELECT id, username, password, email, status, deleted_at, version, created_at, updated_at
+        FROM users
+        WHERE username_normalized = lower($1)
+        "#,
+      )
+      .bind(username)
+      .fetch_one(&self.pool),
     )
-    .bind(limit)
-    .bind(offset)
-    .fetch_all(&self.pool)
     .await
-    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    .map_err(|_| AppError::NotFound(format!("User: {} not found", username)))
+  }
 
-    if users_info.is_empty() {
-      let total_count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM users")
+  pub async fn get_users(&self, limit: i64, offset: i64) -> Result<PaginatedUsers, AppError> {
+    self
+      .search_users(
+        &UserFilter::default(),
+        &PaginationParams::offset(limit, offset),
+      )
+      .await
+  }
+
+  /// List the users of the active organization matching `filter`.
+  ///
+  /// Pages after `page.after` by keyset on the sort key and id, or by `page.offset`
+  /// for older clients. The total is counted for offset pages and on request.
+  pub async fn search_users(
+    &self,
+    filter: &UserFilter,
+    page: &PaginationParams,
+  ) -> Result<PaginatedUsers, AppError> {
+    let (sort_field, sort_order) = filter.sort()?;
+    if page.offset.is_some() && page.after.is_some() {
+      return Err(AppError::BadRequest(
+        "offset and after cannot be combined".to_string(),
+      ));
+    }
+    let cursor = page
+      .after
+      .as_deref()
+      .map(|after| UserCursor::decode(after, sort_field, sort_order))
+      .transpose()?;
+
+    let mut query = QueryBuilder::<Postgres>::new(
+      "SELECT u.id, u.username, u.email, u.status, u.deleted_at, u.version, u.created_at, u.updated_at FROM users u",
+    );
+    push_user_filters(&mut query, filter)?;
+    if let Some(cursor) = &cursor {
+      push_keyset(&mut query, sort_field, sort_order, cursor)?;
+    }
+    query.push(format_args!(
+      " ORDER BY u.{} {}, u.id {}",
+      sort_field.as_ref(),
+      sort_order.as_ref(),
+      sort_order.as_ref()
+    ));
+    // one extra row tells whether there is a next page
+    query.push(" LIMIT ").push_bind(page.limit + 1);
+    if let Some(offset) = page.offset {
+      query.push(" OFFSET ").push_bind(offset);
+    }
+    let mut users_info = query
+      .build_query_as::<UserInfo>()
+      .fetch_all(&self.pool)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    let has_more = users_info.len() as i64 > page.limit;
+    users_info.truncate(page.limit as usize);
+    let next_cursor = match users_info.last() {
+      Some(last) if has_more => Some(UserCursor::new(sort_field, sort_order, last).encode()),
+      _ => None,
+    };
+    let users = self.load_users_grants(users_info).await?;
+
+    let total_count = if page.offset.is_some() || page.include_total {
+      let mut count = QueryBuilder::<Postgres>::new("SELECT COUNT(*) FROM users u");
+      push_user_filters(&mut count, filter)?;
+      let total_count: i64 = count
+        .build_query_scalar()
         .fetch_one(&self.pool)
         .await
         .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+      Some(total_count)
+    } else {
+      None
+    };
 
-      return Ok(PaginatedUsers {
-        users: vec![],
-        total_count: total_count.0,
-      });
+    Ok(PaginatedUsers {
+      users,
+      total_count,
+      next_cursor,
+    })
+  }
+
+  /// Batch load the roles, permissions and groups of a page of users.
+  async fn load_users_grants(&self, users_info: Vec<UserInfo>) -> Result<Vec<User>, AppError> {
+    if users_info.is_empty() {
+      return Ok(vec![]);
     }
 
     // Batch fetch all roles for these users
@@ -347,18 +757,33 @@ impl AppState {
     let user_roles_map: std::collections::HashMap<i32, Vec<Role>> =
       sqlx::query_as::<_, UserRoleRow>(
         r#"
-      SELECT
-        ur.user_id,
+      SELECT DISTINCT ON (g.user_id, r.id)
+        g.user_id,
         r.id,
         r.name,
         r.created_at,
-        r.updated_at
-      FROM user_roles ur
-      JOIN roles r ON ur.role_id = r.id
-      WHERE ur.user_id = ANY($1)
+        r.updated_at,
+        g.expires_at
+      FROM (
+        SELECT user_id, role_id, expires_at
+        FROM user_roles
+        WHERE user_id = ANY($1)
+        AND organization_id = $2
+        AND (expires_at IS NULL OR expires_at > NOW())
+        UNION ALL
+        SELECT gm.user_id, gr.role_id, NULL
+        FROM group_roles gr
+        JOIN group_members gm ON gm.group_id = gr.group_id
+        JOIN groups gs ON gs.id = gr.group_id
+        WHERE gm.user_id = ANY($1)
+        AND gs.organization_id = $2
+      ) g
+      JOIN roles r ON g.role_id = r.id
+      ORDER BY g.user_id, r.id, g.expires_at DESC NULLS FIRST
       "#,
       )
       .bind(&user_ids)
+      .bind(current_tenant()?)
       .fetch_all(&self.pool)
       .await
       .map_err(|err| AppError::DatabaseError(err.to_string()))?
@@ -369,6 +794,7 @@ impl AppState {
           name: row.name,
           created_at: row.created_at,
           updated_at: row.updated_at,
+          expires_at: row.expires_at,
         };
         map.entry(row.user_id).or_insert_with(Vec::new).push(role);
         map
@@ -378,18 +804,41 @@ impl AppState {
     let user_permissions_map: std::collections::HashMap<i32, Vec<Permission>> =
       sqlx::query_as::<_, UserPermissionRow>(
         r#"
-      SELECT
-        up.user_id,
+      SELECT DISTINCT ON (g.user_id, p.id)
+        g.user_id,
         p.id,
         p.name,
         p.created_at,
-        p.updated_at
-      FROM user_permissions up
-      JOIN permissions p ON up.permission_id = p.id
-      WHERE up.user_id = ANY($1)
+        p.updated_at,
+        g.expires_at
+      FROM (
+        SELECT user_id, permission_id, expires_at
+        FROM user_permissions
+        WHERE user_id = ANY($1)
+        AND organization_id = $2
+        AND (expires_at IS NULL OR expires_at > NOW())
+        UNION ALL
+        SELECT gm.user_id, gp.permission_id, NULL
+        FROM group_permissions gp
+        JOIN group_members gm ON gm.group_id = gp.group_id
+        JOIN groups gs ON gs.id = gp.group_id
+        WHERE gm.user_id = ANY($1)
+        AND gs.organization_id = $2
+        UNION ALL
+        SELECT gm.user_id, rp.permission_id, NULL
+        FROM role_permissions rp
+        JOIN group_roles gr ON gr.role_id = rp.role_id
+        JOIN group_members gm ON gm.group_id = gr.group_id
+        JOIN groups gs ON gs.id = gr.group_id
+        WHERE gm.user_id = ANY($1)
+        AND gs.organization_id = $2
+      ) g
+      JOIN permissions p ON g.permission_id = p.id
+      ORDER BY g.user_id, p.id, g.expires_at DESC NULLS FIRST
       "#,
       )
       .bind(&user_ids)
+      .bind(current_tenant()?)
       .fetch_all(&self.pool)
       .await
       .map_err(|err| AppError::DatabaseError(err.to_string()))?
@@ -400,6 +849,7 @@ impl AppState {
           name: row.name,
           created_at: row.created_at,
           updated_at: row.updated_at,
+          expires_at: row.expires_at,
         };
         map
           .entry(row.user_id)
@@ -408,8 +858,49 @@ impl AppState {
         map
       });
 
+    // Batch fetch all group memberships for these users
+    let user_groups_map: std::collections::HashMap<i32, Vec<GroupSummary>> =
+      sqlx::query_as::<_, UserGroupRow>(
+        r#"
+      SELECT gm.user_id, g.id, g.name
+      FROM group_members gm
+      JOIN groups g ON g.id = gm.group_id
+      WHERE gm.user_id = ANY($1)
+      AND g.organization_id = $2
+      ORDER BY g.id
+      "#,
+      )
+      .bind(&user_ids)
+      .bind(current_tenant()?)
+      .fetch_all(&self.pool)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?
+      .into_iter()
+      .fold(std::collections::HashMap::new(), |mut map, row| {
+        let group = GroupSummary {
+          id: row.id,
+          name: row.name,
+        };
+        map.entry(row.user_id).or_insert_with(Vec::new).push(group);
+        map
+      });
+
+    // Batch fetch the profiles of these users
+    let user_profiles_map: std::collections::HashMap<i32, UserProfile> =
+      sqlx::query_as::<_, UserProfile>(&format!(
+        "SELECT {} FROM user_profiles WHERE user_id = ANY($1)",
+        PROFILE_COLUMNS
+      ))
+      .bind(&user_ids)
+      .fetch_all(&self.pool)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?
+      .into_iter()
+      .map(|profile| (profile.user_id, profile))
+      .collect();
+
     // Construct User objects efficiently
-    let users: Vec<User> = users_info
+    let users = users_info
       .into_iter()
       .map(|user_info| {
         let roles = user_roles_map
@@ -421,7 +912,16 @@ impl AppState {
           .cloned()
           .unwrap_or_default();
 
-        User::new(
+        let groups = user_groups_map
+          .get(&user_info.id)
+          .cloned()
+          .unwrap_or_default();
+        let profile = user_profiles_map
+          .get(&user_info.id)
+          .cloned()
+          .unwrap_or_default();
+
+        User::new(
           UserInfo {
             password: String::new(), // Not needed for listing
             ..user_info
@@ -429,42 +929,167 @@ impl AppState {
           roles,
           permissions,
         )
+        .with_groups(groups)
+        .with_profile(profile)
       })
       .collect();
+    Ok(users)
+  }
+  pub(crate) async fn get_user_obj_by_user_info(
+    &self,
+    user_info: UserInfo,
+  ) -> Result<User, AppError> {
+    let roles = self.get_user_roles(user_info.id).await?;
+    let permissions = self.get_user_permissions(user_info.id).await?;
+    let groups = self.get_user_groups(user_info.id).await?;
+    let profile = self.get_user_profile(user_info.id).await?;
+    let user = User::new(user_info, roles, permissions)
+      .with_groups(groups)
+      .with_profile(profile);
+    Ok(user)
+  }
+
+  /// The profile of the user, empty when it never set one.
+  pub async fn get_user_profile(&self, user_id: i32) -> Result<UserProfile, AppError> {
+    let profile = sqlx::query_as(&format!(
+      "SELECT {} FROM user_profiles WHERE user_id = $1",
+      PROFILE_COLUMNS
+    ))
+    .bind(user_id)
+    .fetch_optional(&self.pool)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    Ok(profile.unwrap_or_default())
+  }
 
-    let total_count: (i64,) = sqlx::query_as(
+  pub async fn update_user_profile(
+    &self,
+    user_id: i32,
+    input: UpdateProfile,
+  ) -> Result<User, AppError> {
+    sqlx::query(
       r#"
-      SELECT COUNT(*)
-      FROM users
+      INSERT INTO user_profiles (user_id, display_name, locale, timezone, bio, created_at, updated_at)
+      VALUES ($1, $2, $3, $4, $5, $6, $6)
+      ON CONFLICT (user_id)
+      DO UPDATE SET
+        display_name = EXCLUDED.display_name,
+        locale = EXCLUDED.locale,
+        timezone = EXCLUDED.timezone,
+        bio = EXCLUDED.bio,
+        updated_at = EXCLUDED.updated_at
       "#,
     )
-    .fetch_one(&self.pool)
+    .bind(user_id)
+    .bind(input.display_name)
+    .bind(input.locale)
+    .bind(input.timezone)
+    .bind(input.bio)
+    .bind(Utc::now())
+    .execute(&self.pool)
     .await
     .map_err(|err| AppError::DatabaseError(err.to_string()))?;
 
-    Ok(PaginatedUsers {
-      users,
-      total_count: total_count.0,
-    })
+    self.get_user_by_id(user_id).await
   }
 
-  async fn get_user_obj_by_user_info(&self, user_info: UserInfo) -> Result<User, AppError> {
-    let roles = self.get_user_roles(user_info.id).await?;
-    let permissions = self.get_user_permissions(user_info.id).await?;
-    let user = User::new(user_info, roles, permissions);
-    Ok(user)
+  /// Check, re-encode and store an uploaded avatar, replacing the previous one.
+  pub async fn set_user_avatar(&self, user_id: i32, upload: Vec<u8>) -> Result<User, AppError> {
+    let config = self.config.avatars.clone();
+    let avatar = tokio::task::spawn_blocking(move || reencode_avatar(&upload, &config))
+      .await
+      .map_err(|_| AppError::InternalServerError)??;
+
+    let key = format!("avatars/{}/{}.png", user_id, uuid::Uuid::new_v4());
+    self.storage.put(&key, avatar).await?;
+
+    let previous = self.replace_avatar_key(user_id, &key).await;
+    let previous = match previous {
+      Ok(previous) => previous,
+      Err(err) => {
+        let _ = self.storage.delete(&key).await;
+        return Err(err);
+      }
+    };
+    if let Some(previous) = previous
+      && let Err(err) = self.storage.delete(&previous).await
+    {
+      warn!(user_id, key = previous, error = ?err, "failed to delete replaced avatar");
+    }
+
+    self.get_user_by_id(user_id).await
+  }
+
+  /// The stored avatar of the user, re-encoded as PNG.
+  pub async fn get_user_avatar(&self, user_id: i32) -> Result<Option<Vec<u8>>, AppError> {
+    let key: Option<String> = self.get_user_profile(user_id).await?.avatar_key;
+    match key {
+      Some(key) => self.storage.get(&key).await,
+      None => Ok(None),
+    }
+  }
+
+  /// Point the profile at the new avatar, returning the key it replaces.
+  async fn replace_avatar_key(&self, user_id: i32, key: &str) -> Result<Option<String>, AppError> {
+    let mut transaction = self
+      .pool
+      .begin()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    let previous: Option<Option<String>> =
+      sqlx::query_scalar("SELECT avatar_key FROM user_profiles WHERE user_id = $1 FOR UPDATE")
+        .bind(user_id)
+        .fetch_optional(&mut *transaction)
+        .await
+        .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    sqlx::query(
+      r#"
+      INSERT INTO user_profiles (user_id, avatar_key, created_at, updated_at)
+      VALUES ($1, $2, $3, $3)
+      ON CONFLICT (user_id)
+      DO UPDATE SET avatar_key = EXCLUDED.avatar_key, updated_at = EXCLUDED.updated_at
+      "#,
+    )
+    .bind(user_id)
+    .bind(key)
+    .bind(Utc::now())
+    .execute(&mut *transaction)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    transaction
+      .commit()
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    Ok(previous.flatten())
   }
 
   pub async fn get_user_roles(&self, user_id: i32) -> Result<Vec<Role>, AppError> {
     let roles = sqlx::query_as(
       r#"
-      SELECT r.id, r.name, r.created_at, r.updated_at
+      SELECT DISTINCT ON (r.id) r.id, r.name, r.created_at, r.updated_at, g.expires_at
       FROM roles r
-      INNER JOIN user_roles ur ON r.id = ur.role_id
-      WHERE ur.user_id = $1
+      INNER JOIN (
+        SELECT role_id, expires_at
+        FROM user_roles
+        WHERE user_id = $1
+        AND organization_id = $2
+        AND (expires_at IS NULL OR expires_at > NOW())
+        UNION ALL
+        SELECT gr.role_id, NULL
+        FROM group_roles gr
+        JOIN group_members gm ON gm.group_id = gr.group_id
+        JOIN groups gs ON gs.id = gr.group_id
+        WHERE gm.user_id = $1
+        AND gs.organization_id = $2
+      ) g ON r.id = g.role_id
+      ORDER BY r.id, g.expires_at DESC NULLS FIRST
       "#,
     )
     .bind(user_id)
+    .bind(current_tenant()?)
     .fetch_all(&self.pool)
     .await
     .map_err(|err| AppError::DatabaseError(err.to_string()))?;
@@ -475,13 +1100,35 @@ impl AppState {
   pub async fn get_user_permissions(&self, user_id: i32) -> Result<Vec<Permission>, AppError> {
     let permissions = sqlx::query_as(
       r#"
-      SELECT p.id, p.name, p.created_at, p.updated_at
+      SELECT DISTINCT ON (p.id) p.id, p.name, p.created_at, p.updated_at, g.expires_at
       FROM permissions p
-      INNER JOIN user_permissions up ON p.id = up.permission_id
-      WHERE up.user_id = $1
+      INNER JOIN (
+        SELECT permission_id, expires_at
+        FROM user_permissions
+        WHERE user_id = $1
+        AND organization_id = $2
+        AND (expires_at IS NULL OR expires_at > NOW())
+        UNION ALL
+        SELECT gp.permission_id, NULL
+        FROM group_permissions gp
+        JOIN group_members gm ON gm.group_id = gp.group_id
+        JOIN groups gs ON gs.id = gp.group_id
+        WHERE gm.user_id = $1
+        AND gs.organization_id = $2
+        UNION ALL
+        SELECT rp.permission_id, NULL
+        FROM role_permissions rp
+        JOIN group_roles gr ON gr.role_id = rp.role_id
+        JOIN group_members gm ON gm.group_id = gr.group_id
+        JOIN groups gs ON gs.id = gr.group_id
+        WHERE gm.user_id = $1
+        AND gs.organization_id = $2
+      ) g ON p.id = g.permission_id
+      ORDER BY p.id, g.expires_at DESC NULLS FIRST
       "#,
     )
     .bind(user_id)
+    .bind(current_tenant()?)
     .fetch_all(&self.pool)
     .await
     .map_err(|err| AppError::DatabaseError(err.to_string()))?;
@@ -489,17 +1136,39 @@ impl AppState {
     Ok(permissions)
   }
 
+  pub async fn get_user_groups(&self, user_id: i32) -> Result<Vec<GroupSummary>, AppError> {
+    let groups = sqlx::query_as(
+      r#"
+      SELECT g.id, g.name
+      FROM groups g
+      INNER JOIN group_members gm ON g.id = gm.group_id
+      WHERE gm.user_id = $1
+      AND g.organization_id = $2
+      ORDER BY g.id
+      "#,
+    )
+    .bind(user_id)
+    .bind(current_tenant()?)
+    .fetch_all(&self.pool)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    Ok(groups)
+  }
+
   pub async fn is_user_exists_by_id(&self, user_id: i32) -> Result<bool, AppError> {
     let result = sqlx::query_scalar(
       r#"
       SELECT EXISTS (
         SELECT 1
-        FROM users
-        WHERE id = $1
+        FROM organization_members
+        WHERE user_id = $1
+        AND organization_id = $2
       )
       "#,
     )
     .bind(user_id)
+    .bind(current_tenant()?)
     .fetch_one(&self.pool)
     .await
     .map_err(|err| AppError::DatabaseError(err.to_string()))?;
@@ -512,7 +1181,7 @@ impl AppState {
       SELECT EXISTS (
         SELECT 1
         FROM users
-        WHERE username = $1
+        WHERE username_normalized = lower($1)
       )
       "#,
     )
@@ -523,16 +1192,19 @@ impl AppState {
     Ok(result)
   }
 
-  pub async fn update_permissions(
+  /// Replace the direct permission grants of `user_id` within `transaction`. Each permission
+  /// must come with one of the user's active roles.
+  ///
+  /// Publishes `PermissionsChanged` and returns the audit event, for the caller to record
+  /// last before committing.
+  pub(crate) async fn update_permissions(
     &self,
-    permission_ids: Vec<i32>,
+    transaction: &mut Transaction<'_, Postgres>,
+    permissions: Vec<PermissionIn>,
     user_id: i32,
-  ) -> Result<(), AppError> {
-    let mut transaction = self
-      .pool
-      .begin()
-      .await
-      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    granted_by: i32,
+  ) -> Result<NewAuditEvent, AppError> {
+    let before = user_snapshot(transaction, user_id).await?;
 
     // check permission_ids is valid in current user role's permissions
     let role_permissions: Vec<i32> = sqlx::query_scalar(
@@ -542,44 +1214,49 @@ impl AppState {
       JOIN role_permissions rp ON rp.permission_id = p.id
       JOIN user_roles ur ON ur.role_id = rp.role_id
       WHERE ur.user_id = $1
+      AND ur.organization_id = $2
+      AND (ur.expires_at IS NULL OR ur.expires_at > NOW())
       "#,
     )
     .bind(user_id)
-    .fetch_all(&mut *transaction)
+    .bind(current_tenant()?)
+    .fetch_all(&mut **transaction)
     .await
     .map_err(|err| AppError::DatabaseError(err.to_string()))?;
 
-    for permission_id in &permission_ids {
-      if !role_permissions.contains(permission_id) {
+    for permission in &permissions {
+      if !role_permissions.contains(&permission.id) {
         return Err(AppError::BadRequest(format!(
           "Permission: {} is not valid for user: {}",
-          permission_id, user_id
+          permission.id, user_id
         )));
       }
     }
 
-    // compute need delete and insert permission ids
+    // compute need delete and upsert permission ids
     let old_permissions: HashSet<i32> = sqlx::query_scalar(
       r#"
       SELECT permission_id
       FROM user_permissions
       WHERE user_id = $1
+      AND organization_id = $2
       "#,
     )
     .bind(user_id)
-    .fetch_all(&mut *transaction)
+    .bind(current_tenant()?)
+    .fetch_all(&mut **transaction)
     .await?
     .into_iter()
     .collect();
 
-    let new_permissions: HashSet<i32> = permission_ids.iter().cloned().collect();
+    let new_permissions: HashMap<i32, Option<DateTime<Utc>>> = permissions
+      .iter()
+      .map(|permission| (permission.id, permission.expires_at))
+      .collect();
 
     let permissions_to_delete: Vec<i32> = old_permissions
-      .difference(&new_permissions)
-      .cloned()
-      .collect();
-    let permissions_to_insert: Vec<i32> = new_permissions
-      .difference(&old_permissions)
+      .iter()
+      .filter(|id| !new_permissions.contains_key(id))
       .cloned()
       .collect();
 
@@ -590,48 +1267,67 @@ impl AppState {
         DELETE FROM user_permissions
         WHERE user_id = $1
         AND permission_id = $2
+        AND organization_id = $3
         "#,
       )
       .bind(user_id)
       .bind(permission_id)
-      .execute(&mut *transaction)
+      .bind(current_tenant()?)
+      .execute(&mut **transaction)
       .await
       .map_err(|err| AppError::DatabaseError(err.to_string()))?;
     }
 
-    // insert user permissions in user_permissions by permissions_to_insert
-    for permission_id in permissions_to_insert {
-      sqlx::query(
-        r#"
-        INSERT INTO user_permissions (user_id, permission_id, created_at, updated_at)
-        VALUES ($1, $2, $3, $4)
-        ON CONFLICT (user_id, permission_id)
-        DO UPDATE SET updated_at = EXCLUDED.updated_at
-        "#,
-      )
-      .bind(user_id)
-      .bind(permission_id)
-      .bind(Utc::now())
-      .bind(Utc::now())
-      .execute(&mut *transaction)
-      .await
-      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    for (permission_id, expires_at) in new_permissions {
+      upsert_user_permission(transaction, user_id, permission_id, expires_at, granted_by).await?;
     }
 
-    // commit the transaction
-    transaction
-      .commit()
-      .await
-      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
-    Ok(())
+    let after = user_snapshot(transaction, user_id).await?;
+    let permissions = snapshot_grants(&after, "permissions");
+    publish_event(
+      transaction,
+      DomainEvent::PermissionsChanged {
+        user_id,
+        permissions,
+      },
+    )
+    .await?;
+    Ok(
+      NewAuditEvent::user(AuditAction::UserUpdatePermissions, Some(user_id)).changes(before, after),
+    )
   }
 
-  async fn update_roles(&self, role_ids: Vec<i32>, user_id: i32) -> Result<(), AppError> {
-    let mut transaction = self
-      .pool
-      .begin()
-      .await
-      .map_err(|err| AppError::DatabaseError(format!("Failed to begin transaction: {}", err)))?;
+  /// Replace the roles of `user_id` within `transaction`, and re-derive the user's permissions
+  /// from them. A permission expires with the last of the roles granting it.
+  ///
+  /// Publishes `RolesChanged` and returns the audit event, for the caller to record last
+  /// before committing: appends to the audit chain are serialized.
+  pub(crate) async fn update_roles(
+    &self,
+    transaction: &mut Transaction<'_, Postgres>,
+    roles: Vec<RoleIn>,
+    user_id: i32,
+    granted_by: i32,
+  ) -> Result<NewAuditEvent, AppError> {
+    let before = user_snapshot(transaction, user_id).await?;
+    self
+      .apply_roles(transaction, roles, user_id, granted_by)
+      .await?;
+    let after = user_snapshot(transaction, user_id).await?;
+    let roles = snapshot_grants(&after, "roles");
+    publish_event(transaction, DomainEvent::RolesChanged { user_id, roles }).await?;
+    Ok(NewAuditEvent::user(AuditAction::UserUpdateRoles, Some(user_id)).changes(before, after))
+  }
+
+  /// The grants of `update_roles`, without its event and audit.
+  pub(crate) async fn apply_roles(
+    &self,
+    transaction: &mut Transaction<'_, Postgres>,
+    roles: Vec<RoleIn>,
+    user_id: i32,
+    granted_by: i32,
+  ) -> Result<(), AppError> {
+    let admins = lock_admins(transaction, user_id).await?;
 
     // get current user permissions
     let old_permissions: HashSet<i32> = sqlx::query_scalar(
@@ -639,10 +1335,12 @@ impl AppState {
       SELECT permission_id
       FROM user_permissions
       WHERE user_id = $1
+      AND organization_id = $2
       "#,
     )
     .bind(user_id)
-    .fetch_all(&mut *transaction)
+    .bind(current_tenant()?)
+    .fetch_all(&mut **transaction)
     .await?
     .into_iter()
     .collect();
@@ -653,23 +1351,40 @@ impl AppState {
        SELECT role_id
        FROM user_roles
        WHERE user_id = $1
+       AND organization_id = $2
       "#,
     )
     .bind(user_id)
-    .fetch_all(&mut *transaction)
+    .bind(current_tenant()?)
+    .fetch_all(&mut **transaction)
     .await?
     .into_iter()
     .collect();
 
-    // compute need delete and insert role ids
-    let new_role_ids: HashSet<i32> = role_ids.into_iter().collect();
+    // compute need delete and upsert role ids
+    let new_roles: HashMap<i32, Option<DateTime<Utc>>> = roles
+      .iter()
+      .map(|role| (role.id, role.expires_at))
+      .collect();
+
+    // Admin through a group stays whatever the direct grants
+    let group_admin = admins.is_group_admin(user_id);
+    let remains_admin = group_admin || new_roles.contains_key(&admins.role_id);
+    let remains_permanent_admin = group_admin || new_roles.get(&admins.role_id) == Some(&None);
+    if admins.is_admin(user_id) && !remains_admin && user_id == granted_by {
+      return Err(AppError::InvariantViolation(
+        "admins cannot remove their own Admin role".to_string(),
+      ));
+    }
+    if admins.is_last_permanent_admin(user_id) && !remains_permanent_admin {
+      return Err(AppError::InvariantViolation(
+        "cannot remove or limit the Admin role of the last admin".to_string(),
+      ));
+    }
 
     let roles_to_delete: Vec<i32> = current_role_ids
-      .difference(&new_role_ids)
-      .cloned()
-      .collect();
-    let roles_to_insert: Vec<i32> = new_role_ids
-      .difference(&current_role_ids)
+      .iter()
+      .filter(|id| !new_roles.contains_key(id))
       .cloned()
       .collect();
 
@@ -680,66 +1395,79 @@ impl AppState {
         DELETE FROM user_roles
         WHERE user_id = $1
         AND role_id = $2
+        AND organization_id = $3
         "#,
       )
       .bind(user_id)
       .bind(role_id)
-      .execute(&mut *transaction)
+      .bind(current_tenant()?)
+      .execute(&mut **transaction)
       .await
       .map_err(|err| {
         AppError::DatabaseError(format!("Failed to delete role_id: {}, {}", role_id, err))
       })?;
     }
 
-    for role_id in roles_to_insert {
+    for (role_id, expires_at) in &new_roles {
       sqlx::query(
         r#"
-        INSERT INTO user_roles (user_id, role_id, created_at, updated_at)
-        VALUES ($1, $2, $3, $4)
-        ON CONFLICT (user_id, role_id)
-        DO UPDATE SET updated_at = EXCLUDED.updated_at
+        INSERT INTO user_roles (user_id, role_id, organization_id, granted_at, expires_at, granted_by, created_at, updated_at)
+        VALUES ($1, $2, $6, $3, $4, $5, $3, $3)
+        ON CONFLICT (user_id, role_id, organization_id)
+        DO UPDATE SET
+          granted_at = EXCLUDED.granted_at,
+          expires_at = EXCLUDED.expires_at,
+          granted_by = EXCLUDED.granted_by,
+          updated_at = EXCLUDED.updated_at
+        WHERE user_roles.expires_at IS DISTINCT FROM EXCLUDED.expires_at
         "#,
       )
       .bind(user_id)
       .bind(role_id)
       .bind(Utc::now())
-      .bind(Utc::now())
-      .execute(&mut *transaction)
+      .bind(expires_at)
+      .bind(granted_by)
+      .bind(current_tenant()?)
+      .execute(&mut **transaction)
       .await
       .map_err(|err| {
         AppError::DatabaseError(format!("Failed to insert role_id {}: {}", role_id, err))
       })?;
     }
 
-    // get all of new role's permissions
-    let mut new_permissions = HashSet::<i32>::new();
-    for role_id in &new_role_ids {
-      let permissions: Vec<i32> = sqlx::query_scalar(
-        r#"
-        SELECT DISTINCT permission_id
-        FROM role_permissions
-        WHERE role_id = $1
-        "#,
-      )
-      .bind(role_id)
-      .fetch_all(&mut *transaction)
-      .await
-      .map_err(|err| {
-        AppError::DatabaseError(format!(
-          "Failed to fetch permissions for role_id {}: {}",
-          role_id, err
-        ))
-      })?;
-
-      new_permissions.extend(permissions);
+    // get all of new role's permissions, expiring with the longest-lived role
+    let role_ids: Vec<i32> = new_roles.keys().cloned().collect();
+    let role_permissions: Vec<(i32, i32)> = sqlx::query_as(
+      r#"
+      SELECT role_id, permission_id
+      FROM role_permissions
+      WHERE role_id = ANY($1)
+      "#,
+    )
+    .bind(&role_ids)
+    .fetch_all(&mut **transaction)
+    .await
+    .map_err(|err| {
+      AppError::DatabaseError(format!("Failed to fetch permissions for roles: {}", err))
+    })?;
+
+    let mut new_permissions = HashMap::<i32, Option<DateTime<Utc>>>::new();
+    for (role_id, permission_id) in role_permissions {
+      let role_expires_at = new_roles.get(&role_id).cloned().flatten();
+      new_permissions
+        .entry(permission_id)
+        .and_modify(|expires_at| {
+          *expires_at = match (*expires_at, role_expires_at) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            _ => None,
+          }
+        })
+        .or_insert(role_expires_at);
     }
 
     let permissions_to_delete: Vec<i32> = old_permissions
-      .difference(&new_permissions)
-      .cloned()
-      .collect();
-    let permissions_to_insert: Vec<i32> = new_permissions
-      .difference(&old_permissions)
+      .iter()
+      .filter(|id| !new_permissions.contains_key(id))
       .cloned()
       .collect();
 
@@ -750,11 +1478,13 @@ impl AppState {
         DELETE FROM user_permissions
         WHERE user_id = $1
         AND permission_id = $2
+        AND organization_id = $3
         "#,
       )
       .bind(user_id)
       .bind(permission_id)
-      .execute(&mut *transaction)
+      .bind(current_tenant()?)
+      .execute(&mut **transaction)
       .await
       .map_err(|err| {
         AppError::DatabaseError(format!(
@@ -764,41 +1494,582 @@ impl AppState {
       })?;
     }
 
-    for permission_id in permissions_to_insert {
-      sqlx::query(
-        r#"
-        INSERT INTO user_permissions (user_id, permission_id, created_at, updated_at)
-        VALUES ($1, $2, $3, $4)
-        ON CONFLICT (user_id, permission_id)
-        DO UPDATE SET updated_at = EXCLUDED.updated_at
-        "#,
-      )
-      .bind(user_id)
-      .bind(permission_id)
-      .bind(Utc::now())
-      .bind(Utc::now())
+    for (permission_id, expires_at) in new_permissions {
+      upsert_user_permission(transaction, user_id, permission_id, expires_at, granted_by).await?;
+    }
+    Ok(())
+  }
+
+  /// Remove role and permission grants, of every organization, whose `expires_at` has passed.
+  pub async fn sweep_expired_grants(&self) -> Result<u64, AppError> {
+    let mut transaction = across_tenants(self.pool.begin())
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    let roles = sqlx::query("DELETE FROM user_roles WHERE expires_at <= NOW()")
       .execute(&mut *transaction)
       .await
-      .map_err(|err| {
-        AppError::DatabaseError(format!(
-          "Failed to insert permission_id {}: {}",
-          permission_id, err
-        ))
-      })?;
-    }
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    let permissions = sqlx::query("DELETE FROM user_permissions WHERE expires_at <= NOW()")
+      .execute(&mut *transaction)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
 
     transaction
       .commit()
       .await
-      .map_err(|err| AppError::DatabaseError(format!("Failed to commit transaction: {}", err)))?;
-    Ok(())
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    Ok(roles.rows_affected() + permissions.rows_affected())
+  }
+
+  /// Purge the users of every organization soft deleted more than `retention` seconds ago,
+  /// each within its organization. Those that can't be purged, like the last admin, are skipped.
+  pub async fn purge_deleted_users(&self, retention: u64) -> Result<u64, AppError> {
+    let members: Vec<(i32, i32)> = across_tenants(
+      sqlx::query_as(
+        r#"
+        SELECT m.organization_id, u.id FROM users u
+        JOIN organization_members m ON m.user_id = u.id
+        WHERE u.status = 'deleted' AND u.deleted_at <= NOW() - make_interval(secs => $1)
+        ORDER BY u.id
+        "#,
+      )
+      .bind(retention as f64)
+      .fetch_all(&self.pool),
+    )
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    let mut purged = 0;
+    for (organization_id, user_id) in members {
+      match with_tenant(organization_id, self.purge_user(user_id, None)).await {
+        Ok(()) => purged += 1,
+        Err(AppError::InvariantViolation(reason)) => {
+          warn!(user_id, reason, "deleted user kept");
+        }
+        Err(e) => return Err(e),
+      }
+    }
+    Ok(purged)
+  }
+}
+
+/// Append the `WHERE` clause selecting the members of the active organization matching `filter`.
+/// Roles and permissions match direct grants as well as those inherited from groups.
+fn push_user_filters(
+  query: &mut QueryBuilder<'_, Postgres>,
+  filter: &UserFilter,
+) -> Result<(), AppError> {
+  let tenant = current_tenant()?;
+  query.push(
+    " WHERE EXISTS (SELECT 1 FROM organization_members m WHERE m.user_id = u.id AND m.organization_id = ",
+  );
+  query.push_bind(tenant).push(")");
+
+  if let Some(username) = &filter.username {
+    query
+      .push(" AND u.username ILIKE ")
+      .push_bind(format!("%{}%", escape_like(username)));
+  }
+  if let Some(prefix) = &filter.username_prefix {
+    query
+      .push(" AND lower(u.username) LIKE ")
+      .push_bind(format!("{}%", escape_like(&prefix.to_lowercase())));
+  }
+  match filter.status {
+    Some(status) => query
+      .push(" AND u.status = ")
+      .push_bind(status.as_ref().to_string()),
+    None => query.push(" AND u.status <> 'deleted'"),
+  };
+  if let Some(created_after) = filter.created_after {
+    query.push(" AND u.created_at >= ").push_bind(created_after);
   }
+  if let Some(created_before) = filter.created_before {
+    query.push(" AND u.created_at < ").push_bind(created_before);
+  }
+  if let Some(role) = &filter.role {
+    query.push(
+      r#"
+      AND EXISTS (
+        SELECT 1 FROM user_roles ur JOIN roles r ON r.id = ur.role_id
+        WHERE ur.user_id = u.id
+        AND (ur.expires_at IS NULL OR ur.expires_at > NOW())
+        AND ur.organization_id = "#,
+    );
+    query.push_bind(tenant).push(" AND r.name = ");
+    query.push_bind(role.clone());
+    query.push(
+      r#"
+        UNION ALL
+        SELECT 1 FROM group_members gm
+        JOIN groups g ON g.id = gm.group_id
+        JOIN group_roles gr ON gr.group_id = g.id
+        JOIN roles r ON r.id = gr.role_id
+        WHERE gm.user_id = u.id AND g.organization_id = "#,
+    );
+    query.push_bind(tenant).push(" AND r.name = ");
+    query.push_bind(role.clone()).push(")");
+  }
+  if let Some(permission) = &filter.permission {
+    query.push(
+      r#"
+      AND EXISTS (
+        SELECT 1 FROM user_permissions up JOIN permissions p ON p.id = up.permission_id
+        WHERE up.user_id = u.id
+        AND (up.expires_at IS NULL OR up.expires_at > NOW())
+        AND up.organization_id = "#,
+    );
+    query.push_bind(tenant).push(" AND p.name = ");
+    query.push_bind(permission.clone());
+    query.push(
+      r#"
+        UNION ALL
+        SELECT 1 FROM group_members gm
+        JOIN groups g ON g.id = gm.group_id
+        JOIN group_permissions gp ON gp.group_id = g.id
+        JOIN permissions p ON p.id = gp.permission_id
+        WHERE gm.user_id = u.id AND g.organization_id = "#,
+    );
+    query.push_bind(tenant).push(" AND p.name = ");
+    query.push_bind(permission.clone());
+    query.push(
+      r#"
+        UNION ALL
+        SELECT 1 FROM group_members gm
+        JOIN groups g ON g.id = gm.group_id
+        JOIN group_roles gr ON gr.group_id = g.id
+        JOIN role_permissions rp ON rp.role_id = gr.role_id
+        JOIN permissions p ON p.id = rp.permission_id
+        WHERE gm.user_id = u.id AND g.organization_id = "#,
+    );
+    query.push_bind(tenant).push(" AND p.name = ");
+    query.push_bind(permission.clone()).push(")");
+  }
+  Ok(())
+}
 
-  pub async fn get_role_by_claim(&self, claims: &User, user_id: i32) -> Result<IsWho, AppError> {
-    let is_own_user = claims.user_info.id == user_id;
-    let is_moderator = claims.roles.contains_name(RoleName::Moderator);
-    let is_admin = claims.roles.contains_name(RoleName::Admin);
+/// Columns of `UserProfile`, the avatar is served by `GET /users/{id}/avatar`.
+const PROFILE_COLUMNS: &str = "user_id, display_name, locale, timezone, bio, avatar_key, \
+  CASE WHEN avatar_key IS NULL THEN NULL ELSE '/users/' || user_id || '/avatar' END AS avatar_url";
+
+/// Decode an upload of an allowed format by its content, whatever it claims to be,
+/// and re-encode it as a square PNG thumbnail, dropping metadata and anything smuggled along.
+fn reencode_avatar(upload: &[u8], config: &AvatarsConfig) -> Result<Vec<u8>, AppError> {
+  let format = image::guess_format(upload)
+    .ok()
+    .filter(|format| {
+      matches!(
+        format,
+        ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::Gif | ImageFormat::WebP
+      )
+    })
+    .ok_or_else(|| {
+      AppError::UnsupportedMediaType("avatar must be a PNG, JPEG, GIF or WebP image".to_string())
+    })?;
+
+  let mut limits = Limits::default();
+  limits.max_image_width = Some(config.max_dimension);
+  limits.max_image_height = Some(config.max_dimension);
+  let mut reader = ImageReader::with_format(Cursor::new(upload), format);
+  reader.limits(limits);
+  let image = reader.decode().map_err(|err| match err {
+    ImageError::Limits(_) => AppError::PayloadTooLarge(format!(
+      "avatar must be at most {0}x{0} pixels",
+      config.max_dimension
+    )),
+    _ => AppError::BadRequest("avatar is not a valid image".to_string()),
+  })?;
+
+  let avatar = image.resize_to_fill(config.size, config.size, FilterType::Lanczos3);
+  let mut encoded = Cursor::new(Vec::new());
+  avatar
+    .write_to(&mut encoded, ImageFormat::Png)
+    .map_err(|_| AppError::InternalServerError)?;
+  Ok(encoded.into_inner())
+}
 
-    Ok(IsWho::new(is_own_user, is_moderator, is_admin))
+/// Restrict to the users sorted after `cursor`, ties broken by id.
+fn push_keyset(
+  query: &mut QueryBuilder<'_, Postgres>,
+  field: UserSortField,
+  order: SortOrder,
+  cursor: &UserCursor,
+) -> Result<(), AppError> {
+  let op = match order {
+    SortOrder::Asc => ">",
+    SortOrder::Desc => "<",
+  };
+  let invalid = || AppError::BadRequest("invalid cursor".to_string());
+  let key = cursor.key.clone().unwrap_or_default();
+  match field {
+    UserSortField::Id => {
+      query.push(format_args!(" AND u.id {} ", op));
+    }
+    UserSortField::Username => {
+      let username: String = serde_json::from_value(key).map_err(|_| invalid())?;
+      query.push(format_args!(" AND (u.username, u.id) {} (", op));
+      query.push_bind(username).push(", ");
+    }
+    UserSortField::CreatedAt | UserSortField::UpdatedAt => {
+      let at: DateTime<Utc> = serde_json::from_value(key).map_err(|_| invalid())?;
+      query.push(format_args!(" AND (u.{}, u.id) {} (", field.as_ref(), op));
+      query.push_bind(at).push(", ");
+    }
   }
+  query.push_bind(cursor.id);
+  if field != UserSortField::Id {
+    query.push(")");
+  }
+  Ok(())
+}
+
+/// Escape the `LIKE` wildcards of user input.
+fn escape_like(value: &str) -> String {
+  value
+    .replace('\\', "\\\\")
+    .replace('%', "\\%")
+    .replace('_', "\\_")
+}
+
+/// Insert the user and make it a member of the active organization, without any roles.
+pub(crate) async fn insert_user(
+  transaction: &mut Transaction<'_, Postgres>,
+  input: &CreateUser,
+) -> Result<UserInfo, AppError> {
+  let hashed_password = hash_password(&input.password)?;
+  insert_user_hashed(transaction, input, hashed_password).await
+}
+
+/// Like `insert_user` for a password hashed already, `input.password` is ignored.
+pub(crate) async fn insert_user_hashed(
+  transaction: &mut Transaction<'_, Postgres>,
+  input: &CreateUser,
+  hashed_password: String,
+) -> Result<UserInfo, AppError> {
+  // the user is only visible in the organization once a member, too late for `RETURNING`
+  let user_id: i32 =
+    sqlx::query_scalar("SELECT nextval(pg_get_serial_sequence('users', 'id'))::INTEGER")
+      .fetch_one(&mut **transaction)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+  sqlx::query(
+    r#"
+    INSERT INTO users (id, username, password, email, created_at, updated_at)
+    VALUES ($1, $2, $3, $4, $5, $6)
+    "#,
+  )
+  .bind(user_id)
+  .bind(&input.username)
+  .bind(hashed_password)
+  .bind(&input.email)
+  .bind(Utc::now())
+  .bind(Utc::now())
+  .execute(&mut **transaction)
+  .await
+  .map_err(|err| username_error(err, &input.username))?;
+
+  sqlx::query(
+    r#"
+    INSERT INTO organization_members (organization_id, user_id, created_at)
+    VALUES ($1, $2, $3)
+    "#,
+  )
+  .bind(current_tenant()?)
+  .bind(user_id)
+  .bind(Utc::now())
+  .execute(&mut **transaction)
+  .await
+  .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+  sqlx::query_as(
+    r#"
+    SELECT id, username, password, email, status, deleted_at, version, created_at, updated_at
+    FROM users
+    WHERE id = $1
+    "#,
+  )
+  .bind(user_id)
+  .fetch_one(&mut **transaction)
+  .await
+  .map_err(|err| AppError::DatabaseError(err.to_string()))
+}
+
+/// Direct, unexpired role grants of `user_id` in the active organization.
+pub(crate) async fn direct_roles(
+  connection: &mut PgConnection,
+  user_id: i32,
+) -> Result<Vec<RoleIn>, AppError> {
+  let roles: Vec<(i32, String, Option<DateTime<Utc>>)> = sqlx::query_as(
+    r#"
+    SELECT r.id, r.name, ur.expires_at
+    FROM user_roles ur
+    JOIN roles r ON r.id = ur.role_id
+    WHERE ur.user_id = $1
+    AND ur.organization_id = $2
+    AND (ur.expires_at IS NULL OR ur.expires_at > NOW())
+    ORDER BY r.id
+    "#,
+  )
+  .bind(user_id)
+  .bind(current_tenant()?)
+  .fetch_all(connection)
+  .await
+  .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+  Ok(
+    roles
+      .into_iter()
+      .map(|(id, name, expires_at)| RoleIn {
+        id,
+        name,
+        expires_at,
+      })
+      .collect(),
+  )
+}
+
+/// Audited state of a user: its account, its direct grants and its groups with their grants
+/// in the active organization, `None` once the user is gone.
+pub(crate) async fn user_snapshot(
+  transaction: &mut Transaction<'_, Postgres>,
+  user_id: i32,
+) -> Result<Option<Value>, AppError> {
+  sqlx::query_scalar(
+    r#"
+    SELECT jsonb_build_object(
+      'username', u.username,
+      'email', u.email,
+      'status', u.status,
+      'password', u.password,
+      'roles', COALESCE((
+        SELECT jsonb_agg(jsonb_build_object('name', r.name, 'expires_at', ur.expires_at) ORDER BY r.name)
+        FROM user_roles ur
+        JOIN roles r ON r.id = ur.role_id
+        WHERE ur.user_id = u.id AND ur.organization_id = $2
+      ), '[]'::jsonb),
+      'permissions', COALESCE((
+        SELECT jsonb_agg(jsonb_build_object('name', p.name, 'expires_at', up.expires_at) ORDER BY p.name)
+        FROM user_permissions up
+        JOIN permissions p ON p.id = up.permission_id
+        WHERE up.user_id = u.id AND up.organization_id = $2
+      ), '[]'::jsonb),
+      'groups', COALESCE((
+        SELECT jsonb_agg(jsonb_build_object(
+          'name', g.name,
+          'roles', COALESCE((
+            SELECT jsonb_agg(r.name ORDER BY r.name)
+            FROM group_roles gr
+            JOIN roles r ON r.id = gr.role_id
+            WHERE gr.group_id = g.id
+          ), '[]'::jsonb),
+          'permissions', COALESCE((
+            SELECT jsonb_agg(p.name ORDER BY p.name)
+            FROM group_permissions gp
+            JOIN permissions p ON p.id = gp.permission_id
+            WHERE gp.group_id = g.id
+          ), '[]'::jsonb)
+        ) ORDER BY g.name)
+        FROM group_members gm
+        JOIN groups g ON g.id = gm.group_id
+        WHERE gm.user_id = u.id AND g.organization_id = $2
+      ), '[]'::jsonb)
+    )
+    FROM users u
+    WHERE u.id = $1
+    "#,
+  )
+  .bind(user_id)
+  .bind(current_tenant()?)
+  .fetch_optional(&mut **transaction)
+  .await
+  .map_err(|err| AppError::DatabaseError(err.to_string()))
+}
+
+/// Names of the `roles` or `permissions` of a `user_snapshot`, direct or inherited from groups.
+pub(crate) fn snapshot_grants(snapshot: &Option<Value>, field: &str) -> Vec<String> {
+  let Some(snapshot) = snapshot else {
+    return vec![];
+  };
+  let direct = snapshot[field]
+    .as_array()
+    .into_iter()
+    .flatten()
+    .filter_map(|grant| grant["name"].as_str());
+  let inherited = snapshot["groups"]
+    .as_array()
+    .into_iter()
+    .flatten()
+    .flat_map(|group| group[field].as_array().into_iter().flatten())
+    .filter_map(Value::as_str);
+  let mut names: Vec<String> = direct.chain(inherited).map(str::to_string).collect();
+  names.sort();
+  names.dedup();
+  names
+}
+
+/// Account fields, not grants, differing between two `user_snapshot`s.
+fn snapshot_changes(before: &Option<Value>, after: &Option<Value>) -> Vec<String> {
+  let (Some(before), Some(after)) = (before, after) else {
+    return vec![];
+  };
+  ["username", "email", "status", "password"]
+    .into_iter()
+    .filter(|field| before[*field] != after[*field])
+    .map(str::to_string)
+    .collect()
+}
+
+/// `UserCreated` for a user just inserted, with the roles of its `user_snapshot`.
+pub(crate) fn user_created(user_info: &UserInfo, snapshot: &Option<Value>) -> DomainEvent {
+  DomainEvent::UserCreated {
+    user_id: user_info.id,
+    username: user_info.username.clone(),
+    email: user_info.email.clone(),
+    roles: snapshot_grants(snapshot, "roles"),
+  }
+}
+
+/// Active Admin grants of the active organization held by active accounts, direct or through a
+/// group, locked until the end of the transaction so concurrent demotions and deletions can't
+/// both pass the last-admin check.
+pub(crate) struct AdminGrants {
+  role_id: i32,
+  grants: Vec<(i32, Option<DateTime<Utc>>)>,
+  /// members of groups holding the Admin role, which doesn't expire
+  group_admins: Vec<i32>,
+}
+
+impl AdminGrants {
+  pub(crate) fn is_admin(&self, user_id: i32) -> bool {
+    self.grants.iter().any(|(id, _)| *id == user_id) || self.is_group_admin(user_id)
+  }
+
+  pub(crate) fn is_group_admin(&self, user_id: i32) -> bool {
+    self.group_admins.contains(&user_id)
+  }
+
+  pub(crate) fn is_last_permanent_admin(&self, user_id: i32) -> bool {
+    self.permanent_admins() == [user_id]
+  }
+
+  pub(crate) fn has_permanent_admin(&self) -> bool {
+    !self.permanent_admins().is_empty()
+  }
+
+  fn permanent_admins(&self) -> Vec<i32> {
+    let mut permanent: Vec<i32> = self
+      .grants
+      .iter()
+      .filter(|(_, expires_at)| expires_at.is_none())
+      .map(|(id, _)| *id)
+      .chain(self.group_admins.iter().cloned())
+      .collect();
+    permanent.sort_unstable();
+    permanent.dedup();
+    permanent
+  }
+}
+
+pub(crate) async fn lock_admins(
+  transaction: &mut Transaction<'_, Postgres>,
+  user_id: i32,
+) -> Result<AdminGrants, AppError> {
+  // serialize changes to the same user
+  sqlx::query("SELECT id FROM users WHERE id = $1 FOR UPDATE")
+    .bind(user_id)
+    .execute(&mut **transaction)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+  lock_admin_grants(transaction).await
+}
+
+/// `lock_admins` for changes that aren't about one user, like the grants of a group.
+pub(crate) async fn lock_admin_grants(
+  transaction: &mut Transaction<'_, Postgres>,
+) -> Result<AdminGrants, AppError> {
+  let role_id: i32 = sqlx::query_scalar("SELECT id FROM roles WHERE name = $1")
+    .bind(RoleName::Admin.as_ref())
+    .fetch_one(&mut **transaction)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+  let grants: Vec<(i32, Option<DateTime<Utc>>)> = sqlx::query_as(
+    r#"
+    SELECT ur.user_id, ur.expires_at
+    FROM user_roles ur
+    JOIN users u ON u.id = ur.user_id
+    WHERE ur.role_id = $1
+    AND ur.organization_id = $2
+    AND (ur.expires_at IS NULL OR ur.expires_at > NOW())
+    AND u.status = 'active'
+    FOR UPDATE OF ur
+    "#,
+  )
+  .bind(role_id)
+  .bind(current_tenant()?)
+  .fetch_all(&mut **transaction)
+  .await
+  .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+  let group_admins: Vec<i32> = sqlx::query_scalar(
+    r#"
+    SELECT gm.user_id
+    FROM group_members gm
+    JOIN group_roles gr ON gr.group_id = gm.group_id
+    JOIN groups g ON g.id = gm.group_id
+    JOIN users u ON u.id = gm.user_id
+    WHERE gr.role_id = $1
+    AND g.organization_id = $2
+    AND u.status = 'active'
+    FOR UPDATE OF gm, gr
+    "#,
+  )
+  .bind(role_id)
+  .bind(current_tenant()?)
+  .fetch_all(&mut **transaction)
+  .await
+  .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+  Ok(AdminGrants {
+    role_id,
+    grants,
+    group_admins,
+  })
+}
+
+async fn upsert_user_permission(
+  transaction: &mut Transaction<'_, Postgres>,
+  user_id: i32,
+  permission_id: i32,
+  expires_at: Option<DateTime<Utc>>,
+  granted_by: i32,
+) -> Result<(), AppError> {
+  sqlx::query(
+    r#"
+    INSERT INTO user_permissions (user_id, permission_id, organization_id, granted_at, expires_at, granted_by, created_at, updated_at)
+    VALUES ($1, $2, $6, $3, $4, $5, $3, $3)
+    ON CONFLICT (user_id, permission_id, organization_id)
+    DO UPDATE SET
+      granted_at = EXCLUDED.granted_at,
+      expires_at = EXCLUDED.expires_at,
+      granted_by = EXCLUDED.granted_by,
+      updated_at = EXCLUDED.updated_at
+    WHERE user_permissions.expires_at IS DISTINCT FROM EXCLUDED.expires_at
+    "#,
+  )
+  .bind(user_id)
+  .bind(permission_id)
+  .bind(Utc::now())
+  .bind(expires_at)
+  .bind(granted_by)
+  .bind(current_tenant()?)
+  .execute(&mut **transaction)
+  .await
+  .map_err(|err| {
+    AppError::DatabaseError(format!(
+      "Failed to upsert permission_id {}: {}",
+      permission_id, err
+    ))
+  })?;
+  Ok(())
 }
@@ -1,11 +1,14 @@
 // #[allow(unused_imports)]
 #[cfg(test)]
 mod util_tests {
-  pub use crate::AppState;
   pub use crate::common::auth::*;
+  use crate::common::{DEFAULT_ORGANIZATION_ID, EntityTag, EntityTags, with_tenant};
   pub use crate::modules::users::*;
+  pub use crate::{AppError, AppState};
   pub use anyhow::Result;
+  use axum::http::{HeaderMap, header};
   use serial_test::serial;
+  use validator::Validate;
 
   #[test]
   #[serial]
@@ -21,87 +24,849 @@ mod util_tests {
   #[serial]
   async fn create_user_test() -> Result<()> {
     let (_tdb, state) = AppState::init_test_state().await?;
-    let user = CreateUser::new("alice1", "alice_password");
-    let user = state.create_user(user).await?;
-    assert_eq!(user.user_info.username, "alice1");
-    Ok(())
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let user = CreateUser::new("alice1", "alice_password");
+      let user = state.create_user(user).await?;
+      assert_eq!(user.user_info.username, "alice1");
+      anyhow::Ok(())
+    })
+    .await
   }
 
   #[tokio::test]
   #[serial]
   async fn delete_user_test() -> Result<()> {
     let (_tdb, state) = AppState::init_test_state().await?;
-    let user = CreateUser::new("bob1", "bob_password");
-    let user = state.create_user(user).await?;
-    state.delete_user(user.user_info.id).await?;
-    Ok(())
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let user = CreateUser::new("bob1", "bob_password");
+      let user = state.create_user(user).await?;
+      state.delete_user(user.user_info.id, None).await?;
+      anyhow::Ok(())
+    })
+    .await
   }
 
   #[tokio::test]
   #[serial]
   async fn update_user_test() -> Result<()> {
     let (_tdb, state) = AppState::init_test_state().await?;
-    let user = CreateUser::new("charlie1", "charlie_password");
-    let user = state.create_user(user).await?;
-    let is_who = IsWho {
-      is_own_user: true,
-      is_moderator: false,
-      is_admin: false,
-    };
-    let user_options = UpdateUserOptions {
-      username: Some("charlie_updated".to_string()),
-      password: Some("charlie_password_updated".to_string()),
-      roles: None,
-      permissions: None,
-    };
-    let updated_user = UpdateUser::new(user_options, is_who);
-    let updated_user = state.update_user(user.user_info.id, updated_user).await?;
-    assert_eq!(updated_user.user_info.username, "charlie_updated");
-    Ok(())
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let user = CreateUser::new("charlie1", "charlie_password");
+      let user = state.create_user(user).await?;
+      let user_options = UpdateUserOptions {
+        username: Some("charlie_updated".to_string()),
+        password: Some("charlie_password_updated".to_string()),
+        email: None,
+        roles: None,
+        permissions: None,
+      };
+      let (updated_user, _) = state
+        .update_user(&user, user.user_info.id, user_options, None)
+        .await?;
+      assert_eq!(updated_user.user_info.username, "charlie_updated");
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn update_user_atomic_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let superman = state.get_user_by_id(1).await?;
+      let version = state.get_user_by_id(2).await?.user_info.version;
+      // neither role comes with permission 99
+      let input: UpdateUserOptions = serde_json::from_value(serde_json::json!({
+        "roles": [{"id": 2, "name": "Moderator"}],
+        "permissions": [{"id": 99, "name": "missing"}],
+      }))?;
+      let result = state.update_user(&superman, 2, input, None).await;
+      assert!(matches!(result, Err(AppError::BadRequest(_))));
+
+      let alice = state.get_user_by_id(2).await?;
+      assert_eq!(alice.user_info.version, version);
+      assert!(alice.roles.is_empty());
+      let audits: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM audit_events WHERE target_id = 2")
+        .fetch_one(&state.pool)
+        .await?;
+      let events: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM outbox_events")
+        .fetch_one(&state.pool)
+        .await?;
+      assert_eq!((audits, events), (0, 0));
+      anyhow::Ok(())
+    })
+    .await
   }
 
   #[tokio::test]
   #[serial]
   async fn get_users_test() -> Result<()> {
     let (_tdb, state) = AppState::init_test_state().await?;
-    let ret = state.get_users(2, 0).await?;
-    assert_eq!(ret.users.len(), 2);
-    let ret = state.get_users(1, 1).await?;
-    assert_eq!(ret.users.len(), 1);
-    assert_eq!(ret.total_count, 11);
-    Ok(())
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let ret = state.get_users(2, 0).await?;
+      assert_eq!(ret.users.len(), 2);
+      let ret = state.get_users(1, 1).await?;
+      assert_eq!(ret.users.len(), 1);
+      assert_eq!(ret.total_count, Some(11));
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn search_users_test() -> Result<()> {
+    use crate::modules::groups::CreateGroup;
+
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let search = |filter: UserFilter| {
+        let state = state.clone();
+        async move {
+          state
+            .search_users(&filter, &PaginationParams::offset(20, 0))
+            .await
+        }
+      };
+      let names = |ret: &PaginatedUsers| -> Vec<String> {
+        ret
+          .users
+          .iter()
+          .map(|u| u.user_info.username.clone())
+          .collect()
+      };
+
+      let ret = search(UserFilter {
+        username: Some("LI".to_string()),
+        ..Default::default()
+      })
+      .await?;
+      assert_eq!(names(&ret), vec!["alice", "charlie"]);
+      assert_eq!(ret.total_count, Some(2));
+
+      let ret = search(UserFilter {
+        username_prefix: Some("A".to_string()),
+        ..Default::default()
+      })
+      .await?;
+      assert_eq!(names(&ret), vec!["alice"]);
+
+      // wildcards are matched literally
+      let ret = search(UserFilter {
+        username: Some("%".to_string()),
+        ..Default::default()
+      })
+      .await?;
+      assert_eq!(ret.total_count, Some(0));
+
+      let ret = search(UserFilter {
+        role: Some("Admin".to_string()),
+        ..Default::default()
+      })
+      .await?;
+      assert_eq!(names(&ret), vec!["superman"]);
+
+      let ret = search(UserFilter {
+        permission: Some("MANAGE_USERS".to_string()),
+        ..Default::default()
+      })
+      .await?;
+      assert_eq!(names(&ret), vec!["superman"]);
+
+      let ret = search(UserFilter {
+        sort: Some("username:desc".to_string()),
+        ..Default::default()
+      })
+      .await?;
+      assert_eq!(ret.users[0].user_info.username, "superman");
+      assert_eq!(ret.total_count, Some(11));
+
+      let ret = search(UserFilter {
+        created_after: Some(chrono::Utc::now() + chrono::Duration::days(1)),
+        ..Default::default()
+      })
+      .await?;
+      assert_eq!(ret.total_count, Some(0));
+
+      let ret = search(UserFilter {
+        status: Some(UserStatus::Active),
+        ..Default::default()
+      })
+      .await?;
+      assert_eq!(ret.total_count, Some(11));
+
+      assert!(matches!(
+        search(UserFilter {
+          sort: Some("password".to_string()),
+          ..Default::default()
+        })
+        .await,
+        Err(AppError::BadRequest(_))
+      ));
+
+      // roles granted through a group count too
+      let bob = state.get_user_by_username("bob").await?;
+      let group = state
+        .create_group(CreateGroup {
+          name: "reviewers".to_string(),
+          description: None,
+        })
+        .await?;
+      sqlx::query("INSERT INTO group_roles (group_id, role_id) VALUES ($1, 2)")
+        .bind(group.id)
+        .execute(&state.pool)
+        .await?;
+      state.add_group_member(group.id, bob.user_info.id).await?;
+      let ret = search(UserFilter {
+        role: Some("Moderator".to_string()),
+        ..Default::default()
+      })
+      .await?;
+      assert_eq!(names(&ret), vec!["bob"]);
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn cursor_pagination_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      // the fixture users share created_at, so this also exercises the id tie-break
+      for sort in [
+        "id:asc",
+        "username:desc",
+        "created_at:asc",
+        "updated_at:desc",
+      ] {
+        let filter = UserFilter {
+          sort: Some(sort.to_string()),
+          ..Default::default()
+        };
+        let all = state
+          .search_users(&filter, &PaginationParams::offset(100, 0))
+          .await?;
+        let mut page = PaginationParams {
+          limit: 4,
+          ..Default::default()
+        };
+        let mut seen = vec![];
+        loop {
+          let ret = state.search_users(&filter, &page).await?;
+          assert_eq!(ret.total_count, None);
+          seen.extend(ret.users.into_iter().map(|u| u.user_info.id));
+          match ret.next_cursor {
+            Some(cursor) => page.after = Some(cursor),
+            None => break,
+          }
+        }
+        let expected: Vec<i32> = all.users.iter().map(|u| u.user_info.id).collect();
+        assert_eq!(seen, expected, "sort: {}", sort);
+      }
+
+      let page = PaginationParams {
+        limit: 4,
+        include_total: true,
+        ..Default::default()
+      };
+      let ret = state.search_users(&UserFilter::default(), &page).await?;
+      assert_eq!(ret.total_count, Some(11));
+      let cursor = ret.next_cursor.unwrap();
+
+      let bad_request =
+        |ret: Result<PaginatedUsers, AppError>| matches!(ret, Err(AppError::BadRequest(_)));
+      let by_username = UserFilter {
+        sort: Some("username:asc".to_string()),
+        ..Default::default()
+      };
+      let after = |after: &str| PaginationParams {
+        limit: 4,
+        after: Some(after.to_string()),
+        ..Default::default()
+      };
+      assert!(bad_request(
+        state.search_users(&by_username, &after(&cursor)).await
+      ));
+      assert!(bad_request(
+        state
+          .search_users(&UserFilter::default(), &after("garbage"))
+          .await
+      ));
+      let both = PaginationParams {
+        offset: Some(0),
+        ..after(&cursor)
+      };
+      assert!(bad_request(
+        state.search_users(&UserFilter::default(), &both).await
+      ));
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn user_status_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let bob = state.get_user_by_username("bob").await?.user_info.id;
+
+      let user = state.suspend_user(bob).await?;
+      assert_eq!(user.user_info.status, UserStatus::Suspended);
+      assert!(matches!(
+        state.verify_user("bob", "123456").await,
+        Err(AppError::Forbidden(_))
+      ));
+      // suspended users are still listed
+      assert_eq!(state.get_users(20, 0).await?.total_count, Some(11));
+      let user = state.restore_user(bob).await?;
+      assert_eq!(user.user_info.status, UserStatus::Active);
+      state.verify_user("bob", "123456").await?;
+
+      state.delete_user(bob, None).await?;
+      let user = state.get_user_by_id(bob).await?;
+      assert_eq!(user.user_info.status, UserStatus::Deleted);
+      assert!(user.user_info.deleted_at.is_some());
+      assert_eq!(state.get_users(20, 0).await?.total_count, Some(10));
+      let deleted = UserFilter {
+        status: Some(UserStatus::Deleted),
+        ..Default::default()
+      };
+      let ret = state
+        .search_users(&deleted, &PaginationParams::offset(20, 0))
+        .await?;
+      assert_eq!(ret.users[0].user_info.id, bob);
+      assert!(matches!(
+        state.suspend_user(bob).await,
+        Err(AppError::Conflict(_))
+      ));
+      let user = state.restore_user(bob).await?;
+      assert_eq!(user.user_info.status, UserStatus::Active);
+      assert_eq!(user.user_info.deleted_at, None);
+
+      state.purge_user(bob, None).await?;
+      assert!(matches!(
+        state.get_user_by_id(bob).await,
+        Err(AppError::NotFound(_))
+      ));
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn suspend_last_admin_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      assert!(matches!(
+        state.suspend_user(1).await,
+        Err(AppError::InvariantViolation(_))
+      ));
+
+      // a suspended admin doesn't count as a remaining admin
+      state.set_roles(roles(&[3]), 2, 1).await?;
+      state.suspend_user(2).await?;
+      assert!(matches!(
+        state.suspend_user(1).await,
+        Err(AppError::InvariantViolation(_))
+      ));
+      assert!(matches!(
+        state.delete_user(1, None).await,
+        Err(AppError::InvariantViolation(_))
+      ));
+      state.restore_user(2).await?;
+      state.suspend_user(1).await?;
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn user_profile_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let profile = UpdateProfile {
+        display_name: Some("Alice Liddell".to_string()),
+        locale: Some("zh-Hans-CN".to_string()),
+        timezone: Some("America/Argentina/Buenos_Aires".to_string()),
+        bio: Some("curious".to_string()),
+      };
+      profile.validate()?;
+      let user = state.update_user_profile(2, profile).await?;
+      assert_eq!(user.profile.display_name.as_deref(), Some("Alice Liddell"));
+      assert_eq!(user.profile.avatar_url, None);
+
+      let users = state.get_users(2, 0).await?;
+      assert_eq!(users.users[0].profile, UserProfile::default());
+      assert_eq!(users.users[1].profile.locale.as_deref(), Some("zh-Hans-CN"));
+
+      // replacing the profile clears the omitted fields
+      let user = state
+        .update_user_profile(
+          2,
+          UpdateProfile {
+            timezone: Some("UTC".to_string()),
+            ..Default::default()
+          },
+        )
+        .await?;
+      assert_eq!(user.profile.display_name, None);
+      assert_eq!(user.profile.timezone.as_deref(), Some("UTC"));
+
+      for (locale, timezone) in [("english", "UTC"), ("en", "../etc/passwd"), ("en", "Asia")] {
+        let profile = UpdateProfile {
+          locale: Some(locale.to_string()),
+          timezone: Some(timezone.to_string()),
+          ..Default::default()
+        };
+        assert!(profile.validate().is_err(), "{} {}", locale, timezone);
+      }
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  pub fn png(width: u32, height: u32) -> Vec<u8> {
+    let image = image::RgbImage::from_pixel(width, height, image::Rgb([200, 30, 30]));
+    let mut bytes = std::io::Cursor::new(Vec::new());
+    image.write_to(&mut bytes, image::ImageFormat::Png).unwrap();
+    bytes.into_inner()
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn user_avatar_test() -> Result<()> {
+    use crate::common::{LocalStorage, Storage};
+    use std::sync::Arc;
+
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let root = std::env::temp_dir().join(format!("avatars-{}", uuid::Uuid::new_v4()));
+      let storage = LocalStorage::new(&root);
+      let state = state.with_storage(Arc::new(storage.clone()));
+
+      let user = state.set_user_avatar(2, png(600, 300)).await?;
+      assert_eq!(user.profile.avatar_url.as_deref(), Some("/users/2/avatar"));
+      let avatar = state.get_user_avatar(2).await?.unwrap();
+      let decoded = image::load_from_memory(&avatar)?;
+      assert_eq!(image::guess_format(&avatar)?, image::ImageFormat::Png);
+      assert_eq!((decoded.width(), decoded.height()), (256, 256));
+
+      // the replaced avatar is removed from storage
+      state.set_user_avatar(2, png(64, 64)).await?;
+      assert_eq!(std::fs::read_dir(root.join("avatars/2"))?.count(), 1);
+
+      assert!(matches!(
+        state
+          .set_user_avatar(2, b"<svg xmlns='http://www.w3.org/2000/svg'/>".to_vec())
+          .await,
+        Err(AppError::UnsupportedMediaType(_))
+      ));
+      let mut truncated = png(64, 64);
+      truncated.truncate(40);
+      assert!(matches!(
+        state.set_user_avatar(2, truncated).await,
+        Err(AppError::BadRequest(_))
+      ));
+      let mut config = state.config.clone();
+      config.avatars.max_dimension = 100;
+      let strict =
+        AppState::new(config, state.pool.clone()).with_storage(Arc::new(storage.clone()));
+      assert!(matches!(
+        strict.set_user_avatar(2, png(101, 10)).await,
+        Err(AppError::PayloadTooLarge(_))
+      ));
+
+      assert!(matches!(
+        storage.put("../escape.png", vec![1]).await,
+        Err(AppError::BadRequest(_))
+      ));
+      assert_eq!(storage.get("avatars/missing.png").await?, None);
+      std::fs::remove_dir_all(&root)?;
+      anyhow::Ok(())
+    })
+    .await
   }
 
   #[tokio::test]
   #[serial]
   async fn get_user_by_id_test() -> Result<()> {
     let (_tdb, state) = AppState::init_test_state().await?;
-    let user = CreateUser::new("mike", "mike_password");
-    let user = state.create_user(user).await?;
-    let user = state.get_user_by_id(user.user_info.id).await?;
-    assert_eq!(user.user_info.username, "mike");
-    Ok(())
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let user = CreateUser::new("mike", "mike_password");
+      let user = state.create_user(user).await?;
+      let user = state.get_user_by_id(user.user_info.id).await?;
+      assert_eq!(user.user_info.username, "mike");
+      anyhow::Ok(())
+    })
+    .await
   }
 
   #[tokio::test]
   #[serial]
   async fn verify_user_test() -> Result<()> {
     let (_tdb, state) = AppState::init_test_state().await?;
-    let user = CreateUser::new("nancy", "nancy_password");
-    let user = state.create_user(user).await?;
-    let user = state
-      .verify_user(&user.user_info.username, "nancy_password")
-      .await?;
-    assert_eq!(user.user_info.username, "nancy");
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let user = CreateUser::new("nancy", "nancy_password");
+      let user = state.create_user(user).await?;
+      let user = state
+        .verify_user(&user.user_info.username, "nancy_password")
+        .await?;
+      assert_eq!(user.user_info.username, "nancy");
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn time_bound_role_grant_test() -> Result<()> {
+    use chrono::SubsecRound;
+
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let admin = state.get_user_by_id(1).await?;
+      let moderator = |expires_at| UpdateUserOptions {
+        username: None,
+        password: None,
+        email: None,
+        roles: Some(vec![RoleIn {
+          id: 2,
+          name: "Moderator".to_string(),
+          expires_at: Some(expires_at),
+        }]),
+        permissions: None,
+      };
+
+      let expires_at = (chrono::Utc::now() + chrono::Duration::hours(1)).trunc_subsecs(0);
+      let (user, _) = state
+        .update_user(&admin, 2, moderator(expires_at), None)
+        .await?;
+      assert_eq!(user.roles.len(), 1);
+      assert_eq!(user.roles[0].expires_at, Some(expires_at));
+      assert!(
+        user
+          .permissions
+          .iter()
+          .all(|p| p.expires_at == Some(expires_at))
+      );
+      let granted_by: Option<i32> =
+        sqlx::query_scalar("SELECT granted_by FROM user_roles WHERE user_id = 2")
+          .fetch_one(&state.pool)
+          .await?;
+      assert_eq!(granted_by, Some(1));
+
+      let expired_at = chrono::Utc::now() - chrono::Duration::hours(1);
+      let (user, _) = state
+        .update_user(&admin, 2, moderator(expired_at), None)
+        .await?;
+      assert!(user.roles.is_empty());
+      assert!(user.permissions.is_empty());
+      assert!(state.sweep_expired_grants().await? > 0);
+      assert_eq!(state.sweep_expired_grants().await?, 0);
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  fn roles(ids: &[i32]) -> Vec<RoleIn> {
+    ids
+      .iter()
+      .map(|id| RoleIn {
+        id: *id,
+        name: ["User", "Moderator", "Admin"][*id as usize - 1].to_string(),
+        expires_at: None,
+      })
+      .collect()
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn protect_last_admin_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let ret = state.delete_user(1, None).await;
+      assert!(matches!(ret, Err(AppError::InvariantViolation(_))));
+      let ret = state.set_roles(roles(&[1]), 1, 2).await;
+      assert!(matches!(ret, Err(AppError::InvariantViolation(_))));
+      // an expiring Admin grant would lock everyone out later
+      let mut expiring = roles(&[3]);
+      expiring[0].expires_at = Some(chrono::Utc::now() + chrono::Duration::days(1));
+      let ret = state.set_roles(expiring, 1, 2).await;
+      assert!(matches!(ret, Err(AppError::InvariantViolation(_))));
+
+      // with a second admin, superman can be demoted, but not by himself
+      state.set_roles(roles(&[3]), 2, 1).await?;
+      let ret = state.set_roles(roles(&[1]), 1, 1).await;
+      assert!(matches!(ret, Err(AppError::InvariantViolation(_))));
+      state.set_roles(roles(&[1]), 1, 2).await?;
+      assert!(
+        !state
+          .get_user_roles(1)
+          .await?
+          .contains_name(RoleName::Admin)
+      );
+
+      let ret = state.delete_user(2, None).await;
+      assert!(matches!(ret, Err(AppError::InvariantViolation(_))));
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn concurrent_admin_demotion_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      state.set_roles(roles(&[3]), 2, 1).await?;
+
+      // each admin demotes the other at the same time: only one may win
+      let (a, b) = tokio::join!(
+        state.set_roles(roles(&[1]), 1, 2),
+        state.set_roles(roles(&[1]), 2, 1),
+      );
+      assert!(a.is_ok() != b.is_ok());
+      let admins: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM user_roles WHERE role_id = 3")
+        .fetch_one(&state.pool)
+        .await?;
+      assert_eq!(admins, 1);
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[test]
+  fn entity_tags_test() -> Result<()> {
+    let mut headers = HeaderMap::new();
+    assert!(EntityTags::from_headers(&headers, header::IF_MATCH)?.is_none());
+    headers.insert(header::IF_MATCH, r#""3", W/"4""#.parse()?);
+    let tags = EntityTags::from_headers(&headers, header::IF_MATCH)?.unwrap();
+    assert!(tags.matches_strong(3));
+    assert!(!tags.matches_strong(4));
+    assert!(tags.matches_weak(4));
+    assert_eq!(tags.strong_versions(), Some(vec![3]));
+    headers.insert(header::IF_MATCH, "*".parse()?);
+    let tags = EntityTags::from_headers(&headers, header::IF_MATCH)?;
+    assert_eq!(tags, Some(EntityTags::Any));
+    headers.insert(header::IF_MATCH, "3".parse()?);
+    let tags = EntityTags::from_headers(&headers, header::IF_MATCH);
+    assert!(matches!(tags, Err(AppError::BadRequest(_))));
+    Ok(())
+  }
+
+  #[test]
+  fn json_patch_test() -> Result<()> {
+    use crate::common::{PatchOperation, apply_patch, merge_patch};
+    use serde_json::json;
+
+    let mut document = json!({"a": "b", "c": {"d": "e", "f": "g"}});
+    merge_patch(&mut document, &json!({"a": "z", "c": {"f": null}}));
+    assert_eq!(document, json!({"a": "z", "c": {"d": "e"}}));
+
+    let mut document = json!({"roles": [{"id": 1}], "tag/~x": 1});
+    let operations: Vec<PatchOperation> = serde_json::from_value(json!([
+      {"op": "test", "path": "/roles/0/id", "value": 1},
+      {"op": "add", "path": "/roles/-", "value": {"id": 2}},
+      {"op": "copy", "from": "/roles/0", "path": "/first"},
+      {"op": "move", "from": "/tag~1~0x", "path": "/moved"},
+      {"op": "replace", "path": "/roles/1/id", "value": 3},
+      {"op": "remove", "path": "/roles/0"}
+    ]))?;
+    apply_patch(&mut document, &operations)?;
+    assert_eq!(
+      document,
+      json!({"roles": [{"id": 3}], "first": {"id": 1}, "moved": 1})
+    );
+
+    // a failing operation leaves the document untouched
+    let operations: Vec<PatchOperation> = serde_json::from_value(json!([
+      {"op": "remove", "path": "/moved"},
+      {"op": "replace", "path": "/missing", "value": 1}
+    ]))?;
+    let result = apply_patch(&mut document, &operations);
+    assert!(matches!(result, Err(AppError::ValidationError(_))));
+    assert_eq!(document["moved"], 1);
+    let operations: Vec<PatchOperation> =
+      serde_json::from_value(json!([{"op": "test", "path": "/moved", "value": 2}]))?;
+    let result = apply_patch(&mut document, &operations);
+    assert!(matches!(result, Err(AppError::Conflict(_))));
+    let operations: Vec<PatchOperation> =
+      serde_json::from_value(json!([{"op": "add", "path": "/roles/01", "value": 2}]))?;
+    assert!(apply_patch(&mut document, &operations).is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn user_document_changes_test() -> Result<()> {
+    let document = UserDocument {
+      username: "alice".to_string(),
+      email: Some("alice@example.com".to_string()),
+      password: None,
+      roles: vec![RoleIn {
+        id: 1,
+        name: "User".to_string(),
+        expires_at: None,
+      }],
+      permissions: vec![],
+    };
+    let patched = document.patch(
+      PatchFormat::MergePatch,
+      br#"{"email": null, "roles": [{"id": 1, "name": "User"}]}"#,
+    )?;
+    let changes = document.changes(patched);
+    assert_eq!(changes.fields(), vec!["email"]);
+    assert_eq!(changes.email, Some(None));
+
+    let patched = document.patch(
+      PatchFormat::JsonPatch,
+      br#"[{"op": "add", "path": "/password", "value": "secret1"},
+           {"op": "remove", "path": "/roles/0"}]"#,
+    )?;
+    assert_eq!(
+      document.changes(patched).fields(),
+      vec!["password", "roles"]
+    );
+
+    // plain JSON keeps null meaning unchanged, the email aside
+    let patched = document.patch(
+      PatchFormat::Json,
+      br#"{"username": null, "password": null, "email": null, "roles": null, "permissions": null}"#,
+    )?;
+    assert_eq!(document.changes(patched).fields(), vec!["email"]);
+    let patched = document.patch(PatchFormat::MergePatch, br#"{"roles": null}"#)?;
+    assert_eq!(document.changes(patched).fields(), vec!["roles"]);
+    assert_eq!(
+      PatchFormat::from_media_type("application/json; charset=utf-8"),
+      Some(PatchFormat::Json)
+    );
+
+    let result = document.patch(PatchFormat::MergePatch, br#"{"status": "active"}"#);
+    assert!(matches!(result, Err(AppError::ValidationError(_))));
+    let result = document.patch(PatchFormat::MergePatch, br#"{"username": null}"#);
+    assert!(matches!(result, Err(AppError::ValidationError(_))));
+    let result = document.patch(PatchFormat::JsonPatch, b"{}");
+    assert!(matches!(result, Err(AppError::BadRequest(_))));
     Ok(())
   }
 
+  #[tokio::test]
+  #[serial]
+  async fn user_version_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let tag = |version: i64| {
+        EntityTags::Tags(vec![EntityTag {
+          weak: false,
+          opaque: version.to_string(),
+        }])
+      };
+      let version = state.get_user_by_id(2).await?.user_info.version;
+      // grants live in their own table and still bump the version
+      state.set_roles(roles(&[2]), 2, 1).await?;
+      let granted = state.get_user_by_id(2).await?.user_info.version;
+      assert!(granted > version);
+
+      let result = state.claim_user_version(2, &tag(version)).await;
+      assert!(matches!(result, Err(AppError::PreconditionFailed(_))));
+      state.claim_user_version(2, &tag(granted)).await?;
+      // an ETag is claimed only once
+      let result = state.claim_user_version(2, &tag(granted)).await;
+      assert!(matches!(result, Err(AppError::PreconditionFailed(_))));
+      state.claim_user_version(2, &EntityTags::Any).await?;
+      let result = state.claim_user_version(99, &EntityTags::Any).await;
+      assert!(matches!(result, Err(AppError::NotFound(_))));
+
+      // so do the grants of the user's groups
+      let group = state
+        .create_group(crate::modules::groups::CreateGroup {
+          name: "moderators".to_string(),
+          description: None,
+        })
+        .await?;
+      state.add_group_member(group.id, 2).await?;
+      let member = state.get_user_by_id(2).await?.user_info.version;
+      let grants = crate::modules::groups::UpdateGroup {
+        permission_ids: Some(vec![1]),
+        ..Default::default()
+      };
+      state.update_group(group.id, grants, 1).await?;
+      assert!(state.get_user_by_id(2).await?.user_info.version > member);
+
+      // deletes check the version in their own transaction, a failed one doesn't claim it
+      let admin = state.get_user_by_id(1).await?.user_info.version;
+      let result = state.delete_user(1, Some(&tag(admin))).await;
+      assert!(matches!(result, Err(AppError::InvariantViolation(_))));
+      assert_eq!(state.get_user_by_id(1).await?.user_info.version, admin);
+      let current = state.get_user_by_id(2).await?.user_info.version;
+      let result = state.delete_user(2, Some(&tag(current - 1))).await;
+      assert!(matches!(result, Err(AppError::PreconditionFailed(_))));
+      state.delete_user(2, Some(&tag(current))).await?;
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn username_rules_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let rename = |username: &str| UpdateUserOptions {
+        username: Some(username.to_string()),
+        password: None,
+        email: None,
+        roles: None,
+        permissions: None,
+      };
+
+      let result = state.create_user(CreateUser::new("ALICE", "123456")).await;
+      assert!(matches!(result, Err(AppError::UserExisted(_))));
+      let result = state.create_user(CreateUser::new("Root", "123456")).await;
+      assert!(matches!(result, Err(AppError::ValidationError(_))));
+
+      let alice = state.get_user_by_id(2).await?;
+      state.update_user(&alice, 2, rename("alicia"), None).await?;
+      let history = state.get_username_history(2).await?;
+      assert_eq!(history.len(), 1);
+      assert_eq!(history[0].old_username, "alice");
+      assert_eq!(history[0].changed_by, Some(2));
+      let result = state.update_user(&alice, 2, rename("alicia2"), None).await;
+      assert!(matches!(result, Err(AppError::Conflict(_))));
+
+      // the old name still leads to alice and nobody else can take it
+      assert_eq!(state.find_user_by_username("ALICE").await?.user_info.id, 2);
+      assert_eq!(state.find_user_by_username("alicia").await?.user_info.id, 2);
+      let result = state.create_user(CreateUser::new("alice", "123456")).await;
+      assert!(matches!(result, Err(AppError::Conflict(_))));
+      let bob = state.get_user_by_id(3).await?;
+      let result = state.update_user(&bob, 3, rename("Alice"), None).await;
+      assert!(matches!(result, Err(AppError::Conflict(_))));
+
+      let mut config = state.config.clone();
+      config.usernames.change_cooldown = 0;
+      let state = AppState::new(config, state.pool.clone());
+      let result = state.update_user(&bob, 3, rename("charlie"), None).await;
+      assert!(matches!(result, Err(AppError::UserExisted(_))));
+      state.update_user(&alice, 2, rename("alice"), None).await?;
+      assert_eq!(state.get_username_history(2).await?.len(), 2);
+      assert_eq!(state.verify_user("Alice", "123456").await?.user_info.id, 2);
+      anyhow::Ok(())
+    })
+    .await
+  }
+
   #[cfg(test)]
   impl CreateUser {
     pub fn new(username: &str, password: &str) -> Self {
       Self {
         username: username.to_string(),
         password: password.to_string(),
+        email: None,
       }
     }
   }
@@ -109,6 +874,7 @@ mod util_tests {
 
 #[cfg(test)]
 mod integration_tests {
+  use crate::common::{DEFAULT_ORGANIZATION_ID, with_tenant};
   use crate::{AppState, get_router};
   use anyhow::Result;
   use axum::Router;
@@ -210,6 +976,58 @@ mod integration_tests {
     assert_eq!(users_page["users"].as_array().unwrap().len(), 1);
     assert_eq!(users_page["total_count"].as_i64().unwrap(), 11);
 
+    let response = client
+      .get(format!(
+        "http://{}/users?limit=10&offset=0&username=li&sort=username:desc",
+        addr
+      ))
+      .header("Authorization", format!("Bearer {}", token))
+      .send()
+      .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let users_page: serde_json::Value = response.json().await?;
+    assert_eq!(users_page["total_count"].as_i64().unwrap(), 2);
+    assert_eq!(users_page["users"][0]["user_info"]["username"], "charlie");
+
+    let response = client
+      .get(format!("http://{}/users?limit=4&sort=username:asc", addr))
+      .header("Authorization", format!("Bearer {}", token))
+      .send()
+      .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let link = response.headers()["link"].to_str()?.to_string();
+    assert!(link.starts_with("</users?limit=4&sort=username:asc>; rel=\"first\""));
+    let users_page: serde_json::Value = response.json().await?;
+    assert!(users_page.get("total_count").is_none());
+    let next_cursor = users_page["next_cursor"].as_str().unwrap();
+    let next = format!(
+      "</users?limit=4&sort=username:asc&after={}>; rel=\"next\"",
+      next_cursor
+    );
+    assert!(link.contains(&next));
+
+    // follow the next link like a generic client would
+    let next_url = next.trim_start_matches('<').split('>').next().unwrap();
+    let response = client
+      .get(format!("http://{}{}&include_total=true", addr, next_url))
+      .header("Authorization", format!("Bearer {}", token))
+      .send()
+      .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let users_page: serde_json::Value = response.json().await?;
+    assert_eq!(users_page["total_count"], 11);
+    assert_eq!(users_page["users"][0]["user_info"]["username"], "eve");
+
+    let response = client
+      .get(format!(
+        "http://{}/users?limit=10&offset=0&sort=password",
+        addr
+      ))
+      .header("Authorization", format!("Bearer {}", token))
+      .send()
+      .await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
     tx.send(()).unwrap();
     Ok(())
   }
@@ -278,13 +1096,558 @@ mod integration_tests {
       .header("Authorization", format!("Bearer {}", token))
       .send()
       .await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let output: serde_json::Value = response.json().await?;
+    assert_eq!(
+      output["error"],
+      "users:update_info is not allowed: no rule allows this action, cannot update: username, password"
+    );
+
+    let response = client
+      .patch(format!("http://{}/users/{}", addr, 4))
+      .json(&json!({"username": "charlie_updated"}))
+      .header("Authorization", format!("Bearer {}", token))
+      .header("X-Authz-Debug", "1")
+      .send()
+      .await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let output: serde_json::Value = response.json().await?;
+    assert_eq!(output["decision"]["action"], "users:update_info");
+    assert_eq!(output["decision"]["allowed"], false);
+
+    let response = client
+      .get(format!("http://{}/users/{}", addr, 4))
+      .header("Authorization", format!("Bearer {}", token))
+      .send()
+      .await?;
+    let user: serde_json::Value = response.json().await?;
+    assert_eq!(&user["user_info"]["username"], "charlie");
+
+    tx.send(()).unwrap();
+
+    Ok(())
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn grant_role_with_expiry_handler_test() -> Result<()> {
+    let (_tdb, app) = setup_test_app().await?;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let (tx, rx) = oneshot::channel();
+    tokio::spawn(async move {
+      axum::serve(listener, app)
+        .with_graceful_shutdown(async {
+          rx.await.ok();
+        })
+        .await
+        .unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let client = Client::builder().no_proxy().build().unwrap();
+    let token = get_token(&client, &addr.to_string()).await?;
+
+    let response = client
+      .patch(format!("http://{}/users/{}", addr, 5))
+      .json(&json!({"roles": [
+        {"id": 2, "name": "Moderator", "expires_at": "2099-01-01T00:00:00Z"}
+      ]}))
+      .header("Authorization", format!("Bearer {}", token))
+      .send()
+      .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let user: serde_json::Value = response.json().await?;
+    assert_eq!(user["roles"][0]["name"], "Moderator");
+    assert_eq!(user["roles"][0]["expires_at"], "2099-01-01T00:00:00Z");
+
+    tx.send(()).unwrap();
+    Ok(())
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn suspend_and_restore_handler_test() -> Result<()> {
+    let (_tdb, app) = setup_test_app().await?;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let (tx, rx) = oneshot::channel();
+    tokio::spawn(async move {
+      axum::serve(listener, app)
+        .with_graceful_shutdown(async {
+          rx.await.ok();
+        })
+        .await
+        .unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let client = Client::builder().no_proxy().build().unwrap();
+    let token = get_token(&client, &addr.to_string()).await?;
+    let signin = || {
+      client
+        .post(format!("http://{}/auth/signin", addr))
+        .json(&json!({"username": "alice", "password": "123456"}))
+        .send()
+    };
+    let response = signin().await?;
+    let alice_token = response.json::<serde_json::Value>().await?["token"]
+      .as_str()
+      .unwrap()
+      .to_string();
+    let get_alice = || {
+      client
+        .get(format!("http://{}/users/2", addr))
+        .header("Authorization", format!("Bearer {}", alice_token))
+        .send()
+    };
+
+    let response = client
+      .post(format!("http://{}/users/2/suspend", addr))
+      .header("Authorization", format!("Bearer {}", token))
+      .send()
+      .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let user: serde_json::Value = response.json().await?;
+    assert_eq!(user["user_info"]["status"], "suspended");
+
+    // issued tokens stop working and signing in is refused
+    let response = get_alice().await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(response.text().await?, "account is suspended");
+    assert_eq!(signin().await?.status(), StatusCode::FORBIDDEN);
+
+    let response = client
+      .post(format!("http://{}/users/2/restore", addr))
+      .header("Authorization", format!("Bearer {}", token))
+      .send()
+      .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(get_alice().await?.status(), StatusCode::OK);
+
+    // delete is soft by default, `hard=true` removes the user
+    let response = client
+      .delete(format!("http://{}/users/3", addr))
+      .header("Authorization", format!("Bearer {}", token))
+      .send()
+      .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client
+      .get(format!("http://{}/users/3", addr))
+      .header("Authorization", format!("Bearer {}", token))
+      .send()
+      .await?;
+    let user: serde_json::Value = response.json().await?;
+    assert_eq!(user["user_info"]["status"], "deleted");
+
+    let response = client
+      .delete(format!("http://{}/users/3?hard=true", addr))
+      .header("Authorization", format!("Bearer {}", alice_token))
+      .send()
+      .await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = client
+      .delete(format!("http://{}/users/3?hard=true", addr))
+      .header("Authorization", format!("Bearer {}", token))
+      .send()
+      .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client
+      .get(format!("http://{}/users/3", addr))
+      .header("Authorization", format!("Bearer {}", token))
+      .send()
+      .await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    tx.send(()).unwrap();
+    Ok(())
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn upload_avatar_handler_test() -> Result<()> {
+    use crate::common::LocalStorage;
+    use crate::modules::users::tests::util_tests::png;
+    use std::sync::Arc;
+
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let root = std::env::temp_dir().join(format!("avatars-{}", uuid::Uuid::new_v4()));
+      let state = state.with_storage(Arc::new(LocalStorage::new(&root)));
+      let max_bytes = state.config.avatars.max_bytes;
+      let app = get_router(state).await?;
+
+      let listener = TcpListener::bind("127.0.0.1:0").await?;
+      let addr = listener.local_addr()?;
+      let (tx, rx) = oneshot::channel();
+      tokio::spawn(async move {
+        axum::serve(listener, app)
+          .with_graceful_shutdown(async {
+            rx.await.ok();
+          })
+          .await
+          .unwrap();
+      });
+      tokio::time::sleep(Duration::from_millis(100)).await;
+
+      let client = Client::builder().no_proxy().build().unwrap();
+      let admin_token = get_token(&client, &addr.to_string()).await?;
+      let response = client
+        .post(format!("http://{}/auth/signin", addr))
+        .json(&json!({"username": "alice", "password": "123456"}))
+        .send()
+        .await?;
+      let token = response.json::<serde_json::Value>().await?["token"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+      let boundary = "avatar-boundary";
+      let upload = |token: &str, content_type: &str, bytes: &[u8]| {
+        let mut body = format!(
+          "--{boundary}\r\nContent-Disposition: form-data; name=\"avatar\"; filename=\"a\"\r\nContent-Type: {content_type}\r\n\r\n"
+        )
+        .into_bytes();
+        body.extend_from_slice(bytes);
+        body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+        client
+          .put(format!("http://{}/users/2/avatar", addr))
+          .header("Authorization", format!("Bearer {}", token))
+          .header(
+            "Content-Type",
+            format!("multipart/form-data; boundary={boundary}"),
+          )
+          .body(body)
+          .send()
+      };
+
+      // the declared type is ignored, the content decides
+      let response = upload(&token, "text/plain", &png(300, 300)).await?;
+      assert_eq!(response.status(), StatusCode::OK);
+      let user: serde_json::Value = response.json().await?;
+      assert_eq!(user["profile"]["avatar_url"], "/users/2/avatar");
+
+      let response = upload(&token, "image/png", &png(64, 64)[..40]).await?;
+      assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+      let response = upload(&token, "image/png", b"#!/bin/sh").await?;
+      assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+      let response = upload(&token, "image/png", &vec![0; max_bytes + 1]).await?;
+      assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+      // admins can't change other users' info
+      let response = upload(&admin_token, "image/png", &png(10, 10)).await?;
+      assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+      let response = client
+        .get(format!("http://{}/users/2/avatar", addr))
+        .header("Authorization", format!("Bearer {}", admin_token))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+      assert_eq!(response.headers()["content-type"], "image/png");
+      let avatar = response.bytes().await?;
+      assert_eq!(image::load_from_memory(&avatar)?.width(), 256);
+
+      let response = client
+        .get(format!("http://{}/users/3/avatar", addr))
+        .header("Authorization", format!("Bearer {}", admin_token))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+      tx.send(()).unwrap();
+      std::fs::remove_dir_all(&root)?;
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn delete_last_admin_handler_test() -> Result<()> {
+    let (_tdb, app) = setup_test_app().await?;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let (tx, rx) = oneshot::channel();
+    tokio::spawn(async move {
+      axum::serve(listener, app)
+        .with_graceful_shutdown(async {
+          rx.await.ok();
+        })
+        .await
+        .unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let client = Client::builder().no_proxy().build().unwrap();
+    let token = get_token(&client, &addr.to_string()).await?;
+
+    let response = client
+      .delete(format!("http://{}/users/{}", addr, 1))
+      .header("Authorization", format!("Bearer {}", token))
+      .send()
+      .await?;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let output: serde_json::Value = response.json().await?;
+    assert_eq!(output["error"], "cannot delete the last admin");
+
+    tx.send(()).unwrap();
+    Ok(())
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn etag_handler_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let mut config = state.config.clone();
+      config.concurrency.require_if_match = true;
+      let app = get_router(AppState::new(config, state.pool.clone())).await?;
+
+      let listener = TcpListener::bind("127.0.0.1:0").await?;
+      let addr = listener.local_addr()?;
+      let (tx, rx) = oneshot::channel();
+      tokio::spawn(async move {
+        axum::serve(listener, app)
+          .with_graceful_shutdown(async {
+            rx.await.ok();
+          })
+          .await
+          .unwrap();
+      });
+      tokio::time::sleep(Duration::from_millis(100)).await;
+
+      let client = Client::builder().no_proxy().build().unwrap();
+      let token = get_token(&client, &addr.to_string()).await?;
+      let url = format!("http://{}/users/{}", addr, 3);
+
+      let response = client
+        .get(&url)
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+      let etag = response.headers()["etag"].to_str()?.to_string();
+
+      let response = client
+        .get(&url)
+        .header("Authorization", format!("Bearer {}", token))
+        .header("If-None-Match", format!("W/{}", etag))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+      assert_eq!(response.headers()["etag"], etag.as_str());
+
+      let update = json!({"roles": [{"id": 1, "name": "User"}]});
+      let response = client
+        .patch(&url)
+        .header("Authorization", format!("Bearer {}", token))
+        .json(&update)
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::PRECONDITION_REQUIRED);
+
+      let response = client
+        .patch(&url)
+        .header("Authorization", format!("Bearer {}", token))
+        .header("If-Match", &etag)
+        .json(&update)
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+      let updated = response.headers()["etag"].to_str()?.to_string();
+      assert_ne!(updated, etag);
+
+      // a second writer still holding the first ETag loses
+      let response = client
+        .patch(&url)
+        .header("Authorization", format!("Bearer {}", token))
+        .header("If-Match", &etag)
+        .json(&update)
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+
+      let response = client
+        .get(&url)
+        .header("Authorization", format!("Bearer {}", token))
+        .header("If-None-Match", &etag)
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+
+      let response = client
+        .delete(&url)
+        .header("Authorization", format!("Bearer {}", token))
+        .header("If-Match", format!("W/{}", updated))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+
+      let response = client
+        .delete(&url)
+        .header("Authorization", format!("Bearer {}", token))
+        .header("If-Match", &updated)
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+
+      tx.send(()).unwrap();
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn patch_user_handler_test() -> Result<()> {
+    let (_tdb, app) = setup_test_app().await?;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let (tx, rx) = oneshot::channel();
+    tokio::spawn(async move {
+      axum::serve(listener, app)
+        .with_graceful_shutdown(async {
+          rx.await.ok();
+        })
+        .await
+        .unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let client = Client::builder().no_proxy().build().unwrap();
+    let token = get_token(&client, &addr.to_string()).await?;
+    let response = client
+      .post(format!("http://{}/auth/signin", addr))
+      .json(&json!({"username": "alice", "password": "123456"}))
+      .send()
+      .await?;
+    let alice = response.json::<serde_json::Value>().await?["token"]
+      .as_str()
+      .unwrap()
+      .to_string();
+    let patch = |token: &str, user_id: i32, content_type: &str, body: serde_json::Value| {
+      client
+        .patch(format!("http://{}/users/{}", addr, user_id))
+        .header("Authorization", format!("Bearer {}", token))
+        .header("Content-Type", content_type)
+        .body(body.to_string())
+        .send()
+    };
+
+    let response = patch(&alice, 2, "text/plain", json!({})).await?;
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    let body = json!({"email": "alice@example.com"});
+    let response = patch(&alice, 2, "application/merge-patch+json", body).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let user: serde_json::Value = response.json().await?;
+    assert_eq!(user["user_info"]["email"], "alice@example.com");
+
+    // null unsets, where the old options could only mean "leave alone"
+    let body = json!({"email": null});
+    let response = patch(&alice, 2, "application/merge-patch+json", body).await?;
     assert_eq!(response.status(), StatusCode::OK);
+    let user: serde_json::Value = response.json().await?;
+    assert!(user["user_info"].get("email").is_none());
 
-    let updated_user: serde_json::Value = response.json().await?;
-    assert_eq!(&updated_user["user_info"]["username"], "charlie");
+    let body = json!({"email": "alice@example.com", "roles": [{"id": 3, "name": "Admin"}]});
+    let response = patch(&alice, 2, "application/merge-patch+json", body).await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let output: serde_json::Value = response.json().await?;
+    assert_eq!(
+      output["error"],
+      "users:update_roles is not allowed: no rule allows this action, cannot update: roles"
+    );
+
+    let body = json!([
+      {"op": "test", "path": "/roles", "value": []},
+      {"op": "add", "path": "/roles/-", "value": {"id": 2, "name": "Moderator"}}
+    ]);
+    let response = patch(&token, 2, "application/json-patch+json", body.clone()).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let user: serde_json::Value = response.json().await?;
+    assert_eq!(user["roles"][0]["name"], "Moderator");
+    let response = patch(&token, 2, "application/json-patch+json", body).await?;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let body = json!([{"op": "replace", "path": "/nickname", "value": "al"}]);
+    let response = patch(&token, 2, "application/json-patch+json", body).await?;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
 
     tx.send(()).unwrap();
+    Ok(())
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn get_user_by_username_handler_test() -> Result<()> {
+    let (_tdb, app) = setup_test_app().await?;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let (tx, rx) = oneshot::channel();
+    tokio::spawn(async move {
+      axum::serve(listener, app)
+        .with_graceful_shutdown(async {
+          rx.await.ok();
+        })
+        .await
+        .unwrap();
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let client = Client::builder().no_proxy().build().unwrap();
+    let token = get_token(&client, &addr.to_string()).await?;
+    let response = client
+      .post(format!("http://{}/auth/signin", addr))
+      .json(&json!({"username": "bob", "password": "123456"}))
+      .send()
+      .await?;
+    let bob = response.json::<serde_json::Value>().await?["token"]
+      .as_str()
+      .unwrap()
+      .to_string();
+
+    let response = client
+      .patch(format!("http://{}/users/{}", addr, 3))
+      .header("Authorization", format!("Bearer {}", bob))
+      .json(&json!({"username": "robert"}))
+      .send()
+      .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = client
+      .get(format!("http://{}/users/by-username/{}", addr, "Bob"))
+      .header("Authorization", format!("Bearer {}", token))
+      .send()
+      .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-location"], "/users/3");
+    let user: serde_json::Value = response.json().await?;
+    assert_eq!(user["user_info"]["username"], "robert");
+
+    let response = client
+      .get(format!("http://{}/users/{}/username-history", addr, 3))
+      .header("Authorization", format!("Bearer {}", token))
+      .send()
+      .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let history: serde_json::Value = response.json().await?;
+    assert_eq!(history[0]["old_username"], "bob");
+    assert_eq!(history[0]["new_username"], "robert");
 
+    let response = client
+      .get(format!("http://{}/users/by-username/{}", addr, "nobody"))
+      .header("Authorization", format!("Bearer {}", token))
+      .send()
+      .await?;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    tx.send(()).unwrap();
     Ok(())
   }
 }
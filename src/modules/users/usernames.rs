@@ -0,0 +1,165 @@
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{Postgres, Transaction};
+use tracing::info;
+
+use super::{User, UserInfo, UsernameChange};
+use crate::{AppError, AppState};
+
+/// Unique constraint on the normalized username.
+const USERNAME_CONSTRAINT: &str = "users_username_normalized_unique";
+
+impl AppState {
+  /// Refuse a username that is reserved, taken regardless of case, or held by another user's
+  /// rename history. `user_id` is the user taking the name, it may take back its own old names.
+  pub(crate) async fn ensure_username_available(
+    &self,
+    transaction: &mut Transaction<'_, Postgres>,
+    username: &str,
+    user_id: Option<i32>,
+  ) -> Result<(), AppError> {
+    if self.config.usernames.is_reserved(username) {
+      return Err(AppError::ValidationError(format!(
+        "username {} is reserved",
+        username
+      )));
+    }
+    let (taken, held): (bool, bool) = sqlx::query_as(
+      r#"
+      SELECT
+        EXISTS (
+          SELECT 1 FROM users
+          WHERE username_normalized = lower($1) AND id IS DISTINCT FROM $2
+        ),
+        EXISTS (
+          SELECT 1 FROM username_history
+          WHERE lower(old_username) = lower($1) AND user_id IS DISTINCT FROM $2
+        )
+      "#,
+    )
+    .bind(username)
+    .bind(user_id)
+    .fetch_one(&mut **transaction)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    if taken {
+      return Err(AppError::UserExisted(format!(
+        "User: {} already exists",
+        username
+      )));
+    }
+    if held {
+      return Err(AppError::Conflict(format!(
+        "username {} belonged to another account",
+        username
+      )));
+    }
+    Ok(())
+  }
+
+  /// Record the rename of `user` to `username`, the caller updates the users row in the same
+  /// transaction. Renames are at least `usernames.change_cooldown` apart.
+  pub(crate) async fn rename_user(
+    &self,
+    transaction: &mut Transaction<'_, Postgres>,
+    user: &UserInfo,
+    username: &str,
+    actor_id: i32,
+  ) -> Result<(), AppError> {
+    // serialize renames of one user, the cooldown check would race otherwise
+    sqlx::query("SELECT 1 FROM users WHERE id = $1 FOR UPDATE")
+      .bind(user.id)
+      .execute(&mut **transaction)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    let last_change: Option<DateTime<Utc>> =
+      sqlx::query_scalar("SELECT MAX(changed_at) FROM username_history WHERE user_id = $1")
+        .bind(user.id)
+        .fetch_one(&mut **transaction)
+        .await
+        .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    let cooldown = Duration::seconds(self.config.usernames.change_cooldown as i64);
+    if let Some(last_change) = last_change
+      && last_change + cooldown > Utc::now()
+    {
+      return Err(AppError::Conflict(format!(
+        "username can be changed again after {}",
+        (last_change + cooldown).to_rfc3339()
+      )));
+    }
+    self
+      .ensure_username_available(transaction, username, Some(user.id))
+      .await?;
+
+    sqlx::query(
+      r#"
+      INSERT INTO username_history (user_id, old_username, new_username, changed_by, changed_at)
+      VALUES ($1, $2, $3, $4, $5)
+      "#,
+    )
+    .bind(user.id)
+    .bind(&user.username)
+    .bind(username)
+    .bind(actor_id)
+    .bind(Utc::now())
+    .execute(&mut **transaction)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    info!(user_id = user.id, from = %user.username, to = %username, "user renamed");
+    Ok(())
+  }
+
+  /// Renames of a user, newest first.
+  pub async fn get_username_history(&self, user_id: i32) -> Result<Vec<UsernameChange>, AppError> {
+    sqlx::query_as(
+      r#"
+      SELECT id, old_username, new_username, changed_by, changed_at
+      FROM username_history
+      WHERE user_id = $1
+      ORDER BY changed_at DESC, id DESC
+      "#,
+    )
+    .bind(user_id)
+    .fetch_all(&self.pool)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))
+  }
+
+  /// Find a user by its current username or, failing that, by a name it was renamed from.
+  pub async fn find_user_by_username(&self, username: &str) -> Result<User, AppError> {
+    let user_id: Option<i32> = sqlx::query_scalar(
+      r#"
+      SELECT id FROM (
+        SELECT id, 0 AS rank, NULL::TIMESTAMPTZ AS changed_at
+        FROM users
+        WHERE username_normalized = lower($1)
+        UNION ALL
+        SELECT user_id, 1, changed_at
+        FROM username_history
+        WHERE lower(old_username) = lower($1)
+      ) candidates
+      ORDER BY rank, changed_at DESC
+      LIMIT 1
+      "#,
+    )
+    .bind(username)
+    .fetch_optional(&self.pool)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    let user_id =
+      user_id.ok_or_else(|| AppError::NotFound(format!("User: {} not found", username)))?;
+    self.get_user_by_id(user_id).await
+  }
+}
+
+/// Report a lost race on the username unique constraint as the name being taken.
+pub(crate) fn username_error(err: sqlx::Error, username: &str) -> AppError {
+  let is_taken = err
+    .as_database_error()
+    .and_then(|err| err.constraint())
+    .is_some_and(|constraint| constraint == USERNAME_CONSTRAINT);
+  if is_taken {
+    AppError::UserExisted(format!("User: {} already exists", username))
+  } else {
+    AppError::DatabaseError(err.to_string())
+  }
+}
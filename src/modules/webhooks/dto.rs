@@ -0,0 +1,85 @@
+use super::{DeliveryStatus, Webhook, WebhookDelivery};
+use crate::modules::outbox::DomainEvent;
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+/// webhook create input dto
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct CreateWebhook {
+  #[validate(url, length(max = 2048), custom(function = "validate_scheme"))]
+  pub url: String,
+  #[validate(length(min = 1), custom(function = "validate_event_types"))]
+  pub event_types: Vec<String>,
+  /// HMAC key of the signatures, generated when absent
+  #[validate(length(min = 16, max = 128))]
+  pub secret: Option<String>,
+}
+
+/// webhook update input dto, absent fields are left unchanged
+#[derive(Debug, Default, Deserialize, Serialize, Validate)]
+pub struct UpdateWebhook {
+  #[validate(url, length(max = 2048), custom(function = "validate_scheme"))]
+  pub url: Option<String>,
+  #[validate(length(min = 1), custom(function = "validate_event_types"))]
+  pub event_types: Option<Vec<String>>,
+  pub active: Option<bool>,
+}
+
+/// created webhook output dto, the secret is only returned once
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WebhookCreated {
+  pub webhook: Webhook,
+  pub secret: String,
+}
+
+/// query parameters of `GET /webhooks/{id}/deliveries`, newest first
+#[derive(Debug, Deserialize, Serialize, Validate)]
+pub struct DeliveriesParams {
+  pub status: Option<DeliveryStatus>,
+  /// the previous page's `next_before`
+  pub before: Option<i64>,
+  #[serde(default = "default_limit")]
+  #[validate(range(min = 1, max = 100))]
+  pub limit: i64,
+}
+
+impl Default for DeliveriesParams {
+  fn default() -> Self {
+    Self {
+      status: None,
+      before: None,
+      limit: default_limit(),
+    }
+  }
+}
+
+/// a page of deliveries output dto
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DeliveriesPage {
+  pub deliveries: Vec<WebhookDelivery>,
+  /// pass as `before` for the next page, absent on the last one
+  pub next_before: Option<i64>,
+}
+
+fn default_limit() -> i64 {
+  20
+}
+
+fn validate_scheme(url: &str) -> Result<(), ValidationError> {
+  if url.starts_with("https://") || url.starts_with("http://") {
+    Ok(())
+  } else {
+    Err(ValidationError::new("url must be http or https"))
+  }
+}
+
+fn validate_event_types(event_types: &[String]) -> Result<(), ValidationError> {
+  if event_types
+    .iter()
+    .all(|event_type| DomainEvent::TYPES.contains(&event_type.as_str()))
+  {
+    Ok(())
+  } else {
+    Err(ValidationError::new("unknown event type"))
+  }
+}
@@ -0,0 +1,62 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::FromRow;
+
+/// webhooks table
+#[derive(Clone, Debug, Deserialize, FromRow, Serialize)]
+pub struct Webhook {
+  pub id: i32,
+  pub organization_id: i32,
+  pub url: String,
+  /// the `DomainEvent` types delivered to the endpoint
+  pub event_types: Vec<String>,
+  /// HMAC key of the signatures, only returned when the webhook is created
+  #[serde(skip_serializing, default)]
+  pub secret: String,
+  pub active: bool,
+  pub created_by: Option<i32>,
+  pub created_at: DateTime<Utc>,
+  pub updated_at: DateTime<Utc>,
+}
+
+/// webhook_deliveries table
+#[derive(Clone, Debug, Deserialize, FromRow, Serialize)]
+pub struct WebhookDelivery {
+  pub id: i64,
+  pub webhook_id: i32,
+  /// id of the outbox event, the same for every webhook it's delivered to
+  pub event_id: i64,
+  pub event_type: String,
+  /// the request body sent
+  pub payload: Value,
+  pub status: String,
+  pub attempts: i32,
+  pub next_attempt_at: DateTime<Utc>,
+  /// HTTP status of the last attempt, absent when the endpoint couldn't be reached
+  pub response_status: Option<i32>,
+  pub last_error: Option<String>,
+  pub delivered_at: Option<DateTime<Utc>>,
+  pub created_at: DateTime<Utc>,
+}
+
+/// delivery states
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+  /// waiting for its first attempt or a retry
+  Pending,
+  Succeeded,
+  /// gave up after `webhooks.max_attempts`
+  Failed,
+}
+
+impl AsRef<str> for DeliveryStatus {
+  fn as_ref(&self) -> &str {
+    match *self {
+      DeliveryStatus::Pending => "pending",
+      DeliveryStatus::Succeeded => "succeeded",
+      DeliveryStatus::Failed => "failed",
+    }
+  }
+}
@@ -0,0 +1,105 @@
+use super::{CreateWebhook, DeliveriesParams, UpdateWebhook};
+use crate::modules::authz::{Action, Resource};
+use crate::modules::users::User;
+use crate::{AppError, AppState};
+use axum::{
+  Extension, Json,
+  extract::{Path, Query, State},
+  http::StatusCode,
+  response::IntoResponse,
+};
+use tracing::info;
+use validator::Validate;
+
+pub async fn get_webhooks_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+  info!("Webhooks Handler::get webhooks");
+  state.authorize(&claims, Action::ManageWebhooks, &Resource::webhooks())?;
+  let webhooks = state.get_webhooks().await?;
+  Ok((StatusCode::OK, Json(webhooks)))
+}
+
+pub async fn get_webhook_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Path(webhook_id): Path<i32>,
+) -> Result<impl IntoResponse, AppError> {
+  info!("Webhooks Handler::get webhook: {:?}", webhook_id);
+  state.authorize(&claims, Action::ManageWebhooks, &Resource::webhooks())?;
+  let webhook = state.get_webhook(webhook_id).await?;
+  Ok((StatusCode::OK, Json(webhook)))
+}
+
+pub async fn create_webhook_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Json(input): Json<CreateWebhook>,
+) -> Result<impl IntoResponse, AppError> {
+  input.validate()?;
+  info!(
+    "Webhooks Handler::create webhook: url: {:?}, event_types: {:?}",
+    input.url, input.event_types
+  );
+  state.authorize(&claims, Action::ManageWebhooks, &Resource::webhooks())?;
+  let created = state.create_webhook(input, claims.user_info.id).await?;
+  Ok((StatusCode::CREATED, Json(created)))
+}
+
+pub async fn update_webhook_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Path(webhook_id): Path<i32>,
+  Json(input): Json<UpdateWebhook>,
+) -> Result<impl IntoResponse, AppError> {
+  input.validate()?;
+  info!(
+    "Webhooks Handler::update webhook: {:?}, input: {:?}",
+    webhook_id, input
+  );
+  state.authorize(&claims, Action::ManageWebhooks, &Resource::webhooks())?;
+  let webhook = state.update_webhook(webhook_id, input).await?;
+  Ok((StatusCode::OK, Json(webhook)))
+}
+
+pub async fn delete_webhook_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Path(webhook_id): Path<i32>,
+) -> Result<impl IntoResponse, AppError> {
+  info!("Webhooks Handler::delete webhook: {:?}", webhook_id);
+  state.authorize(&claims, Action::ManageWebhooks, &Resource::webhooks())?;
+  state.delete_webhook(webhook_id).await?;
+  Ok(StatusCode::OK)
+}
+
+pub async fn get_webhook_deliveries_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Path(webhook_id): Path<i32>,
+  Query(params): Query<DeliveriesParams>,
+) -> Result<impl IntoResponse, AppError> {
+  params.validate()?;
+  info!(
+    "Webhooks Handler::get deliveries: {:?}, params: {:?}",
+    webhook_id, params
+  );
+  state.authorize(&claims, Action::ManageWebhooks, &Resource::webhooks())?;
+  let page = state.get_webhook_deliveries(webhook_id, &params).await?;
+  Ok((StatusCode::OK, Json(page)))
+}
+
+pub async fn redeliver_webhook_handler(
+  Extension(claims): Extension<User>,
+  State(state): State<AppState>,
+  Path((webhook_id, delivery_id)): Path<(i32, i64)>,
+) -> Result<impl IntoResponse, AppError> {
+  info!(
+    "Webhooks Handler::redeliver: {:?} of {:?}",
+    delivery_id, webhook_id
+  );
+  state.authorize(&claims, Action::ManageWebhooks, &Resource::webhooks())?;
+  let delivery = state.redeliver_webhook(webhook_id, delivery_id).await?;
+  Ok((StatusCode::OK, Json(delivery)))
+}
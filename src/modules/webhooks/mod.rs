@@ -0,0 +1,42 @@
+pub mod dto;
+pub mod entity;
+pub mod handlers;
+pub mod services;
+pub mod signature;
+pub mod sink;
+pub mod target;
+pub mod tests;
+
+pub use dto::{CreateWebhook, DeliveriesPage, DeliveriesParams, UpdateWebhook, WebhookCreated};
+pub use entity::{DeliveryStatus, Webhook, WebhookDelivery};
+pub use handlers::{
+  create_webhook_handler, delete_webhook_handler, get_webhook_deliveries_handler,
+  get_webhook_handler, get_webhooks_handler, redeliver_webhook_handler, update_webhook_handler,
+};
+pub use signature::{
+  WEBHOOK_DELIVERY_HEADER, WEBHOOK_EVENT_HEADER, WEBHOOK_SIGNATURE_HEADER,
+  WEBHOOK_TIMESTAMP_HEADER, sign_webhook, verify_webhook,
+};
+pub use sink::WebhookSink;
+pub use target::{check_webhook_target, is_public_address};
+
+use crate::AppState;
+use axum::Router;
+use axum::routing::{get, post};
+
+pub fn webhooks_router(state: AppState) -> Router {
+  Router::new()
+    .route("/", get(get_webhooks_handler).post(create_webhook_handler))
+    .route(
+      "/{id}",
+      get(get_webhook_handler)
+        .patch(update_webhook_handler)
+        .delete(delete_webhook_handler),
+    )
+    .route("/{id}/deliveries", get(get_webhook_deliveries_handler))
+    .route(
+      "/{id}/deliveries/{delivery_id}/redeliver",
+      post(redeliver_webhook_handler),
+    )
+    .with_state(state)
+}
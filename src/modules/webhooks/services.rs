@@ -0,0 +1,362 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use futures_util::future::join_all;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Client, redirect};
+use sqlx::{FromRow, Postgres, QueryBuilder};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use super::target::PublicResolver;
+use super::{
+  CreateWebhook, DeliveriesPage, DeliveriesParams, DeliveryStatus, UpdateWebhook,
+  WEBHOOK_DELIVERY_HEADER, WEBHOOK_EVENT_HEADER, WEBHOOK_SIGNATURE_HEADER,
+  WEBHOOK_TIMESTAMP_HEADER, Webhook, WebhookCreated, WebhookDelivery, check_webhook_target,
+  sign_webhook,
+};
+use crate::common::{current_tenant, retry_delay};
+use crate::modules::outbox::RelayReport;
+use crate::{AppError, AppState};
+
+/// A delivery claimed by the dispatcher, with where and how to send it.
+#[derive(FromRow)]
+struct ClaimedDelivery {
+  #[sqlx(flatten)]
+  delivery: WebhookDelivery,
+  url: String,
+  secret: String,
+}
+
+impl AppState {
+  pub async fn create_webhook(
+    &self,
+    input: CreateWebhook,
+    created_by: i32,
+  ) -> Result<WebhookCreated, AppError> {
+    check_webhook_target(&input.url, &self.config.webhooks.allowed_hosts).await?;
+    let secret = input.secret.unwrap_or_else(|| {
+      format!(
+        "whsec_{}{}",
+        Uuid::new_v4().simple(),
+        Uuid::new_v4().simple()
+      )
+    });
+    let webhook: Webhook = sqlx::query_as(
+      r#"
+      INSERT INTO webhooks (organization_id, url, event_types, secret, created_by)
+      VALUES ($1, $2, $3, $4, $5)
+      RETURNING *
+      "#,
+    )
+    .bind(current_tenant()?)
+    .bind(&input.url)
+    .bind(&input.event_types)
+    .bind(&secret)
+    .bind(created_by)
+    .fetch_one(&self.pool)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    info!(
+      webhook_id = webhook.id,
+      url = webhook.url,
+      "webhook created"
+    );
+    Ok(WebhookCreated { webhook, secret })
+  }
+
+  pub async fn get_webhooks(&self) -> Result<Vec<Webhook>, AppError> {
+    sqlx::query_as("SELECT * FROM webhooks WHERE organization_id = $1 ORDER BY id")
+      .bind(current_tenant()?)
+      .fetch_all(&self.pool)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))
+  }
+
+  pub async fn get_webhook(&self, webhook_id: i32) -> Result<Webhook, AppError> {
+    sqlx::query_as("SELECT * FROM webhooks WHERE id = $1 AND organization_id = $2")
+      .bind(webhook_id)
+      .bind(current_tenant()?)
+      .fetch_optional(&self.pool)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?
+      .ok_or(AppError::NotFound(format!(
+        "Webhook: {} not found",
+        webhook_id
+      )))
+  }
+
+  pub async fn update_webhook(
+    &self,
+    webhook_id: i32,
+    input: UpdateWebhook,
+  ) -> Result<Webhook, AppError> {
+    let current = self.get_webhook(webhook_id).await?;
+    if let Some(url) = &input.url {
+      check_webhook_target(url, &self.config.webhooks.allowed_hosts).await?;
+    }
+    sqlx::query_as(
+      r#"
+      UPDATE webhooks
+      SET url = $1, event_types = $2, active = $3, updated_at = $4
+      WHERE id = $5
+      RETURNING *
+      "#,
+    )
+    .bind(input.url.unwrap_or(current.url))
+    .bind(input.event_types.unwrap_or(current.event_types))
+    .bind(input.active.unwrap_or(current.active))
+    .bind(Utc::now())
+    .bind(webhook_id)
+    .fetch_one(&self.pool)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))
+  }
+
+  /// Remove the webhook along with its deliveries.
+  pub async fn delete_webhook(&self, webhook_id: i32) -> Result<(), AppError> {
+    let result = sqlx::query("DELETE FROM webhooks WHERE id = $1 AND organization_id = $2")
+      .bind(webhook_id)
+      .bind(current_tenant()?)
+      .execute(&self.pool)
+      .await
+      .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    if result.rows_affected() == 0 {
+      return Err(AppError::NotFound(format!(
+        "Webhook: {} not found",
+        webhook_id
+      )));
+    }
+    Ok(())
+  }
+
+  pub async fn get_webhook_deliveries(
+    &self,
+    webhook_id: i32,
+    params: &DeliveriesParams,
+  ) -> Result<DeliveriesPage, AppError> {
+    self.get_webhook(webhook_id).await?;
+    let mut query =
+      QueryBuilder::<Postgres>::new("SELECT * FROM webhook_deliveries WHERE webhook_id = ");
+    query.push_bind(webhook_id);
+    if let Some(status) = params.status {
+      query
+        .push(" AND status = ")
+        .push_bind(status.as_ref().to_string());
+    }
+    if let Some(before) = params.before {
+      query.push(" AND id < ").push_bind(before);
+    }
+    // one extra row tells whether there is a next page
+    query
+      .push(" ORDER BY id DESC LIMIT ")
+      .push_bind(params.limit + 1);
+
+    let mut deliveries: Vec<WebhookDelivery> =
+      query
+        .build_query_as()
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+    let next_before = if deliveries.len() as i64 > params.limit {
+      deliveries.truncate(params.limit as usize);
+      deliveries.last().map(|delivery| delivery.id)
+    } else {
+      None
+    };
+    Ok(DeliveriesPage {
+      deliveries,
+      next_before,
+    })
+  }
+
+  /// Send a delivery again right away, whatever its state, with a fresh set of retries.
+  pub async fn redeliver_webhook(
+    &self,
+    webhook_id: i32,
+    delivery_id: i64,
+  ) -> Result<WebhookDelivery, AppError> {
+    let webhook = self.get_webhook(webhook_id).await?;
+    let delivery: WebhookDelivery = sqlx::query_as(
+      r#"
+      UPDATE webhook_deliveries
+      SET status = 'pending', attempts = 0, next_attempt_at = NOW()
+      WHERE id = $1 AND webhook_id = $2
+      RETURNING *
+      "#,
+    )
+    .bind(delivery_id)
+    .bind(webhook_id)
+    .fetch_optional(&self.pool)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?
+    .ok_or(AppError::NotFound(format!(
+      "Delivery: {} not found",
+      delivery_id
+    )))?;
+    let claimed = ClaimedDelivery {
+      delivery,
+      url: webhook.url,
+      secret: webhook.secret,
+    };
+    self
+      .attempt_delivery(&self.webhook_client()?, claimed)
+      .await
+  }
+
+  /// Send the due deliveries of active webhooks, of every organization, concurrently.
+  /// Each is leased to this dispatcher for twice `webhooks.timeout` so others skip it.
+  pub async fn dispatch_webhooks(&self) -> Result<RelayReport, AppError> {
+    let config = &self.config.webhooks;
+    let claimed: Vec<ClaimedDelivery> = sqlx::query_as(
+      r#"
+      UPDATE webhook_deliveries d
+      SET next_attempt_at = NOW() + make_interval(secs => $2)
+      FROM webhooks w
+      WHERE w.id = d.webhook_id AND d.id IN (
+        SELECT due.id
+        FROM webhook_deliveries due
+        JOIN webhooks hook ON hook.id = due.webhook_id
+        WHERE due.status = 'pending' AND due.next_attempt_at <= NOW() AND hook.active
+        ORDER BY due.next_attempt_at
+        LIMIT $1
+        FOR UPDATE OF due SKIP LOCKED
+      )
+      RETURNING d.*, w.url, w.secret
+      "#,
+    )
+    .bind(config.batch_size)
+    .bind((config.timeout * 2) as f64)
+    .fetch_all(&self.pool)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))?;
+
+    let client = self.webhook_client()?;
+    let results = join_all(
+      claimed
+        .into_iter()
+        .map(|claimed| self.attempt_delivery(&client, claimed)),
+    )
+    .await;
+    let mut report = RelayReport::default();
+    for result in results {
+      if result?.status == DeliveryStatus::Succeeded.as_ref() {
+        report.delivered += 1;
+      } else {
+        report.failed += 1;
+      }
+    }
+    Ok(report)
+  }
+
+  /// Deliveries don't follow redirects and only connect to public addresses, but of the
+  /// `webhooks.allowed_hosts`.
+  fn webhook_client(&self) -> Result<Client, AppError> {
+    let allowed_hosts = Arc::new(self.config.webhooks.allowed_hosts.clone());
+    Client::builder()
+      .timeout(Duration::from_secs(self.config.webhooks.timeout))
+      .redirect(redirect::Policy::none())
+      .dns_resolver(Arc::new(PublicResolver { allowed_hosts }))
+      .build()
+      .map_err(|_| AppError::InternalServerError)
+  }
+
+  /// POST the signed payload, then record the outcome: succeeded on a 2xx, otherwise
+  /// retried with backoff until `webhooks.max_attempts` and failed after that.
+  async fn attempt_delivery(
+    &self,
+    client: &Client,
+    claimed: ClaimedDelivery,
+  ) -> Result<WebhookDelivery, AppError> {
+    let ClaimedDelivery {
+      delivery,
+      url,
+      secret,
+    } = claimed;
+    let body = serde_json::to_vec(&delivery.payload).map_err(|_| AppError::InternalServerError)?;
+    let timestamp = Utc::now().timestamp();
+    // the client's resolver doesn't see hosts given as addresses
+    let target = check_webhook_target(&url, &self.config.webhooks.allowed_hosts).await;
+    let result = match target {
+      Ok(()) => client
+        .post(&url)
+        .header(CONTENT_TYPE, "application/json")
+        .header(WEBHOOK_TIMESTAMP_HEADER, timestamp)
+        .header(
+          WEBHOOK_SIGNATURE_HEADER,
+          sign_webhook(&secret, timestamp, &body),
+        )
+        .header(WEBHOOK_EVENT_HEADER, &delivery.event_type)
+        .header(WEBHOOK_DELIVERY_HEADER, delivery.id)
+        .body(body)
+        .send()
+        .await
+        .map_err(|err| err.to_string()),
+      Err(err) => Err(err.to_string()),
+    };
+    let (response_status, error) = match result {
+      Ok(response) if response.status().is_success() => (Some(response.status().as_u16()), None),
+      Ok(response) => (
+        Some(response.status().as_u16()),
+        Some(format!("HTTP {}", response.status())),
+      ),
+      Err(err) => (None, Some(err)),
+    };
+
+    let attempts = delivery.attempts + 1;
+    let config = &self.config.webhooks;
+    let status = match error {
+      None => DeliveryStatus::Succeeded,
+      Some(_) if attempts >= config.max_attempts => DeliveryStatus::Failed,
+      Some(_) => DeliveryStatus::Pending,
+    };
+    let delay = retry_delay(config.retry_base, config.retry_max, attempts);
+    if let Some(error) = &error {
+      warn!(
+        delivery_id = delivery.id,
+        url, attempts, error, "webhook delivery failed"
+      );
+    }
+    sqlx::query_as(
+      r#"
+      UPDATE webhook_deliveries
+      SET status = $1, attempts = $2, response_status = $3, last_error = $4,
+          next_attempt_at = NOW() + make_interval(secs => $5),
+          delivered_at = CASE WHEN $1 = 'succeeded' THEN NOW() ELSE delivered_at END
+      WHERE id = $6
+      RETURNING *
+      "#,
+    )
+    .bind(status.as_ref())
+    .bind(attempts)
+    .bind(response_status.map(i32::from))
+    .bind(error)
+    .bind(delay as f64)
+    .bind(delivery.id)
+    .fetch_one(&self.pool)
+    .await
+    .map_err(|err| AppError::DatabaseError(err.to_string()))
+  }
+
+  /// Periodically run `dispatch_webhooks`, meant to be spawned at startup.
+  pub async fn run_webhook_dispatcher(self) {
+    let mut interval =
+      tokio::time::interval(Duration::from_secs(self.config.webhooks.poll_interval));
+    loop {
+      interval.tick().await;
+      match self.dispatch_webhooks().await {
+        Ok(RelayReport {
+          delivered: 0,
+          failed: 0,
+        }) => (),
+        Ok(report) => info!(
+          delivered = report.delivered,
+          failed = report.failed,
+          "dispatched webhooks"
+        ),
+        Err(e) => warn!(error = ?e, "dispatch webhooks failed"),
+      }
+    }
+  }
+}
@@ -0,0 +1,44 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Unix time, in seconds, the delivery was signed at.
+pub const WEBHOOK_TIMESTAMP_HEADER: &str = "x-webhook-timestamp";
+/// `sha256=` and the hex HMAC-SHA256 of `{timestamp}.{body}` keyed with the webhook's secret.
+pub const WEBHOOK_SIGNATURE_HEADER: &str = "x-webhook-signature";
+/// `event_type` of the delivered event.
+pub const WEBHOOK_EVENT_HEADER: &str = "x-webhook-event";
+/// id of the delivery, the same across retries.
+pub const WEBHOOK_DELIVERY_HEADER: &str = "x-webhook-delivery";
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn mac(secret: &str, timestamp: i64, body: &[u8]) -> HmacSha256 {
+  // HMAC takes keys of any length
+  let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("hmac key");
+  mac.update(timestamp.to_string().as_bytes());
+  mac.update(b".");
+  mac.update(body);
+  mac
+}
+
+/// The `X-Webhook-Signature` of `body` sent at `timestamp`.
+pub fn sign_webhook(secret: &str, timestamp: i64, body: &[u8]) -> String {
+  format!(
+    "sha256={}",
+    hex::encode(mac(secret, timestamp, body).finalize().into_bytes())
+  )
+}
+
+/// Check a signature the way a receiver should, in constant time.
+/// Receivers should also reject timestamps too far from their clock, against replays.
+pub fn verify_webhook(secret: &str, timestamp: i64, body: &[u8], signature: &str) -> bool {
+  let Some(signature) = signature
+    .strip_prefix("sha256=")
+    .and_then(|hex_signature| hex::decode(hex_signature).ok())
+  else {
+    return false;
+  };
+  mac(secret, timestamp, body)
+    .verify_slice(&signature)
+    .is_ok()
+}
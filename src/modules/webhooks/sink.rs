@@ -0,0 +1,41 @@
+use sqlx::PgPool;
+
+use crate::modules::outbox::{EventSink, OutboxEvent, SinkFuture};
+
+/// Queues a delivery of each event to every active webhook of its organization subscribed
+/// to its type, for the dispatcher to send. Endpoints are retried on their own,
+/// a failing one doesn't hold back the outbox.
+#[derive(Debug)]
+pub struct WebhookSink {
+  pool: PgPool,
+}
+
+impl WebhookSink {
+  pub fn new(pool: PgPool) -> Self {
+    Self { pool }
+  }
+}
+
+impl EventSink for WebhookSink {
+  fn deliver<'a>(&'a self, event: &'a OutboxEvent) -> SinkFuture<'a> {
+    Box::pin(async move {
+      sqlx::query(
+        r#"
+        INSERT INTO webhook_deliveries (webhook_id, event_id, event_type, payload)
+        SELECT id, $1, $2, $3
+        FROM webhooks
+        WHERE organization_id = $4 AND active AND $2 = ANY(event_types)
+        ON CONFLICT (webhook_id, event_id) DO NOTHING
+        "#,
+      )
+      .bind(event.id)
+      .bind(&event.event_type)
+      .bind(event.envelope())
+      .bind(event.organization_id)
+      .execute(&self.pool)
+      .await
+      .map_err(|err| err.to_string())?;
+      Ok(())
+    })
+  }
+}
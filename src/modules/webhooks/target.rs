@@ -0,0 +1,111 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+
+use reqwest::Url;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+
+use crate::AppError;
+
+/// Whether `address` is reachable from the public internet. Loopback, private, link-local,
+/// shared, documentation, multicast and unspecified addresses are not, nor are IPv6 addresses
+/// embedding an IPv4 address that isn't.
+pub fn is_public_address(address: IpAddr) -> bool {
+  match address {
+    IpAddr::V4(v4) => {
+      let [a, b, ..] = v4.octets();
+      !(v4.is_loopback()
+        || v4.is_private()
+        || v4.is_link_local()
+        || v4.is_unspecified()
+        || v4.is_broadcast()
+        || v4.is_documentation()
+        || v4.is_multicast()
+        // 100.64.0.0/10, shared address space of carrier-grade NAT
+        || (a == 100 && (64..128).contains(&b))
+        // 0.0.0.0/8, "this network"
+        || a == 0)
+    }
+    IpAddr::V6(v6) => match embedded_ipv4(v6) {
+      Some(v4) => is_public_address(IpAddr::V4(v4)),
+      None => {
+        let [a, b, ..] = v6.segments();
+        !(v6.is_loopback()
+          || v6.is_unspecified()
+          || v6.is_multicast()
+          || v6.is_unique_local()
+          || v6.is_unicast_link_local()
+          // 2001:db8::/32, documentation
+          || (a == 0x2001 && b == 0x0db8))
+      }
+    },
+  }
+}
+
+/// The IPv4 address `v6` stands for: IPv4-mapped `::ffff:a.b.c.d`, IPv4-compatible
+/// `::a.b.c.d` (`::` and `::1` included) and NAT64 `64:ff9b::a.b.c.d` ones reach it.
+fn embedded_ipv4(v6: Ipv6Addr) -> Option<Ipv4Addr> {
+  match v6.segments() {
+    [0x64, 0xff9b, 0, 0, 0, 0, high, low] => {
+      Some(Ipv4Addr::from((u32::from(high) << 16) | u32::from(low)))
+    }
+    _ => v6.to_ipv4(),
+  }
+}
+
+fn is_allowed_host(host: &str, allowed_hosts: &[String]) -> bool {
+  allowed_hosts
+    .iter()
+    .any(|allowed| allowed.eq_ignore_ascii_case(host))
+}
+
+/// Check that `url` may be the target of a webhook: its host is in `webhooks.allowed_hosts`,
+/// or it only resolves to public addresses.
+pub async fn check_webhook_target(url: &str, allowed_hosts: &[String]) -> Result<(), AppError> {
+  let url = Url::parse(url).map_err(|err| AppError::ValidationError(err.to_string()))?;
+  let host = url
+    .host_str()
+    .ok_or(AppError::ValidationError("url has no host".to_string()))?
+    .trim_start_matches('[')
+    .trim_end_matches(']');
+  if is_allowed_host(host, allowed_hosts) {
+    return Ok(());
+  }
+  let addresses: Vec<IpAddr> = match host.parse() {
+    Ok(address) => vec![address],
+    Err(_) => tokio::net::lookup_host((host, 0))
+      .await
+      .map_err(|_| AppError::ValidationError(format!("host {} can't be resolved", host)))?
+      .map(|address| address.ip())
+      .collect(),
+  };
+  if addresses.is_empty() || !addresses.into_iter().all(is_public_address) {
+    return Err(AppError::ValidationError(format!(
+      "host {} is not a public address",
+      host
+    )));
+  }
+  Ok(())
+}
+
+/// Resolves the hosts of deliveries to their public addresses only, so a name checked when
+/// the webhook was saved can't be pointed at an internal address later.
+pub(crate) struct PublicResolver {
+  pub(crate) allowed_hosts: Arc<Vec<String>>,
+}
+
+impl Resolve for PublicResolver {
+  fn resolve(&self, name: Name) -> Resolving {
+    let allowed = is_allowed_host(name.as_str(), &self.allowed_hosts);
+    let host = name.as_str().to_string();
+    Box::pin(async move {
+      let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+        .await?
+        .filter(|address| allowed || is_public_address(address.ip()))
+        .collect();
+      if addresses.is_empty() {
+        return Err(format!("host {} has no public address", host).into());
+      }
+      Ok(Box::new(addresses.into_iter()) as Addrs)
+    })
+  }
+}
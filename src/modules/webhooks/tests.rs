@@ -0,0 +1,381 @@
+#[cfg(test)]
+mod util_tests {
+  use crate::AppState;
+  use crate::common::{DEFAULT_ORGANIZATION_ID, with_tenant};
+  use crate::modules::outbox::{EventSink, OutboxEvent, RelayReport};
+  use crate::modules::users::CreateUser;
+  use crate::modules::webhooks::*;
+  use anyhow::Result;
+  use axum::Router;
+  use axum::body::Bytes;
+  use axum::extract::State;
+  use axum::http::{HeaderMap, StatusCode};
+  use axum::routing::post;
+  use serial_test::serial;
+  use std::collections::VecDeque;
+  use std::sync::{Arc, Mutex};
+  use tokio::net::TcpListener;
+
+  /// Records the requests it gets and answers with the queued statuses, then 200.
+  #[derive(Clone, Default)]
+  struct Receiver {
+    requests: Arc<Mutex<Vec<(HeaderMap, Bytes)>>>,
+    statuses: Arc<Mutex<VecDeque<u16>>>,
+  }
+
+  async fn receive(
+    State(receiver): State<Receiver>,
+    headers: HeaderMap,
+    body: Bytes,
+  ) -> StatusCode {
+    receiver.requests.lock().unwrap().push((headers, body));
+    let status = receiver.statuses.lock().unwrap().pop_front().unwrap_or(200);
+    StatusCode::from_u16(status).unwrap()
+  }
+
+  async fn start_receiver() -> Result<(Receiver, String)> {
+    let receiver = Receiver::default();
+    let app = Router::new()
+      .route("/hook", post(receive))
+      .with_state(receiver.clone());
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    Ok((receiver, format!("http://{}/hook", addr)))
+  }
+
+  #[test]
+  fn webhook_signature_test() {
+    let signature = sign_webhook("secret", 1700000000, b"{}");
+    assert!(signature.starts_with("sha256="));
+    assert!(verify_webhook("secret", 1700000000, b"{}", &signature));
+    assert!(!verify_webhook("secret", 1700000001, b"{}", &signature));
+    assert!(!verify_webhook("other", 1700000000, b"{}", &signature));
+    assert!(!verify_webhook("secret", 1700000000, b"{ }", &signature));
+    assert!(!verify_webhook("secret", 1700000000, b"{}", "sha256=zz"));
+  }
+
+  #[tokio::test]
+  #[serial]
+  async fn webhook_delivery_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      let mut config = state.config.clone();
+      config.webhooks.allowed_hosts = vec!["127.0.0.1".to_string()];
+      let state = AppState::new(config, state.pool.clone());
+      let (receiver, url) = start_receiver().await?;
+      let secret = "test-secret-0123456789";
+      let created = state
+        .create_webhook(
+          CreateWebhook {
+            url,
+            event_types: vec!["user_created".to_string()],
+            secret: Some(secret.to_string()),
+          },
+          1,
+        )
+        .await?;
+      let webhook_id = created.webhook.id;
+      assert_eq!(created.secret, secret);
+
+      // the first attempt gets an error
+      receiver.statuses.lock().unwrap().push_back(500);
+      state
+        .create_user(CreateUser::new("newton", "apple123"))
+        .await?;
+      // only the subscribed event type is queued
+      state.suspend_user(3).await?;
+      state.relay_outbox().await?;
+      let page = state
+        .get_webhook_deliveries(webhook_id, &DeliveriesParams::default())
+        .await?;
+      assert_eq!(page.deliveries.len(), 1);
+      assert_eq!(page.deliveries[0].status, "pending");
+
+      let report = state.dispatch_webhooks().await?;
+      assert_eq!(
+        report,
+        RelayReport {
+          delivered: 0,
+          failed: 1
+        }
+      );
+      let page = state
+        .get_webhook_deliveries(webhook_id, &DeliveriesParams::default())
+        .await?;
+      let delivery = &page.deliveries[0];
+      assert_eq!(delivery.status, "pending");
+      assert_eq!(delivery.attempts, 1);
+      assert_eq!(delivery.response_status, Some(500));
+      assert!(delivery.next_attempt_at > delivery.created_at);
+
+      // retried once due
+      assert_eq!(state.dispatch_webhooks().await?, RelayReport::default());
+      sqlx::query("UPDATE webhook_deliveries SET next_attempt_at = NOW()")
+        .execute(&state.pool)
+        .await?;
+      assert_eq!(state.dispatch_webhooks().await?.delivered, 1);
+      let page = state
+        .get_webhook_deliveries(webhook_id, &DeliveriesParams::default())
+        .await?;
+      let delivery = page.deliveries[0].clone();
+      assert_eq!(delivery.status, "succeeded");
+      assert_eq!(delivery.attempts, 2);
+      assert!(delivery.delivered_at.is_some());
+
+      {
+        let requests = receiver.requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        let (headers, body) = &requests[1];
+        let header = |name: &str| headers[name].to_str().unwrap().to_string();
+        assert_eq!(header(WEBHOOK_EVENT_HEADER), "user_created");
+        assert_eq!(header(WEBHOOK_DELIVERY_HEADER), delivery.id.to_string());
+        let timestamp: i64 = header(WEBHOOK_TIMESTAMP_HEADER).parse()?;
+        assert!(verify_webhook(
+          secret,
+          timestamp,
+          body,
+          &header(WEBHOOK_SIGNATURE_HEADER)
+        ));
+        let body: serde_json::Value = serde_json::from_slice(body)?;
+        assert_eq!(body["id"], delivery.event_id);
+        assert_eq!(body["type"], "user_created");
+        assert_eq!(body["data"]["username"], "newton");
+      }
+
+      // the outbox relaying the event again doesn't queue it twice
+      let event: OutboxEvent = sqlx::query_as("SELECT * FROM outbox_events WHERE id = $1")
+        .bind(delivery.event_id)
+        .fetch_one(&state.pool)
+        .await?;
+      WebhookSink::new(state.pool.clone())
+        .deliver(&event)
+        .await
+        .unwrap();
+      let page = state
+        .get_webhook_deliveries(webhook_id, &DeliveriesParams::default())
+        .await?;
+      assert_eq!(page.deliveries.len(), 1);
+
+      // redelivered on demand
+      let redelivered = state.redeliver_webhook(webhook_id, delivery.id).await?;
+      assert_eq!(redelivered.status, "succeeded");
+      assert_eq!(redelivered.attempts, 1);
+      assert_eq!(receiver.requests.lock().unwrap().len(), 3);
+
+      // given up after the last attempt
+      receiver.statuses.lock().unwrap().push_back(503);
+      sqlx::query(
+        "UPDATE webhook_deliveries SET status = 'pending', attempts = $1, next_attempt_at = NOW()",
+      )
+      .bind(state.config.webhooks.max_attempts - 1)
+      .execute(&state.pool)
+      .await?;
+      assert_eq!(state.dispatch_webhooks().await?.failed, 1);
+      let failed = DeliveriesParams {
+        status: Some(DeliveryStatus::Failed),
+        ..Default::default()
+      };
+      let page = state.get_webhook_deliveries(webhook_id, &failed).await?;
+      assert_eq!(page.deliveries.len(), 1);
+      assert_eq!(page.deliveries[0].response_status, Some(503));
+
+      // not sent once the receiver's host is no longer allowed
+      let mut config = state.config.clone();
+      config.webhooks.allowed_hosts = vec![];
+      let state = AppState::new(config, state.pool.clone());
+      let redelivered = state.redeliver_webhook(webhook_id, delivery.id).await?;
+      assert_eq!(redelivered.status, "pending");
+      assert_eq!(redelivered.response_status, None);
+      assert_eq!(
+        redelivered.last_error.as_deref(),
+        Some("validation error: host 127.0.0.1 is not a public address")
+      );
+      assert_eq!(receiver.requests.lock().unwrap().len(), 4);
+      anyhow::Ok(())
+    })
+    .await
+  }
+
+  #[tokio::test]
+  async fn webhook_target_test() -> Result<()> {
+    for address in [
+      "127.0.0.1",
+      "10.1.2.3",
+      "172.16.0.1",
+      "192.168.1.1",
+      "169.254.169.254",
+      "100.64.0.1",
+      "0.0.0.0",
+      "::1",
+      "fd00::1",
+      "fe80::1",
+      "::ffff:127.0.0.1",
+      "::127.0.0.1",
+      "::10.0.0.1",
+      "64:ff9b::127.0.0.1",
+      "64:ff9b::a9fe:a9fe",
+      "2001:db8::1",
+    ] {
+      assert!(!is_public_address(address.parse()?), "{}", address);
+    }
+    for address in [
+      "93.184.216.34",
+      "2606:2800:220:1::1",
+      "64:ff9b::93.184.216.34",
+    ] {
+      assert!(is_public_address(address.parse()?), "{}", address);
+    }
+
+    for url in [
+      "http://127.0.0.1:8080/hook",
+      "http://localhost/hook",
+      "http://[::1]/hook",
+      "http://169.254.169.254/latest/meta-data",
+    ] {
+      assert!(check_webhook_target(url, &[]).await.is_err(), "{}", url);
+    }
+    check_webhook_target("https://93.184.216.34/hook", &[]).await?;
+    let allowed = vec!["localhost".to_string(), "::1".to_string()];
+    check_webhook_target("http://LOCALHOST:3000/hook", &allowed).await?;
+    check_webhook_target("http://[::1]/hook", &allowed).await?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod integration_tests {
+  use crate::AppState;
+  use crate::common::{DEFAULT_ORGANIZATION_ID, with_tenant};
+  use crate::test_util::{get_token, spawn_app};
+  use anyhow::Result;
+  use axum::http::StatusCode;
+  use reqwest::Client;
+  use serde_json::json;
+  use serial_test::serial;
+
+  #[tokio::test]
+  #[serial]
+  async fn webhook_handler_test() -> Result<()> {
+    let (_tdb, state) = AppState::init_test_state().await?;
+    with_tenant(DEFAULT_ORGANIZATION_ID, async move {
+      // not resolved, there may be no DNS where the tests run
+      let mut config = state.config.clone();
+      config.webhooks.allowed_hosts = vec!["example.com".to_string()];
+      let state = AppState::new(config, state.pool.clone());
+      let (addr, tx) = spawn_app(state).await?;
+
+      let client = Client::builder().no_proxy().build().unwrap();
+      let superman = get_token(&client, &addr.to_string(), "superman", "supermannofly").await?;
+      let alice = get_token(&client, &addr.to_string(), "alice", "123456").await?;
+      let input = json!({"url": "https://example.com/hook", "event_types": ["user_created"]});
+
+      let response = client
+        .post(format!("http://{}/webhooks", addr))
+        .json(&input)
+        .header("Authorization", format!("Bearer {}", alice))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+      let response = client
+        .post(format!("http://{}/webhooks", addr))
+        .json(&json!({"url": "https://example.com/hook", "event_types": ["user_renamed"]}))
+        .header("Authorization", format!("Bearer {}", superman))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+      let response = client
+        .post(format!("http://{}/webhooks", addr))
+        .json(&json!({"url": "ftp://example.com/hook", "event_types": ["user_created"]}))
+        .header("Authorization", format!("Bearer {}", superman))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+      let response = client
+        .post(format!("http://{}/webhooks", addr))
+        .json(&json!({"url": "http://127.0.0.1:3000/hook", "event_types": ["user_created"]}))
+        .header("Authorization", format!("Bearer {}", superman))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+      let response = client
+        .post(format!("http://{}/webhooks", addr))
+        .json(&input)
+        .header("Authorization", format!("Bearer {}", superman))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::CREATED);
+      let created: serde_json::Value = response.json().await?;
+      assert!(created["secret"].as_str().unwrap().starts_with("whsec_"));
+      assert!(created["webhook"].get("secret").is_none());
+      let webhook_id = created["webhook"]["id"].as_i64().unwrap();
+
+      let response = client
+        .patch(format!("http://{}/webhooks/{}", addr, webhook_id))
+        .json(&json!({"event_types": ["user_created", "roles_changed"], "active": false}))
+        .header("Authorization", format!("Bearer {}", superman))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+      let webhook: serde_json::Value = response.json().await?;
+      assert_eq!(
+        webhook["event_types"],
+        json!(["user_created", "roles_changed"])
+      );
+      assert_eq!(webhook["active"], false);
+      assert_eq!(webhook["url"], "https://example.com/hook");
+
+      let response = client
+        .get(format!("http://{}/webhooks", addr))
+        .header("Authorization", format!("Bearer {}", superman))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+      let webhooks: serde_json::Value = response.json().await?;
+      assert_eq!(webhooks.as_array().unwrap().len(), 1);
+
+      let response = client
+        .get(format!(
+          "http://{}/webhooks/{}/deliveries?status=failed",
+          addr, webhook_id
+        ))
+        .header("Authorization", format!("Bearer {}", superman))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+      let page: serde_json::Value = response.json().await?;
+      assert_eq!(page["deliveries"], json!([]));
+
+      let response = client
+        .post(format!(
+          "http://{}/webhooks/{}/deliveries/999/redeliver",
+          addr, webhook_id
+        ))
+        .header("Authorization", format!("Bearer {}", superman))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+      let response = client
+        .delete(format!("http://{}/webhooks/{}", addr, webhook_id))
+        .header("Authorization", format!("Bearer {}", superman))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::OK);
+      let response = client
+        .get(format!("http://{}/webhooks/{}", addr, webhook_id))
+        .header("Authorization", format!("Bearer {}", superman))
+        .send()
+        .await?;
+      assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+      tx.send(()).unwrap();
+      anyhow::Ok(())
+    })
+    .await
+  }
+}